@@ -0,0 +1,94 @@
+//! Peak memory of exporting 1M candles through `/api/candles`, streamed and
+//! on the buffered `project=1` path, run with
+//! `cargo bench --bench export_memory`. Linux only: it reads `/proc/self`.
+//!
+//! Each case runs in its own process, so memory one kept from the allocator
+//! cannot hide what the other needs. Arguments filter cases by name, e.g.
+//! `cargo bench --bench export_memory -- streamed`.
+
+use std::process::Command;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use duckdb::Connection;
+use futures_util::StreamExt;
+use graph::db::initialize_demo_db;
+use graph::demo::DemoSpec;
+use graph::{build_router, AppState, Config, Db};
+use tower::ServiceExt;
+
+const ROWS: usize = 1_000_000;
+
+const CASES: [(&str, &str); 2] = [
+    ("streamed", "/api/candles?limit=1000000"),
+    // `project=1` needs the whole series, so it takes the buffered path.
+    ("buffered", "/api/candles?limit=1000000&project=1"),
+];
+
+fn main() {
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+    if let [name] = args.as_slice() {
+        if let Some((name, uri)) = CASES.iter().find(|(case, _)| case == name) {
+            return measure(name, uri);
+        }
+    }
+    let exe = std::env::current_exe().expect("locate bench binary");
+    for (name, _) in CASES {
+        if args.is_empty() || args.iter().any(|filter| name.contains(filter.as_str())) {
+            let status = Command::new(&exe).arg(name).status().expect("run case");
+            assert!(status.success(), "{name} failed");
+        }
+    }
+}
+
+fn measure(name: &str, uri: &str) {
+    let dir = std::env::temp_dir().join(format!("graph-export-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create bench dir");
+    let conn = Connection::open(dir.join("bench.duckdb")).expect("open bench db");
+    let spec = DemoSpec {
+        rows: ROWS,
+        ..DemoSpec::default()
+    };
+    initialize_demo_db(&conn, &spec).expect("generate bench data");
+    let config = Config {
+        cache_enabled: false,
+        compression_enabled: false,
+        ..Config::default()
+    };
+    let db = Db::new(conn, 2).expect("open pool");
+    let app = build_router(AppState::new(Arc::new(db), config));
+
+    let runtime = tokio::runtime::Runtime::new().expect("build tokio runtime");
+    let (bytes, growth) = runtime.block_on(async {
+        // Resets the peak so it is taken from here on.
+        std::fs::write("/proc/self/clear_refs", "5").expect("reset peak RSS");
+        let baseline = status_kib("VmRSS:");
+        let response = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK, "GET {uri}");
+        let mut chunks = response.into_body().into_data_stream();
+        let mut bytes = 0;
+        while let Some(chunk) = chunks.next().await {
+            bytes += chunk.unwrap().len();
+        }
+        (bytes, status_kib("VmHWM:").saturating_sub(baseline))
+    });
+    println!("{name:<10} {bytes} bytes, peak RSS +{} MiB", growth / 1024);
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+/// A `/proc/self/status` field, in KiB.
+fn status_kib(field: &str) -> u64 {
+    let status = std::fs::read_to_string("/proc/self/status").expect("read /proc/self/status");
+    let line = status
+        .lines()
+        .find(|line| line.starts_with(field))
+        .unwrap_or_else(|| panic!("no {field} in /proc/self/status"));
+    line.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
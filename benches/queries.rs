@@ -0,0 +1,258 @@
+//! Timings for the candle, resampling, fib and indicator paths, and for
+//! preparing the candle query with and without the statement cache, over
+//! generated data, run with `cargo bench`.
+//!
+//! Each case runs against a file database in a temp dir filled by
+//! [`graph::demo`] with a fixed seed, so numbers are comparable across runs
+//! and machines differ only in speed.
+//! `cargo bench --bench queries -- --save-baseline main` records the medians
+//! under `target/bench-baselines/main.tsv`, and
+//! `cargo bench --bench queries -- --baseline main` reports each case's change
+//! against them. Further arguments filter cases by substring, e.g.
+//! `cargo bench --bench queries -- candles`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use axum::Router;
+use duckdb::{params, Connection};
+use graph::db::initialize_demo_db;
+use graph::demo::DemoSpec;
+use graph::indicators::{self, PriceSource};
+use graph::{build_router, AppState, Config, Db};
+use tower::ServiceExt;
+
+/// Samples per case, after one warm-up run.
+const SAMPLES: usize = 10;
+/// A case stops sampling early once it has run this long.
+const CASE_BUDGET: Duration = Duration::from_secs(5);
+
+fn main() {
+    let options = Options::parse();
+    let runtime = tokio::runtime::Runtime::new().expect("build tokio runtime");
+    let mut results = Vec::new();
+    for rows in [10_000, 100_000] {
+        let fixture = Fixture::new(rows);
+        let cases: [(&str, String); 4] = [
+            ("candles", format!("/api/candles?limit={rows}")),
+            (
+                "resample_1h",
+                format!("/api/candles?timeframe=1h&limit={rows}"),
+            ),
+            (
+                "fib_range",
+                "/api/fib?start=2024-01-02%2000:00:00&end=2024-01-05%2000:00:00".to_owned(),
+            ),
+            ("indicators_api", "/api/indicators".to_owned()),
+        ];
+        for (name, uri) in cases {
+            let id = format!("{name}/{rows}");
+            if options.selects(&id) {
+                let app = fixture.app.clone();
+                results.push(bench(&id, || runtime.block_on(fetch(&app, &uri))));
+            }
+        }
+        for cached in [false, true] {
+            let id = if cached {
+                format!("candles_prepare_cached/{rows}")
+            } else {
+                format!("candles_prepare/{rows}")
+            };
+            if options.selects(&id) {
+                let conn = fixture.db_conn();
+                let page = |stmt: &mut duckdb::Statement| {
+                    let open = None::<String>;
+                    stmt.query_map(
+                        params![open, open, open, open, open, open, open, None::<i64>, 500],
+                        |row| row.get::<_, f64>(4),
+                    )
+                    .unwrap()
+                    .count()
+                };
+                results.push(bench(&id, || {
+                    if cached {
+                        page(&mut conn.prepare_cached(CANDLE_PAGE_SQL).unwrap())
+                    } else {
+                        page(&mut conn.prepare(CANDLE_PAGE_SQL).unwrap())
+                    }
+                }));
+            }
+        }
+        let id = format!("indicators_compute/{rows}");
+        if options.selects(&id) {
+            let conn = fixture.db_conn();
+            results.push(bench(&id, || {
+                let points = indicators::compute(&conn, PriceSource::Close).unwrap();
+                assert_eq!(points.len(), rows);
+            }));
+        }
+    }
+    options.finish(&results);
+}
+
+/// The first page of `/api/candles`, open at both ends, as the handler
+/// queries it: `candles_prepare*` time planning it afresh against reusing the
+/// connection's cached statement.
+const CANDLE_PAGE_SQL: &str = "SELECT timestamp, open, high, low, close, volume, rowid
+     FROM candles
+     WHERE (? IS NULL OR timestamp >= CAST(? AS TIMESTAMP))
+       AND (? IS NULL OR timestamp <= CAST(? AS TIMESTAMP))
+       AND (? IS NULL OR timestamp > CAST(? AS TIMESTAMP)
+            OR (timestamp = CAST(? AS TIMESTAMP) AND rowid > ?))
+     ORDER BY timestamp ASC, rowid ASC
+     LIMIT ?";
+
+/// A generated series in its own database file, removed on drop.
+struct Fixture {
+    dir: PathBuf,
+    conn: Connection,
+    app: Router,
+}
+
+impl Fixture {
+    fn new(rows: usize) -> Self {
+        let dir = std::env::temp_dir().join(format!("graph-bench-{}-{rows}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create bench dir");
+        let conn = Connection::open(dir.join("bench.duckdb")).expect("open bench db");
+        let spec = DemoSpec {
+            rows,
+            ..DemoSpec::default()
+        };
+        initialize_demo_db(&conn, &spec).expect("generate bench data");
+        let config = Config {
+            cache_enabled: false,
+            compression_enabled: false,
+            ..Config::default()
+        };
+        let db = Db::new(conn.try_clone().expect("clone bench db"), 2).expect("open pool");
+        let app = build_router(AppState::new(Arc::new(db), config));
+        Self { dir, conn, app }
+    }
+
+    fn db_conn(&self) -> Connection {
+        self.conn.try_clone().expect("clone bench db")
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+async fn fetch(app: &Router, uri: &str) -> usize {
+    let response = app
+        .clone()
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "GET {uri}");
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap()
+        .len()
+}
+
+struct Measurement {
+    id: String,
+    median: Duration,
+    min: Duration,
+    samples: usize,
+}
+
+fn bench<T>(id: &str, mut run: impl FnMut() -> T) -> Measurement {
+    std::hint::black_box(run());
+    let started = Instant::now();
+    let mut times = Vec::with_capacity(SAMPLES);
+    while times.len() < SAMPLES && (times.len() < 2 || started.elapsed() < CASE_BUDGET) {
+        let start = Instant::now();
+        std::hint::black_box(run());
+        times.push(start.elapsed());
+    }
+    times.sort();
+    Measurement {
+        id: id.to_owned(),
+        median: times[times.len() / 2],
+        min: times[0],
+        samples: times.len(),
+    }
+}
+
+#[derive(Default)]
+struct Options {
+    filters: Vec<String>,
+    save_baseline: Option<String>,
+    baseline: Option<String>,
+}
+
+impl Options {
+    fn parse() -> Self {
+        let mut options = Self::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--save-baseline" => options.save_baseline = args.next(),
+                "--baseline" => options.baseline = args.next(),
+                // Passed by `cargo bench` to every bench target.
+                "--bench" => {}
+                _ if arg.starts_with("--") => panic!("unknown option {arg}"),
+                _ => options.filters.push(arg),
+            }
+        }
+        options
+    }
+
+    fn selects(&self, id: &str) -> bool {
+        self.filters.is_empty() || self.filters.iter().any(|filter| id.contains(filter))
+    }
+
+    fn finish(&self, results: &[Measurement]) {
+        let previous = self.baseline.as_deref().map(load_baseline);
+        for result in results {
+            let change = previous
+                .as_ref()
+                .and_then(|previous| previous.get(&result.id))
+                .map(|before| {
+                    let change = result.median.as_secs_f64() / before.as_secs_f64() - 1.0;
+                    format!("  {:+.1}% vs {before:.2?}", change * 100.0)
+                })
+                .unwrap_or_default();
+            println!(
+                "{:<30} median {:>10.2?}  min {:>10.2?}  ({} samples){change}",
+                result.id, result.median, result.min, result.samples
+            );
+        }
+        if let Some(name) = &self.save_baseline {
+            let path = baseline_path(name);
+            std::fs::create_dir_all(path.parent().unwrap()).expect("create baseline dir");
+            let lines = results
+                .iter()
+                .map(|result| format!("{}\t{}\n", result.id, result.median.as_nanos()))
+                .collect::<String>();
+            std::fs::write(&path, lines).expect("write baseline");
+            println!("saved baseline {}", path.display());
+        }
+    }
+}
+
+fn baseline_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("target/bench-baselines")
+        .join(format!("{name}.tsv"))
+}
+
+fn load_baseline(name: &str) -> HashMap<String, Duration> {
+    let path = baseline_path(name);
+    let text = std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("read baseline {}: {err}", path.display()));
+    text.lines()
+        .filter_map(|line| {
+            let (id, nanos) = line.split_once('\t')?;
+            Some((id.to_owned(), Duration::from_nanos(nanos.parse().ok()?)))
+        })
+        .collect()
+}
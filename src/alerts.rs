@@ -0,0 +1,973 @@
+//! Alert rules, `/api/alerts`: a price or volume crossing a level, checked
+//! as candles are stored, with a webhook called when one fires.
+//!
+//! Each rule watches one field of one series, `above` or `below` a level,
+//! and is `armed` or `fired`. An armed rule fires on the first bar that
+//! satisfies it and records that bar; a fired one stays quiet, however many
+//! bars go on satisfying it, until its `rearm` policy arms it again:
+//! `reset` (the default) on the first bar that no longer satisfies it,
+//! `never` for a one-shot alert, or a duration such as `15m` once a bar at
+//! least that long after the one it fired on arrives. A bar it fired on
+//! never fires it twice, even when rewritten.
+//!
+//! Rules are checked on every append or modification the [`crate::bus`]
+//! announces for their series, against the bars from the newest one they
+//! were last checked against onwards; a freshly created rule starts after
+//! the newest bar stored. Wholesale reloads move every rule past the data
+//! without firing it on replaced history.
+//!
+//! When a rule fires, its webhook gets a JSON `POST`, retried after 429,
+//! 5xx answers and failures to connect with a doubling delay, and each
+//! attempt is recorded in `webhook_deliveries`, which
+//! `/api/alerts/{id}/deliveries` lists. Webhooks go through the plain
+//! HTTP/1.1 client in [`crate::http`], so `https` ones need a TLS-terminating
+//! forwarder that the URL points at.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::Json;
+use chrono::{NaiveDateTime, SecondsFormat, Utc};
+use duckdb::types::{FromSql, FromSqlError, FromSqlResult, ValueRef};
+use duckdb::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::bus::DataEvent;
+use crate::config::WebhookSettings;
+use crate::db::Db;
+use crate::error::{bad_request, AppError};
+use crate::handlers::Timeframe;
+use crate::http::{self, Limits};
+use crate::models::{Timestamp, TimestampFormat};
+use crate::AppState;
+
+/// Longest `message` template a rule may carry.
+pub(crate) const MAX_MESSAGE_LEN: usize = 2000;
+
+/// Used when a rule has no `message` of its own.
+const DEFAULT_MESSAGE: &str = "{symbol} {field} is {condition} {level}: {value} at {timestamp}";
+
+/// How long a webhook may take to answer and how much of the answer is read.
+const DELIVERY_LIMITS: Limits = Limits {
+    timeout: Duration::from_secs(10),
+    max_bytes: 1024 * 1024,
+};
+
+/// The longest wait before a retry, whatever `Retry-After` asks for.
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+/// The candle value a rule watches.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Field {
+    Open,
+    High,
+    Low,
+    #[default]
+    Close,
+    Volume,
+}
+
+impl Field {
+    const ALL: [Field; 5] = [
+        Field::Open,
+        Field::High,
+        Field::Low,
+        Field::Close,
+        Field::Volume,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::Open => "open",
+            Field::High => "high",
+            Field::Low => "low",
+            Field::Close => "close",
+            Field::Volume => "volume",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Condition {
+    /// Strictly greater than the level.
+    Above,
+    /// Strictly less than the level.
+    Below,
+}
+
+impl Condition {
+    fn holds(self, value: f64, level: f64) -> bool {
+        match self {
+            Condition::Above => value > level,
+            Condition::Below => value < level,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Condition::Above => "above",
+            Condition::Below => "below",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RuleState {
+    Armed,
+    Fired,
+}
+
+impl RuleState {
+    fn name(self) -> &'static str {
+        match self {
+            RuleState::Armed => "armed",
+            RuleState::Fired => "fired",
+        }
+    }
+}
+
+impl FromSql for Field {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let name = value.as_str()?;
+        Field::ALL
+            .into_iter()
+            .find(|field| field.name() == name)
+            .ok_or(FromSqlError::InvalidType)
+    }
+}
+
+impl FromSql for Condition {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "above" => Ok(Condition::Above),
+            "below" => Ok(Condition::Below),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl FromSql for RuleState {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "armed" => Ok(RuleState::Armed),
+            "fired" => Ok(RuleState::Fired),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// When a fired rule is armed again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Rearm {
+    /// On the first bar that no longer satisfies it.
+    Reset,
+    /// Not at all.
+    Never,
+    /// On the first bar at least this long after the one it fired on.
+    After(Timeframe),
+}
+
+impl Rearm {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reset" => Some(Rearm::Reset),
+            "never" => Some(Rearm::Never),
+            duration => Timeframe::parse(duration).map(Rearm::After),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AlertRequest {
+    /// A series of `symbol_candles`; without one, the main candles.
+    symbol: Option<String>,
+    #[serde(default)]
+    field: Field,
+    condition: Condition,
+    level: f64,
+    webhook_url: String,
+    /// The text sent as `message`, with `{rule_id}`, `{symbol}`,
+    /// `{timestamp}`, `{field}`, `{condition}`, `{level}` and `{value}`
+    /// filled in.
+    message: Option<String>,
+    rearm: Option<String>,
+}
+
+/// A rule and where it stands.
+#[derive(Debug, Serialize)]
+pub(crate) struct AlertRule {
+    id: i64,
+    symbol: Option<String>,
+    field: Field,
+    condition: Condition,
+    level: f64,
+    webhook_url: String,
+    message: Option<String>,
+    rearm: String,
+    state: RuleState,
+    /// The bar it last fired on.
+    fired_at: Option<Timestamp>,
+    /// The newest bar it has been checked against.
+    checked_through: Option<Timestamp>,
+    created_at: Timestamp,
+}
+
+impl AlertRule {
+    const COLUMNS: &'static str = "id, symbol, field, condition, level, webhook_url, message, \
+         rearm, state, fired_at, checked_through, created_at";
+
+    fn from_row(row: &Row<'_>) -> duckdb::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            symbol: row.get(1)?,
+            field: row.get(2)?,
+            condition: row.get(3)?,
+            level: row.get(4)?,
+            webhook_url: row.get(5)?,
+            message: row.get(6)?,
+            rearm: row.get(7)?,
+            state: row.get(8)?,
+            fired_at: row.get(9)?,
+            checked_through: row.get(10)?,
+            created_at: row.get(11)?,
+        })
+    }
+
+    fn with_format(mut self, format: TimestampFormat) -> Self {
+        self.fired_at = self.fired_at.map(|at| at.with_format(format));
+        self.checked_through = self.checked_through.map(|at| at.with_format(format));
+        self.created_at = self.created_at.with_format(format);
+        self
+    }
+}
+
+/// One attempt at calling a rule's webhook.
+#[derive(Debug, Serialize)]
+pub(crate) struct Delivery {
+    id: i64,
+    /// The bar the rule fired on; every attempt for one firing shares it.
+    timestamp: Timestamp,
+    /// 1 for the first attempt, counting up through the retries.
+    attempt: i32,
+    attempted_at: Timestamp,
+    /// The webhook's answer, if it gave one.
+    status: Option<i32>,
+    error: Option<String>,
+    delivered: bool,
+}
+
+/// Creates a rule, armed, to be checked against bars after the newest one
+/// stored.
+pub(crate) async fn post_alert(
+    State(state): State<AppState>,
+    timestamps: TimestampFormat,
+    Json(request): Json<AlertRequest>,
+) -> Result<Json<AlertRule>, AppError> {
+    if request.symbol.as_deref() == Some("") {
+        return Err(bad_request("symbol must not be empty"));
+    }
+    if !request.level.is_finite() {
+        return Err(bad_request("level must be a finite number"));
+    }
+    http::check_url(&request.webhook_url)
+        .map_err(|err| bad_request(format!("webhook_url: {err}")))?;
+    if let Some(message) = &request.message {
+        if message.chars().count() > MAX_MESSAGE_LEN {
+            return Err(bad_request(format!(
+                "message is over {MAX_MESSAGE_LEN} characters"
+            )));
+        }
+    }
+    let rearm = request.rearm.unwrap_or_else(|| "reset".to_owned());
+    if Rearm::parse(&rearm).is_none() {
+        return Err(bad_request(format!(
+            "rearm must be reset, never or a duration such as 15m, not {rearm:?}"
+        )));
+    }
+    let rule = state
+        .db
+        .write(move |conn| {
+            let newest = newest_bar(conn, request.symbol.as_deref())?;
+            conn.prepare_cached(&format!(
+                "INSERT INTO alert_rules
+                    (symbol, field, condition, level, webhook_url, message, rearm, state,
+                     checked_through, created_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, 'armed', ?, ?)
+                 RETURNING {}",
+                AlertRule::COLUMNS
+            ))?
+            .query_row(
+                params![
+                    request.symbol,
+                    request.field.name(),
+                    request.condition.name(),
+                    request.level,
+                    request.webhook_url,
+                    request.message,
+                    rearm,
+                    newest,
+                    Timestamp::new(Utc::now().naive_utc()),
+                ],
+                AlertRule::from_row,
+            )
+        })
+        .await?;
+    Ok(Json(rule.with_format(timestamps)))
+}
+
+pub(crate) async fn get_alerts(
+    State(state): State<AppState>,
+    timestamps: TimestampFormat,
+) -> Result<Json<Vec<AlertRule>>, AppError> {
+    let rules = state
+        .db
+        .read(|conn| {
+            conn.prepare_cached(&format!(
+                "SELECT {} FROM alert_rules ORDER BY id",
+                AlertRule::COLUMNS
+            ))?
+            .query_map([], AlertRule::from_row)?
+            .collect::<duckdb::Result<Vec<_>>>()
+        })
+        .await?;
+    Ok(Json(
+        rules
+            .into_iter()
+            .map(|rule| rule.with_format(timestamps))
+            .collect(),
+    ))
+}
+
+/// Deletes a rule and its delivery history, answering with the rule.
+pub(crate) async fn delete_alert(
+    State(state): State<AppState>,
+    timestamps: TimestampFormat,
+    Path(id): Path<i64>,
+) -> Result<Json<AlertRule>, AppError> {
+    let rule = state
+        .db
+        .write(move |conn| {
+            let tx = conn.unchecked_transaction()?;
+            let rule = find_rule(&tx, id)?;
+            if rule.is_some() {
+                tx.execute("DELETE FROM webhook_deliveries WHERE rule_id = ?", [id])?;
+                tx.execute("DELETE FROM alert_rules WHERE id = ?", [id])?;
+            }
+            tx.commit()?;
+            Ok::<_, duckdb::Error>(rule)
+        })
+        .await?
+        .ok_or_else(|| no_rule(id))?;
+    Ok(Json(rule.with_format(timestamps)))
+}
+
+/// Every attempt at a rule's webhook, oldest first.
+pub(crate) async fn get_deliveries(
+    State(state): State<AppState>,
+    timestamps: TimestampFormat,
+    Path(id): Path<i64>,
+) -> Result<Json<Vec<Delivery>>, AppError> {
+    let deliveries = state
+        .db
+        .read(move |conn| {
+            if find_rule(conn, id)?.is_none() {
+                return Ok(None);
+            }
+            conn.prepare_cached(
+                "SELECT id, timestamp, attempt, attempted_at, status, error, delivered
+                 FROM webhook_deliveries
+                 WHERE rule_id = ?
+                 ORDER BY id",
+            )?
+            .query_map([id], |row| {
+                Ok(Delivery {
+                    id: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    attempt: row.get(2)?,
+                    attempted_at: row.get(3)?,
+                    status: row.get(4)?,
+                    error: row.get(5)?,
+                    delivered: row.get(6)?,
+                })
+            })?
+            .collect::<duckdb::Result<Vec<_>>>()
+            .map(Some)
+        })
+        .await?
+        .ok_or_else(|| no_rule(id))?;
+    Ok(Json(
+        deliveries
+            .into_iter()
+            .map(|mut delivery| {
+                delivery.timestamp = delivery.timestamp.with_format(timestamps);
+                delivery.attempted_at = delivery.attempted_at.with_format(timestamps);
+                delivery
+            })
+            .collect(),
+    ))
+}
+
+fn find_rule(conn: &Connection, id: i64) -> duckdb::Result<Option<AlertRule>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT {} FROM alert_rules WHERE id = ?",
+        AlertRule::COLUMNS
+    ))?;
+    let mut rows = stmt.query_map([id], AlertRule::from_row)?;
+    rows.next().transpose()
+}
+
+fn no_rule(id: i64) -> AppError {
+    AppError::NotFound(format!("no alert rule {id}"))
+}
+
+/// The timestamp of the newest bar of `symbol`'s series.
+fn newest_bar(conn: &Connection, symbol: Option<&str>) -> duckdb::Result<Option<Timestamp>> {
+    match symbol {
+        None => conn.query_row("SELECT max(timestamp) FROM candles", [], |row| row.get(0)),
+        Some(symbol) => conn.query_row(
+            "SELECT max(timestamp) FROM symbol_candles WHERE symbol = ?",
+            [symbol],
+            |row| row.get(0),
+        ),
+    }
+}
+
+/// Checks the rules of each series the bus says changed and calls the
+/// webhooks of those that fire, until the bus closes. A lagged receiver
+/// missed changes to unknown series, so it checks every rule.
+pub(crate) async fn run(
+    db: Arc<Db>,
+    settings: WebhookSettings,
+    mut events: broadcast::Receiver<DataEvent>,
+) {
+    loop {
+        let series = match events.recv().await {
+            Ok(DataEvent::CandlesAppended { symbol, .. })
+            | Ok(DataEvent::CandlesModified { symbol, .. }) => Some(symbol),
+            Ok(DataEvent::Reloaded) => {
+                if let Err(err) = db.write(skip_reloaded).await {
+                    tracing::error!("moving alert rules past reloaded data failed: {err}");
+                }
+                continue;
+            }
+            Err(RecvError::Lagged(_)) => None,
+            Err(RecvError::Closed) => return,
+        };
+        let firings = match db.write(move |conn| evaluate(conn, series.as_ref())).await {
+            Ok(firings) => firings,
+            Err(err) => {
+                tracing::error!("checking alert rules failed: {err}");
+                continue;
+            }
+        };
+        for firing in firings {
+            tokio::spawn(deliver(Arc::clone(&db), settings.clone(), firing));
+        }
+    }
+}
+
+/// A rule that fired, and what its webhook is sent.
+#[derive(Debug)]
+struct Firing {
+    rule_id: i64,
+    url: String,
+    timestamp: NaiveDateTime,
+    payload: Vec<u8>,
+}
+
+/// One bar of a series: its timestamp and values in [`Field::ALL`] order.
+type Bar = (NaiveDateTime, [Option<f64>; 5]);
+
+/// Checks the rules watching `series`, or all of them, against the bars
+/// stored since each was last checked, saving where each now stands.
+fn evaluate(conn: &Connection, series: Option<&Option<String>>) -> duckdb::Result<Vec<Firing>> {
+    let tx = conn.unchecked_transaction()?;
+    let rules = tx
+        .prepare_cached(&format!(
+            "SELECT {} FROM alert_rules
+             WHERE ? OR symbol IS NOT DISTINCT FROM ?
+             ORDER BY id",
+            AlertRule::COLUMNS
+        ))?
+        .query_map(
+            params![series.is_none(), series.and_then(Option::as_deref)],
+            AlertRule::from_row,
+        )?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+    let mut firings = Vec::new();
+    for rule in rules {
+        let bars = bars_since(&tx, rule.symbol.as_deref(), rule.checked_through)?;
+        let Some(&(newest, _)) = bars.last() else {
+            continue;
+        };
+        let rearm = Rearm::parse(&rule.rearm).unwrap_or(Rearm::Reset);
+        let index = Field::ALL
+            .iter()
+            .position(|&field| field == rule.field)
+            .expect("every field is listed");
+        let mut progress = Progress {
+            state: rule.state,
+            fired_at: rule.fired_at.map(|at| at.at),
+        };
+        for (at, values) in &bars {
+            let satisfied =
+                values[index].is_some_and(|value| rule.condition.holds(value, rule.level));
+            if progress.step(rearm, *at, satisfied) {
+                firings.push(Firing {
+                    rule_id: rule.id,
+                    url: rule.webhook_url.clone(),
+                    timestamp: *at,
+                    payload: payload(&rule, *at, values),
+                });
+            }
+        }
+        tx.execute(
+            "UPDATE alert_rules SET state = ?, fired_at = ?, checked_through = ? WHERE id = ?",
+            params![
+                progress.state.name(),
+                progress.fired_at.map(Timestamp::new),
+                Timestamp::new(newest),
+                rule.id
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(firings)
+}
+
+/// Where a rule stands as bars are checked against it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Progress {
+    state: RuleState,
+    fired_at: Option<NaiveDateTime>,
+}
+
+impl Progress {
+    /// Moves on past the bar at `at` and says whether the rule fired on it.
+    fn step(&mut self, rearm: Rearm, at: NaiveDateTime, satisfied: bool) -> bool {
+        if self.state == RuleState::Fired {
+            let rearmed = match (rearm, self.fired_at) {
+                (Rearm::Reset, _) => !satisfied,
+                (Rearm::Never, _) => false,
+                (Rearm::After(timeframe), Some(fired_at)) => at >= timeframe.advance(fired_at, 1),
+                (Rearm::After(_), None) => true,
+            };
+            if rearmed {
+                self.state = RuleState::Armed;
+            }
+        }
+        let fires = self.state == RuleState::Armed && satisfied && self.fired_at != Some(at);
+        if fires {
+            *self = Progress {
+                state: RuleState::Fired,
+                fired_at: Some(at),
+            };
+        }
+        fires
+    }
+}
+
+/// The bars of `symbol`'s series from `since` on, the last-ingested row for
+/// each timestamp; without `since`, the newest bar alone, so a rule on a
+/// series stored after it was created is not run over all its history.
+fn bars_since(
+    conn: &Connection,
+    symbol: Option<&str>,
+    since: Option<Timestamp>,
+) -> duckdb::Result<Vec<Bar>> {
+    let since = match since {
+        Some(since) => Some(since),
+        None => newest_bar(conn, symbol)?,
+    };
+    let Some(since) = since else {
+        return Ok(Vec::new());
+    };
+    let read = |row: &Row<'_>| {
+        Ok((
+            row.get::<_, Timestamp>(0)?.at,
+            [
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ],
+        ))
+    };
+    let mut bars = match symbol {
+        None => conn
+            .prepare_cached(
+                "SELECT timestamp, open, high, low, close, volume
+                 FROM candles
+                 WHERE timestamp >= ?
+                 ORDER BY timestamp, rowid DESC",
+            )?
+            .query_map([since], read)?
+            .collect::<duckdb::Result<Vec<_>>>()?,
+        Some(symbol) => conn
+            .prepare_cached(
+                "SELECT timestamp, open, high, low, close, volume
+                 FROM symbol_candles
+                 WHERE symbol = ? AND timestamp >= ?
+                 ORDER BY timestamp, rowid DESC",
+            )?
+            .query_map(params![symbol, since], read)?
+            .collect::<duckdb::Result<Vec<_>>>()?,
+    };
+    bars.dedup_by_key(|bar| bar.0);
+    Ok(bars)
+}
+
+/// Moves every rule past the newest bar of its series.
+fn skip_reloaded(conn: &Connection) -> duckdb::Result<()> {
+    conn.execute_batch(
+        "UPDATE alert_rules SET checked_through = CASE
+            WHEN symbol IS NULL THEN (SELECT max(timestamp) FROM candles)
+            ELSE (SELECT max(s.timestamp) FROM symbol_candles s
+                  WHERE s.symbol = alert_rules.symbol)
+         END;",
+    )
+}
+
+/// The JSON a webhook is sent when `rule` fires on the bar at `at`.
+fn payload(rule: &AlertRule, at: NaiveDateTime, values: &[Option<f64>; 5]) -> Vec<u8> {
+    let timestamp = at.and_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true);
+    let value = values[Field::ALL
+        .iter()
+        .position(|&field| field == rule.field)
+        .expect("every field is listed")];
+    let template = rule.message.as_deref().unwrap_or(DEFAULT_MESSAGE);
+    let message = template
+        .replace("{rule_id}", &rule.id.to_string())
+        .replace("{symbol}", rule.symbol.as_deref().unwrap_or("main"))
+        .replace("{timestamp}", &timestamp)
+        .replace("{field}", rule.field.name())
+        .replace("{condition}", rule.condition.name())
+        .replace("{level}", &rule.level.to_string())
+        .replace(
+            "{value}",
+            &value.map_or_else(|| "null".to_owned(), |value| value.to_string()),
+        );
+    let candle = Field::ALL
+        .iter()
+        .zip(values)
+        .map(|(field, value)| (field.name().to_owned(), json!(value)))
+        .collect::<serde_json::Map<_, _>>();
+    json!({
+        "rule_id": rule.id,
+        "symbol": rule.symbol,
+        "timestamp": timestamp,
+        "field": rule.field,
+        "condition": rule.condition,
+        "level": rule.level,
+        "value": value,
+        "candle": candle,
+        "message": message,
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// Calls the webhook of a rule that fired, retrying failures, 429 and 5xx
+/// answers up to `max_retries` times, and records every attempt.
+async fn deliver(db: Arc<Db>, settings: WebhookSettings, firing: Firing) {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let (status, retry_after, outcome) =
+            match http::post_json(&firing.url, &firing.payload, DELIVERY_LIMITS).await {
+                Ok(response) if (200..300).contains(&response.status) => {
+                    (Some(response.status), None, Ok(()))
+                }
+                Ok(response) => {
+                    let retry = response.status == 429 || response.status >= 500;
+                    let err = format!("the webhook answered {}", response.status);
+                    (
+                        Some(response.status),
+                        response.retry_after,
+                        Err((retry, err)),
+                    )
+                }
+                Err(err) => (None, None, Err((true, err))),
+            };
+        let error = outcome.as_ref().err().map(|(_, err)| err.clone());
+        let recorded = db
+            .write({
+                let (rule_id, timestamp) = (firing.rule_id, firing.timestamp);
+                move |conn| {
+                    conn.execute(
+                        "INSERT INTO webhook_deliveries
+                            (rule_id, timestamp, attempt, attempted_at, status, error, delivered)
+                         VALUES (?, ?, ?, ?, ?, ?, ?)",
+                        params![
+                            rule_id,
+                            Timestamp::new(timestamp),
+                            attempt,
+                            Timestamp::new(Utc::now().naive_utc()),
+                            status,
+                            error,
+                            error.is_none(),
+                        ],
+                    )
+                }
+            })
+            .await;
+        if let Err(err) = recorded {
+            tracing::error!(
+                "recording a delivery for alert rule {} failed: {err}",
+                firing.rule_id
+            );
+        }
+        let err = match outcome {
+            Ok(()) => return,
+            Err((true, err)) if attempt <= settings.max_retries => err,
+            Err((_, err)) => {
+                tracing::warn!(
+                    "alert rule {}'s webhook failed after {attempt} attempts: {err}",
+                    firing.rule_id
+                );
+                return;
+            }
+        };
+        let wait = retry_after
+            .unwrap_or_else(|| {
+                settings
+                    .retry_delay
+                    .saturating_mul(1 << (attempt - 1).min(16))
+            })
+            .min(MAX_RETRY_WAIT);
+        tracing::debug!(
+            "retrying alert rule {}'s webhook in {wait:?}: {err}",
+            firing.rule_id
+        );
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+    use axum::routing::post;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::build_router;
+    use crate::test_support::{admin_json, keyed_config, seeded_state, TEST_KEY};
+
+    const SEED: &str = "('2024-01-01 00:00:00', 10, 12, 9, 11, 100)";
+
+    fn at(minute: u32) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2024-01-01 00:00", "%Y-%m-%d %H:%M").unwrap()
+            + chrono::TimeDelta::minutes(minute.into())
+    }
+
+    /// Accepts webhook calls, refusing the first with a 503, and keeps the
+    /// bodies of the accepted ones.
+    async fn webhook() -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let hook = {
+            let received = Arc::clone(&received);
+            move |Json(body): Json<serde_json::Value>| {
+                let received = Arc::clone(&received);
+                let calls = Arc::clone(&calls);
+                async move {
+                    if calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+                    }
+                    received.lock().unwrap().push(body);
+                    StatusCode::NO_CONTENT.into_response()
+                }
+            }
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/hook", post(hook));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}/hook"), received)
+    }
+
+    async fn send(
+        app: &Router,
+        method: &str,
+        uri: &str,
+        body: serde_json::Value,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .header("x-api-key", TEST_KEY)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test]
+    fn rules_fire_once_until_rearmed() {
+        let fired = |rearm, bars: &[(u32, bool)]| {
+            let mut progress = Progress {
+                state: RuleState::Armed,
+                fired_at: None,
+            };
+            bars.iter()
+                .filter(|&&(minute, satisfied)| progress.step(rearm, at(minute), satisfied))
+                .map(|&(minute, _)| minute)
+                .collect::<Vec<_>>()
+        };
+        let bars = [
+            (0, true),
+            (1, true),
+            (2, false),
+            (3, true),
+            (4, true),
+            (5, true),
+        ];
+        assert_eq!(fired(Rearm::Reset, &bars), [0, 3]);
+        assert_eq!(fired(Rearm::Never, &bars), [0]);
+        let three_minutes = Rearm::parse("3m").unwrap();
+        assert_eq!(fired(three_minutes, &bars), [0, 3]);
+        // A rewritten bar it fired on does not fire it again.
+        assert_eq!(
+            fired(Rearm::Reset, &[(0, true), (0, false), (0, true)]),
+            [0]
+        );
+        assert!(Rearm::parse("soon").is_none());
+    }
+
+    #[tokio::test]
+    async fn firing_rules_call_their_webhook_and_record_each_attempt() {
+        let (url, received) = webhook().await;
+        let state = seeded_state(SEED);
+        let mut config = keyed_config();
+        config.webhooks.retry_delay = Duration::from_millis(1);
+        let state = AppState::new(state.db, config);
+        tokio::spawn(run(
+            Arc::clone(&state.db),
+            state.config.webhooks.clone(),
+            state.bus.subscribe(),
+        ));
+        let app = build_router(state.clone());
+
+        let rule = json!({
+            "condition": "above",
+            "level": 20,
+            "webhook_url": url,
+            "message": "rule {rule_id}: {symbol} {field} {value} > {level}",
+        });
+        let (status, created) = send(&app, "POST", "/api/alerts", rule).await;
+        assert_eq!(status, StatusCode::OK, "{created}");
+        assert_eq!(created["state"], "armed");
+        assert_eq!(created["rearm"], "reset");
+        assert_eq!(created["checked_through"], "2024-01-01 00:00:00");
+        let id = created["id"].as_i64().unwrap();
+
+        // Only the second new bar closes above the level.
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles VALUES
+                        ('2024-01-01 00:01:00', 11, 15, 10, 14, 100),
+                        ('2024-01-01 00:02:00', 14, 25, 13, 24, 300);",
+                )
+            })
+            .await
+            .unwrap();
+        state.bus.publish([DataEvent::CandlesAppended {
+            symbol: None,
+            from: at(1),
+            to: at(2),
+            count: 2,
+        }]);
+        let deliveries = format!("/api/alerts/{id}/deliveries");
+        let mut attempts = serde_json::Value::Null;
+        for _ in 0..500 {
+            attempts = admin_json(&app, &deliveries).await;
+            if attempts.as_array().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let attempts = attempts.as_array().unwrap();
+        assert_eq!(attempts.len(), 2, "{attempts:?}");
+        assert_eq!(attempts[0]["status"], 503);
+        assert_eq!(attempts[0]["delivered"], false);
+        assert_eq!(attempts[1]["attempt"], 2);
+        assert_eq!(attempts[1]["status"], 204);
+        assert_eq!(attempts[1]["delivered"], true);
+        assert_eq!(attempts[1]["timestamp"], "2024-01-01 00:02:00");
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(
+            received,
+            [json!({
+                "rule_id": id,
+                "symbol": null,
+                "timestamp": "2024-01-01T00:02:00Z",
+                "field": "close",
+                "condition": "above",
+                "level": 20.0,
+                "value": 24.0,
+                "candle": {"open": 14.0, "high": 25.0, "low": 13.0, "close": 24.0, "volume": 300.0},
+                "message": format!("rule {id}: main close 24 > 20"),
+            })]
+        );
+        let rules = admin_json(&app, "/api/alerts").await;
+        assert_eq!(rules[0]["state"], "fired");
+        assert_eq!(rules[0]["fired_at"], "2024-01-01 00:02:00");
+        assert_eq!(rules[0]["checked_through"], "2024-01-01 00:02:00");
+
+        let (status, deleted) = send(&app, "DELETE", &format!("/api/alerts/{id}"), json!({})).await;
+        assert_eq!((status, deleted["id"].as_i64()), (StatusCode::OK, Some(id)));
+        assert_eq!(admin_json(&app, "/api/alerts").await, json!([]));
+        let (status, _) = send(&app, "GET", &deliveries, json!({})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn malformed_rules_are_refused() {
+        let app = build_router(AppState::new(seeded_state(SEED).db, keyed_config()));
+        let rule = |change: serde_json::Value| {
+            let mut rule = json!({
+                "condition": "below",
+                "level": 5,
+                "webhook_url": "http://127.0.0.1:9/hook",
+            });
+            rule.as_object_mut()
+                .unwrap()
+                .extend(change.as_object().unwrap().clone());
+            rule
+        };
+        for change in [
+            json!({"webhook_url": "https://example.com/hook"}),
+            json!({"rearm": "sometimes"}),
+            json!({"symbol": ""}),
+            json!({"message": "x".repeat(MAX_MESSAGE_LEN + 1)}),
+        ] {
+            let (status, body) = send(&app, "POST", "/api/alerts", rule(change.clone())).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{change}: {body}");
+        }
+        let (status, _) = send(&app, "POST", "/api/alerts", rule(json!({"rearm": "1h"}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, "DELETE", "/api/alerts/99", json!({})).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,106 @@
+//! The front end compiled into the binary, served from memory when
+//! `GRAPH_STATIC_DIR` is not set, so a deployment is the binary alone.
+//!
+//! The files keep their names from build to build, so they are sent with
+//! `Cache-Control: no-cache` and an `ETag` of their contents: browsers keep
+//! them but check each time, and get `304 Not Modified` until a new build
+//! changes them.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+
+use crate::error::method_not_allowed;
+
+struct Asset {
+    path: &'static str,
+    content_type: &'static str,
+    body: &'static [u8],
+}
+
+/// Everything in `static/`; a new file there needs an entry here too.
+const ASSETS: &[Asset] = &[
+    Asset {
+        path: "/index.html",
+        content_type: "text/html; charset=utf-8",
+        body: include_bytes!("../static/index.html"),
+    },
+    Asset {
+        path: "/app.js",
+        content_type: "text/javascript; charset=utf-8",
+        body: include_bytes!("../static/app.js"),
+    },
+    Asset {
+        path: "/styles.css",
+        content_type: "text/css; charset=utf-8",
+        body: include_bytes!("../static/styles.css"),
+    },
+];
+
+/// The embedded file at the request's path, `index.html` for a directory,
+/// or `index.html` for a page the browser navigates to that is not a file,
+/// so the front end can route it. Anything else missing is a bare 404, as
+/// `ServeDir` answers.
+pub(crate) async fn serve(request: Request) -> Response {
+    let (method, uri) = (request.method().clone(), request.uri().clone());
+    if method != Method::GET && method != Method::HEAD {
+        return method_not_allowed(method, uri).await.into_response();
+    }
+    let path = match uri.path() {
+        path if path.ends_with('/') => format!("{path}index.html"),
+        path => path.to_owned(),
+    };
+    let asset = ASSETS.iter().find(|asset| asset.path == path).or_else(|| {
+        is_navigation(request.headers(), &path)
+            .then(|| ASSETS.iter().find(|asset| asset.path == "/index.html"))
+            .flatten()
+    });
+    let Some(asset) = asset else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let etag = entity_tag(asset.body);
+    let headers = [
+        (CONTENT_TYPE, HeaderValue::from_static(asset.content_type)),
+        (CACHE_CONTROL, HeaderValue::from_static("no-cache")),
+        (ETAG, etag.clone()),
+    ];
+    let revalidated = request
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .any(|tag| tag.trim() == "*" || tag.trim().as_bytes() == etag.as_bytes())
+        });
+    if revalidated {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+    let body = match method {
+        Method::HEAD => Body::empty(),
+        _ => Body::from(asset.body),
+    };
+    (headers, body).into_response()
+}
+
+/// A browser loading a page rather than fetching a file: it accepts HTML and
+/// the last path segment has no extension.
+pub(crate) fn is_navigation(headers: &HeaderMap, path: &str) -> bool {
+    let accepts_html = headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    let last = path.rsplit('/').next().unwrap_or_default();
+    accepts_html && !last.contains('.')
+}
+
+fn entity_tag(body: &[u8]) -> HeaderValue {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish()))
+        .expect("hex digits are a valid header")
+}
@@ -0,0 +1,216 @@
+//! API keys for the admin and write routes, and optionally every route.
+//!
+//! Callers send `Authorization: Bearer <key>` or `X-Api-Key: <key>`. No key
+//! at all is a `401`; a key that matches none of the configured ones is a
+//! `403`, as is any request while no keys are configured, so protected routes
+//! are never open by accident. Keys are compared in constant time, and only
+//! their ids reach the logs and `/api/admin/stats`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::extract::{Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, HeaderName};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::Serialize;
+
+use crate::config::ApiKey;
+use crate::error::AppError;
+use crate::AppState;
+
+pub(crate) const X_API_KEY: HeaderName = HeaderName::from_static("x-api-key");
+
+/// What a route exposes, and so whether it needs a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Access {
+    /// Public unless `require_auth_for_reads` is set.
+    Read,
+    /// Admin and mutating routes; always keyed.
+    Write,
+}
+
+/// The configured keys with a request count for each.
+pub(crate) struct Keys {
+    keys: Vec<(ApiKey, AtomicU64)>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct KeyUsage {
+    id: String,
+    requests: u64,
+}
+
+impl Keys {
+    pub(crate) fn new(keys: &[ApiKey]) -> Self {
+        Self {
+            keys: keys
+                .iter()
+                .map(|key| (key.clone(), AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+
+    /// The key `presented` matches. Every configured key is compared, so the
+    /// time taken says nothing about which one came close.
+    fn find(&self, presented: &str) -> Option<&(ApiKey, AtomicU64)> {
+        self.keys.iter().fold(None, |found, entry| {
+            let matches = constant_time_eq(presented.as_bytes(), entry.0.secret.as_bytes());
+            found.or(matches.then_some(entry))
+        })
+    }
+
+    pub(crate) fn usage(&self) -> Vec<KeyUsage> {
+        self.keys
+            .iter()
+            .map(|(key, requests)| KeyUsage {
+                id: key.id.clone(),
+                requests: requests.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+pub(crate) async fn require_api_key(
+    State((state, access)): State<(AppState, Access)>,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if access == Access::Read && !state.config.require_auth_for_reads {
+        return Ok(next.run(request).await);
+    }
+    let Some(presented) = presented_key(request.headers()) else {
+        return Err(AppError::Unauthorized(
+            "an API key is required, as Authorization: Bearer <key> or X-Api-Key".to_owned(),
+        ));
+    };
+    if state.config.api_keys.is_empty() {
+        return Err(AppError::Forbidden(
+            "no API keys are configured on this server".to_owned(),
+        ));
+    }
+    let Some((key, requests)) = state.keys.find(presented) else {
+        tracing::warn!(
+            "refused an invalid API key for {} {}",
+            request.method(),
+            request.uri().path()
+        );
+        return Err(AppError::Forbidden("invalid API key".to_owned()));
+    };
+    requests.fetch_add(1, Ordering::Relaxed);
+    tracing::info!(
+        key = %key.id,
+        "{} {}",
+        request.method(),
+        request.uri().path()
+    );
+    Ok(next.run(request).await)
+}
+
+/// The bearer token, or failing that the `X-Api-Key` header.
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| headers.get(X_API_KEY).and_then(|value| value.to_str().ok()))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::*;
+    use crate::{build_router, Config};
+
+    #[tokio::test]
+    async fn protected_routes_need_a_configured_key() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let post = |app: &axum::Router, headers: &[(HeaderName, &str)]| {
+            let mut request = Request::post("/api/admin/generate?rows=5");
+            for (name, value) in headers {
+                request = request.header(name, *value);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        // Without configured keys nothing gets in, not even with a key.
+        let app = build_router(state.clone());
+        let response = get_uri(&app, "/api/admin/stats").await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["www-authenticate"], "Bearer");
+        let response = get_with(&app, "/api/admin/stats", &[(X_API_KEY, "anything")]).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(get_uri(&app, "/api/candles").await.status(), StatusCode::OK);
+
+        let config = Config {
+            api_keys: vec![ApiKey::new("ci", "k-ci"), ApiKey::new("ops", "k-ops")],
+            ..Config::default()
+        };
+        let app = build_router(AppState::new(Arc::clone(&state.db), config));
+        let response = post(&app, &[]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = post(&app, &[(AUTHORIZATION, "Bearer k-cii")])
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["code"], "forbidden");
+
+        let response = post(&app, &[(AUTHORIZATION, "Bearer k-ci")]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get_with(&app, "/api/admin/stats", &[(X_API_KEY, "k-ops")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats["candles"], 5);
+        assert_eq!(
+            stats["api_keys"],
+            serde_json::json!([{"id": "ci", "requests": 1}, {"id": "ops", "requests": 1}])
+        );
+        // Reads stay public by default.
+        assert_eq!(get_uri(&app, "/api/candles").await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn reads_can_be_locked_down_too() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let config = Config {
+            api_keys: vec![ApiKey::new("app", "k-app")],
+            require_auth_for_reads: true,
+            ..Config::default()
+        };
+        let app = build_router(AppState::new(state.db, config));
+        for uri in [
+            "/api/candles",
+            "/api/indicators",
+            "/api/ws",
+            "/api/openapi.json",
+            "/docs",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "GET {uri}");
+        }
+        let response = get_with(&app, "/api/candles", &[(X_API_KEY, "k-app")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get_with(&app, "/api/openapi.json", &[(X_API_KEY, "k-app")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(get_uri(&app, "/healthz").await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,450 @@
+//! `POST /api/backfill`: history paged in from an upstream HTTP API and
+//! stored as it arrives.
+//!
+//! The upstream is `GRAPH_BACKFILL_URL`, whose `{symbol}`, `{start}`, `{end}`
+//! and `{limit}` placeholders are filled in for each page, the times as
+//! RFC 3339 in UTC. A page is a JSON array of candles shaped like
+//! `/api/candles` rows, so another instance of this server can be the
+//! upstream. Each page starts just after the newest candle of the one
+//! before, and a short page, or one past `end`, ends the run. Pages refused
+//! with 429 or 503, or that fail to arrive, are asked for again after
+//! `Retry-After` or a doubling delay. Candles at timestamps already stored
+//! are left alone, so an interrupted run can simply be repeated.
+//!
+//! Pages come through the plain HTTP/1.1 client in [`crate::http`], so an
+//! `https` upstream needs a TLS-terminating forwarder that the URL points at.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::Json;
+use chrono::{NaiveDateTime, SecondsFormat, TimeDelta};
+use duckdb::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::config::BackfillSettings;
+use crate::error::{bad_request, AppError};
+use crate::handlers::{json_timestamp, parse_range};
+use crate::http::{self, Limits};
+use crate::models::{Candle, QuoteValues, Timestamp, Volume};
+use crate::AppState;
+
+/// How long one page may take to arrive and how large it may be.
+const PAGE_LIMITS: Limits = Limits {
+    timeout: Duration::from_secs(30),
+    max_bytes: 64 * 1024 * 1024,
+};
+
+/// The longest wait before a retry, whatever `Retry-After` asks for.
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+pub(crate) struct BackfillRequest {
+    /// Stored in `symbol_candles`; without one, in the main table.
+    symbol: Option<String>,
+    start: String,
+    end: String,
+}
+
+/// What a backfill fetched and stored.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub(crate) struct BackfillReport {
+    pub(crate) pages: usize,
+    /// Candles received within the requested range.
+    pub(crate) fetched: usize,
+    pub(crate) inserted: usize,
+    /// Candles at timestamps already stored, which were left as they were.
+    pub(crate) duplicates: usize,
+    /// Pages asked for again after failing or being refused.
+    pub(crate) retries: u32,
+}
+
+/// One candle of an upstream page.
+#[derive(Deserialize)]
+struct UpstreamCandle {
+    /// Any query timestamp format, or unix seconds or milliseconds.
+    timestamp: serde_json::Value,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    #[serde(default)]
+    volume: f64,
+}
+
+impl UpstreamCandle {
+    fn candle(self, index: usize) -> Result<Candle, AppError> {
+        let at = json_timestamp(&format!("candle {index}'s timestamp"), &self.timestamp)?;
+        Ok(Candle {
+            timestamp: Timestamp::new(at),
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            volume: Volume::new(self.volume),
+            events: None,
+            quotes: QuoteValues::default(),
+        })
+    }
+}
+
+/// Fetches `start` through `end` from the upstream a page at a time,
+/// storing each page before asking for the next.
+pub(crate) async fn post_backfill(
+    State(state): State<AppState>,
+    Json(request): Json<BackfillRequest>,
+) -> Result<Json<BackfillReport>, AppError> {
+    let settings = &state.config.backfill;
+    let Some(template) = settings.url.as_deref() else {
+        return Err(AppError::Unavailable(
+            "backfill is not configured; set GRAPH_BACKFILL_URL".to_owned(),
+        ));
+    };
+    let symbol = request.symbol;
+    if symbol.as_deref() == Some("") {
+        return Err(bad_request("symbol must not be empty"));
+    }
+    let (Some(start), Some(end)) = parse_range(Some(&request.start), Some(&request.end))? else {
+        unreachable!("both bounds were given");
+    };
+
+    let mut report = BackfillReport::default();
+    let mut cursor = start.at;
+    loop {
+        let url = page_url(
+            template,
+            symbol.as_deref(),
+            cursor,
+            end.at,
+            settings.page_size,
+        );
+        let page = report.pages + 1;
+        let body = fetch(settings, &url, &mut report.retries)
+            .await
+            .map_err(|err| interrupted(&report, format!("fetching page {page} failed: {err}")))?;
+        let rows = serde_json::from_slice::<Vec<UpstreamCandle>>(&body).map_err(|err| {
+            interrupted(
+                &report,
+                format!("page {page} is not a JSON array of candles: {err}"),
+            )
+        })?;
+        report.pages = page;
+        let full = rows.len() >= settings.page_size;
+        let mut candles = rows
+            .into_iter()
+            .enumerate()
+            .map(|(index, row)| row.candle(index))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| interrupted(&report, format!("page {page}: {}", err.message())))?;
+        candles.retain(|candle| (cursor..=end.at).contains(&candle.timestamp.at));
+        // The last candle a page gives for a timestamp wins, as the last
+        // row stored does in the tables.
+        candles.sort_by_key(|candle| candle.timestamp.at);
+        candles.reverse();
+        candles.dedup_by_key(|candle| candle.timestamp.at);
+        candles.reverse();
+        let Some(newest) = candles.last().map(|candle| candle.timestamp.at) else {
+            break;
+        };
+
+        report.fetched += candles.len();
+        report.inserted += store(&state, symbol.clone(), candles).await?;
+        report.duplicates = report.fetched - report.inserted;
+        tracing::info!(
+            "backfill page {page} of {} stored through {newest}: {} candles so far",
+            symbol.as_deref().unwrap_or("the main series"),
+            report.inserted
+        );
+        match newest.checked_add_signed(TimeDelta::microseconds(1)) {
+            Some(next) if full && next <= end.at => cursor = next,
+            _ => break,
+        }
+    }
+    Ok(Json(report))
+}
+
+/// An upstream failure part way through, saying what was already stored.
+fn interrupted(report: &BackfillReport, message: String) -> AppError {
+    AppError::Unavailable(match report.pages {
+        0 => message,
+        pages => format!(
+            "{message}; the {pages} pages before it stored {} candles",
+            report.inserted
+        ),
+    })
+}
+
+/// `template` with its placeholders filled in for one page.
+fn page_url(
+    template: &str,
+    symbol: Option<&str>,
+    from: NaiveDateTime,
+    end: NaiveDateTime,
+    limit: usize,
+) -> String {
+    let time = |at: NaiveDateTime| at.and_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true);
+    template
+        .replace("{symbol}", &encode(symbol.unwrap_or_default()))
+        .replace("{start}", &time(from))
+        .replace("{end}", &time(end))
+        .replace("{limit}", &limit.to_string())
+}
+
+/// Percent-encodes everything but the characters a URL never reserves.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// Stores the `candles` at timestamps not stored yet, holding the hub's
+/// watermark until they are published, and says how many there were.
+async fn store(
+    state: &AppState,
+    symbol: Option<String>,
+    candles: Vec<Candle>,
+) -> Result<usize, AppError> {
+    let mut watermark = state.hub.hold_watermark().await;
+    let fresh = state
+        .db
+        .write({
+            let symbol = symbol.clone();
+            move |conn| append_new(conn, symbol.as_deref(), candles)
+        })
+        .await?;
+    let inserted = fresh.len();
+    if inserted > 0 {
+        let stored = fresh.into_iter().map(|candle| (candle, false)).collect();
+        state.hub.stored(&mut watermark, symbol.as_deref(), stored);
+    }
+    Ok(inserted)
+}
+
+/// Appends those of `candles`, in timestamp order, whose timestamps the
+/// series does not hold yet, and returns them.
+fn append_new(
+    conn: &Connection,
+    symbol: Option<&str>,
+    candles: Vec<Candle>,
+) -> duckdb::Result<Vec<Candle>> {
+    let (Some(first), Some(last)) = (candles.first(), candles.last()) else {
+        return Ok(candles);
+    };
+    let (first, last) = (first.timestamp, last.timestamp);
+    let tx = conn.unchecked_transaction()?;
+    let stored = match symbol {
+        None => tx
+            .prepare_cached("SELECT timestamp FROM candles WHERE timestamp BETWEEN ? AND ?")?
+            .query_map(params![first, last], |row| row.get::<_, Timestamp>(0))?
+            .map(|at| at.map(|at| at.at))
+            .collect::<duckdb::Result<HashSet<_>>>()?,
+        Some(symbol) => tx
+            .prepare_cached(
+                "SELECT timestamp FROM symbol_candles
+                 WHERE symbol = ? AND timestamp BETWEEN ? AND ?",
+            )?
+            .query_map(params![symbol, first, last], |row| {
+                row.get::<_, Timestamp>(0)
+            })?
+            .map(|at| at.map(|at| at.at))
+            .collect::<duckdb::Result<HashSet<_>>>()?,
+    };
+    let fresh = candles
+        .into_iter()
+        .filter(|candle| !stored.contains(&candle.timestamp.at))
+        .collect::<Vec<_>>();
+    let mut appender = tx.appender(match symbol {
+        None => "candles",
+        Some(_) => "symbol_candles",
+    })?;
+    for candle in &fresh {
+        let Candle {
+            timestamp,
+            open,
+            high,
+            low,
+            close,
+            volume,
+            ..
+        } = candle;
+        match symbol {
+            None => appender.append_row(params![timestamp, open, high, low, close, volume])?,
+            Some(symbol) => {
+                appender.append_row(params![symbol, timestamp, open, high, low, close, volume])?
+            }
+        }
+    }
+    appender.flush()?;
+    drop(appender);
+    tx.commit()?;
+    Ok(fresh)
+}
+
+/// The body of `url`, retrying failures and 429 and 503 answers up to
+/// `max_retries` times and counting each retry in `retries`.
+async fn fetch(
+    settings: &BackfillSettings,
+    url: &str,
+    retries: &mut u32,
+) -> Result<Vec<u8>, String> {
+    let mut attempt = 0;
+    loop {
+        let (retry_after, err) =
+            match http::get(url, settings.authorization.as_deref(), PAGE_LIMITS).await {
+                Ok(response) if (200..300).contains(&response.status) => return Ok(response.body),
+                Ok(response) if matches!(response.status, 429 | 503) => (
+                    response.retry_after,
+                    format!("the upstream answered {}", response.status),
+                ),
+                Ok(response) => return Err(format!("the upstream answered {}", response.status)),
+                Err(err) => (None, err),
+            };
+        if attempt >= settings.max_retries {
+            return Err(err);
+        }
+        let wait = retry_after
+            .unwrap_or_else(|| settings.retry_delay.saturating_mul(1 << attempt.min(16)))
+            .min(MAX_RETRY_WAIT);
+        tracing::debug!("retrying a backfill page in {wait:?}: {err}");
+        attempt += 1;
+        *retries += 1;
+        tokio::time::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::extract::{Query, Request};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+    use chrono::DateTime;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::build_router;
+    use crate::test_support::{get_json, keyed_config, seeded_state, TEST_KEY};
+
+    /// Stored before the backfill, and kept by it.
+    const SEED: &str = "('2024-01-01 00:01:00', 5, 5, 5, 5, 5)";
+
+    /// Serves minute candles from 00:00 to 00:04 a page at a time, refusing
+    /// the first request with a 429 and any without the bearer token.
+    async fn upstream() -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&requests);
+        let ohlc = move |headers: HeaderMap, Query(query): Query<HashMap<String, String>>| {
+            let requests = Arc::clone(&counted);
+            async move {
+                if headers["authorization"] != "Bearer secret" {
+                    return StatusCode::UNAUTHORIZED.into_response();
+                }
+                if requests.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return ([("retry-after", "0")], StatusCode::TOO_MANY_REQUESTS).into_response();
+                }
+                let bound = |name: &str| {
+                    DateTime::parse_from_rfc3339(&query[name])
+                        .unwrap()
+                        .naive_utc()
+                };
+                let (start, end) = (bound("start"), bound("end"));
+                let limit = query["limit"].parse().unwrap();
+                let midnight = NaiveDateTime::parse_from_str("2024-01-01 00:00", "%Y-%m-%d %H:%M");
+                let candles = (0..5)
+                    .map(|minute| midnight.unwrap() + TimeDelta::minutes(minute))
+                    .filter(|at| (start..=end).contains(at))
+                    .take(limit)
+                    .map(|at| {
+                        json!({
+                            "timestamp": at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                            "open": 1, "high": 2, "low": 1, "close": 2, "volume": 10,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                axum::Json(candles).into_response()
+            }
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/ohlc", get(ohlc));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let url = format!(
+            "http://{addr}/ohlc?symbol={{symbol}}&start={{start}}&end={{end}}&limit={{limit}}"
+        );
+        (url, requests)
+    }
+
+    async fn post(app: &Router, body: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/api/backfill")
+            .header("content-type", "application/json")
+            .header("x-api-key", TEST_KEY)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn backfill_pages_through_the_range_and_skips_stored_candles() {
+        let (url, requests) = upstream().await;
+        let state = seeded_state(SEED);
+        let mut config = keyed_config();
+        config.backfill.url = Some(url);
+        config.backfill.authorization = Some("Bearer secret".to_owned());
+        config.backfill.page_size = 2;
+        let app = build_router(AppState::new(state.db, config));
+
+        let range = json!({"start": "2024-01-01", "end": "2024-01-01 00:03:00"});
+        let report = |pages, inserted, duplicates, retries| {
+            json!({
+                "pages": pages,
+                "fetched": 4,
+                "inserted": inserted,
+                "duplicates": duplicates,
+                "retries": retries,
+            })
+        };
+        let (status, body) = post(&app, range.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body, report(2, 3, 1, 1));
+        // The refused request, then one per page.
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        let candles = get_json(&app, "/api/candles").await;
+        let closes = candles
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|candle| candle["close"].as_f64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(closes, [2.0, 5.0, 2.0, 2.0]);
+
+        // Everything is stored now, so a repeat inserts nothing.
+        let (_, body) = post(&app, range).await;
+        assert_eq!(body, report(2, 0, 4, 0));
+
+        let (status, _) = post(&app, json!({"start": "2024-01-02", "end": "2024-01-01"})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let unconfigured = AppState::new(seeded_state(SEED).db, keyed_config());
+        let range = json!({"start": "2024-01-01", "end": "2024-01-02"});
+        let (status, _) = post(&build_router(unconfigured), range).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
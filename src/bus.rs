@@ -0,0 +1,203 @@
+//! Typed notifications that the stored data changed, for the subsystems that
+//! derive something from it.
+//!
+//! Every path that writes candles publishes here once its write has
+//! committed: the hub's poller for rows other processes add, `/api/ticks`
+//! for the bars it stores, and demo generation and repairs for wholesale
+//! changes. Consumers subscribe rather than each inventing a hook.
+//!
+//! Ordering: the data version is bumped before the events describing a
+//! change are sent, so a subscriber woken by an event never reads an older
+//! version. Writes to the candles tables hold the hub's watermark (see
+//! [`Hub::hold_watermark`](crate::hub::Hub::hold_watermark)) from the write
+//! until its events are sent, so events arrive in the order the writes
+//! committed. A subscriber that falls more than the channel's capacity
+//! behind is told it lagged and should treat that as [`DataEvent::Reloaded`].
+
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use tokio::sync::{broadcast, watch};
+
+use crate::models::Candle;
+
+/// Events buffered per subscriber before it lags.
+pub(crate) const BUS_CAPACITY: usize = 256;
+
+/// What changed. `symbol` is `None` for the main candles table.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum DataEvent {
+    /// `count` candles at timestamps not stored before, from `from` to `to`.
+    CandlesAppended {
+        symbol: Option<String>,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        count: usize,
+    },
+    /// Newer rows for `count` candles already stored, from `from` to `to`.
+    CandlesModified {
+        symbol: Option<String>,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+        count: usize,
+    },
+    /// The data was replaced or repaired wholesale; anything derived from it
+    /// should be rebuilt.
+    Reloaded,
+}
+
+impl DataEvent {
+    /// Describes candles just stored for `symbol`, each flagged with whether
+    /// it replaced a stored row: an append for the new ones and a
+    /// modification for the others, whichever there are.
+    pub(crate) fn stored<'a>(
+        symbol: Option<&str>,
+        candles: impl IntoIterator<Item = (&'a Candle, bool)>,
+    ) -> Vec<DataEvent> {
+        let mut appended: Option<(NaiveDateTime, NaiveDateTime, usize)> = None;
+        let mut modified = None;
+        for (candle, replaced) in candles {
+            let at = candle.timestamp.at;
+            let span = if replaced {
+                &mut modified
+            } else {
+                &mut appended
+            };
+            *span = Some(match *span {
+                Some((from, to, count)) => (from.min(at), to.max(at), count + 1),
+                None => (at, at, 1),
+            });
+        }
+        let symbol = symbol.map(str::to_owned);
+        let appended = appended.map(|(from, to, count)| DataEvent::CandlesAppended {
+            symbol: symbol.clone(),
+            from,
+            to,
+            count,
+        });
+        let modified = modified.map(|(from, to, count)| DataEvent::CandlesModified {
+            symbol,
+            from,
+            to,
+            count,
+        });
+        appended.into_iter().chain(modified).collect()
+    }
+
+    /// Whether the event concerns the main candles table.
+    pub(crate) fn touches_main(&self) -> bool {
+        match self {
+            DataEvent::CandlesAppended { symbol, .. }
+            | DataEvent::CandlesModified { symbol, .. } => symbol.is_none(),
+            DataEvent::Reloaded => true,
+        }
+    }
+}
+
+/// The data version and the events that move it on.
+#[derive(Clone)]
+pub(crate) struct Bus {
+    events: broadcast::Sender<DataEvent>,
+    version: Arc<watch::Sender<u64>>,
+}
+
+impl Bus {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (events, _) = broadcast::channel(capacity);
+        let (version, _) = watch::channel(0);
+        Self {
+            events,
+            version: Arc::new(version),
+        }
+    }
+
+    /// Monotonic counter bumped whenever the stored data changes; anything
+    /// derived from the data is valid only for the version it was built from.
+    pub(crate) fn data_version(&self) -> u64 {
+        *self.version.borrow()
+    }
+
+    /// Bumps the data version once, then sends `events` in order. Nothing
+    /// happens without events.
+    pub(crate) fn publish(&self, events: impl IntoIterator<Item = DataEvent>) {
+        let mut events = events.into_iter().peekable();
+        if events.peek().is_none() {
+            return;
+        }
+        self.version.send_modify(|version| *version += 1);
+        for event in events {
+            tracing::debug!("data changed: {event:?}");
+            // No subscribers is fine; nobody needed telling.
+            let _ = self.events.send(event);
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<DataEvent> {
+        self.events.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{QuoteValues, Timestamp, Volume};
+
+    fn candle(at: &str) -> Candle {
+        Candle {
+            timestamp: Timestamp::new(
+                NaiveDateTime::parse_from_str(at, "%Y-%m-%d %H:%M:%S").unwrap(),
+            ),
+            open: 1.0,
+            high: 1.0,
+            low: 1.0,
+            close: 1.0,
+            volume: Volume::new(1.0),
+            events: None,
+            quotes: QuoteValues::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn events_follow_the_version_bump_in_order() {
+        let bus = Bus::new(BUS_CAPACITY);
+        let mut events = bus.subscribe();
+        bus.publish([]);
+        assert_eq!(bus.data_version(), 0);
+
+        let (first, second, third) = (
+            candle("2024-01-01 00:00:00"),
+            candle("2024-01-01 00:01:00"),
+            candle("2024-01-01 00:02:00"),
+        );
+        let stored = DataEvent::stored(
+            Some("AAA"),
+            [(&third, false), (&first, true), (&second, false)],
+        );
+        bus.publish(stored.clone());
+        bus.publish([DataEvent::Reloaded]);
+        assert_eq!(bus.data_version(), 2);
+        assert_eq!(
+            stored,
+            [
+                DataEvent::CandlesAppended {
+                    symbol: Some("AAA".to_owned()),
+                    from: second.timestamp.at,
+                    to: third.timestamp.at,
+                    count: 2,
+                },
+                DataEvent::CandlesModified {
+                    symbol: Some("AAA".to_owned()),
+                    from: first.timestamp.at,
+                    to: first.timestamp.at,
+                    count: 1,
+                },
+            ]
+        );
+        for expected in stored.into_iter().chain([DataEvent::Reloaded]) {
+            assert_eq!(events.recv().await.unwrap(), expected);
+        }
+        assert!(events.try_recv().is_err());
+        assert!(!DataEvent::stored(Some("AAA"), [(&first, false)])[0].touches_main());
+        assert!(DataEvent::stored(None, [(&first, false)])[0].touches_main());
+    }
+}
@@ -0,0 +1,585 @@
+//! HTTP caching: the in-memory response cache, ETag revalidation and
+//! `Cache-Control`.
+//!
+//! Every data route goes through both layers. [`conditional_get`] runs first:
+//! it fingerprints the stored data, answers revalidations, and hands the
+//! fingerprint on to [`cache_response`], which keys entries by it as well as
+//! by the normalized query and `Accept`. An entry therefore never outlives
+//! the data it was built from, even between polls of the hub, whose data
+//! version clears the whole cache when new candles arrive.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::LazyLock;
+use std::time::SystemTime;
+
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::{Query, Request, State};
+use axum::http::header::{ACCEPT, CACHE_CONTROL, ETAG, IF_NONE_MATCH, VARY};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use duckdb::Connection;
+
+use crate::error::internal_error;
+use crate::AppState;
+
+/// Serialized responses keyed by path, normalized query and `Accept`, valid
+/// for a single data version and bounded by total body size with LRU
+/// eviction.
+pub(crate) struct ResponseCache {
+    max_bytes: usize,
+    entries: std::sync::Mutex<CacheEntries>,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    version: u64,
+    bytes: usize,
+    clock: u64,
+    by_key: HashMap<String, CachedResponse>,
+    by_use: BTreeMap<u64, String>,
+}
+
+#[derive(Clone)]
+struct CachedResponse {
+    headers: HeaderMap,
+    body: Bytes,
+    last_used: u64,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            entries: std::sync::Mutex::new(CacheEntries::default()),
+        }
+    }
+
+    fn get(&self, key: &str, version: u64) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().expect("cache poisoned");
+        entries.sync_version(version);
+        entries.clock += 1;
+        let clock = entries.clock;
+        let entry = entries.by_key.get_mut(key)?;
+        let previous = std::mem::replace(&mut entry.last_used, clock);
+        let hit = entry.clone();
+        entries.by_use.remove(&previous);
+        entries.by_use.insert(clock, key.to_owned());
+        Some(hit)
+    }
+
+    /// Stores a response computed against `version`. Results computed before
+    /// a data change are dropped rather than served stale.
+    fn insert(&self, key: String, version: u64, headers: HeaderMap, body: Bytes) {
+        if body.len() > self.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().expect("cache poisoned");
+        entries.sync_version(version);
+        if entries.version != version {
+            return;
+        }
+        entries.remove(&key);
+        while entries.bytes + body.len() > self.max_bytes {
+            let Some((_, oldest)) = entries.by_use.pop_first() else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        entries.clock += 1;
+        let last_used = entries.clock;
+        entries.bytes += body.len();
+        entries.by_use.insert(last_used, key.clone());
+        entries.by_key.insert(
+            key,
+            CachedResponse {
+                headers,
+                body,
+                last_used,
+            },
+        );
+    }
+}
+
+impl CacheEntries {
+    fn sync_version(&mut self, version: u64) {
+        if version > self.version {
+            *self = Self {
+                version,
+                ..Self::default()
+            };
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.by_key.remove(key) {
+            self.bytes -= entry.body.len();
+            self.by_use.remove(&entry.last_used);
+        }
+    }
+}
+
+/// Path plus query pairs sorted by name, so `?a=1&b=2` and `?b=2&a=1` share
+/// an entry. A repeated name keeps its values in order, since handlers read
+/// the first.
+fn cache_key(uri: &Uri) -> String {
+    let mut pairs = Query::<Vec<(String, String)>>::try_from_uri(uri)
+        .map(|Query(pairs)| pairs)
+        .unwrap_or_default();
+    pairs.sort_by(|a, b| a.0.cmp(&b.0));
+    let query = pairs
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{query}", uri.path())
+}
+
+/// Bodies larger than this are sent uncached, so one large response cannot
+/// crowd everything else out of the cache.
+const MAX_ENTRY_BYTES: usize = 4 * 1024 * 1024;
+
+/// Serves repeat requests from [`ResponseCache`]. Needs the
+/// [`DataFingerprint`] left by [`conditional_get`]; without one, for excluded
+/// paths, for requests sending `Cache-Control: no-cache` or `no-store`, and
+/// for streamed or oversized bodies, it steps aside.
+pub(crate) async fn cache_response(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(cache) = &state.cache else {
+        return next.run(request).await;
+    };
+    let fingerprint = request.extensions().get::<DataFingerprint>().copied();
+    let excluded = state
+        .config
+        .cache_exclude
+        .iter()
+        .any(|path| path == request.uri().path());
+    let (Some(DataFingerprint(fingerprint)), false, false) =
+        (fingerprint, excluded, refuses_cache(request.headers()))
+    else {
+        let mut response = next.run(request).await;
+        response
+            .headers_mut()
+            .insert(CACHE_STATUS, HeaderValue::from_static("bypass"));
+        return response;
+    };
+    // Handlers encode in the negotiated format, so `Accept` is part of the key.
+    let accept = request
+        .headers()
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let key = format!("{fingerprint:016x} {} {accept}", cache_key(request.uri()));
+    let version = state.bus.data_version();
+    if let Some(hit) = cache.get(&key, version) {
+        let mut response = Response::new(Body::from(hit.body));
+        *response.headers_mut() = hit.headers;
+        response
+            .headers_mut()
+            .insert(CACHE_STATUS, HeaderValue::from_static("hit"));
+        return response;
+    }
+
+    let response = next.run(request).await;
+    if response.status() != StatusCode::OK {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Streamed exports have no exact length; they pass through as they are.
+    if body
+        .size_hint()
+        .exact()
+        .is_none_or(|size| size > MAX_ENTRY_BYTES as u64)
+    {
+        parts
+            .headers
+            .insert(CACHE_STATUS, HeaderValue::from_static("bypass"));
+        return Response::from_parts(parts, body);
+    }
+    let headers = parts.headers.clone();
+    parts
+        .headers
+        .insert(CACHE_STATUS, HeaderValue::from_static("miss"));
+    let body = match axum::body::to_bytes(body, MAX_ENTRY_BYTES).await {
+        Ok(body) => body,
+        Err(err) => return internal_error(err).into_response(),
+    };
+    cache.insert(key, version, headers, body.clone());
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Whether the request asks for a response from the source, not a cache.
+fn refuses_cache(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|directive| {
+            directive.eq_ignore_ascii_case("no-cache") || directive.eq_ignore_ascii_case("no-store")
+        })
+}
+
+const CACHE_STATUS: HeaderName = HeaderName::from_static("cache-status");
+
+/// Row count and latest timestamp of the candles table; any ingest changes it.
+/// Rows rewritten in place, such as a late tick correcting a stored bar or a
+/// `symbol_candles` write, leave it as it was, so [`conditional_get`] adds the
+/// bus's data version, which every write through this process bumps.
+fn data_fingerprint(conn: &Connection) -> duckdb::Result<(i64, Option<String>)> {
+    conn.prepare_cached("SELECT count(*), CAST(max(timestamp) AS VARCHAR) FROM candles")?
+        .query_row([], |row| Ok((row.get(0)?, row.get(1)?)))
+}
+
+/// When this process first fingerprinted the data.
+static PROCESS_START: LazyLock<SystemTime> = LazyLock::new(SystemTime::now);
+
+/// A hash of [`data_fingerprint`] and the data version, passed from
+/// [`conditional_get`] to [`cache_response`] in the request extensions.
+#[derive(Clone, Copy)]
+struct DataFingerprint(u64);
+
+/// Strong validator for a data response: the data fingerprint, the
+/// normalized query and the `Accept` header (which selects the encoding).
+fn entity_tag(fingerprint: DataFingerprint, key: &str, accept: &[u8]) -> HeaderValue {
+    let mut hasher = DefaultHasher::new();
+    (fingerprint.0, key, accept).hash(&mut hasher);
+    HeaderValue::from_str(&format!("\"{:016x}\"", hasher.finish()))
+        .expect("hex digits are a valid header value")
+}
+
+fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Some(tag) = etag.to_str().ok() else {
+        return false;
+    };
+    headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == tag)
+}
+
+/// `Cache-Control` for data responses: revalidate every time by default,
+/// which the ETag makes cheap, or reuse for `max_age`. Responses that needed
+/// an API key are only for the caller's own cache.
+fn cache_control(state: &AppState) -> HeaderValue {
+    let scope = if state.config.require_auth_for_reads {
+        "private"
+    } else {
+        "public"
+    };
+    let value = match state.config.cache_max_age.as_secs() {
+        0 => format!("{scope}, no-cache"),
+        seconds => format!("{scope}, max-age={seconds}"),
+    };
+    HeaderValue::from_str(&value).expect("cache directives are a valid header value")
+}
+
+/// Sets `ETag` and `Cache-Control` on successful data responses and answers
+/// `304 Not Modified` when the client's `If-None-Match` already names the
+/// current `ETag`.
+pub(crate) async fn conditional_get(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let version = state.bus.data_version();
+    let fingerprint = match state.db.read(data_fingerprint).await {
+        Ok(fingerprint) => {
+            let mut hasher = DefaultHasher::new();
+            // The version restarts with the process, so its start is mixed in
+            // too: a tag from before a restart never matches after it.
+            (fingerprint, version, *PROCESS_START).hash(&mut hasher);
+            DataFingerprint(hasher.finish())
+        }
+        Err(err) => {
+            tracing::warn!("skipping ETag, fingerprint query failed: {err}");
+            return next.run(request).await;
+        }
+    };
+    let accept = request
+        .headers()
+        .get(ACCEPT)
+        .map(|value| value.as_bytes().to_vec())
+        .unwrap_or_default();
+    let etag = entity_tag(fingerprint, &cache_key(request.uri()), &accept);
+    let validators = |headers: &mut HeaderMap| {
+        headers.insert(ETAG, etag.clone());
+        headers.insert(VARY, HeaderValue::from_static("accept"));
+        headers.insert(CACHE_CONTROL, cache_control(&state));
+    };
+
+    if if_none_match(request.headers(), &etag) {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        validators(response.headers_mut());
+        return response;
+    }
+
+    request.extensions_mut().insert(fingerprint);
+    let mut response = next.run(request).await;
+    if response.status() == StatusCode::OK {
+        validators(response.headers_mut());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use axum::http::header::CONTENT_TYPE;
+
+    use chrono::NaiveDateTime;
+
+    use super::*;
+    use crate::bus::DataEvent;
+    use crate::config::CsvMode;
+    use crate::db::initialize_db;
+    use crate::models::TIMESTAMP_FORMAT;
+    use crate::test_support::*;
+    use crate::{build_router, Config, Db};
+
+    fn cache_status(response: &Response) -> Option<&str> {
+        response
+            .headers()
+            .get(CACHE_STATUS)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn indicators_are_cached_until_data_changes() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = build_router(state.clone());
+        state.hub.start(&state.db).await.unwrap();
+
+        let first = get_uri(&app, "/api/indicators").await;
+        assert_eq!(cache_status(&first), Some("miss"));
+        let second = get_uri(&app, "/api/indicators").await;
+        assert_eq!(cache_status(&second), Some("hit"));
+        let cached = axum::body::to_bytes(second.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Vec<serde_json::Value>>(&cached)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles VALUES ('2024-01-01 00:01:00', 2, 2, 2, 2, 2);",
+                )
+                .unwrap()
+            })
+            .await;
+        state.hub.poll(&state.db).await.unwrap();
+
+        let refetched = get_uri(&app, "/api/indicators").await;
+        assert_eq!(cache_status(&refetched), Some("miss"));
+        let body = axum::body::to_bytes(refetched.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Vec<serde_json::Value>>(&body)
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn every_data_route_is_cached_against_the_stored_data() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = build_router(state.clone());
+
+        assert_eq!(cache_status(&get_uri(&app, "/api/fib").await), Some("miss"));
+        let hit = get_uri(&app, "/api/fib").await;
+        assert_eq!(cache_status(&hit), Some("hit"));
+        assert_eq!(hit.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(hit.headers()[CACHE_CONTROL], "public, no-cache");
+        assert!(hit.headers().contains_key(ETAG));
+
+        // A write the hub has not polled yet still changes the key.
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles VALUES ('2024-01-01 00:01:00', 2, 2, 2, 2, 2);",
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(cache_status(&get_uri(&app, "/api/fib").await), Some("miss"));
+
+        let refused = get_with(&app, "/api/fib", &[(CACHE_CONTROL, "no-cache")]).await;
+        assert_eq!(cache_status(&refused), Some("bypass"));
+        // Errors are not kept.
+        for _ in 0..2 {
+            let response = get_uri(&app, "/api/fib?start=soon").await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(cache_status(&response), None);
+        }
+    }
+
+    #[tokio::test]
+    async fn excluded_routes_and_max_age_follow_the_config() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let config = Config {
+            cache_exclude: vec!["/api/candles".to_owned()],
+            cache_max_age: std::time::Duration::from_secs(30),
+            require_auth_for_reads: true,
+            ..keyed_config()
+        };
+        let app = build_router(AppState::new(state.db, config));
+        let key = [(crate::auth::X_API_KEY, TEST_KEY)];
+        for _ in 0..2 {
+            let response = get_with(&app, "/api/candles", &key).await;
+            assert_eq!(cache_status(&response), Some("bypass"));
+            assert_eq!(response.headers()[CACHE_CONTROL], "private, max-age=30");
+        }
+        get_with(&app, "/api/fib", &key).await;
+        let response = get_with(&app, "/api/fib", &key).await;
+        assert_eq!(cache_status(&response), Some("hit"));
+    }
+
+    #[tokio::test]
+    async fn cache_can_be_disabled() {
+        let conn = Connection::open_in_memory().unwrap();
+        initialize_db(&conn, Path::new("data/stocks.csv"), CsvMode::Lenient).unwrap();
+        let config = Config {
+            cache_enabled: false,
+            ..Config::default()
+        };
+        let app = build_router(AppState::new(Arc::new(Db::new(conn, 1).unwrap()), config));
+        for _ in 0..2 {
+            let response = get_uri(&app, "/api/indicators").await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(cache_status(&response), None);
+        }
+    }
+
+    #[test]
+    fn cache_keys_ignore_query_order() {
+        let a: Uri = "/api/indicators?b=2&a=1".parse().unwrap();
+        let b: Uri = "/api/indicators?a=1&b=2".parse().unwrap();
+        assert_eq!(cache_key(&a), cache_key(&b));
+        let first: Uri = "/api/candles?format=json&format=lwc".parse().unwrap();
+        let last: Uri = "/api/candles?format=lwc&format=json".parse().unwrap();
+        assert_ne!(cache_key(&first), cache_key(&last));
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used_past_the_size_cap() {
+        let cache = ResponseCache::new(10);
+        cache.insert("a".into(), 0, HeaderMap::new(), Bytes::from_static(b"aaaa"));
+        cache.insert("b".into(), 0, HeaderMap::new(), Bytes::from_static(b"bbbb"));
+        assert!(cache.get("a", 0).is_some());
+        cache.insert("c".into(), 0, HeaderMap::new(), Bytes::from_static(b"cccc"));
+        assert!(cache.get("a", 0).is_some());
+        assert!(cache.get("b", 0).is_none());
+        assert!(cache.get("c", 0).is_some());
+
+        cache.insert(
+            "stale".into(),
+            0,
+            HeaderMap::new(),
+            Bytes::from_static(b"x"),
+        );
+        assert!(cache.get("a", 1).is_none());
+        cache.insert("late".into(), 0, HeaderMap::new(), Bytes::from_static(b"x"));
+        assert!(cache.get("late", 1).is_none());
+    }
+
+    #[tokio::test]
+    async fn conditional_requests_revalidate_against_the_data() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = build_router(state.clone());
+
+        let first = get_uri(&app, "/api/candles?limit=10").await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let etag = first.headers()[ETAG].to_str().unwrap().to_owned();
+
+        let revalidated = get_with(&app, "/api/candles?limit=10", &[(IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(revalidated.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(revalidated.headers()[ETAG], etag.as_str());
+        let body = axum::body::to_bytes(revalidated.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        let other_query = get_uri(&app, "/api/candles?limit=11").await;
+        assert_ne!(other_query.headers()[ETAG], etag.as_str());
+        let other_accept = get_with(
+            &app,
+            "/api/candles?limit=10",
+            &[(ACCEPT, "application/msgpack"), (IF_NONE_MATCH, &etag)],
+        )
+        .await;
+        assert_eq!(other_accept.status(), StatusCode::OK);
+        assert_ne!(other_accept.headers()[ETAG], etag.as_str());
+
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles VALUES ('2024-01-02 00:00:00', 2, 2, 2, 2, 2);",
+                )
+            })
+            .await
+            .unwrap();
+        let changed = get_with(&app, "/api/candles?limit=10", &[(IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(changed.status(), StatusCode::OK);
+        assert_ne!(changed.headers()[ETAG], etag.as_str());
+    }
+
+    #[tokio::test]
+    async fn bars_corrected_in_place_get_a_new_tag() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = build_router(state.clone());
+        let first = get_uri(&app, "/api/candles").await;
+        let etag = first.headers()[ETAG].to_str().unwrap().to_owned();
+
+        // As a late tick corrects the stored bar: same count, same latest
+        // timestamp, new values.
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "DELETE FROM candles WHERE timestamp = '2024-01-01 00:00:00';
+                     INSERT INTO candles VALUES ('2024-01-01 00:00:00', 1, 3, 1, 2, 5);",
+                )
+            })
+            .await
+            .unwrap();
+        let at = NaiveDateTime::parse_from_str("2024-01-01 00:00:00", TIMESTAMP_FORMAT).unwrap();
+        state.bus.publish([DataEvent::CandlesModified {
+            symbol: None,
+            from: at,
+            to: at,
+            count: 1,
+        }]);
+
+        let revalidated = get_with(&app, "/api/candles", &[(IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(revalidated.status(), StatusCode::OK);
+        assert_ne!(revalidated.headers()[ETAG], etag.as_str());
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(revalidated.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body[0]["high"], 3.0);
+    }
+}
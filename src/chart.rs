@@ -0,0 +1,652 @@
+//! `GET /api/chart.png`: a candlestick chart drawn on the server, for reports
+//! and alerts that cannot run a browser.
+//!
+//! The candles come from [`fit_candles`], as `/api/adaptive_candles` sends
+//! them, and the overlays from the same indicator code as `/api/indicators`,
+//! so a chart always shows what the JSON endpoints return. Ranges with more
+//! candles than the plot has room for are resampled to a coarser timeframe,
+//! and each overlay is read at the close of every bar it is drawn against.
+//!
+//! There is no plotting crate in this build: the chart is rasterised here
+//! and written as an uncompressed-filter RGB PNG, with a small digit font
+//! for the price and time axes.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDateTime;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+
+use crate::error::{bad_request, internal_error, AppError};
+use crate::formula::MAX_PERIOD;
+use crate::handlers::{
+    check_indicator_period, fit_candles, indicator_points, parse_range, MAX_ADAPTIVE_POINTS,
+};
+use crate::indicators::{self, PriceSource};
+use crate::models::{Candle, IndicatorPoint, PeriodIndicator, TimestampFormat};
+use crate::AppState;
+
+/// Bounds of `width` and `height`, in pixels.
+pub(crate) const MIN_WIDTH: u32 = 200;
+pub(crate) const MAX_WIDTH: u32 = 3000;
+pub(crate) const MIN_HEIGHT: u32 = 150;
+pub(crate) const MAX_HEIGHT: u32 = 2000;
+
+/// The most overlays one chart draws.
+pub(crate) const MAX_OVERLAYS: usize = 8;
+
+/// The narrowest a candle is drawn; a range with more candles than fit at
+/// this width is resampled.
+const MIN_CANDLE_PX: u32 = 2;
+
+/// Space around the plot, and for the price and time labels.
+const MARGIN: i64 = 10;
+const PRICE_AXIS: i64 = 80;
+const TIME_AXIS: i64 = 24;
+
+/// Glyphs are 3 by 5 cells, drawn `SCALE` pixels to a cell.
+const SCALE: i64 = 2;
+const ADVANCE: i64 = 4 * SCALE;
+
+type Rgb = [u8; 3];
+
+/// Candle colours, the same as the volume colours of [`crate::lwc`].
+const UP: Rgb = [0x26, 0xa6, 0x9a];
+const DOWN: Rgb = [0xef, 0x53, 0x50];
+
+/// Overlay colours, in the order the overlays are requested.
+const PALETTE: [Rgb; MAX_OVERLAYS] = [
+    [0x29, 0x62, 0xff],
+    [0xff, 0x98, 0x00],
+    [0x9c, 0x27, 0xb0],
+    [0x00, 0xbc, 0xd4],
+    [0xe9, 0x1e, 0x63],
+    [0x8b, 0xc3, 0x4a],
+    [0x79, 0x55, 0x48],
+    [0x60, 0x7d, 0x8b],
+];
+
+#[derive(Deserialize)]
+pub(crate) struct ChartQuery {
+    start: Option<String>,
+    end: Option<String>,
+    /// Comma-separated overlays such as `sma:20,ema:50,hma:9`, on the closes.
+    indicators: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    theme: Option<Theme>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+struct Palette {
+    background: Rgb,
+    grid: Rgb,
+    text: Rgb,
+}
+
+impl Theme {
+    /// lightweight-charts' own light and dark colours.
+    fn palette(self) -> Palette {
+        match self {
+            Theme::Light => Palette {
+                background: [0xff, 0xff, 0xff],
+                grid: [0xe0, 0xe3, 0xeb],
+                text: [0x43, 0x46, 0x51],
+            },
+            Theme::Dark => Palette {
+                background: [0x13, 0x17, 0x22],
+                grid: [0x2a, 0x2e, 0x39],
+                text: [0xd1, 0xd4, 0xdc],
+            },
+        }
+    }
+}
+
+/// A line drawn over the candles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Overlay {
+    Period(PeriodIndicator, usize),
+    Hull(usize),
+}
+
+impl Overlay {
+    fn value(self, point: &IndicatorPoint) -> Option<f64> {
+        match self {
+            Overlay::Period(PeriodIndicator::Sma, indicators::PERIOD) => point.sma_14,
+            Overlay::Period(PeriodIndicator::Ema, indicators::PERIOD) => point.ema_14,
+            Overlay::Period(indicator, period) => point
+                .periods
+                .0
+                .iter()
+                .find(|&&(i, p, _)| (i, p) == (indicator, period))
+                .and_then(|&(_, _, value)| value),
+            Overlay::Hull(period) => point
+                .hma
+                .0
+                .iter()
+                .find(|&&(p, _)| p == period)
+                .and_then(|&(_, value)| value),
+        }
+    }
+}
+
+/// `sma:20,ema:50,hma:9`, in order and without repeats. RSI is not a price
+/// and has no place on the price axis.
+fn parse_overlays(text: &str) -> Result<Vec<Overlay>, AppError> {
+    let mut overlays = Vec::new();
+    for part in text.split_terminator(',') {
+        let invalid = || {
+            bad_request(format!(
+                "invalid indicator {part:?}; expected sma:N, ema:N or hma:N"
+            ))
+        };
+        let (name, period) = part.trim().split_once(':').ok_or_else(invalid)?;
+        let period = period.trim().parse::<usize>().map_err(|_| invalid())?;
+        let overlay = match name.trim() {
+            "sma" => Overlay::Period(PeriodIndicator::Sma, period),
+            "ema" => Overlay::Period(PeriodIndicator::Ema, period),
+            "hma" => Overlay::Hull(period),
+            _ => return Err(invalid()),
+        };
+        match overlay {
+            Overlay::Period(indicator, period) => check_indicator_period(indicator, period)?,
+            Overlay::Hull(period) if !(2..=MAX_PERIOD).contains(&period) => {
+                return Err(bad_request(format!(
+                    "invalid hma period {period}; expected whole numbers from 2 to {MAX_PERIOD}"
+                )))
+            }
+            Overlay::Hull(_) => {}
+        }
+        if !overlays.contains(&overlay) {
+            overlays.push(overlay);
+        }
+    }
+    if overlays.len() > MAX_OVERLAYS {
+        return Err(bad_request(format!(
+            "at most {MAX_OVERLAYS} indicators can be drawn on one chart"
+        )));
+    }
+    Ok(overlays)
+}
+
+/// A PNG of the candles in a range, with the requested overlays.
+pub(crate) async fn get_chart(
+    State(state): State<AppState>,
+    timestamps: TimestampFormat,
+    Query(query): Query<ChartQuery>,
+) -> Result<Response, AppError> {
+    let width = query.width.unwrap_or(1200);
+    let height = query.height.unwrap_or(600);
+    if !(MIN_WIDTH..=MAX_WIDTH).contains(&width) {
+        return Err(bad_request(format!(
+            "width must be from {MIN_WIDTH} to {MAX_WIDTH}"
+        )));
+    }
+    if !(MIN_HEIGHT..=MAX_HEIGHT).contains(&height) {
+        return Err(bad_request(format!(
+            "height must be from {MIN_HEIGHT} to {MAX_HEIGHT}"
+        )));
+    }
+    let overlays = parse_overlays(query.indicators.as_deref().unwrap_or_default())?;
+    let (from, until) = parse_range(query.start.as_deref(), query.end.as_deref())?;
+
+    let plot_width = width - (MARGIN + PRICE_AXIS) as u32;
+    let points = (plot_width / MIN_CANDLE_PX).min(MAX_ADAPTIVE_POINTS);
+    let candles = fit_candles(&state, from, until, points, timestamps)
+        .await?
+        .data;
+    let lines = if overlays.is_empty() || candles.is_empty() {
+        vec![Vec::new(); overlays.len()]
+    } else {
+        let cached = Arc::clone(&state.indicators);
+        let (hulls, periods) = requested_periods(&overlays);
+        let values = state
+            .db
+            .read(move |conn| {
+                let source = PriceSource::Close;
+                let mut points = indicator_points(conn, &cached, source)?;
+                indicators::add_hull_averages(conn, source, &hulls, &mut points)?;
+                indicators::add_period_indicators(conn, source, &periods, &mut points)?;
+                Ok::<_, duckdb::Error>(points)
+            })
+            .await?;
+        let closes = bar_closes(&candles, until.map(|until| until.at));
+        overlays
+            .iter()
+            .map(|&overlay| at_closes(&values, &closes, overlay))
+            .collect()
+    };
+
+    let theme = query.theme.unwrap_or_default();
+    let png = tokio::task::spawn_blocking(move || {
+        let canvas = render(&candles, &lines, width, height, theme, timestamps);
+        canvas.encode()
+    })
+    .await
+    .map_err(internal_error)?;
+    Ok(([(CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+/// The Hull periods and the other periods `add_period_indicators` has to
+/// compute; period 14 SMA and EMA are already default columns.
+fn requested_periods(overlays: &[Overlay]) -> (Vec<usize>, Vec<(PeriodIndicator, usize)>) {
+    let mut hulls = Vec::new();
+    let mut periods = Vec::new();
+    for &overlay in overlays {
+        match overlay {
+            Overlay::Hull(period) => hulls.push(period),
+            Overlay::Period(_, indicators::PERIOD) => {}
+            Overlay::Period(indicator, period) => periods.push((indicator, period)),
+        }
+    }
+    (hulls, periods)
+}
+
+/// The last moment each bar covers: just before the next bar opens, and up
+/// to `until` for the last one.
+fn bar_closes(candles: &[Candle], until: Option<NaiveDateTime>) -> Vec<Option<NaiveDateTime>> {
+    candles
+        .iter()
+        .enumerate()
+        .map(|(i, _)| match candles.get(i + 1) {
+            Some(next) => Some(next.timestamp.at),
+            None => until,
+        })
+        .collect()
+}
+
+/// The overlay's value at the close of each bar: that of the newest point
+/// before the next bar opens, or at or before `until` for the last bar, or
+/// the newest of all when there is no `until`. A raw candle reads its own.
+fn at_closes(
+    points: &[IndicatorPoint],
+    closes: &[Option<NaiveDateTime>],
+    overlay: Overlay,
+) -> Vec<Option<f64>> {
+    let last = closes.len().saturating_sub(1);
+    let mut next = 0;
+    let mut current = None;
+    closes
+        .iter()
+        .enumerate()
+        .map(|(i, close)| {
+            while let Some(point) = points.get(next) {
+                let inside = match close {
+                    Some(close) if i < last => point.timestamp.at < *close,
+                    Some(close) => point.timestamp.at <= *close,
+                    None => true,
+                };
+                if !inside {
+                    break;
+                }
+                current = Some(point);
+                next += 1;
+            }
+            current.and_then(|point| overlay.value(point))
+        })
+        .collect()
+}
+
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, background: Rgb) -> Self {
+        let (width, height) = (width as usize, height as usize);
+        Self {
+            width,
+            height,
+            pixels: background.repeat(width * height),
+        }
+    }
+
+    fn set(&mut self, x: i64, y: i64, color: Rgb) {
+        if (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y) {
+            let at = (y as usize * self.width + x as usize) * 3;
+            self.pixels[at..at + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// The rectangle with corners `(x0, y0)` and `(x1, y1)`, both included.
+    fn fill(&mut self, (x0, y0): (i64, i64), (x1, y1): (i64, i64), color: Rgb) {
+        for y in y0.min(y1)..=y0.max(y1) {
+            for x in x0.min(x1)..=x0.max(x1) {
+                self.set(x, y, color);
+            }
+        }
+    }
+
+    /// Bresenham's line, `thickness` pixels tall.
+    fn line(&mut self, (x0, y0): (i64, i64), (x1, y1): (i64, i64), color: Rgb, thickness: i64) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+        loop {
+            for offset in 0..thickness {
+                self.set(x, y + offset, color);
+            }
+            if (x, y) == (x1, y1) {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += sx;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// `text` with its top left corner at `(x, y)`; characters without a
+    /// glyph are left blank.
+    fn text(&mut self, x: i64, y: i64, text: &str, color: Rgb) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i as i64 * ADVANCE;
+            for (row, bits) in glyph(c).into_iter().enumerate() {
+                for column in 0..3 {
+                    if bits & (0b100 >> column) != 0 {
+                        let (cx, cy) = (left + column * SCALE, y + row as i64 * SCALE);
+                        self.fill((cx, cy), (cx + SCALE - 1, cy + SCALE - 1), color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// An 8-bit RGB PNG, every row unfiltered.
+    fn encode(&self) -> Vec<u8> {
+        let mut rows = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.pixels.chunks(self.width * 3) {
+            rows.push(0);
+            rows.extend_from_slice(row);
+        }
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&rows)
+            .expect("writing to memory cannot fail");
+        let data = zlib.finish().expect("writing to memory cannot fail");
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // Bit depth 8, truecolour, deflate, adaptive filtering, no interlace.
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, body) in [(b"IHDR", &header), (b"IDAT", &data), (b"IEND", &Vec::new())] {
+            png.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            png.extend_from_slice(kind);
+            png.extend_from_slice(body);
+            let mut crc = crc32fast::Hasher::new();
+            crc.update(kind);
+            crc.update(body);
+            png.extend_from_slice(&crc.finalize().to_be_bytes());
+        }
+        png
+    }
+}
+
+/// Rows of a 3 by 5 glyph, the high bit leftmost.
+fn glyph(c: char) -> [u8; 5] {
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        _ => [0; 5],
+    }
+}
+
+/// A step of 1, 2 or 5 times a power of ten giving about five gridlines.
+fn grid_step(span: f64) -> f64 {
+    let rough = span / 5.0;
+    let magnitude = 10f64.powf(rough.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|step| *step >= rough)
+        .unwrap_or(10.0 * magnitude)
+}
+
+fn render(
+    candles: &[Candle],
+    lines: &[Vec<Option<f64>>],
+    width: u32,
+    height: u32,
+    theme: Theme,
+    timestamps: TimestampFormat,
+) -> Canvas {
+    let palette = theme.palette();
+    let mut canvas = Canvas::new(width, height, palette.background);
+    let (left, top) = (MARGIN, MARGIN);
+    let (right, bottom) = (i64::from(width) - PRICE_AXIS, i64::from(height) - TIME_AXIS);
+
+    let prices = candles
+        .iter()
+        .flat_map(|candle| [candle.low, candle.high])
+        .chain(lines.iter().flatten().flatten().copied())
+        .filter(|price| price.is_finite());
+    let (low, high) = prices.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), price| {
+        (low.min(price), high.max(price))
+    });
+    if low > high {
+        return canvas;
+    }
+    let pad = match high - low {
+        span if span > 0.0 => span * 0.05,
+        _ => low.abs().max(1.0) * 0.01,
+    };
+    let (low, high) = (low - pad, high + pad);
+    let y =
+        |price: f64| top + ((high - price) / (high - low) * (bottom - top) as f64).round() as i64;
+
+    let step = grid_step(high - low);
+    let decimals = (-step.log10().floor()).clamp(0.0, 8.0) as usize;
+    let mut level = (low / step).ceil() * step;
+    while level <= high {
+        let at = y(level);
+        canvas.fill((left, at), (right, at), palette.grid);
+        let label = format!("{level:.decimals$}");
+        canvas.text(right + 6, at - 2 * SCALE, &label, palette.text);
+        level += step;
+    }
+
+    let slot = (right - left) as f64 / candles.len() as f64;
+    let center = |i: usize| left + ((i as f64 + 0.5) * slot) as i64;
+    let body = ((slot * 0.7) as i64).max(1);
+    let labels = (((right - left) / 160) as usize).clamp(1, candles.len());
+    for k in 0..labels {
+        let i = match labels {
+            1 => 0,
+            _ => k * (candles.len() - 1) / (labels - 1),
+        };
+        let at = center(i);
+        canvas.fill((at, top), (at, bottom), palette.grid);
+        let shown = candles[i].timestamp.at + timestamps.offset;
+        let label = shown.format("%Y-%m-%d %H:%M").to_string();
+        let half = label.len() as i64 * ADVANCE / 2;
+        let x = (at - half).clamp(0, i64::from(width) - 2 * half);
+        canvas.text(x, bottom + 8, &label, palette.text);
+    }
+
+    for (i, candle) in candles.iter().enumerate() {
+        let ohlc = [candle.open, candle.high, candle.low, candle.close];
+        if !ohlc.iter().all(|price| price.is_finite()) {
+            continue;
+        }
+        let color = if candle.close >= candle.open {
+            UP
+        } else {
+            DOWN
+        };
+        let at = center(i);
+        canvas.fill((at, y(candle.high)), (at, y(candle.low)), color);
+        let x0 = at - body / 2;
+        canvas.fill(
+            (x0, y(candle.open)),
+            (x0 + body - 1, y(candle.close)),
+            color,
+        );
+    }
+
+    for (line, color) in lines.iter().zip(PALETTE) {
+        let mut previous = None;
+        for (i, value) in line.iter().enumerate() {
+            let point = value
+                .filter(|value| value.is_finite())
+                .map(|value| (center(i), y(value)));
+            match (previous, point) {
+                (Some(from), Some(to)) => canvas.line(from, to, color, 2),
+                (None, Some(to)) => canvas.line(to, to, color, 2),
+                _ => {}
+            }
+            previous = point;
+        }
+    }
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use axum::http::header::CONTENT_TYPE;
+    use axum::http::StatusCode;
+    use chrono::NaiveDateTime;
+    use flate2::read::ZlibDecoder;
+
+    use super::{at_closes, Overlay, DOWN, PALETTE, UP};
+    use crate::build_router;
+    use crate::models::{IndicatorPoint, PeriodIndicator, Timestamp};
+    use crate::test_support::{get_uri, seeded_state};
+
+    /// Width, height and RGB pixels of a PNG as [`super::Canvas`] writes it.
+    fn decode(png: &[u8]) -> (usize, usize, Vec<[u8; 3]>) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let (mut header, mut data, mut at) = (Vec::new(), Vec::new(), 8);
+        while at < png.len() {
+            let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            let body = &png[at + 8..at + 8 + len];
+            match &png[at + 4..at + 8] {
+                b"IHDR" => header = body.to_vec(),
+                b"IDAT" => data.extend_from_slice(body),
+                _ => {}
+            }
+            at += 12 + len;
+        }
+        let width = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+        let mut rows = Vec::new();
+        ZlibDecoder::new(&data[..]).read_to_end(&mut rows).unwrap();
+        let pixels = rows
+            .chunks(width * 3 + 1)
+            .flat_map(|row| row[1..].chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]))
+            .collect::<Vec<_>>();
+        assert_eq!(pixels.len(), width * height);
+        (width, height, pixels)
+    }
+
+    #[tokio::test]
+    async fn charts_draw_candles_and_overlays() {
+        let rows = (0..10)
+            .map(|minute| {
+                let (open, close) = if minute % 3 == 0 { (5, 3) } else { (3, 5) };
+                format!("('2024-01-01 00:0{minute}:00', {open}, 6, 2, {close}, 1)")
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let app = build_router(seeded_state(&rows));
+
+        let response = get_uri(
+            &app,
+            "/api/chart.png?width=400&height=300&theme=dark&indicators=sma:3,ema:14",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "image/png");
+        let png = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let (width, height, pixels) = decode(&png);
+        assert_eq!((width, height), (400, 300));
+        assert_eq!(pixels[0], [0x13, 0x17, 0x22]);
+        for color in [UP, DOWN, PALETTE[0], PALETTE[1]] {
+            assert!(pixels.contains(&color), "no {color:?} pixels");
+        }
+
+        let response = get_uri(&app, "/api/chart.png?width=200&height=150").await;
+        let png = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let (_, _, pixels) = decode(&png);
+        assert_eq!(pixels[0], [0xff, 0xff, 0xff]);
+        assert!(!pixels.contains(&PALETTE[0]));
+
+        for uri in [
+            "/api/chart.png?width=199",
+            "/api/chart.png?height=2001",
+            "/api/chart.png?theme=sepia",
+            "/api/chart.png?indicators=rsi:14",
+            "/api/chart.png?indicators=sma:0",
+            "/api/chart.png?indicators=hma:1",
+            "/api/chart.png?indicators=sma",
+            "/api/chart.png?indicators=sma:1,sma:2,sma:3,sma:4,sma:5,sma:6,sma:7,sma:8,sma:9",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[test]
+    fn overlays_are_read_at_each_bar_close() {
+        let at = |minute: u32| {
+            NaiveDateTime::parse_from_str(
+                &format!("2024-01-01 00:{minute:02}:00"),
+                "%Y-%m-%d %H:%M:%S",
+            )
+            .unwrap()
+        };
+        let points = (0..6)
+            .map(|minute| IndicatorPoint {
+                timestamp: Timestamp::new(at(minute)),
+                sma_14: Some(f64::from(minute)),
+                ema_14: None,
+                rsi_14: None,
+                hma: Default::default(),
+                periods: Default::default(),
+            })
+            .collect::<Vec<_>>();
+        let sma = Overlay::Period(PeriodIndicator::Sma, 14);
+        // Bars opening at 00:00 and 00:03: each reads the point just before
+        // the next opens, the last the one at `until`.
+        let values = at_closes(&points, &[Some(at(3)), Some(at(4))], sma);
+        assert_eq!(values, [Some(2.0), Some(4.0)]);
+        let values = at_closes(&points, &[Some(at(3)), None], sma);
+        assert_eq!(values, [Some(2.0), Some(5.0)]);
+    }
+}
@@ -0,0 +1,345 @@
+//! The `graph` command line. `graph serve`, or no subcommand at all, runs
+//! the HTTP server; `ingest`, `summary` and `export` work on the database
+//! file directly, print JSON to stdout and exit, so scripts and cron jobs
+//! need no server. DuckDB locks the file for the process that writes it, so
+//! they fail while a server has it open, exiting nonzero as on any other
+//! failure.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use duckdb::{AccessMode, Connection};
+use serde_json::json;
+
+use crate::db::{
+    create_schema, export_candles, ingest_csv, ingest_table, ExportFormat, IngestMode,
+};
+use crate::handlers::{candle_extent, parse_range};
+use crate::xlsx::summarize;
+use crate::Config;
+
+pub const USAGE: &str = "usage:
+  graph [serve] [--generate-demo-data] [--static-dir <dir>]
+  graph ingest <file-or-dir> [--mode incremental|replace]
+  graph summary [--start <time>] [--end <time>]
+  graph export --format csv|parquet --out <path>
+The database is GRAPH_DB_PATH; see the README for the other settings.";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve {
+        demo_data: bool,
+        static_dir: Option<PathBuf>,
+    },
+    /// Stores a candles CSV, or every `.csv` file of a directory in name
+    /// order, in the table its header names.
+    Ingest {
+        path: PathBuf,
+        mode: IngestMode,
+    },
+    /// The range statistics the xlsx export's summary sheet holds.
+    Summary {
+        start: Option<String>,
+        end: Option<String>,
+    },
+    /// Every main candle, oldest first.
+    Export {
+        format: ExportFormat,
+        out: PathBuf,
+    },
+    Help,
+}
+
+impl Command {
+    /// Reads the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut args = args.into_iter().peekable();
+        let name = match args.peek().map(String::as_str) {
+            None => "serve".to_owned(),
+            Some(flag) if flag.starts_with("--") && flag != "--help" => "serve".to_owned(),
+            Some(_) => args.next().expect("peeked"),
+        };
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some("generate-demo-data") => options.push((arg, String::new())),
+                Some(_) => {
+                    let value = args
+                        .next()
+                        .with_context(|| format!("{arg} needs a value"))?;
+                    options.push((arg, value));
+                }
+                None => positional.push(arg),
+            }
+        }
+        let mut option = |name: &str| {
+            options
+                .iter()
+                .position(|(flag, _)| flag == name)
+                .map(|index| options.remove(index).1)
+        };
+        let command = match name.as_str() {
+            "serve" => Command::Serve {
+                demo_data: option("--generate-demo-data").is_some(),
+                static_dir: option("--static-dir").map(PathBuf::from),
+            },
+            "ingest" => Command::Ingest {
+                path: match positional.len() {
+                    1 => PathBuf::from(positional.remove(0)),
+                    _ => anyhow::bail!("ingest takes one file or directory\n{USAGE}"),
+                },
+                mode: match option("--mode").as_deref() {
+                    None | Some("incremental") => IngestMode::Incremental,
+                    Some("replace") => IngestMode::Replace,
+                    Some(mode) => {
+                        anyhow::bail!("--mode must be incremental or replace, not {mode:?}")
+                    }
+                },
+            },
+            "summary" => Command::Summary {
+                start: option("--start"),
+                end: option("--end"),
+            },
+            "export" => Command::Export {
+                format: match option("--format").as_deref() {
+                    Some("csv") => ExportFormat::Csv,
+                    Some("parquet") => ExportFormat::Parquet,
+                    Some(format) => {
+                        anyhow::bail!("--format must be csv or parquet, not {format:?}")
+                    }
+                    None => anyhow::bail!("export needs --format csv|parquet\n{USAGE}"),
+                },
+                out: option("--out")
+                    .map(PathBuf::from)
+                    .with_context(|| format!("export needs --out <path>\n{USAGE}"))?,
+            },
+            "help" | "--help" => Command::Help,
+            other => anyhow::bail!("unknown command {other:?}\n{USAGE}"),
+        };
+        if let Some(arg) = positional
+            .first()
+            .filter(|_| !matches!(command, Command::Ingest { .. }))
+        {
+            anyhow::bail!("unexpected argument {arg:?}\n{USAGE}");
+        }
+        if let Some((flag, _)) = options.first() {
+            anyhow::bail!("{name} does not take {flag}\n{USAGE}");
+        }
+        Ok(command)
+    }
+
+    /// Whether the command prints its result to stdout, which logs then
+    /// stay out of.
+    pub fn prints(&self) -> bool {
+        !matches!(self, Command::Serve { .. })
+    }
+}
+
+/// Runs `command` to completion.
+pub fn run(command: Command, mut config: Config) -> anyhow::Result<()> {
+    match command {
+        Command::Serve {
+            demo_data,
+            static_dir,
+        } => {
+            config.demo_data |= demo_data;
+            config.static_dir = static_dir.or(config.static_dir);
+            serve(config)
+        }
+        Command::Ingest { path, mode } => ingest(&config, &path, mode),
+        Command::Summary { start, end } => summary(&config, start, end),
+        Command::Export { format, out } => {
+            let conn = open_read_only(&config.db_path)?;
+            let rows = export_candles(&conn, &out, format)?;
+            println!("{}", json!({ "rows": rows, "out": out }));
+            Ok(())
+        }
+        Command::Help => {
+            println!("{USAGE}");
+            Ok(())
+        }
+    }
+}
+
+fn serve(config: Config) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        config.worker_threads > 0 && config.max_blocking_threads > 0,
+        "GRAPH_WORKER_THREADS and GRAPH_MAX_BLOCKING_THREADS must be at least 1"
+    );
+    anyhow::ensure!(
+        !config.require_auth_for_reads || !config.api_keys.is_empty(),
+        "GRAPH_REQUIRE_AUTH_FOR_READS requires GRAPH_API_KEYS"
+    );
+    anyhow::ensure!(
+        config
+            .backfill
+            .url
+            .as_deref()
+            .is_none_or(|url| url.starts_with("http://")),
+        "GRAPH_BACKFILL_URL must be an http:// URL; there is no TLS client, so point it at a \
+         local TLS-terminating forwarder to reach an https upstream"
+    );
+    if config.api_keys.is_empty() {
+        tracing::warn!(
+            "no GRAPH_API_KEYS configured; admin and write endpoints will refuse every request"
+        );
+    }
+    if let Some(dir) = config.static_dir.as_ref().filter(|dir| !dir.is_dir()) {
+        tracing::warn!(
+            "static directory {} does not exist; the front end will be missing",
+            dir.display()
+        );
+    }
+    if config.max_blocking_threads <= config.read_pool_size {
+        tracing::warn!(
+            "GRAPH_MAX_BLOCKING_THREADS={} leaves no room beyond the {} pooled readers",
+            config.max_blocking_threads,
+            config.read_pool_size
+        );
+    }
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(config.worker_threads)
+        .max_blocking_threads(config.max_blocking_threads)
+        .enable_all()
+        .build()
+        .context("build tokio runtime")?
+        .block_on(crate::serve(config))
+}
+
+/// Ingests `path`, printing a line of JSON per file. With
+/// [`IngestMode::Replace`], each table is replaced by the first file for it
+/// and the later ones add to it.
+fn ingest(config: &Config, path: &Path, mode: IngestMode) -> anyhow::Result<()> {
+    let files = match path.is_dir() {
+        true => {
+            let mut files = std::fs::read_dir(path)
+                .with_context(|| format!("read {}", path.display()))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            files.retain(|file| {
+                file.is_file()
+                    && file
+                        .extension()
+                        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+            });
+            files.sort();
+            anyhow::ensure!(!files.is_empty(), "{} has no .csv files", path.display());
+            files
+        }
+        false => vec![path.to_owned()],
+    };
+    let conn = Connection::open(&config.db_path)
+        .with_context(|| format!("open {}", config.db_path.display()))?;
+    create_schema(&conn)?;
+    let mut replaced = Vec::new();
+    for file in files {
+        let table = ingest_table(&file)?;
+        let mode = match replaced.contains(&table) {
+            true => IngestMode::Incremental,
+            false => mode,
+        };
+        replaced.push(table);
+        let summary = ingest_csv(&conn, &file, mode, config.csv_mode)
+            .with_context(|| format!("ingest {}", file.display()))?;
+        let mut line = serde_json::to_value(&summary)?;
+        line["file"] = json!(file);
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn summary(config: &Config, start: Option<String>, end: Option<String>) -> anyhow::Result<()> {
+    let (from, until) = parse_range(start.as_deref(), end.as_deref())
+        .map_err(|err| anyhow::anyhow!(err.message().to_owned()))?;
+    let conn = open_read_only(&config.db_path)?;
+    let (candles, first, last) = candle_extent(&conn, from, until)?;
+    let summary = summarize(&conn, from, until)?;
+    let line = json!({
+        "symbol": config.udf.symbol,
+        "start": from.or(first),
+        "end": until.or(last),
+        "candles": candles,
+        "open": summary.open,
+        "high": summary.high,
+        "low": summary.low,
+        "close": summary.close,
+        "volume": summary.volume,
+        "change": summary.change(),
+    });
+    println!("{}", serde_json::to_string_pretty(&line)?);
+    Ok(())
+}
+
+/// Opens the database for reading only, which several processes may do at
+/// once.
+fn open_read_only(path: &Path) -> anyhow::Result<Connection> {
+    Connection::open_with_flags(
+        path,
+        duckdb::Config::default().access_mode(AccessMode::ReadOnly)?,
+    )
+    .with_context(|| format!("open {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> anyhow::Result<Command> {
+        Command::parse(args.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    fn arguments_name_a_command_or_serve_as_before() {
+        let serve = |demo_data, static_dir: Option<&str>| Command::Serve {
+            demo_data,
+            static_dir: static_dir.map(PathBuf::from),
+        };
+        assert_eq!(parse("").unwrap(), serve(false, None));
+        assert_eq!(
+            parse("--generate-demo-data --static-dir web").unwrap(),
+            serve(true, Some("web"))
+        );
+        assert_eq!(
+            parse("serve --static-dir web").unwrap(),
+            serve(false, Some("web"))
+        );
+        assert_eq!(
+            parse("ingest data --mode replace").unwrap(),
+            Command::Ingest {
+                path: "data".into(),
+                mode: IngestMode::Replace
+            }
+        );
+        assert_eq!(
+            parse("summary --end 2024-01-02").unwrap(),
+            Command::Summary {
+                start: None,
+                end: Some("2024-01-02".to_owned())
+            }
+        );
+        assert_eq!(
+            parse("export --out a.parquet --format parquet").unwrap(),
+            Command::Export {
+                format: ExportFormat::Parquet,
+                out: "a.parquet".into()
+            }
+        );
+        assert_eq!(parse("--help").unwrap(), Command::Help);
+
+        for wrong in [
+            "ingest",
+            "ingest a b",
+            "ingest a --mode append",
+            "export --format csv",
+            "export --format xlsx --out a",
+            "summary --start",
+            "summary extra",
+            "summary --mode replace",
+            "serve --dry-run x",
+            "backfill",
+        ] {
+            assert!(parse(wrong).is_err(), "{wrong}");
+        }
+    }
+}
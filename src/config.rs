@@ -0,0 +1,574 @@
+//! Runtime settings, read from `GRAPH_*` environment variables.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub struct Config {
+    pub bind_addr: SocketAddr,
+    pub db_path: PathBuf,
+    pub csv_path: PathBuf,
+    pub events_csv_path: PathBuf,
+    /// Candles for further symbols, one `symbol` column ahead of the OHLCV.
+    pub symbols_csv_path: PathBuf,
+    /// Whether a malformed CSV row fails the load or is skipped and logged.
+    pub csv_mode: CsvMode,
+    /// Delete duplicated candle timestamps at startup, keeping the
+    /// last-ingested row of each, instead of only reporting them.
+    pub repair_duplicates: bool,
+    /// Refuse data reads while a stored candle's high and low do not contain
+    /// its open and close.
+    pub strict_ohlc: bool,
+    /// Symbol used when a request names none, e.g. `/api/spread` without `a`.
+    pub default_symbol: Option<String>,
+    /// How the `/udf` datafeed describes its symbols to TradingView.
+    pub udf: UdfSettings,
+    /// Where `POST /api/backfill` fetches history from.
+    pub backfill: BackfillSettings,
+    /// How alert webhooks are retried.
+    pub webhooks: WebhookSettings,
+    /// Where the front end is served from; `None` serves the copy built
+    /// into the binary.
+    pub static_dir: Option<PathBuf>,
+    pub read_pool_size: usize,
+    pub poll_interval: Duration,
+    /// Most `/api/ws` connections open at once; further upgrades are refused
+    /// with 503. `None` leaves them unlimited.
+    pub max_websockets: Option<usize>,
+    /// How often the watchdog probes the database.
+    pub watchdog_interval: Duration,
+    /// Consecutive failed probes after which the database is reopened; 0
+    /// turns the watchdog off.
+    pub watchdog_failures: u32,
+    /// Length of the candles built from ticks posted to `/api/ticks`.
+    pub tick_interval: Duration,
+    /// How long after a bar's interval ends late ticks may still correct it.
+    pub tick_grace: Duration,
+    pub cache_enabled: bool,
+    pub cache_max_bytes: usize,
+    /// Data routes, such as `/api/candles`, whose responses are never cached.
+    pub cache_exclude: Vec<String>,
+    /// `max-age` sent on data responses; zero sends `no-cache`, so clients
+    /// revalidate with the `ETag` every time.
+    pub cache_max_age: Duration,
+    /// Quantiles reported by `/api/percentile`, as percentages.
+    pub percentiles: Vec<f64>,
+    pub compression_enabled: bool,
+    /// Responses smaller than this are sent uncompressed.
+    pub compression_min_bytes: u16,
+    /// Tokio worker threads for async work: I/O, WebSockets and middleware.
+    pub worker_threads: usize,
+    /// Upper bound on tokio's blocking pool, where every DuckDB call runs.
+    /// Keep it above `read_pool_size` plus one for the writer, or queries
+    /// holding a pooled reader will queue for a thread.
+    pub max_blocking_threads: usize,
+    /// Time limit for a data request before it is answered with 504.
+    pub query_timeout: Duration,
+    /// Time limit for `/api/candles`, whose exports legitimately run longer.
+    pub export_timeout: Duration,
+    /// Most rows `/api/export/xlsx` writes across its sheets; a workbook is
+    /// built whole in memory, so larger exports are refused.
+    pub xlsx_max_rows: usize,
+    /// Default time `/api/adaptive_candles` plans each answer to take.
+    pub candle_budget: Duration,
+    /// Candles a second the server is taken to read and send, which turns
+    /// that budget into a number of candles.
+    pub candle_rows_per_sec: u64,
+    /// DuckDB resource settings applied when the database is opened.
+    pub duckdb: DuckDbLimits,
+    /// Per-client request rates; unlimited by default.
+    pub rate_limits: RateLimits,
+    /// Cross-origin access for front ends served elsewhere; off by default.
+    pub cors: CorsSettings,
+    /// Decimal places candle volumes are rounded to in responses and before
+    /// volume indicators use them; at 0 they are written as integers. `None`
+    /// returns them as stored.
+    pub volume_precision: Option<u32>,
+    /// Widest date range a resampled `/api/candles`, `/api/fib` or
+    /// `/api/percentile` request may cover; open bounds count up to the first
+    /// or last candle. `None` leaves ranges unlimited.
+    pub max_range: Option<Duration>,
+    /// Serve `/api/admin/explain`, which runs queries under `EXPLAIN ANALYZE`,
+    /// and `/api/admin/sql`, which shows them with their bound values.
+    pub explain_enabled: bool,
+    /// Serve `POST /api/query`, which runs an operator's read-only SQL.
+    pub sql_enabled: bool,
+    /// Most rows `POST /api/query` returns; the rest are cut off.
+    pub sql_max_rows: usize,
+    /// Keys accepted on `/api/admin/*` and mutating routes. With none, those
+    /// routes refuse every request.
+    pub api_keys: Vec<ApiKey>,
+    /// Require a key on every `/api/` route, not only the protected ones.
+    pub require_auth_for_reads: bool,
+    /// Fill an empty database with generated candles instead of the CSV; set
+    /// by `--generate-demo-data`.
+    pub demo_data: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_addr: SocketAddr::from(([0, 0, 0, 0], 8000)),
+            db_path: PathBuf::from("data/data.duckdb"),
+            csv_path: PathBuf::from("data/stocks.csv"),
+            events_csv_path: PathBuf::from("data/events.csv"),
+            symbols_csv_path: PathBuf::from("data/symbols.csv"),
+            csv_mode: CsvMode::Lenient,
+            repair_duplicates: false,
+            strict_ohlc: false,
+            default_symbol: None,
+            udf: UdfSettings::default(),
+            backfill: BackfillSettings::default(),
+            webhooks: WebhookSettings::default(),
+            static_dir: None,
+            read_pool_size: 4,
+            poll_interval: Duration::from_secs(1),
+            max_websockets: None,
+            watchdog_interval: Duration::from_secs(5),
+            watchdog_failures: 3,
+            tick_interval: Duration::from_secs(60),
+            tick_grace: Duration::from_secs(2),
+            cache_enabled: true,
+            cache_max_bytes: 64 * 1024 * 1024,
+            cache_exclude: Vec::new(),
+            cache_max_age: Duration::ZERO,
+            percentiles: vec![10.0, 25.0, 50.0, 75.0, 90.0],
+            compression_enabled: true,
+            compression_min_bytes: 1024,
+            worker_threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            max_blocking_threads: 64,
+            query_timeout: Duration::from_secs(30),
+            export_timeout: Duration::from_secs(600),
+            xlsx_max_rows: 200_000,
+            candle_budget: Duration::from_secs(1),
+            candle_rows_per_sec: 500_000,
+            duckdb: DuckDbLimits::default(),
+            rate_limits: RateLimits::default(),
+            cors: CorsSettings::default(),
+            volume_precision: None,
+            max_range: None,
+            explain_enabled: false,
+            sql_enabled: false,
+            sql_max_rows: 10_000,
+            api_keys: Vec::new(),
+            require_auth_for_reads: false,
+            demo_data: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let defaults = Self::default();
+        Ok(Self {
+            bind_addr: env_or("GRAPH_BIND", defaults.bind_addr)?,
+            db_path: env_or("GRAPH_DB_PATH", defaults.db_path)?,
+            csv_path: env_or("GRAPH_CSV_PATH", defaults.csv_path)?,
+            events_csv_path: env_or("GRAPH_EVENTS_CSV_PATH", defaults.events_csv_path)?,
+            symbols_csv_path: env_or("GRAPH_SYMBOLS_CSV_PATH", defaults.symbols_csv_path)?,
+            csv_mode: match env_or("GRAPH_CSV_STRICT", false)? {
+                true => CsvMode::Strict,
+                false => defaults.csv_mode,
+            },
+            repair_duplicates: env_or("GRAPH_REPAIR_DUPLICATES", defaults.repair_duplicates)?,
+            strict_ohlc: env_or("GRAPH_STRICT_OHLC", defaults.strict_ohlc)?,
+            default_symbol: env_opt("GRAPH_DEFAULT_SYMBOL")?,
+            udf: UdfSettings {
+                symbol: env_or("GRAPH_UDF_SYMBOL", defaults.udf.symbol)?,
+                pricescale: env_or("GRAPH_UDF_PRICESCALE", defaults.udf.pricescale)?.max(1),
+                session: env_or("GRAPH_UDF_SESSION", defaults.udf.session)?,
+                timezone: env_or("GRAPH_UDF_TIMEZONE", defaults.udf.timezone)?,
+            },
+            backfill: BackfillSettings {
+                url: env_opt("GRAPH_BACKFILL_URL")?,
+                authorization: env_opt("GRAPH_BACKFILL_AUTHORIZATION")?,
+                page_size: env_or("GRAPH_BACKFILL_PAGE_SIZE", defaults.backfill.page_size)?.max(1),
+                max_retries: env_or("GRAPH_BACKFILL_MAX_RETRIES", defaults.backfill.max_retries)?,
+                retry_delay: env_millis_or(
+                    "GRAPH_BACKFILL_RETRY_MS",
+                    defaults.backfill.retry_delay,
+                )?,
+            },
+            webhooks: WebhookSettings {
+                max_retries: env_or("GRAPH_WEBHOOK_MAX_RETRIES", defaults.webhooks.max_retries)?,
+                retry_delay: env_millis_or(
+                    "GRAPH_WEBHOOK_RETRY_MS",
+                    defaults.webhooks.retry_delay,
+                )?,
+            },
+            static_dir: env_opt("GRAPH_STATIC_DIR")?.or(defaults.static_dir),
+            read_pool_size: env_or("GRAPH_READ_POOL_SIZE", defaults.read_pool_size)?,
+            poll_interval: env_millis_or("GRAPH_POLL_INTERVAL_MS", defaults.poll_interval)?,
+            max_websockets: match env_or("GRAPH_MAX_WEBSOCKETS", 0usize)? {
+                0 => defaults.max_websockets,
+                limit => Some(limit),
+            },
+            watchdog_interval: env_millis_or(
+                "GRAPH_WATCHDOG_INTERVAL_MS",
+                defaults.watchdog_interval,
+            )?,
+            watchdog_failures: env_or("GRAPH_WATCHDOG_FAILURES", defaults.watchdog_failures)?,
+            tick_interval: env_millis_or("GRAPH_TICK_INTERVAL_MS", defaults.tick_interval)?
+                .max(Duration::from_millis(1)),
+            tick_grace: env_millis_or("GRAPH_TICK_GRACE_MS", defaults.tick_grace)?,
+            cache_enabled: env_or("GRAPH_CACHE_ENABLED", defaults.cache_enabled)?,
+            cache_max_bytes: env_or("GRAPH_CACHE_MAX_BYTES", defaults.cache_max_bytes)?,
+            cache_exclude: env_paths("GRAPH_CACHE_EXCLUDE")?,
+            cache_max_age: Duration::from_secs(env_or(
+                "GRAPH_CACHE_MAX_AGE_SECS",
+                defaults.cache_max_age.as_secs(),
+            )?),
+            percentiles: env_percentiles_or("GRAPH_PERCENTILES", defaults.percentiles)?,
+            compression_enabled: env_or("GRAPH_COMPRESSION_ENABLED", defaults.compression_enabled)?,
+            compression_min_bytes: env_or(
+                "GRAPH_COMPRESSION_MIN_BYTES",
+                defaults.compression_min_bytes,
+            )?,
+            worker_threads: env_or("GRAPH_WORKER_THREADS", defaults.worker_threads)?,
+            max_blocking_threads: env_or(
+                "GRAPH_MAX_BLOCKING_THREADS",
+                defaults.max_blocking_threads,
+            )?,
+            query_timeout: env_millis_or("GRAPH_QUERY_TIMEOUT_MS", defaults.query_timeout)?,
+            export_timeout: env_millis_or("GRAPH_EXPORT_TIMEOUT_MS", defaults.export_timeout)?,
+            xlsx_max_rows: env_or("GRAPH_XLSX_MAX_ROWS", defaults.xlsx_max_rows)?,
+            candle_budget: env_millis_or("GRAPH_CANDLE_BUDGET_MS", defaults.candle_budget)?,
+            candle_rows_per_sec: env_or("GRAPH_CANDLE_ROWS_PER_SEC", defaults.candle_rows_per_sec)?
+                .max(1),
+            duckdb: DuckDbLimits {
+                memory_limit: env_opt("GRAPH_DUCKDB_MEMORY_LIMIT")?,
+                threads: env_opt("GRAPH_DUCKDB_THREADS")?,
+                temp_directory: env_opt("GRAPH_DUCKDB_TEMP_DIR")?,
+            },
+            rate_limits: RateLimits {
+                global: env_rate("GRAPH_RATE_LIMIT_RPS", "GRAPH_RATE_LIMIT_BURST")?,
+                expensive: env_rate(
+                    "GRAPH_RATE_LIMIT_EXPENSIVE_RPS",
+                    "GRAPH_RATE_LIMIT_EXPENSIVE_BURST",
+                )?,
+                trusted_proxy: env_or("GRAPH_TRUSTED_PROXY", defaults.rate_limits.trusted_proxy)?,
+            },
+            cors: CorsSettings {
+                allowed_origins: env_origins("GRAPH_CORS_ORIGINS")?,
+                max_age: Duration::from_secs(env_or(
+                    "GRAPH_CORS_MAX_AGE_SECS",
+                    defaults.cors.max_age.as_secs(),
+                )?),
+            },
+            volume_precision: env_opt("GRAPH_VOLUME_PRECISION")?,
+            max_range: match env_or("GRAPH_MAX_RANGE_DAYS", 0u64)? {
+                0 => defaults.max_range,
+                days => Some(Duration::from_secs(days * 86_400)),
+            },
+            explain_enabled: env_or("GRAPH_EXPLAIN_ENABLED", defaults.explain_enabled)?,
+            sql_enabled: env_or("GRAPH_SQL_ENABLED", defaults.sql_enabled)?,
+            sql_max_rows: env_or("GRAPH_SQL_MAX_ROWS", defaults.sql_max_rows)?,
+            api_keys: env_api_keys("GRAPH_API_KEYS", "GRAPH_ADMIN_TOKEN")?,
+            require_auth_for_reads: env_or(
+                "GRAPH_REQUIRE_AUTH_FOR_READS",
+                defaults.require_auth_for_reads,
+            )?,
+            demo_data: defaults.demo_data,
+        })
+    }
+}
+
+/// How CSV loads treat rows DuckDB cannot parse.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CsvMode {
+    /// Load the good rows and log a summary of the rest.
+    #[default]
+    Lenient,
+    /// Load nothing if any row is malformed.
+    Strict,
+}
+
+/// The upstream HTTP API `POST /api/backfill` pages history from.
+#[derive(Clone, Debug)]
+pub struct BackfillSettings {
+    /// An `http://` URL with `{symbol}`, `{start}`, `{end}` and `{limit}`
+    /// placeholders, filled in for each page. `None` turns backfill off.
+    pub url: Option<String>,
+    /// Sent as the `Authorization` header, e.g. `Bearer <token>`.
+    pub authorization: Option<String>,
+    /// Candles asked for per page.
+    pub page_size: usize,
+    /// Further attempts at a page that failed to arrive or was refused with
+    /// 429 or 503.
+    pub max_retries: u32,
+    /// The wait before the first retry that has no `Retry-After`; each
+    /// further one doubles it.
+    pub retry_delay: Duration,
+}
+
+impl Default for BackfillSettings {
+    fn default() -> Self {
+        Self {
+            url: None,
+            authorization: None,
+            page_size: 1000,
+            max_retries: 5,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Delivery of the webhooks alert rules call when they fire.
+#[derive(Clone, Debug)]
+pub struct WebhookSettings {
+    /// Further attempts at a delivery that failed to arrive or was answered
+    /// with 429 or a 5xx status.
+    pub max_retries: u32,
+    /// The wait before the first retry that has no `Retry-After`; each
+    /// further one doubles it.
+    pub retry_delay: Duration,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Symbol metadata for the `/udf` datafeed. Timestamps are stored in UTC
+/// whatever these say; `session` and `timezone` only tell the chart where
+/// trading days start and end.
+#[derive(Clone, Debug)]
+pub struct UdfSettings {
+    /// The name the main candles table is listed under, beside the symbols
+    /// in `symbol_candles`.
+    pub symbol: String,
+    /// Price increments per unit: `100` shows prices to the cent.
+    pub pricescale: u32,
+    /// Trading hours in TradingView's syntax, e.g. `0930-1600` or `24x7`.
+    pub session: String,
+    /// An IANA zone such as `America/New_York` the session is read in.
+    pub timezone: String,
+}
+
+impl Default for UdfSettings {
+    fn default() -> Self {
+        Self {
+            symbol: "MAIN".to_owned(),
+            pricescale: 100,
+            session: "24x7".to_owned(),
+            timezone: "Etc/UTC".to_owned(),
+        }
+    }
+}
+
+/// DuckDB settings that bound a query's footprint. `None` keeps DuckDB's own
+/// default: 80% of RAM, one thread per core, and spilling to a `.tmp`
+/// directory next to the database file.
+#[derive(Clone, Debug, Default)]
+pub struct DuckDbLimits {
+    /// Memory cap such as `512MB` or `2GiB`; larger work spills to disk.
+    pub memory_limit: Option<String>,
+    pub threads: Option<usize>,
+    /// Where spill files go once `memory_limit` is reached.
+    pub temp_directory: Option<PathBuf>,
+}
+
+/// Token-bucket limits per client IP. `global` covers every route but
+/// `/healthz`; `expensive` additionally covers the analytics endpoints.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimits {
+    pub global: Option<RateLimit>,
+    pub expensive: Option<RateLimit>,
+    /// Key clients by the last `X-Forwarded-For` hop instead of the peer
+    /// address; only safe behind a proxy that sets it.
+    pub trusted_proxy: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Sustained requests per second.
+    pub per_second: f64,
+    /// Requests a client may make at once after being idle.
+    pub burst: u32,
+}
+
+/// A rate from `rps_key` (unset or `0` disables it) with a burst from
+/// `burst_key`, defaulting to one second's worth.
+fn env_rate(rps_key: &str, burst_key: &str) -> anyhow::Result<Option<RateLimit>> {
+    let per_second: f64 = env_or(rps_key, 0.0)?;
+    anyhow::ensure!(
+        per_second.is_finite() && per_second >= 0.0,
+        "invalid {rps_key}={per_second}: expected a non-negative rate"
+    );
+    if per_second == 0.0 {
+        return Ok(None);
+    }
+    let burst = env_or(burst_key, per_second.ceil() as u32)?.max(1);
+    Ok(Some(RateLimit { per_second, burst }))
+}
+
+/// Reads a comma-separated list of `/api/` paths.
+fn env_paths(key: &str) -> anyhow::Result<Vec<String>> {
+    let Ok(value) = std::env::var(key) else {
+        return Ok(Vec::new());
+    };
+    let paths = value
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    anyhow::ensure!(
+        paths.iter().all(|path| path.starts_with("/api/")),
+        "invalid {key}={value:?}: expected paths like /api/candles"
+    );
+    Ok(paths)
+}
+
+/// Which origins may call the API from a browser, and how long browsers may
+/// cache a preflight answer.
+#[derive(Clone, Debug)]
+pub struct CorsSettings {
+    /// Origins such as `http://localhost:5173`, or `*` for any; empty
+    /// disables CORS.
+    pub allowed_origins: Vec<String>,
+    pub max_age: Duration,
+}
+
+impl Default for CorsSettings {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            max_age: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Reads a comma-separated list of `http(s)://` origins, or a lone `*`.
+fn env_origins(key: &str) -> anyhow::Result<Vec<String>> {
+    let Ok(value) = std::env::var(key) else {
+        return Ok(Vec::new());
+    };
+    let origins = value
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    let any = origins.iter().any(|origin| origin == "*");
+    let valid = origins.iter().all(|origin| {
+        let host = origin
+            .strip_prefix("https://")
+            .or_else(|| origin.strip_prefix("http://"));
+        host.is_some_and(|host| !host.is_empty() && !host.contains('/') && origin.is_ascii())
+    });
+    anyhow::ensure!(
+        (any && origins.len() == 1) || (!any && valid),
+        "invalid {key}={value:?}: expected origins like http://localhost:5173, or * alone"
+    );
+    Ok(origins)
+}
+
+/// A secret callers present as a bearer token or `X-Api-Key`, and the id
+/// logged in its place.
+#[derive(Clone)]
+pub struct ApiKey {
+    pub id: String,
+    pub secret: String,
+}
+
+impl ApiKey {
+    pub fn new(id: impl Into<String>, secret: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            secret: secret.into(),
+        }
+    }
+}
+
+/// Never prints the secret.
+impl std::fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reads comma-separated `id:secret` pairs from `key`, plus the older single
+/// `legacy_key` token under the id `admin`.
+fn env_api_keys(key: &str, legacy_key: &str) -> anyhow::Result<Vec<ApiKey>> {
+    let mut keys = Vec::new();
+    if let Ok(value) = std::env::var(key) {
+        for pair in value
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            let parsed = pair
+                .split_once(':')
+                .filter(|(id, secret)| !id.is_empty() && !secret.is_empty());
+            let Some((id, secret)) = parsed else {
+                anyhow::bail!("invalid {key}: expected id:secret pairs separated by commas");
+            };
+            keys.push(ApiKey::new(id, secret));
+        }
+    }
+    if let Ok(secret) = std::env::var(legacy_key) {
+        keys.push(ApiKey::new("admin", secret));
+    }
+    let mut ids = keys.iter().map(|key| key.id.as_str()).collect::<Vec<_>>();
+    ids.sort_unstable();
+    if let Some(pair) = ids.windows(2).find(|pair| pair[0] == pair[1]) {
+        anyhow::bail!("API key id {:?} is used twice", pair[0]);
+    }
+    Ok(keys)
+}
+
+/// Reads a comma-separated list of percentages such as `10,50,90`.
+fn env_percentiles_or(key: &str, default: Vec<f64>) -> anyhow::Result<Vec<f64>> {
+    let Ok(value) = std::env::var(key) else {
+        return Ok(default);
+    };
+    value
+        .split(',')
+        .map(|part| match part.trim().parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent),
+            _ => Err(anyhow::anyhow!(
+                "invalid {key}={value:?}: expected percentages between 0 and 100"
+            )),
+        })
+        .collect()
+}
+
+fn env_millis_or(key: &str, default: Duration) -> anyhow::Result<Duration> {
+    let millis = env_or(key, default.as_millis() as u64)?;
+    Ok(Duration::from_millis(millis))
+}
+
+fn env_opt<T>(key: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| anyhow::anyhow!("invalid {key}={value:?}: {err}")),
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_or<T>(key: &str, default: T) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map_err(|err| anyhow::anyhow!("invalid {key}={value:?}: {err}")),
+        Err(_) => Ok(default),
+    }
+}
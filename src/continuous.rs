@@ -0,0 +1,247 @@
+//! Continuous futures series stitched from contract months.
+//!
+//! Each contract supplies the bars from the previous roll up to (not
+//! including) its own roll; the last contract runs to the end of its data. At
+//! every roll the gap between the two contracts is measured on the latest bar
+//! before the roll that both of them have, and every earlier bar is shifted by
+//! the gaps of all later rolls, so the newest contract keeps its real prices
+//! and the series has no artificial jumps for indicators to react to.
+
+use serde::Deserialize;
+
+use crate::models::{ContinuousCandle, ContinuousSeries, Roll, Timestamp};
+
+/// Back-adjustment applied to bars before each roll.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Adjustment {
+    /// Concatenate segments as stored, keeping the gaps.
+    None,
+    /// Add the price difference between the contracts at the roll.
+    #[default]
+    Add,
+    /// Multiply by the price ratio between the contracts at the roll.
+    Ratio,
+}
+
+/// One bar of a contract, oldest first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bar {
+    pub timestamp: Timestamp,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+pub struct Contract {
+    pub symbol: String,
+    /// Ordered by timestamp.
+    pub bars: Vec<Bar>,
+}
+
+/// Stitches `contracts` at `rolls`, which must be one fewer and strictly
+/// increasing. Fails when two adjacent contracts share no bar before their
+/// roll, since the gap cannot be measured then.
+pub fn stitch(
+    contracts: &[Contract],
+    rolls: &[Timestamp],
+    adjustment: Adjustment,
+) -> Result<ContinuousSeries, String> {
+    assert_eq!(
+        contracts.len(),
+        rolls.len() + 1,
+        "one roll between each pair"
+    );
+    let mut gaps = Vec::with_capacity(rolls.len());
+    for (pair, &roll) in contracts.windows(2).zip(rolls) {
+        let (old, new) = (&pair[0], &pair[1]);
+        let Some((reference, old_close, new_close)) = overlap_before(old, new, roll) else {
+            return Err(format!(
+                "{} and {} share no bar before the roll at {roll}",
+                old.symbol, new.symbol
+            ));
+        };
+        let gap = match adjustment {
+            Adjustment::None => 0.0,
+            Adjustment::Add => new_close - old_close,
+            Adjustment::Ratio if old_close == 0.0 => {
+                return Err(format!(
+                    "{} closes at 0 on {reference}, so no ratio can be taken",
+                    old.symbol
+                ))
+            }
+            Adjustment::Ratio => new_close / old_close,
+        };
+        gaps.push(Roll {
+            timestamp: roll,
+            from: old.symbol.clone(),
+            to: new.symbol.clone(),
+            reference,
+            gap,
+        });
+    }
+
+    // Bars before roll `i` carry the gaps of rolls `i..`, accumulated from
+    // the newest contract backwards.
+    let identity = match adjustment {
+        Adjustment::Ratio => 1.0,
+        Adjustment::None | Adjustment::Add => 0.0,
+    };
+    let mut offsets = vec![identity; contracts.len()];
+    for index in (0..gaps.len()).rev() {
+        offsets[index] = match adjustment {
+            Adjustment::None => 0.0,
+            Adjustment::Add => offsets[index + 1] + gaps[index].gap,
+            Adjustment::Ratio => offsets[index + 1] * gaps[index].gap,
+        };
+    }
+
+    let mut candles = Vec::new();
+    for (index, contract) in contracts.iter().enumerate() {
+        let from = index.checked_sub(1).map(|previous| rolls[previous].at);
+        let until = rolls.get(index).map(|roll| roll.at);
+        let adjust = |price: f64| match adjustment {
+            Adjustment::None => price,
+            Adjustment::Add => price + offsets[index],
+            Adjustment::Ratio => price * offsets[index],
+        };
+        candles.extend(
+            contract
+                .bars
+                .iter()
+                .filter(|bar| from.is_none_or(|from| bar.timestamp.at >= from))
+                .filter(|bar| until.is_none_or(|until| bar.timestamp.at < until))
+                .map(|bar| ContinuousCandle {
+                    timestamp: bar.timestamp,
+                    open: adjust(bar.open),
+                    high: adjust(bar.high),
+                    low: adjust(bar.low),
+                    close: adjust(bar.close),
+                    volume: bar.volume,
+                    contract: contract.symbol.clone(),
+                }),
+        );
+    }
+    Ok(ContinuousSeries {
+        candles,
+        rolls: gaps,
+    })
+}
+
+/// The latest timestamp before `roll` with a bar in both contracts, and the
+/// two closes there.
+fn overlap_before(
+    old: &Contract,
+    new: &Contract,
+    roll: Timestamp,
+) -> Option<(Timestamp, f64, f64)> {
+    let before = |bars: &[Bar]| bars.partition_point(|bar| bar.timestamp.at < roll.at);
+    let (mut i, mut j) = (before(&old.bars), before(&new.bars));
+    // Walk both back from the roll until the timestamps meet.
+    while i > 0 && j > 0 {
+        let (a, b) = (&old.bars[i - 1], &new.bars[j - 1]);
+        match a.timestamp.at.cmp(&b.timestamp.at) {
+            std::cmp::Ordering::Equal => return Some((a.timestamp, a.close, b.close)),
+            std::cmp::Ordering::Greater => i -= 1,
+            std::cmp::Ordering::Less => j -= 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn day(day: u32) -> Timestamp {
+        Timestamp::new(
+            NaiveDate::from_ymd_opt(2024, 3, day)
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .unwrap(),
+        )
+    }
+
+    fn contract(symbol: &str, closes: &[(u32, f64)]) -> Contract {
+        Contract {
+            symbol: symbol.to_owned(),
+            bars: closes
+                .iter()
+                .map(|&(d, close)| Bar {
+                    timestamp: day(d),
+                    open: close,
+                    high: close + 1.0,
+                    low: close - 1.0,
+                    close,
+                    volume: 10.0,
+                })
+                .collect(),
+        }
+    }
+
+    fn closes(series: &ContinuousSeries) -> Vec<(u32, f64, &str)> {
+        series
+            .candles
+            .iter()
+            .map(|c| {
+                let day = c.timestamp.at.date().format("%d").to_string();
+                (day.parse().unwrap(), c.close, c.contract.as_str())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn back_adjustment_removes_the_gap_at_each_roll() {
+        let contracts = [
+            contract("H", &[(1, 100.0), (2, 101.0), (3, 102.0)]),
+            // Trades 5 above H on the 2nd, the last shared bar before the roll.
+            contract("M", &[(2, 106.0), (3, 107.0), (4, 108.0), (5, 109.0)]),
+            // No bar on the 4th; the 3rd is the reference, 2 above M.
+            contract("U", &[(3, 109.0), (5, 111.0), (6, 112.0)]),
+        ];
+        let rolls = [day(3), day(5)];
+
+        let raw = stitch(&contracts, &rolls, Adjustment::None).unwrap();
+        assert_eq!(
+            closes(&raw),
+            [
+                (1, 100.0, "H"),
+                (2, 101.0, "H"),
+                (3, 107.0, "M"),
+                (4, 108.0, "M"),
+                (5, 111.0, "U"),
+                (6, 112.0, "U"),
+            ]
+        );
+
+        let added = stitch(&contracts, &rolls, Adjustment::Add).unwrap();
+        assert_eq!(added.rolls[0].gap, 5.0);
+        assert_eq!(added.rolls[0].reference, day(2));
+        assert_eq!(added.rolls[1].gap, 2.0);
+        assert_eq!(added.rolls[1].reference, day(3));
+        let adjusted = closes(&added)
+            .into_iter()
+            .map(|(_, close, _)| close)
+            .collect::<Vec<_>>();
+        assert_eq!(adjusted, [107.0, 108.0, 109.0, 110.0, 111.0, 112.0]);
+        assert_eq!(added.candles[0].high, 108.0);
+        assert_eq!(added.candles[0].volume, 10.0);
+
+        let ratio = stitch(&contracts, &rolls, Adjustment::Ratio).unwrap();
+        let factor = (106.0 / 101.0) * (109.0 / 107.0);
+        assert!((ratio.candles[0].close - 100.0 * factor).abs() < 1e-9);
+        assert_eq!(ratio.candles[5].close, 112.0);
+    }
+
+    #[test]
+    fn contracts_without_an_overlap_cannot_be_stitched() {
+        let contracts = [contract("H", &[(1, 100.0)]), contract("M", &[(2, 106.0)])];
+        let err = stitch(&contracts, &[day(2)], Adjustment::Add)
+            .err()
+            .unwrap();
+        assert!(err.contains("share no bar"), "{err}");
+    }
+}
@@ -0,0 +1,1371 @@
+//! DuckDB connection management and CSV ingestion.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use axum::body::{Body, Bytes};
+use axum::BoxError;
+use duckdb::Connection;
+use futures_util::stream::{self, StreamExt};
+use serde::Serialize;
+use tokio::sync::mpsc::error::SendTimeoutError;
+use tokio::sync::{mpsc, Mutex, OwnedSemaphorePermit, Semaphore};
+
+use crate::config::{CsvMode, DuckDbLimits};
+use crate::demo::{self, DemoSpec};
+use crate::models::Timestamp;
+
+/// Prepared statements kept per connection. Handlers use
+/// [`Connection::prepare_cached`] with fully parameterized SQL, so each
+/// distinct statement text is parsed and planned once per connection.
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// Connections to a single DuckDB database: a fixed pool of readers handed out
+/// per request plus one designated writer, so reads never queue behind each
+/// other and writes never conflict.
+///
+/// All connections are clones of the one that opened the file, which DuckDB
+/// treats as sessions on the same in-process database instance. Readers are
+/// not opened read-only: access mode belongs to the instance, and a second
+/// read-only instance on the same file is refused while this process holds it
+/// for writing. Instead the split is enforced by the API — readers are only
+/// reachable through [`Db::read`], the writer only through [`Db::write`] — and
+/// DuckDB's MVCC lets readers keep seeing the last committed state while a
+/// long write transaction is open. DuckDB calls
+/// are synchronous, so [`Db::read`] and [`Db::write`] run the whole closure —
+/// query and row mapping — on tokio's blocking pool and only hand the result
+/// back to the async side.
+pub struct Db {
+    readers: std::sync::Mutex<Vec<Connection>>,
+    read_pool_size: usize,
+    read_permits: Arc<Semaphore>,
+    writer: Arc<Mutex<Connection>>,
+    limits: DuckDbLimits,
+}
+
+/// How [`Db::reopen`] gets the database back.
+pub struct Reopen {
+    pub open: Box<dyn Fn() -> anyhow::Result<Connection> + Send + Sync>,
+    /// The idempotent setup startup runs: tables, migrations and first loads.
+    pub setup: Box<Setup>,
+}
+
+pub type Setup = dyn Fn(&Connection) -> anyhow::Result<()> + Send + Sync;
+
+impl Db {
+    pub fn new(writer: Connection, read_pool_size: usize) -> anyhow::Result<Self> {
+        Self::with_limits(writer, read_pool_size, &DuckDbLimits::default())
+    }
+
+    /// Like [`Db::new`], applying `limits` first. DuckDB keeps these settings
+    /// per database instance rather than per connection, so setting them on
+    /// the connection that opened the file, before the pool is cloned from
+    /// it, covers every reader and the writer alike.
+    pub fn with_limits(
+        writer: Connection,
+        read_pool_size: usize,
+        limits: &DuckDbLimits,
+    ) -> anyhow::Result<Self> {
+        apply_limits(&writer, limits).context("apply DuckDB limits")?;
+        let read_pool_size = read_pool_size.max(1);
+        let readers = clone_readers(&writer, read_pool_size)?;
+        Ok(Self {
+            readers: std::sync::Mutex::new(readers),
+            read_pool_size,
+            read_permits: Arc::new(Semaphore::new(read_pool_size)),
+            writer: Arc::new(Mutex::new(writer)),
+            limits: limits.clone(),
+        })
+    }
+
+    /// Replaces every connection with a fresh instance from `reopen`, for
+    /// when the current one has stopped answering.
+    ///
+    /// The old instance is closed before the new one opens, since two
+    /// instances on one file in one process would not see each other's
+    /// locks. That means waiting for every reader and the writer to be idle;
+    /// if they are not within `grace`, nothing is touched. Should opening or
+    /// setup fail, the pool is left on an empty in-memory placeholder, so
+    /// queries fail cleanly until the next attempt succeeds.
+    pub async fn reopen(
+        self: &Arc<Self>,
+        reopen: Arc<Reopen>,
+        grace: Duration,
+    ) -> anyhow::Result<()> {
+        let idle = async {
+            let permits = Arc::clone(&self.read_permits)
+                .acquire_many_owned(self.read_pool_size as u32)
+                .await
+                .expect("read semaphore is never closed");
+            (permits, Arc::clone(&self.writer).lock_owned().await)
+        };
+        let (_permits, mut writer) = tokio::time::timeout(grace, idle)
+            .await
+            .map_err(|_| anyhow::anyhow!("connections still busy after {grace:?}"))?;
+        let db = Arc::clone(self);
+        run_blocking(move || {
+            let mut readers = db.readers.lock().expect("reader pool poisoned");
+            readers.clear();
+            *writer = Connection::open_in_memory().context("open placeholder")?;
+            let opened = (reopen.open)().and_then(|conn| {
+                apply_limits(&conn, &db.limits).context("apply DuckDB limits")?;
+                (reopen.setup)(&conn)?;
+                Ok(conn)
+            });
+            let reopened = opened.map(|conn| *writer = conn);
+            *readers = clone_readers(&writer, db.read_pool_size)?;
+            reopened
+        })
+        .await
+    }
+
+    /// Runs `f` against a pooled reader on a blocking thread.
+    pub async fn read<T, F>(self: &Arc<Self>, f: F) -> T
+    where
+        F: FnOnce(&Connection) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = Arc::clone(&self.read_permits)
+            .acquire_owned()
+            .await
+            .expect("read semaphore is never closed");
+        let db = Arc::clone(self);
+        run_blocking(move || {
+            let conn = ReadConn::checkout(db, permit);
+            f(&conn)
+        })
+        .await
+    }
+
+    /// Runs `f` against the single writer connection on a blocking thread.
+    pub async fn write<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(&Connection) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = Arc::clone(&self.writer).lock_owned().await;
+        run_blocking(move || f(&conn)).await
+    }
+
+    /// Runs `f` against a pooled reader on a blocking thread and streams what
+    /// it writes as a response body.
+    ///
+    /// The reader stays checked out until `f` returns, which it does early once
+    /// the client disconnects or `timeout` passes and [`BodyWriter::write`]
+    /// starts failing; a client that stops reading cannot hold it past the
+    /// deadline either. Errors before the first flush are returned here so the
+    /// handler can still answer with a proper status; later ones, and running
+    /// out of time, abort the body mid-stream.
+    pub(crate) async fn read_stream<F>(
+        self: &Arc<Self>,
+        timeout: Duration,
+        f: F,
+    ) -> duckdb::Result<Body>
+    where
+        F: FnOnce(&Connection, &mut BodyWriter) -> duckdb::Result<()> + Send + 'static,
+    {
+        let deadline = Instant::now() + timeout;
+        let permit = Arc::clone(&self.read_permits)
+            .acquire_owned()
+            .await
+            .expect("read semaphore is never closed");
+        let db = Arc::clone(self);
+        let (tx, mut rx) = mpsc::channel(STREAM_CHANNEL_CHUNKS);
+        let runtime = tokio::runtime::Handle::current();
+        tokio::task::spawn_blocking(move || {
+            let conn = ReadConn::checkout(db, permit);
+            let mut out = BodyWriter::new(tx, runtime, deadline);
+            match f(&conn, &mut out) {
+                Ok(()) => out.flush(),
+                Err(err) => {
+                    tracing::error!("streamed query failed: {err}");
+                    out.fail(err);
+                }
+            }
+            if out.timed_out {
+                tracing::warn!("streamed query exceeded its {timeout:?} time limit");
+            }
+        });
+
+        let first = match rx.recv().await {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(err)) => return Err(err),
+            None => Bytes::new(),
+        };
+        // The writer gives up silently at the deadline, so an early end of
+        // the channel after it is reported as a truncated body.
+        let rest = stream::unfold(Some(rx), move |rx| async move {
+            let mut rx = rx?;
+            match rx.recv().await {
+                Some(chunk) => Some((chunk.map_err(BoxError::from), Some(rx))),
+                None if Instant::now() >= deadline => {
+                    let err = std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("export exceeded the {timeout:?} time limit"),
+                    );
+                    Some((Err(err.into()), None))
+                }
+                None => None,
+            }
+        });
+        Ok(Body::from_stream(
+            stream::once(async move { Ok(first) }).chain(rest),
+        ))
+    }
+}
+
+/// Bytes buffered by a [`BodyWriter`] before they are handed to the body.
+const STREAM_CHUNK_BYTES: usize = 32 * 1024;
+
+/// Chunks in flight between a streaming query and the client. Together with
+/// [`STREAM_CHUNK_BYTES`] this bounds the memory a single export can pin.
+const STREAM_CHANNEL_CHUNKS: usize = 8;
+
+/// Batches encoded rows into chunks for a streamed response body.
+pub(crate) struct BodyWriter {
+    buf: Vec<u8>,
+    tx: mpsc::Sender<duckdb::Result<Bytes>>,
+    runtime: tokio::runtime::Handle,
+    deadline: Instant,
+    closed: bool,
+    timed_out: bool,
+}
+
+impl BodyWriter {
+    fn new(
+        tx: mpsc::Sender<duckdb::Result<Bytes>>,
+        runtime: tokio::runtime::Handle,
+        deadline: Instant,
+    ) -> Self {
+        Self {
+            buf: Vec::with_capacity(STREAM_CHUNK_BYTES),
+            tx,
+            runtime,
+            deadline,
+            closed: false,
+            timed_out: false,
+        }
+    }
+
+    /// Appends to the current chunk, blocking while the client catches up.
+    /// Returns `false` once the client has gone away or time has run out.
+    pub(crate) fn write(&mut self, encode: impl FnOnce(&mut Vec<u8>)) -> bool {
+        if !self.closed && Instant::now() >= self.deadline {
+            self.closed = true;
+            self.timed_out = true;
+        }
+        encode(&mut self.buf);
+        if self.buf.len() >= STREAM_CHUNK_BYTES {
+            self.flush();
+        }
+        !self.closed
+    }
+
+    pub(crate) fn flush(&mut self) {
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buf,
+            Vec::with_capacity(STREAM_CHUNK_BYTES),
+        ));
+        if self.closed {
+            return;
+        }
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        match self
+            .runtime
+            .block_on(self.tx.send_timeout(Ok(chunk), remaining))
+        {
+            Ok(()) => {}
+            Err(SendTimeoutError::Timeout(_)) => {
+                self.closed = true;
+                self.timed_out = true;
+            }
+            Err(SendTimeoutError::Closed(_)) => self.closed = true,
+        }
+    }
+
+    /// Aborts the body with `err` after whatever was already flushed.
+    fn fail(&mut self, err: duckdb::Error) {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        let _ = self
+            .runtime
+            .block_on(self.tx.send_timeout(Err(err), remaining));
+    }
+}
+
+fn clone_readers(writer: &Connection, count: usize) -> anyhow::Result<Vec<Connection>> {
+    let readers = (0..count)
+        .map(|_| writer.try_clone())
+        .collect::<Result<Vec<_>, _>>()
+        .context("clone DuckDB connection")?;
+    for conn in readers.iter().chain([writer]) {
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    }
+    Ok(readers)
+}
+
+async fn run_blocking<T, F>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+/// A pooled reader, returned to the pool on drop (including on panic).
+struct ReadConn {
+    db: Arc<Db>,
+    conn: Option<Connection>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl ReadConn {
+    fn checkout(db: Arc<Db>, permit: OwnedSemaphorePermit) -> Self {
+        let conn = db
+            .readers
+            .lock()
+            .expect("reader pool poisoned")
+            .pop()
+            .expect("a permit guarantees an idle reader");
+        Self {
+            db,
+            conn: Some(conn),
+            _permit: permit,
+        }
+    }
+}
+
+impl Deref for ReadConn {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().expect("connection present until drop")
+    }
+}
+
+impl Drop for ReadConn {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.db
+                .readers
+                .lock()
+                .expect("reader pool poisoned")
+                .push(conn);
+        }
+    }
+}
+
+/// Creates the candles table and loads `csv_path` into it on first run,
+/// returning what the load did. A missing CSV leaves the table empty rather
+/// than failing startup. Quote columns after `volume` in the CSV header
+/// (any of [`QUOTE_COLUMNS`]) are stored alongside the candles.
+pub fn initialize_db(
+    conn: &Connection,
+    csv_path: &Path,
+    mode: CsvMode,
+) -> anyhow::Result<Option<ImportSummary>> {
+    let summary = if create_candles(conn)? && csv_path.exists() {
+        add_quote_columns(conn, csv_path)?;
+        Some(load_csv(conn, "candles", csv_path, mode)?)
+    } else {
+        None
+    };
+    migrate(conn)?;
+    Ok(summary)
+}
+
+/// Like [`initialize_db`], but fills an empty candles table with a generated
+/// series instead of the CSV.
+pub fn initialize_demo_db(conn: &Connection, spec: &DemoSpec) -> anyhow::Result<()> {
+    if create_candles(conn)? {
+        let rows = demo::load(conn, spec)?;
+        tracing::info!("generated {rows} demo candles (seed {})", spec.seed);
+    }
+    migrate(conn)
+}
+
+fn apply_limits(conn: &Connection, limits: &DuckDbLimits) -> anyhow::Result<()> {
+    let mut settings = Vec::new();
+    if let Some(memory_limit) = &limits.memory_limit {
+        settings.push(format!("SET memory_limit = {};", sql_string(memory_limit)));
+    }
+    if let Some(threads) = limits.threads {
+        settings.push(format!("SET threads = {threads};"));
+    }
+    if let Some(temp_directory) = &limits.temp_directory {
+        let path = temp_directory
+            .to_str()
+            .context("DuckDB temp directory not valid UTF-8")?;
+        settings.push(format!("SET temp_directory = {};", sql_string(path)));
+    }
+    conn.execute_batch(&settings.concat())?;
+    let (memory_limit, threads, temp_directory): (String, String, String) = conn.query_row(
+        "SELECT
+            CAST(current_setting('memory_limit') AS VARCHAR),
+            CAST(current_setting('threads') AS VARCHAR),
+            CAST(current_setting('temp_directory') AS VARCHAR)",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    tracing::info!(
+        "DuckDB memory_limit={memory_limit} threads={threads} temp_directory={temp_directory:?}"
+    );
+    Ok(())
+}
+
+/// Creates the candles table if needed and reports whether it is empty.
+fn create_candles(conn: &Connection) -> duckdb::Result<bool> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS candles (
+            timestamp TIMESTAMP,
+            open DOUBLE,
+            high DOUBLE,
+            low DOUBLE,
+            close DOUBLE,
+            volume DOUBLE
+        );",
+    )?;
+    let existing: i64 = conn.query_row("SELECT COUNT(*) FROM candles", [], |row| row.get(0))?;
+    Ok(existing == 0)
+}
+
+/// Optional candle columns for datasets that carry quotes, such as FX and
+/// crypto, in the order they are returned.
+pub const QUOTE_COLUMNS: [&str; 3] = ["bid", "ask", "spread"];
+
+/// Adds a `DOUBLE` column to the candles table for each quote column the
+/// CSV header has after the six standard ones, in header order so the load
+/// still matches columns by position. A header with anything else there is
+/// left for the load to reject.
+fn add_quote_columns(conn: &Connection, csv_path: &Path) -> anyhow::Result<()> {
+    let mut header = String::new();
+    BufReader::new(File::open(csv_path)?).read_line(&mut header)?;
+    let names = header
+        .split(',')
+        .map(|name| name.trim().trim_matches('"').to_ascii_lowercase())
+        .collect::<Vec<_>>();
+    let extra = names.get(6..).unwrap_or_default();
+    let known = |name: &String| QUOTE_COLUMNS.contains(&name.as_str());
+    let unique = extra
+        .iter()
+        .enumerate()
+        .all(|(i, name)| !extra[..i].contains(name));
+    if extra.is_empty() || !extra.iter().all(known) || !unique {
+        return Ok(());
+    }
+    for name in extra {
+        conn.execute_batch(&format!(
+            "ALTER TABLE candles ADD COLUMN IF NOT EXISTS {name} DOUBLE;"
+        ))?;
+    }
+    tracing::info!("storing quote columns {} from the CSV", extra.join(", "));
+    Ok(())
+}
+
+/// The [`QUOTE_COLUMNS`] the candles table has, in that order.
+pub fn quote_columns(conn: &Connection) -> duckdb::Result<Vec<&'static str>> {
+    let present = conn
+        .prepare_cached(
+            "SELECT column_name FROM information_schema.columns WHERE table_name = 'candles'",
+        )?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+    Ok(QUOTE_COLUMNS
+        .into_iter()
+        .filter(|name| present.iter().any(|column| column == name))
+        .collect())
+}
+
+/// Schema changes, applied in order to existing and new databases alike. The
+/// number applied so far is recorded in `schema_version`; append new entries,
+/// never edit old ones.
+const MIGRATIONS: &[&str] = &[
+    // 1: materialized indicators for the default periods on `close`, each row
+    // tagged with the hash of the candle it was computed from.
+    "CREATE TABLE indicators (
+        timestamp TIMESTAMP NOT NULL,
+        sma_14 DOUBLE,
+        ema_14 DOUBLE,
+        rsi_14 DOUBLE,
+        candle_hash UBIGINT NOT NULL
+    );
+    CREATE INDEX indicators_timestamp ON indicators (timestamp);
+    CREATE TABLE indicator_refresh (
+        refreshed_at TIMESTAMP,
+        last_timestamp TIMESTAMP,
+        rows BIGINT,
+        recomputed BIGINT
+    );",
+    // 2: alert rules, with the state of each, and a row per webhook attempt
+    // made when one fired.
+    "CREATE SEQUENCE alert_rule_ids START 1;
+    CREATE TABLE alert_rules (
+        id BIGINT NOT NULL DEFAULT nextval('alert_rule_ids'),
+        symbol VARCHAR,
+        field VARCHAR NOT NULL,
+        condition VARCHAR NOT NULL,
+        level DOUBLE NOT NULL,
+        webhook_url VARCHAR NOT NULL,
+        message VARCHAR,
+        rearm VARCHAR NOT NULL,
+        state VARCHAR NOT NULL,
+        fired_at TIMESTAMP,
+        checked_through TIMESTAMP,
+        created_at TIMESTAMP NOT NULL
+    );
+    CREATE SEQUENCE webhook_delivery_ids START 1;
+    CREATE TABLE webhook_deliveries (
+        id BIGINT NOT NULL DEFAULT nextval('webhook_delivery_ids'),
+        rule_id BIGINT NOT NULL,
+        timestamp TIMESTAMP NOT NULL,
+        attempt INTEGER NOT NULL,
+        attempted_at TIMESTAMP NOT NULL,
+        status INTEGER,
+        error VARCHAR,
+        delivered BOOLEAN NOT NULL
+    );
+    CREATE INDEX webhook_deliveries_rule ON webhook_deliveries (rule_id);",
+];
+
+pub fn migrate(conn: &Connection) -> anyhow::Result<()> {
+    conn.execute_batch("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL);")?;
+    let applied: i64 = conn.query_row(
+        "SELECT coalesce(max(version), 0) FROM schema_version",
+        [],
+        |row| row.get(0),
+    )?;
+    for (version, sql) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        let version = version + 1;
+        conn.execute_batch(&format!(
+            "BEGIN TRANSACTION;
+             {sql}
+             INSERT INTO schema_version VALUES ({version});
+             COMMIT;"
+        ))
+        .with_context(|| format!("apply schema migration {version}"))?;
+        tracing::info!("applied schema migration {version}");
+    }
+    Ok(())
+}
+
+/// Creates the sparse `events` overlay table and loads it from `csv_path`
+/// on first run. The file is optional; without it the table stays empty.
+pub fn initialize_events(
+    conn: &Connection,
+    csv_path: &Path,
+    mode: CsvMode,
+) -> anyhow::Result<Option<ImportSummary>> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS events (
+            timestamp TIMESTAMP,
+            type VARCHAR,
+            label VARCHAR
+        );",
+    )?;
+
+    let existing: i64 = conn.query_row("SELECT COUNT(*) FROM events", [], |row| row.get(0))?;
+    if existing == 0 && csv_path.exists() {
+        return load_csv(conn, "events", csv_path, mode).map(Some);
+    }
+    Ok(None)
+}
+
+/// Creates the `symbol_candles` table, which holds candles for any number of
+/// named symbols alongside the main series, and loads it from `csv_path`
+/// (`symbol,timestamp,open,high,low,close,volume`) on first run. The file is
+/// optional; without it the table stays empty.
+pub fn initialize_symbols(
+    conn: &Connection,
+    csv_path: &Path,
+    mode: CsvMode,
+) -> anyhow::Result<Option<ImportSummary>> {
+    create_symbol_candles(conn)?;
+    let existing: i64 =
+        conn.query_row("SELECT COUNT(*) FROM symbol_candles", [], |row| row.get(0))?;
+    if existing == 0 && csv_path.exists() {
+        return load_csv(conn, "symbol_candles", csv_path, mode).map(Some);
+    }
+    Ok(None)
+}
+
+fn create_symbol_candles(conn: &Connection) -> duckdb::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS symbol_candles (
+            symbol VARCHAR NOT NULL,
+            timestamp TIMESTAMP NOT NULL,
+            open DOUBLE,
+            high DOUBLE,
+            low DOUBLE,
+            close DOUBLE,
+            volume DOUBLE
+        );
+        CREATE INDEX IF NOT EXISTS symbol_candles_symbol_timestamp
+            ON symbol_candles (symbol, timestamp);",
+    )
+}
+
+/// Creates the candles tables and applies the migrations, loading nothing.
+pub fn create_schema(conn: &Connection) -> anyhow::Result<()> {
+    create_candles(conn)?;
+    create_symbol_candles(conn)?;
+    migrate(conn)
+}
+
+/// How [`ingest_csv`] treats the rows a table already holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IngestMode {
+    /// Keep them, and add only the file's rows at keys not stored yet, so a
+    /// file can be ingested again without doubling it.
+    Incremental,
+    /// Delete them all and store the file's rows instead.
+    Replace,
+}
+
+/// What [`ingest_csv`] did to a table.
+#[derive(Debug, Serialize)]
+pub struct IngestSummary {
+    pub table: &'static str,
+    pub inserted: usize,
+    /// Rows at keys already stored, left as they were.
+    pub skipped: usize,
+    /// Rows deleted to make way for the file's.
+    pub removed: usize,
+    pub rejected: usize,
+    /// The first few malformed rows, in file order.
+    pub sample_errors: Vec<RejectedRow>,
+}
+
+/// Stores a headed candles CSV: in `symbol_candles` when its first column
+/// is `symbol`, otherwise in the main table, whose quote columns it may
+/// add as the startup load does. The file is parsed through [`load_csv`]
+/// into a staging table and then stored in one transaction, so a strict
+/// load that fails, or a replacement, leaves no half-written table.
+pub fn ingest_csv(
+    conn: &Connection,
+    csv_path: &Path,
+    mode: IngestMode,
+    csv_mode: CsvMode,
+) -> anyhow::Result<IngestSummary> {
+    let table = ingest_table(csv_path)?;
+    let key = match table {
+        "symbol_candles" => "t.symbol = s.symbol AND t.timestamp = s.timestamp",
+        _ => {
+            add_quote_columns(conn, csv_path)?;
+            "t.timestamp = s.timestamp"
+        }
+    };
+    conn.execute_batch(&format!(
+        "CREATE OR REPLACE TEMP TABLE ingest_staging AS SELECT * FROM {table} LIMIT 0;"
+    ))?;
+    let loaded = load_csv(conn, "ingest_staging", csv_path, csv_mode);
+    let stored = loaded.and_then(|loaded| {
+        let tx = conn.unchecked_transaction()?;
+        let removed = match mode {
+            IngestMode::Replace => tx.execute(&format!("DELETE FROM {table}"), [])?,
+            IngestMode::Incremental => 0,
+        };
+        let inserted = tx.execute(
+            &format!(
+                "INSERT INTO {table} SELECT * FROM ingest_staging s
+                 WHERE NOT EXISTS (SELECT 1 FROM {table} t WHERE {key})"
+            ),
+            [],
+        )?;
+        tx.commit()?;
+        Ok(IngestSummary {
+            table,
+            inserted,
+            skipped: loaded.inserted - inserted,
+            removed,
+            rejected: loaded.rejected,
+            sample_errors: loaded.sample_errors,
+        })
+    });
+    conn.execute_batch("DROP TABLE IF EXISTS temp.ingest_staging;")?;
+    stored
+}
+
+/// The table [`ingest_csv`] stores `csv_path` in.
+pub fn ingest_table(csv_path: &Path) -> anyhow::Result<&'static str> {
+    let mut header = String::new();
+    BufReader::new(File::open(csv_path).with_context(|| format!("open {}", csv_path.display()))?)
+        .read_line(&mut header)?;
+    let first = header.split(',').next().unwrap_or_default();
+    Ok(
+        match first
+            .trim()
+            .trim_matches('"')
+            .eq_ignore_ascii_case("symbol")
+        {
+            true => "symbol_candles",
+            false => "candles",
+        },
+    )
+}
+
+/// What [`export_candles`] writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// With a header row.
+    Csv,
+    Parquet,
+}
+
+/// Writes every row of the main candles table, oldest first, to `path` and
+/// says how many there were.
+pub fn export_candles(
+    conn: &Connection,
+    path: &Path,
+    format: ExportFormat,
+) -> anyhow::Result<usize> {
+    let path = path.to_str().context("export path not valid UTF-8")?;
+    let options = match format {
+        ExportFormat::Csv => "FORMAT csv, HEADER",
+        ExportFormat::Parquet => "FORMAT parquet",
+    };
+    let rows = conn
+        .execute(
+            &format!(
+                "COPY (SELECT * FROM candles ORDER BY timestamp, rowid) TO {} ({options})",
+                sql_string(path)
+            ),
+            [],
+        )
+        .with_context(|| format!("export the candles to {path}"))?;
+    Ok(rows)
+}
+
+/// Rows quoted in an [`ImportSummary`] and in the log.
+const SAMPLE_ERRORS: usize = 5;
+
+/// What a CSV load inserted and what it skipped.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub inserted: usize,
+    pub rejected: usize,
+    /// The first few problems, in file order.
+    pub sample_errors: Vec<RejectedRow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RejectedRow {
+    pub line: u64,
+    pub column: Option<String>,
+    pub error: String,
+    /// The row as it appears in the file; DuckDB's line numbers are not
+    /// always exact for timestamp columns.
+    pub row: String,
+}
+
+/// Appends a headed CSV to `table`, matching columns by position and casting
+/// each to the table's type. The path is bound as a parameter, never spliced
+/// into the SQL, so quotes and other characters in it are harmless;
+/// file-based loaders should go through here.
+///
+/// Rows DuckDB cannot parse are collected with `store_rejects` rather than
+/// aborting the load: they are skipped and logged under
+/// [`CsvMode::Lenient`], and fail the whole load, naming the lines, under
+/// [`CsvMode::Strict`].
+fn load_csv(
+    conn: &Connection,
+    table: &'static str,
+    csv_path: &Path,
+    mode: CsvMode,
+) -> anyhow::Result<ImportSummary> {
+    let path = csv_path
+        .to_str()
+        .with_context(|| format!("{table} CSV path not valid UTF-8"))?;
+    let columns = column_types(conn, table)?;
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(DROP_REJECTS)?;
+    let inserted = tx
+        .execute(
+            &format!(
+                "INSERT INTO {table} SELECT * FROM read_csv(?, header = true, \
+                 columns = {columns}, store_rejects = true)"
+            ),
+            [path],
+        )
+        .with_context(|| format!("load {} into {table}", csv_path.display()))?;
+    let summary = ImportSummary {
+        inserted,
+        ..rejected_rows(&tx)?
+    };
+    tx.execute_batch(DROP_REJECTS)?;
+
+    if summary.rejected > 0 {
+        let detail = summary
+            .sample_errors
+            .iter()
+            .map(|row| format!("line {}: {} ({})", row.line, row.error, row.row))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if mode == CsvMode::Strict {
+            anyhow::bail!(
+                "{} has {} malformed rows, so nothing was loaded into {table}: {detail}",
+                csv_path.display(),
+                summary.rejected
+            );
+        }
+        tracing::warn!(
+            "skipped {} malformed rows in {}: {detail}",
+            summary.rejected,
+            csv_path.display()
+        );
+    }
+    tx.commit()?;
+    tracing::info!(
+        "loaded {inserted} rows into {table} from {}",
+        csv_path.display()
+    );
+    Ok(summary)
+}
+
+/// `store_rejects` writes to these session tables and refuses to start
+/// while they exist.
+const DROP_REJECTS: &str =
+    "DROP TABLE IF EXISTS temp.reject_errors; DROP TABLE IF EXISTS temp.reject_scans;";
+
+/// `table`'s columns as a `read_csv` `columns` struct, e.g.
+/// `{'timestamp': 'TIMESTAMP', ...}`.
+fn column_types(conn: &Connection, table: &str) -> duckdb::Result<String> {
+    let columns = conn
+        .prepare(
+            "SELECT column_name, data_type FROM information_schema.columns
+             WHERE table_name = ? ORDER BY ordinal_position",
+        )?
+        .query_map([table], |row| {
+            let (name, data_type): (String, String) = (row.get(0)?, row.get(1)?);
+            Ok(format!("{}: {}", sql_string(&name), sql_string(&data_type)))
+        })?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+    Ok(format!("{{{}}}", columns.join(", ")))
+}
+
+/// The rows the last `store_rejects` load skipped. A row with several bad
+/// fields is reported once per field but counted once.
+fn rejected_rows(conn: &Connection) -> duckdb::Result<ImportSummary> {
+    let rejected: i64 = conn.query_row(
+        "SELECT count(DISTINCT line) FROM temp.reject_errors",
+        [],
+        |row| row.get(0),
+    )?;
+    let sample_errors = conn
+        .prepare(
+            "SELECT line, column_name, error_message, csv_line FROM temp.reject_errors
+             ORDER BY line, column_idx LIMIT ?",
+        )?
+        .query_map([SAMPLE_ERRORS as i64], |row| {
+            Ok(RejectedRow {
+                line: row.get(0)?,
+                column: row.get(1)?,
+                error: row.get(2)?,
+                row: row.get(3)?,
+            })
+        })?
+        .collect::<duckdb::Result<_>>()?;
+    Ok(ImportSummary {
+        inserted: 0,
+        rejected: rejected as usize,
+        sample_errors,
+    })
+}
+
+/// Tables whose rows should be unique per key: the table, its key columns
+/// and the symbol column (`NULL` for the single-series table).
+const UNIQUE_KEYS: [(&str, &str, &str); 2] = [
+    ("candles", "timestamp", "NULL"),
+    ("symbol_candles", "symbol, timestamp", "symbol"),
+];
+
+/// Keys stored more than once, per table.
+#[derive(Debug, Serialize)]
+pub struct IntegrityReport {
+    pub tables: Vec<TableIntegrity>,
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.tables.iter().all(|table| table.duplicate_keys == 0)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableIntegrity {
+    pub table: &'static str,
+    /// Timestamps (or symbol and timestamp pairs) with more than one row.
+    pub duplicate_keys: i64,
+    /// Rows a repair would delete: all but one per duplicated key.
+    pub extra_rows: i64,
+    /// The earliest few duplicated keys.
+    pub samples: Vec<DuplicateKey>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DuplicateKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    pub timestamp: Timestamp,
+    pub rows: i64,
+}
+
+/// Finds duplicated keys in the candle tables that exist. Loads before ingest
+/// deduplicated could store a timestamp twice, which repeats points in every
+/// series computed from it.
+///
+/// The statements are not cached: DuckDB 0.10 plans string grouping from the
+/// column statistics at prepare time, and a plan made while `symbol_candles`
+/// was empty trips over the symbols stored since.
+pub fn integrity_report(conn: &Connection) -> duckdb::Result<IntegrityReport> {
+    let mut tables = Vec::new();
+    for (table, key, symbol) in UNIQUE_KEYS {
+        let exists: bool = conn
+            .prepare("SELECT count(*) > 0 FROM duckdb_tables() WHERE table_name = ?")?
+            .query_row([table], |row| row.get(0))?;
+        if !exists {
+            continue;
+        }
+        let duplicates = format!(
+            "SELECT {symbol}, timestamp, count(*) AS rows FROM {table}
+             GROUP BY {key} HAVING count(*) > 1"
+        );
+        let (duplicate_keys, extra_rows): (i64, i64) = conn
+            .prepare(&format!(
+                "SELECT count(*), coalesce(sum(rows - 1), 0) FROM ({duplicates})"
+            ))?
+            .query_row([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let samples = conn
+            .prepare(&format!("{duplicates} ORDER BY timestamp, 1 LIMIT ?"))?
+            .query_map([SAMPLE_ERRORS as i64], |row| {
+                Ok(DuplicateKey {
+                    symbol: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    rows: row.get(2)?,
+                })
+            })?
+            .collect::<duckdb::Result<_>>()?;
+        tables.push(TableIntegrity {
+            table,
+            duplicate_keys,
+            extra_rows,
+            samples,
+        });
+    }
+    Ok(IntegrityReport { tables })
+}
+
+/// Deletes all but the last-ingested row of each duplicated key and returns
+/// how many rows went.
+pub fn repair_duplicates(conn: &Connection) -> duckdb::Result<usize> {
+    let report = integrity_report(conn)?;
+    let tx = conn.unchecked_transaction()?;
+    let mut removed = 0;
+    for (table, key, _) in UNIQUE_KEYS {
+        let duplicated = report
+            .tables
+            .iter()
+            .any(|found| found.table == table && found.duplicate_keys > 0);
+        if duplicated {
+            removed += tx.execute(
+                &format!(
+                    "DELETE FROM {table}
+                     WHERE rowid NOT IN (SELECT max(rowid) FROM {table} GROUP BY {key})"
+                ),
+                [],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// The startup check: logs any duplicated keys, and with `repair` removes
+/// them first.
+pub fn check_integrity(conn: &Connection, repair: bool) -> duckdb::Result<IntegrityReport> {
+    let mut report = integrity_report(conn)?;
+    if !report.is_clean() && repair {
+        let removed = repair_duplicates(conn)?;
+        tracing::warn!(
+            "removed {removed} duplicate candle rows, keeping the last-ingested of each"
+        );
+        report = integrity_report(conn)?;
+    }
+    for table in report
+        .tables
+        .iter()
+        .filter(|table| table.duplicate_keys > 0)
+    {
+        let samples = table
+            .samples
+            .iter()
+            .map(|sample| sample.timestamp.to_string())
+            .collect::<Vec<_>>();
+        tracing::warn!(
+            "{} has {} duplicated timestamps ({} extra rows), e.g. {}; set \
+             GRAPH_REPAIR_DUPLICATES=true or POST /api/admin/integrity to keep \
+             the last-ingested row of each",
+            table.table,
+            table.duplicate_keys,
+            table.extra_rows,
+            samples.join(", ")
+        );
+    }
+    Ok(report)
+}
+
+/// A single-quoted SQL string literal, for statements such as `SET` that
+/// take no parameters.
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_support::*;
+
+    #[tokio::test]
+    async fn readers_do_not_serialize() {
+        let db = memory_db(2);
+        let (release, held) = hold_reader(&db).await;
+        let one: i64 = tokio::time::timeout(
+            Duration::from_millis(500),
+            db.read(|conn| conn.query_row("SELECT 1", [], |row| row.get(0)).unwrap()),
+        )
+        .await
+        .expect("second reader should not wait for the first");
+        assert_eq!(one, 1);
+        release.send(()).unwrap();
+        held.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pool_blocks_when_exhausted_and_recycles() {
+        let db = memory_db(1);
+        let (release, held) = hold_reader(&db).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), db.read(|_| ()))
+                .await
+                .is_err()
+        );
+        release.send(()).unwrap();
+        held.await.unwrap();
+        tokio::time::timeout(Duration::from_millis(500), db.read(|_| ()))
+            .await
+            .expect("reader returned to the pool after use");
+    }
+
+    #[tokio::test]
+    async fn writes_are_visible_to_readers() {
+        let db = memory_db(2);
+        db.write(|conn| {
+            conn.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (42);")
+                .unwrap()
+        })
+        .await;
+        let x: i32 = db
+            .read(|conn| {
+                conn.query_row("SELECT x FROM t", [], |row| row.get(0))
+                    .unwrap()
+            })
+            .await;
+        assert_eq!(x, 42);
+    }
+
+    #[tokio::test]
+    async fn candle_reads_stay_responsive_during_a_bulk_ingest() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = crate::build_router(state.clone());
+        let (started_tx, started) = tokio::sync::oneshot::channel();
+        let (release, parked) = std::sync::mpsc::channel::<()>();
+        let ingest = tokio::spawn({
+            let db = Arc::clone(&state.db);
+            async move {
+                db.write(move |conn| {
+                    let tx = conn.unchecked_transaction()?;
+                    tx.execute_batch(
+                        "INSERT INTO candles
+                         SELECT TIMESTAMP '2024-01-02' + INTERVAL (i) MINUTE, 1, 1, 1, 1, 1
+                         FROM range(200000) AS t(i)",
+                    )?;
+                    started_tx.send(()).unwrap();
+                    parked.recv().ok();
+                    tx.commit()
+                })
+                .await
+            }
+        });
+        started.await.unwrap();
+
+        for _ in 0..20 {
+            let candles = tokio::time::timeout(
+                Duration::from_millis(500),
+                get_json(&app, "/api/candles?limit=10"),
+            )
+            .await
+            .expect("reads should not wait for the writer");
+            // The uncommitted ingest is invisible until it commits.
+            assert_eq!(candles.as_array().unwrap().len(), 1);
+        }
+        release.send(()).unwrap();
+        ingest.await.unwrap().unwrap();
+        let candles = get_json(&app, "/api/candles?limit=10").await;
+        assert_eq!(candles.as_array().unwrap().len(), 10);
+    }
+
+    #[test]
+    fn csv_paths_with_quotes_load() {
+        let dir = std::env::temp_dir().join(format!("graph-o'brien's data-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+        let candles = csv(
+            "stocks.csv",
+            "timestamp,open,high,low,close,volume\n2024-01-01 00:00:00,1,2,0.5,1.5,100\n",
+        );
+        let events = csv(
+            "events.csv",
+            "timestamp,type,label\n2024-01-01 00:00:00,news,earnings\n",
+        );
+        let symbols = csv(
+            "symbols.csv",
+            "symbol,timestamp,open,high,low,close,volume\nAAA,2024-01-01 00:00:00,1,1,1,1,1\n",
+        );
+
+        let conn = Connection::open_in_memory().unwrap();
+        initialize_db(&conn, &candles, CsvMode::Lenient).unwrap();
+        initialize_events(&conn, &events, CsvMode::Lenient).unwrap();
+        initialize_symbols(&conn, &symbols, CsvMode::Lenient).unwrap();
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT count(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!(count("candles"), 1);
+        assert_eq!(count("events"), 1);
+        assert_eq!(count("symbol_candles"), 1);
+        let close: f64 = conn
+            .query_row("SELECT close FROM candles", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(close, 1.5);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn malformed_csv_rows_are_skipped_or_fail_the_load() {
+        let path = std::env::temp_dir().join(format!("graph-messy-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "timestamp,open,high,low,close,volume\n\
+             2024-01-01 00:00:00,1,2,0.5,1.5,100\n\
+             2024-01-01 00:01:00,abc,2,0.5,1.5,100\n\
+             2024-01-01 00:02:00,1,2,0.5,1.5,100\n\
+             2024-01-01 00:03:00,1,2,0.5,1.5\n\
+             2024-01-01 00:04:00,1,2,0.5,1.5,100\n",
+        )
+        .unwrap();
+        let count = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT count(*) FROM candles", [], |row| row.get(0))
+                .unwrap()
+        };
+
+        let conn = Connection::open_in_memory().unwrap();
+        let summary = initialize_db(&conn, &path, CsvMode::Lenient)
+            .unwrap()
+            .unwrap();
+        assert_eq!((summary.inserted, summary.rejected), (3, 2));
+        assert_eq!(count(&conn), 3);
+        let [open, short] = &summary.sample_errors[..] else {
+            panic!("{:?}", summary.sample_errors);
+        };
+        assert_eq!((open.line, open.column.as_deref()), (3, Some("open")));
+        assert!(open.error.contains("\"abc\""), "{}", open.error);
+        assert_eq!(open.row, "2024-01-01 00:01:00,abc,2,0.5,1.5,100");
+        assert_eq!(short.line, 5);
+        // Loading another file starts from a clean slate of rejects.
+        let events = initialize_events(&conn, Path::new("data/events.csv"), CsvMode::Lenient)
+            .unwrap()
+            .unwrap();
+        assert_eq!(events.rejected, 0);
+
+        let conn = Connection::open_in_memory().unwrap();
+        let err = initialize_db(&conn, &path, CsvMode::Strict).unwrap_err();
+        let message = format!("{err:#}");
+        assert!(message.contains("2 malformed rows"), "{message}");
+        assert!(message.contains("line 3:"), "{message}");
+        assert_eq!(count(&conn), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ingest_adds_new_keys_or_replaces_the_table() {
+        let dir = std::env::temp_dir().join(format!("graph-ingest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, second, symbols) = (
+            dir.join("first.csv"),
+            dir.join("second.csv"),
+            dir.join("symbols.csv"),
+        );
+        std::fs::write(
+            &first,
+            "timestamp,open,high,low,close,volume\n\
+             2024-01-01 00:00:00,1,2,0.5,1.5,100\n\
+             2024-01-01 00:01:00,1,2,0.5,1.5,100\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "timestamp,open,high,low,close,volume\n\
+             2024-01-01 00:01:00,9,9,9,9,9\n\
+             2024-01-01 00:02:00,1,2,0.5,1.5,100\n\
+             2024-01-01 00:03:00,x,2,0.5,1.5,100\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &symbols,
+            "symbol,timestamp,open,high,low,close,volume\n\
+             ETH,2024-01-01 00:00:00,1,2,0.5,1.5,100\n",
+        )
+        .unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        create_schema(&conn).unwrap();
+        let ingest = |path, mode| ingest_csv(&conn, path, mode, CsvMode::Lenient).unwrap();
+
+        let summary = ingest(&first, IngestMode::Incremental);
+        assert_eq!((summary.table, summary.inserted), ("candles", 2));
+        let summary = ingest(&second, IngestMode::Incremental);
+        assert_eq!(
+            (summary.inserted, summary.skipped, summary.rejected),
+            (1, 1, 1)
+        );
+        let close: f64 = conn
+            .query_row(
+                "SELECT close FROM candles WHERE timestamp = '2024-01-01 00:01:00'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(close, 1.5);
+        let summary = ingest(&second, IngestMode::Replace);
+        assert_eq!((summary.removed, summary.inserted), (3, 2));
+        let summary = ingest(&symbols, IngestMode::Incremental);
+        assert_eq!((summary.table, summary.inserted), ("symbol_candles", 1));
+
+        let err = ingest_csv(&conn, &second, IngestMode::Replace, CsvMode::Strict).unwrap_err();
+        assert!(format!("{err:#}").contains("malformed"), "{err:#}");
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM candles", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2, "a failed replacement leaves the table alone");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exports_read_back_as_the_stored_candles() {
+        let dir = std::env::temp_dir().join(format!("graph-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let csv = dir.join("input.csv");
+        std::fs::write(
+            &csv,
+            "timestamp,open,high,low,close,volume\n\
+             2024-01-01 00:01:00,1,2,0.5,1.25,100\n\
+             2024-01-01 00:00:00,1,2,0.5,1.5,100\n",
+        )
+        .unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        initialize_db(&conn, &csv, CsvMode::Lenient).unwrap();
+        let stored: i64 = conn
+            .query_row("SELECT count(*) FROM candles", [], |row| row.get(0))
+            .unwrap();
+        for (format, name, reader) in [
+            (ExportFormat::Csv, "candles.csv", "read_csv_auto"),
+            (ExportFormat::Parquet, "candles.parquet", "read_parquet"),
+        ] {
+            let path = dir.join(name);
+            assert_eq!(
+                export_candles(&conn, &path, format).unwrap(),
+                stored as usize
+            );
+            let differing: i64 = conn
+                .query_row(
+                    &format!(
+                        "SELECT count(*) FROM (
+                             SELECT timestamp, close FROM {reader}({path})
+                             EXCEPT SELECT timestamp, close FROM candles)",
+                        path = sql_string(path.to_str().unwrap())
+                    ),
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(differing, 0, "{name}");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn repairs_keep_the_last_ingested_row_per_key() {
+        let conn = Connection::open_in_memory().unwrap();
+        initialize_db(&conn, Path::new("/nonexistent.csv"), CsvMode::Lenient).unwrap();
+        initialize_symbols(&conn, Path::new("/nonexistent.csv"), CsvMode::Lenient).unwrap();
+        assert!(check_integrity(&conn, false).unwrap().is_clean());
+        conn.execute_batch(
+            "INSERT INTO candles VALUES
+                ('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+                ('2024-01-01 00:00:00', 2, 2, 2, 2, 2);
+             INSERT INTO symbol_candles VALUES
+                ('AAA', '2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+                ('BBB', '2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+                ('AAA', '2024-01-01 00:00:00', 3, 3, 3, 3, 3);",
+        )
+        .unwrap();
+
+        let report = check_integrity(&conn, false).unwrap();
+        let [candles, symbols] = &report.tables[..] else {
+            panic!("{report:?}");
+        };
+        assert_eq!((candles.duplicate_keys, candles.extra_rows), (1, 1));
+        assert_eq!(symbols.samples[0].symbol.as_deref(), Some("AAA"));
+        assert_eq!(symbols.samples[0].rows, 2);
+
+        assert!(check_integrity(&conn, true).unwrap().is_clean());
+        let opens = conn
+            .prepare(
+                "SELECT open FROM candles UNION ALL SELECT open FROM symbol_candles ORDER BY 1",
+            )
+            .unwrap()
+            .query_map([], |row| row.get::<_, f64>(0))
+            .unwrap()
+            .collect::<duckdb::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(opens, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn migrations_apply_once() {
+        let conn = Connection::open_in_memory().unwrap();
+        initialize_db(&conn, Path::new("data/stocks.csv"), CsvMode::Lenient).unwrap();
+        initialize_db(&conn, Path::new("data/stocks.csv"), CsvMode::Lenient).unwrap();
+        let versions: Vec<i64> = conn
+            .prepare("SELECT version FROM schema_version ORDER BY version")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<duckdb::Result<_>>()
+            .unwrap();
+        assert_eq!(versions, (1..=MIGRATIONS.len() as i64).collect::<Vec<_>>());
+    }
+}
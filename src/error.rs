@@ -0,0 +1,359 @@
+//! The error type handlers return, the status each kind is answered with,
+//! and the JSON shape every API error takes on the way out.
+//!
+//! Handlers, extractor rejections and route fallbacks all produce plain-text
+//! error responses; [`json_errors`] wraps them as
+//! `{"error": {"code", "message", "request_id"}}` in one place, so errors
+//! from axum itself look the same as ours. Bodies of 500s are replaced with a
+//! generic message after the detail is logged under the request id.
+
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use axum::extract::Request;
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE, WWW_AUTHENTICATE};
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+
+/// A failed request. Client mistakes carry a message meant for the caller;
+/// `Internal` carries detail for the log and reaches clients as a generic 500.
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    /// No credentials, or not the kind the route takes.
+    Unauthorized(String),
+    /// Credentials that were checked and refused.
+    Forbidden(String),
+    NotFound(String),
+    MethodNotAllowed(String),
+    /// A response format the route cannot produce; the message lists the
+    /// ones it can.
+    NotAcceptable(String),
+    Conflict(String),
+    /// Well-formed but not answerable, e.g. too little data under `strict`.
+    Unprocessable(String),
+    /// An answer too big to build, such as an xlsx export over its row cap.
+    TooLarge(String),
+    /// Over a rate limit; the caller adds `Retry-After`.
+    TooManyRequests(String),
+    Internal(String),
+    Unavailable(String),
+    /// The request ran past its time limit.
+    Timeout(String),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::NotAcceptable(_) => StatusCode::NOT_ACCEPTABLE,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::MethodNotAllowed(message)
+            | AppError::NotAcceptable(message)
+            | AppError::Conflict(message)
+            | AppError::Unprocessable(message)
+            | AppError::TooLarge(message)
+            | AppError::TooManyRequests(message)
+            | AppError::Internal(message)
+            | AppError::Unavailable(message)
+            | AppError::Timeout(message) => message,
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let mut response = (self.status(), self.message().to_owned()).into_response();
+        if let AppError::Unauthorized(_) = self {
+            response
+                .headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+/// User-supplied values reach DuckDB as parameters (`start`, `end`, ...),
+/// so a value DuckDB cannot convert or rejects as input is the caller's
+/// mistake; anything else is an engine failure.
+impl From<duckdb::Error> for AppError {
+    fn from(err: duckdb::Error) -> Self {
+        match &err {
+            duckdb::Error::DuckDBFailure(_, Some(message))
+                if message.starts_with("Conversion Error")
+                    || message.starts_with("Invalid Input Error") =>
+            {
+                AppError::BadRequest(message.clone())
+            }
+            _ => AppError::Internal(err.to_string()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(err: anyhow::Error) -> Self {
+        match err.downcast::<duckdb::Error>() {
+            Ok(err) => err.into(),
+            Err(err) => AppError::Internal(format!("{err:#}")),
+        }
+    }
+}
+
+/// For `map_err` on failures that are never the caller's fault.
+pub(crate) fn internal_error(error: impl std::fmt::Display) -> AppError {
+    AppError::Internal(error.to_string())
+}
+
+pub(crate) fn bad_request(message: impl Into<String>) -> AppError {
+    AppError::BadRequest(message.into())
+}
+
+/// A 404 for aggregates over no candles, e.g. on a fresh database without a
+/// CSV or for an empty range. List endpoints answer `[]` instead.
+pub(crate) fn no_data(detail: &str) -> AppError {
+    AppError::NotFound(format!("no data: {detail}"))
+}
+
+/// The answer for unknown paths under `/api`, which never reach the static
+/// file service.
+pub(crate) async fn api_not_found(uri: Uri) -> AppError {
+    AppError::NotFound(format!("no endpoint at {}", uri.path()))
+}
+
+/// `/api` itself and everything below it.
+pub(crate) fn is_api_path(path: &str) -> bool {
+    path.strip_prefix("/api")
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+pub(crate) async fn method_not_allowed(method: Method, uri: Uri) -> AppError {
+    AppError::MethodNotAllowed(format!("{method} is not supported on {}", uri.path()))
+}
+
+pub(crate) const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Error bodies larger than this are cut off rather than buffered whole.
+const MAX_ERROR_BODY: usize = 64 * 1024;
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    code: &'static str,
+    message: &'a str,
+    request_id: &'a str,
+}
+
+/// Tags every response with an `X-Request-Id` (the caller's, if it sent a
+/// usable one) and turns non-JSON error responses under `/api/` into the
+/// structured error body.
+pub(crate) async fn json_errors(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID)
+        .filter(|value| value.len() <= 64)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map_or_else(next_request_id, str::to_owned);
+    let api = is_api_path(request.uri().path());
+    let path = request.uri().path().to_owned();
+    let mut response = next.run(request).await;
+    let header = HeaderValue::from_str(&request_id).expect("request ids are visible ASCII");
+    response.headers_mut().insert(REQUEST_ID, header);
+
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if !api || is_json || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let body = axum::body::to_bytes(body, MAX_ERROR_BODY)
+        .await
+        .unwrap_or_default();
+    let detail = String::from_utf8_lossy(&body);
+    let detail = detail.trim();
+    let message = if status == StatusCode::INTERNAL_SERVER_ERROR {
+        tracing::error!(%request_id, "{path} failed: {detail}");
+        "internal server error"
+    } else {
+        if status.is_server_error() {
+            tracing::warn!(%request_id, "{path} answered {status}: {detail}");
+        }
+        match detail {
+            "" => status.canonical_reason().unwrap_or("error"),
+            detail => detail,
+        }
+    };
+    let body = ErrorBody {
+        error: ErrorDetail {
+            code: error_code(status),
+            message,
+            request_id: &request_id,
+        },
+    };
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.remove(CONTENT_TYPE);
+    let mut response = Json(body).into_response();
+    response.headers_mut().extend(parts.headers);
+    *response.status_mut() = status;
+    response
+}
+
+/// Stable, machine-readable names for the statuses the API answers with.
+fn error_code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::NOT_ACCEPTABLE => "not_acceptable",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNPROCESSABLE_ENTITY => "unprocessable",
+        StatusCode::TOO_MANY_REQUESTS => "too_many_requests",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        StatusCode::GATEWAY_TIMEOUT => "timeout",
+        status if status.is_server_error() => "internal",
+        _ => "error",
+    }
+}
+
+/// A per-process random prefix plus a counter: unique within a run, and
+/// unlikely to repeat across restarts.
+fn next_request_id() -> String {
+    static PREFIX: OnceLock<u32> = OnceLock::new();
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    let prefix = PREFIX.get_or_init(|| RandomState::new().hash_one(std::process::id()) as u32);
+    format!("{prefix:08x}-{:08x}", NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::build_router;
+    use crate::test_support::*;
+
+    async fn error_json(response: Response) -> serde_json::Value {
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["error"].clone()
+    }
+
+    #[tokio::test]
+    async fn api_errors_share_one_json_shape() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = build_router(state.clone());
+
+        let response = get_with(&app, "/api/candles?as_of=soon", &[(REQUEST_ID, "abc-1")]).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[REQUEST_ID], "abc-1");
+        let error = error_json(response).await;
+        assert_eq!(error["code"], "bad_request");
+        assert_eq!(error["request_id"], "abc-1");
+        assert!(error["message"].as_str().unwrap().contains("as_of"));
+
+        // Extractor rejections and fallbacks take the same shape.
+        let error = error_json(get_uri(&app, "/api/candles?limit=-1").await).await;
+        assert_eq!(error["code"], "bad_request");
+        let error = error_json(get_uri(&app, "/api/nope").await).await;
+        assert_eq!(error["code"], "not_found");
+        assert_eq!(error["message"], "no endpoint at /api/nope");
+        let post = Request::post("/api/candles").body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(post).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(error_json(response).await["code"], "method_not_allowed");
+
+        state
+            .db
+            .write(|conn| conn.execute_batch("DROP TABLE candles"))
+            .await
+            .unwrap();
+        let response = get_uri(&app, "/api/fib").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let generated = response.headers()[REQUEST_ID].to_str().unwrap().to_owned();
+        let error = error_json(response).await;
+        assert_eq!(error["code"], "internal");
+        // The DuckDB message names the missing table; clients never see it.
+        assert_eq!(error["message"], "internal server error");
+        assert_eq!(error["request_id"], generated.as_str());
+    }
+
+    #[tokio::test]
+    async fn api_paths_never_reach_the_static_files() {
+        let app = build_router(seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)"));
+        for path in ["/api", "/api/", "/api/candels", "/api/index.html"] {
+            let response = get_uri(&app, path).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "GET {path}");
+            let error = error_json(response).await;
+            assert_eq!(error["message"], format!("no endpoint at {path}"));
+        }
+        for (method, path) in [
+            (Method::DELETE, "/api/candles"),
+            (Method::PUT, "/api/admin/stats"),
+        ] {
+            let request = Request::builder().method(&method).uri(path);
+            let response = app
+                .clone()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{method} {path}"
+            );
+            let error = error_json(response).await;
+            assert_eq!(
+                error["message"],
+                format!("{method} is not supported on {path}")
+            );
+        }
+
+        // Everything else is still the front end.
+        let page = get_uri(&app, "/").await;
+        assert_eq!(page.status(), StatusCode::OK);
+        assert!(page.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        let missing = get_uri(&app, "/apix").await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert!(missing.headers().get(CONTENT_TYPE).is_none());
+        assert!(is_api_path("/api") && is_api_path("/api/x") && !is_api_path("/apix"));
+    }
+}
@@ -0,0 +1,522 @@
+//! The expression language of `/api/formula`: arithmetic over candle columns
+//! and indicator series, evaluated bar by bar.
+//!
+//! A formula is parsed into a small tree and evaluated in Rust; it never
+//! reaches SQL. The grammar knows numbers, the series names of [`Series`],
+//! `+ - * / ^`, parentheses and the functions of [`Function`], and rejects
+//! anything else with the position it stopped at. Length and nesting are
+//! capped so a formula cannot exhaust the stack. A bar where any series it
+//! reads is missing, or where the arithmetic leaves the finite numbers (such
+//! as a division by zero), has no value.
+
+use crate::indicators;
+
+/// Longest formula accepted, in bytes.
+pub const MAX_FORMULA_LEN: usize = 256;
+
+/// Deepest nesting of parentheses, calls and operators.
+const MAX_DEPTH: usize = 32;
+
+/// Longest lookback an indicator series may ask for.
+pub const MAX_PERIOD: usize = 1000;
+
+/// A series a formula can read: a candle column or an indicator over `close`
+/// (ATR over the whole candle) with its period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Series {
+    Open,
+    High,
+    Low,
+    Close,
+    Volume,
+    Sma(usize),
+    Ema(usize),
+    Rsi(usize),
+    Atr(usize),
+    /// Sample standard deviation of `close`, as `/api/stddev` has it.
+    Stddev(usize),
+    Variance(usize),
+}
+
+impl Series {
+    fn parse(name: &str) -> Option<Result<Self, String>> {
+        let column = match name {
+            "open" => Some(Series::Open),
+            "high" => Some(Series::High),
+            "low" => Some(Series::Low),
+            "close" => Some(Series::Close),
+            "volume" => Some(Series::Volume),
+            _ => None,
+        };
+        if let Some(column) = column {
+            return Some(Ok(column));
+        }
+        let (kind, period) = name.split_once('_')?;
+        let series: fn(usize) -> Series = match kind {
+            "sma" => Series::Sma,
+            "ema" => Series::Ema,
+            "rsi" => Series::Rsi,
+            "atr" => Series::Atr,
+            "stddev" => Series::Stddev,
+            "var" => Series::Variance,
+            _ => return None,
+        };
+        Some(match period.parse::<usize>() {
+            Ok(period @ 1..=MAX_PERIOD) => Ok(series(period)),
+            _ => Err(format!(
+                "{name}: the period must be a whole number from 1 to {MAX_PERIOD}"
+            )),
+        })
+    }
+
+    /// The series over `candles`, one value per candle.
+    fn compute(self, candles: &Columns) -> Vec<Option<f64>> {
+        let raw = |values: &[f64]| values.iter().copied().map(Some).collect();
+        match self {
+            Series::Open => raw(&candles.open),
+            Series::High => raw(&candles.high),
+            Series::Low => raw(&candles.low),
+            Series::Close => raw(&candles.close),
+            Series::Volume => raw(&candles.volume),
+            Series::Sma(period) => indicators::simple_moving_average(&candles.close, period),
+            Series::Ema(period) => indicators::exponential_moving_average(&candles.close, period),
+            Series::Rsi(period) => indicators::relative_strength_index(&candles.close, period),
+            Series::Atr(period) => {
+                indicators::average_true_range(&candles.high, &candles.low, &candles.close, period)
+            }
+            Series::Stddev(period) => indicators::rolling_variance(&candles.close, period)
+                .into_iter()
+                .map(|variance| variance.map(f64::sqrt))
+                .collect(),
+            Series::Variance(period) => indicators::rolling_variance(&candles.close, period),
+        }
+    }
+}
+
+/// The candles a formula is evaluated over, column by column, oldest first.
+#[derive(Default)]
+pub struct Columns {
+    pub open: Vec<f64>,
+    pub high: Vec<f64>,
+    pub low: Vec<f64>,
+    pub close: Vec<f64>,
+    pub volume: Vec<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Function {
+    Abs,
+    Sqrt,
+    Ln,
+    Exp,
+    Min,
+    Max,
+}
+
+impl Function {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "abs" => Function::Abs,
+            "sqrt" => Function::Sqrt,
+            "ln" => Function::Ln,
+            "exp" => Function::Exp,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            _ => return None,
+        })
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Min | Function::Max => 2,
+            _ => 1,
+        }
+    }
+
+    fn apply(self, args: &[f64]) -> f64 {
+        match (self, args) {
+            (Function::Abs, [x]) => x.abs(),
+            (Function::Sqrt, [x]) => x.sqrt(),
+            (Function::Ln, [x]) => x.ln(),
+            (Function::Exp, [x]) => x.exp(),
+            (Function::Min, [a, b]) => a.min(*b),
+            (Function::Max, [a, b]) => a.max(*b),
+            _ => f64::NAN,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Number(f64),
+    /// An index into [`Formula::series`].
+    Series(usize),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+/// A parsed formula and the series it reads.
+#[derive(Debug)]
+pub struct Formula {
+    expr: Expr,
+    series: Vec<Series>,
+}
+
+impl Formula {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if text.len() > MAX_FORMULA_LEN {
+            return Err(format!(
+                "formula is {} bytes long; at most {MAX_FORMULA_LEN} are allowed",
+                text.len()
+            ));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            next: 0,
+            depth: 0,
+            series: Vec::new(),
+            end: text.len(),
+        };
+        let expr = parser.expr()?;
+        if let Some((at, token)) = parser.tokens.get(parser.next) {
+            return Err(format!("unexpected {token} at {at}"));
+        }
+        Ok(Self {
+            expr,
+            series: parser.series,
+        })
+    }
+
+    /// The formula's value at every candle.
+    pub fn evaluate(&self, candles: &Columns) -> Vec<Option<f64>> {
+        let series = self
+            .series
+            .iter()
+            .map(|series| series.compute(candles))
+            .collect::<Vec<_>>();
+        (0..candles.close.len())
+            .map(|bar| eval(&self.expr, &series, bar).filter(|value| value.is_finite()))
+            .collect()
+    }
+}
+
+fn eval(expr: &Expr, series: &[Vec<Option<f64>>], bar: usize) -> Option<f64> {
+    Some(match expr {
+        Expr::Number(value) => *value,
+        Expr::Series(index) => series[*index][bar]?,
+        Expr::Neg(operand) => -eval(operand, series, bar)?,
+        Expr::Binary(op, left, right) => {
+            let (left, right) = (eval(left, series, bar)?, eval(right, series, bar)?);
+            match op {
+                '+' => left + right,
+                '-' => left - right,
+                '*' => left * right,
+                '/' => left / right,
+                _ => left.powf(right),
+            }
+        }
+        Expr::Call(function, args) => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, series, bar))
+                .collect::<Option<Vec<_>>>()?;
+            function.apply(&args)
+        }
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    /// An operator, a parenthesis or a comma.
+    Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "number {value}"),
+            Token::Name(name) => write!(f, "{name:?}"),
+            Token::Symbol(symbol) => write!(f, "'{symbol}'"),
+        }
+    }
+}
+
+/// The tokens of `text`, each with its byte offset.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        let mut taken = |accept: fn(char) -> bool| {
+            let mut end = at;
+            while let Some((i, c)) = chars.next_if(|&(_, c)| accept(c)) {
+                end = i + c.len_utf8();
+            }
+            &text[at..end]
+        };
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let literal = taken(|c| c.is_ascii_digit() || c == '.');
+                match literal.parse::<f64>() {
+                    Ok(value) if value.is_finite() => tokens.push((at, Token::Number(value))),
+                    _ => return Err(format!("invalid number {literal:?} at {at}")),
+                }
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let name = taken(|c| c.is_ascii_alphanumeric() || c == '_');
+                tokens.push((at, Token::Name(name.to_ascii_lowercase())));
+            }
+            '+' | '-' | '*' | '/' | '^' | '(' | ')' | ',' => {
+                chars.next();
+                tokens.push((at, Token::Symbol(c)));
+            }
+            c => return Err(format!("unexpected {c:?} at {at}")),
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent, loosest binding first:
+///
+/// ```text
+/// expr  = term (("+" | "-") term)*
+/// term  = unary (("*" | "/") unary)*
+/// unary = "-" unary | power
+/// power = atom ("^" unary)?
+/// atom  = number | series | function "(" expr ("," expr)* ")" | "(" expr ")"
+/// ```
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    depth: usize,
+    series: Vec<Series>,
+    /// Where the text ends, for errors about a formula that stops short.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn take_symbol(&mut self, symbols: &[char]) -> Option<char> {
+        match self.peek() {
+            Some(Token::Symbol(symbol)) if symbols.contains(symbol) => {
+                let symbol = *symbol;
+                self.next += 1;
+                Some(symbol)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        match self.take_symbol(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err(self.unexpected(&format!("'{symbol}'"))),
+        }
+    }
+
+    fn unexpected(&self, wanted: &str) -> String {
+        match self.tokens.get(self.next) {
+            Some((at, token)) => format!("expected {wanted} at {at}, found {token}"),
+            None => format!("expected {wanted} at {}, found the end", self.end),
+        }
+    }
+
+    /// Runs `parse` one level deeper, failing past [`MAX_DEPTH`].
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("formula nests more than {MAX_DEPTH} levels deep"));
+        }
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.nested(|parser| {
+            let mut expr = parser.term()?;
+            while let Some(op) = parser.take_symbol(&['+', '-']) {
+                expr = Expr::Binary(op, Box::new(expr), Box::new(parser.term()?));
+            }
+            Ok(expr)
+        })
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.take_symbol(&['*', '/']) {
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        self.nested(|parser| {
+            if parser.take_symbol(&['-']).is_some() {
+                return Ok(Expr::Neg(Box::new(parser.unary()?)));
+            }
+            let base = parser.atom()?;
+            if parser.take_symbol(&['^']).is_some() {
+                return Ok(Expr::Binary('^', Box::new(base), Box::new(parser.unary()?)));
+            }
+            Ok(base)
+        })
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        let Some((at, token)) = self.tokens.get(self.next).cloned() else {
+            return Err(self.unexpected("a number, series or '('"));
+        };
+        match token {
+            Token::Number(value) => {
+                self.next += 1;
+                Ok(Expr::Number(value))
+            }
+            Token::Symbol('(') => {
+                self.next += 1;
+                let expr = self.expr()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Token::Name(name) => {
+                self.next += 1;
+                if self.take_symbol(&['(']).is_some() {
+                    return self.call(&name, at);
+                }
+                match Series::parse(&name) {
+                    Some(series) => Ok(Expr::Series(self.series_index(series?))),
+                    None => Err(format!("unknown series {name:?} at {at}")),
+                }
+            }
+            Token::Symbol(_) => Err(self.unexpected("a number, series or '('")),
+        }
+    }
+
+    fn call(&mut self, name: &str, at: usize) -> Result<Expr, String> {
+        let Some(function) = Function::parse(name) else {
+            return Err(format!("unknown function {name:?} at {at}"));
+        };
+        let mut args = vec![self.expr()?];
+        while self.take_symbol(&[',']).is_some() {
+            args.push(self.expr()?);
+        }
+        self.expect(')')?;
+        if args.len() != function.arity() {
+            return Err(format!(
+                "{name} takes {} argument(s), not {}",
+                function.arity(),
+                args.len()
+            ));
+        }
+        Ok(Expr::Call(function, args))
+    }
+
+    /// Each distinct series is computed once, however often it appears.
+    fn series_index(&mut self, series: Series) -> usize {
+        self.series
+            .iter()
+            .position(|known| *known == series)
+            .unwrap_or_else(|| {
+                self.series.push(series);
+                self.series.len() - 1
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candles(closes: &[f64]) -> Columns {
+        Columns {
+            open: closes.to_vec(),
+            high: closes.iter().map(|close| close + 1.0).collect(),
+            low: closes.iter().map(|close| close - 1.0).collect(),
+            close: closes.to_vec(),
+            volume: vec![10.0; closes.len()],
+        }
+    }
+
+    fn evaluate(formula: &str, closes: &[f64]) -> Vec<Option<f64>> {
+        Formula::parse(formula).unwrap().evaluate(&candles(closes))
+    }
+
+    #[test]
+    fn operators_follow_the_usual_precedence() {
+        let value = |formula| evaluate(formula, &[1.0])[0];
+        assert_eq!(value("1 + 2 * 3"), Some(7.0));
+        assert_eq!(value("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(value("-2 ^ 2"), Some(-4.0));
+        assert_eq!(value("2 ^ 3 ^ 2"), Some(512.0));
+        assert_eq!(value("10 - 4 - 3"), Some(3.0));
+        assert_eq!(value("max(close, 3) / min(4, abs(-8))"), Some(0.75));
+        assert_eq!(value("sqrt(16) + ln(exp(2))"), Some(6.0));
+        // Leaving the finite numbers leaves the bar empty.
+        assert_eq!(value("close / 0"), None);
+        assert_eq!(value("sqrt(-1)"), None);
+    }
+
+    #[test]
+    fn series_warm_up_and_are_read_once() {
+        let formula = Formula::parse("(close - sma_3) / atr_2 + sma_3 * 0").unwrap();
+        assert_eq!(
+            formula.series,
+            [Series::Close, Series::Sma(3), Series::Atr(2)]
+        );
+        // Highs and lows sit 1 either side of each close.
+        let values = formula.evaluate(&candles(&[1.0, 2.0, 3.0, 5.0]));
+        assert_eq!(
+            values,
+            [None, None, Some(1.0 / 2.0), Some((5.0 - 10.0 / 3.0) / 2.5)]
+        );
+        assert_eq!(
+            evaluate("rsi_2", &[1.0, 2.0, 1.0, 3.0]),
+            [None, None, Some(50.0), Some(100.0 - 100.0 / 3.0)]
+        );
+        assert_eq!(
+            evaluate("ema_1 + volume", &[4.0, 5.0]),
+            [Some(14.0), Some(15.0)]
+        );
+        assert_eq!(
+            evaluate("var_3 - stddev_3 ^ 2", &[1.0, 2.0, 3.0, 5.0]),
+            [None, None, Some(0.0), Some(0.0)]
+        );
+        assert_eq!(
+            evaluate("var_3", &[1.0, 2.0, 3.0, 7.0]),
+            [None, None, Some(1.0), Some(7.0)]
+        );
+        // A sample of one has no spread to speak of.
+        assert_eq!(evaluate("stddev_1", &[1.0, 2.0]), [None, None]);
+    }
+
+    #[test]
+    fn anything_outside_the_grammar_is_rejected() {
+        let error = |formula: &str| Formula::parse(formula).unwrap_err();
+        assert_eq!(
+            error("close +"),
+            "expected a number, series or '(' at 7, found the end"
+        );
+        assert_eq!(error("close; DROP TABLE candles"), "unexpected ';' at 5");
+        assert_eq!(error("read_csv('/etc/passwd')"), "unexpected '\\'' at 9");
+        assert_eq!(error("system(1)"), "unknown function \"system\" at 0");
+        assert_eq!(error("timestamp"), "unknown series \"timestamp\" at 0");
+        assert_eq!(error("close close"), "unexpected \"close\" at 6");
+        assert_eq!(error("min(1)"), "min takes 2 argument(s), not 1");
+        assert!(error("sma_0").contains("from 1 to 1000"));
+        assert!(error("sma_1001").contains("from 1 to 1000"));
+        assert!(error("1.2.3").contains("invalid number"));
+        assert!(error(&"(".repeat(40)).contains("levels deep"));
+        assert!(error(&"1+".repeat(200)).contains("at most 256"));
+    }
+}
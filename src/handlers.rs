@@ -0,0 +1,6599 @@
+//! Route handlers and the query types they accept.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use axum::body::Body;
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{FromRequestParts, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode, Uri};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{FixedOffset, NaiveDateTime, Timelike};
+use duckdb::types::ToSqlOutput;
+use duckdb::{params, params_from_iter, Connection, ToSql};
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, watch};
+
+use crate::auth::KeyUsage;
+use crate::continuous::{self, Adjustment, Bar, Contract};
+use crate::db::{self, Db, IntegrityReport};
+use crate::demo::{self, DemoSpec};
+use crate::error::{api_not_found, bad_request, internal_error, no_data, AppError};
+use crate::formula::{self, Columns, Formula};
+use crate::hub::{latest_candle, Published, Subscription, Update};
+use crate::indicators::{
+    self, IndicatorFeed, IndicatorState, KamaPeriods, PriceSource, RefreshStatus, StcPeriods,
+};
+use crate::lwc;
+use crate::models::{
+    fin_or_null, AdaptiveCandles, AdaptiveMeta, AdxPoint, Candle, CandleRow, CmoPoint,
+    ContinuousSeries, CorrelationPoint, DisplayHints, DpoPoint, Envelope, Event, FibLevel,
+    FibLevels, FibTimeZone, FibTimeZones, FormulaPoint, IndicatorPoint, IndicatorUpdate, KamaPoint,
+    Meta, OverlayPoint, Percentiles, PeriodIndicator, PnfColumn, ProjectedBar, Quantiles,
+    QuoteValues, RollingPricePoint, SpreadPoint, Stamped, StcPoint, StdDevPoint, StreamMessage,
+    SymbolInfo, Timestamp, TimestampFormat, TimestampStyle, VolumeIndicatorPoint, VortexPoint,
+    ZScorePoint, BINARY_HEADER, TIMESTAMP_FORMAT,
+};
+use crate::negotiate::{self, DataResponse, Format, ResponseFormat};
+use crate::pnf;
+use crate::precision;
+use crate::ticks::{Tick, TickReport};
+use crate::timeout::{gateway_timeout, Deadline};
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub(crate) struct CandleQuery {
+    limit: Option<u32>,
+    /// Resample into buckets of this width, e.g. `15m`, `4h`, `1d`.
+    timeframe: Option<String>,
+    /// A moment buckets are aligned to, or a time of day such as `09:30`.
+    origin: Option<String>,
+    open: Option<Aggregation>,
+    high: Option<Aggregation>,
+    low: Option<Aggregation>,
+    close: Option<Aggregation>,
+    volume: Option<Aggregation>,
+    /// Comma-separated extras to attach to each candle: `events`, `bidask`
+    /// or `spread`.
+    include: Option<String>,
+    /// Append this many empty bars after the last candle at future timestamps.
+    project: Option<u32>,
+    /// With `format=lwc`, colour each volume bar by whether its candle rose
+    /// or fell.
+    volume_colors: Option<bool>,
+    /// Only candles at or after this moment.
+    start: Option<String>,
+    /// Only candles at or before this moment.
+    end: Option<String>,
+    /// Only candles at or before this moment, as if it were the current time.
+    as_of: Option<String>,
+    /// `desc` returns the newest candles first, so `limit` keeps the latest N.
+    order: Option<SortOrder>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    fn sql(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+
+    /// How the paging cursor moves past the last row seen.
+    fn after(self) -> &'static str {
+        match self {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct TimestampQuery {
+    ts_format: Option<TimestampStyle>,
+    /// `UTC` or a fixed offset such as `+05:30`.
+    tz: Option<String>,
+}
+
+/// `ts_format` and `tz`, accepted by every endpoint that returns timestamps.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TimestampFormat {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<TimestampQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| bad_request(rejection.body_text()))?;
+        let offset = match query.tz.as_deref() {
+            None | Some("UTC" | "utc" | "Z") => FixedOffset::east_opt(0).expect("zero offset"),
+            Some(tz) => tz.parse().map_err(|_| {
+                bad_request(format!(
+                    "invalid tz {tz:?}; expected UTC or an offset such as +05:30"
+                ))
+            })?,
+        };
+        Ok(TimestampFormat {
+            style: query.ts_format.unwrap_or_default(),
+            offset,
+        })
+    }
+}
+
+/// The encodings `/api/candles` streams row by row.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CandleFormat {
+    Json,
+    Ndjson,
+    Csv,
+    /// Packed little-endian records; see [`BINARY_HEADER`].
+    Bin,
+    /// A page with one table row per bar, for looking at in a browser; at
+    /// most [`MAX_HTML_ROWS`] of them.
+    Html,
+}
+
+/// Rows a `format=html` page shows before saying the rest were left out.
+pub(crate) const MAX_HTML_ROWS: usize = 5000;
+
+impl CandleFormat {
+    /// The streamed encoding of `format`, if it is one.
+    fn streamed(format: Format) -> Option<Self> {
+        match format {
+            Format::Json => Some(CandleFormat::Json),
+            Format::Ndjson => Some(CandleFormat::Ndjson),
+            Format::Csv => Some(CandleFormat::Csv),
+            Format::Bin => Some(CandleFormat::Bin),
+            Format::Html => Some(CandleFormat::Html),
+            Format::Msgpack | Format::Lwc => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            CandleFormat::Json => Format::Json,
+            CandleFormat::Ndjson => Format::Ndjson,
+            CandleFormat::Csv => Format::Csv,
+            CandleFormat::Bin => Format::Bin,
+            CandleFormat::Html => Format::Html,
+        }
+        .content_type()
+    }
+
+    fn begin(self, buf: &mut Vec<u8>) {
+        match self {
+            CandleFormat::Json => buf.push(b'['),
+            CandleFormat::Ndjson => {}
+            CandleFormat::Csv => buf.extend_from_slice(b"timestamp,open,high,low,close,volume\n"),
+            CandleFormat::Bin => buf.extend_from_slice(&BINARY_HEADER),
+            CandleFormat::Html => buf.extend_from_slice(&negotiate::html_head(
+                "Candles",
+                &["timestamp", "open", "high", "low", "close", "volume"],
+            )),
+        }
+    }
+
+    fn row(self, buf: &mut Vec<u8>, index: usize, row: &CandleRow) {
+        match self {
+            CandleFormat::Json => {
+                if index > 0 {
+                    buf.push(b',');
+                }
+                serde_json::to_writer(&mut *buf, row).expect("candle rows always serialize");
+            }
+            CandleFormat::Ndjson => {
+                serde_json::to_writer(&mut *buf, row).expect("candle rows always serialize");
+                buf.push(b'\n');
+            }
+            CandleFormat::Csv => row.write_csv(buf),
+            CandleFormat::Bin => row.write_binary(buf),
+            CandleFormat::Html if index < MAX_HTML_ROWS => row.write_html(buf),
+            // Queries fetch one row more than a page shows, so this one only
+            // says that more were left out.
+            CandleFormat::Html if index == MAX_HTML_ROWS => negotiate::html_notice(buf, 6),
+            CandleFormat::Html => {}
+        }
+    }
+
+    fn end(self, buf: &mut Vec<u8>) {
+        match self {
+            CandleFormat::Json => buf.push(b']'),
+            CandleFormat::Html => buf.extend_from_slice(negotiate::HTML_TAIL),
+            _ => {}
+        }
+    }
+
+    fn encode(self, rows: impl IntoIterator<Item = CandleRow>) -> Response {
+        let mut buf = Vec::new();
+        self.begin(&mut buf);
+        for (index, row) in rows.into_iter().enumerate() {
+            self.row(&mut buf, index, &row);
+        }
+        self.end(&mut buf);
+        self.response(Body::from(buf))
+    }
+
+    fn response(self, body: Body) -> Response {
+        ([(CONTENT_TYPE, self.content_type())], body).into_response()
+    }
+}
+
+/// Upper bound on `project`, so one request cannot ask for an unbounded axis.
+pub(crate) const MAX_PROJECTED_BARS: u32 = 1000;
+
+/// Optional data attached to candles via `include=`.
+#[derive(Default)]
+struct CandleIncludes {
+    events: bool,
+    bidask: bool,
+    spread: bool,
+}
+
+impl CandleIncludes {
+    fn parse(value: Option<&str>) -> Result<Self, String> {
+        let mut includes = Self::default();
+        for name in value.unwrap_or_default().split(',').map(str::trim) {
+            match name {
+                "" => {}
+                "events" => includes.events = true,
+                "bidask" => includes.bidask = true,
+                "spread" => includes.spread = true,
+                other => {
+                    return Err(format!(
+                        "unknown include {other:?}; expected events, bidask or spread"
+                    ))
+                }
+            }
+        }
+        Ok(includes)
+    }
+
+    /// The quote columns asked for that `present` (the candles table's
+    /// [`QUOTE_COLUMNS`](crate::db::QUOTE_COLUMNS)) can supply, as the name
+    /// each is returned under and the SQL computing it from a row. A stored
+    /// spread is used as is; otherwise it is `ask - bid`.
+    fn quotes(&self, present: &[&str]) -> Vec<(&'static str, &'static str)> {
+        let has = |name| present.contains(&name);
+        let mut quotes = Vec::new();
+        if self.bidask {
+            quotes.extend(
+                ["bid", "ask"]
+                    .into_iter()
+                    .filter(|name| has(name))
+                    .map(|name| (name, name)),
+            );
+        }
+        if self.spread {
+            if has("spread") {
+                quotes.push(("spread", "spread"));
+            } else if has("bid") && has("ask") {
+                quotes.push(("spread", "ask - bid"));
+            }
+        }
+        quotes
+    }
+
+    fn wants_quotes(&self) -> bool {
+        self.bidask || self.spread
+    }
+}
+
+/// Whitelisted per-field aggregations for resampling. Each maps to a fixed
+/// SQL aggregate chosen here, so user input never reaches the SQL text.
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Aggregation {
+    First,
+    Last,
+    Min,
+    Max,
+    Mean,
+    Median,
+    Sum,
+}
+
+impl Aggregation {
+    fn sql(self, column: &str) -> String {
+        match self {
+            Aggregation::First => format!("arg_min({column}, timestamp)"),
+            Aggregation::Last => format!("arg_max({column}, timestamp)"),
+            Aggregation::Min => format!("min({column})"),
+            Aggregation::Max => format!("max({column})"),
+            Aggregation::Mean => format!("avg({column})"),
+            Aggregation::Median => format!("median({column})"),
+            Aggregation::Sum => format!("sum({column})"),
+        }
+    }
+}
+
+/// A resampling bucket: fixed-width, such as `30s`, `15m`, `4h` or `1d`, or
+/// a calendar week (`1w`, from Monday) or month (`1M`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Timeframe {
+    count: u32,
+    unit: TimeUnit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TimeUnit {
+    Seconds,
+    Minutes,
+    Hours,
+    Days,
+    Weeks,
+    Months,
+}
+
+impl Timeframe {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        let split = value.find(|c: char| !c.is_ascii_digit())?;
+        let (count, unit) = value.split_at(split);
+        let count: u32 = count.parse().ok().filter(|&count| count > 0)?;
+        let unit = match unit {
+            "s" => TimeUnit::Seconds,
+            "m" => TimeUnit::Minutes,
+            "h" => TimeUnit::Hours,
+            "d" => TimeUnit::Days,
+            "w" if count == 1 => TimeUnit::Weeks,
+            "M" if count == 1 => TimeUnit::Months,
+            _ => return None,
+        };
+        Some(Self { count, unit })
+    }
+
+    /// The `date_trunc` part for calendar timeframes, which bucket by the
+    /// period a candle falls in rather than by fixed-width windows.
+    pub(crate) fn calendar(self) -> Option<&'static str> {
+        match self.unit {
+            TimeUnit::Weeks => Some("week"),
+            TimeUnit::Months => Some("month"),
+            _ => None,
+        }
+    }
+
+    /// DuckDB interval text suitable for binding as `CAST(? AS INTERVAL)`.
+    pub(crate) fn sql_interval(self) -> String {
+        let unit = match self.unit {
+            TimeUnit::Seconds => "seconds",
+            TimeUnit::Minutes => "minutes",
+            TimeUnit::Hours => "hours",
+            TimeUnit::Days => "days",
+            TimeUnit::Weeks => "weeks",
+            TimeUnit::Months => "months",
+        };
+        format!("{} {unit}", self.count)
+    }
+
+    /// The timeframe as it is written in a query, e.g. `15m`.
+    fn label(self) -> String {
+        let suffix = match self.unit {
+            TimeUnit::Seconds => "s",
+            TimeUnit::Minutes => "m",
+            TimeUnit::Hours => "h",
+            TimeUnit::Days => "d",
+            TimeUnit::Weeks => "w",
+            TimeUnit::Months => "M",
+        };
+        format!("{}{suffix}", self.count)
+    }
+
+    /// The bucket width; for months, the shortest one.
+    fn duration(self) -> chrono::Duration {
+        let unit_seconds = match self.unit {
+            TimeUnit::Seconds => 1,
+            TimeUnit::Minutes => 60,
+            TimeUnit::Hours => 60 * 60,
+            TimeUnit::Days => 24 * 60 * 60,
+            TimeUnit::Weeks => 7 * 24 * 60 * 60,
+            TimeUnit::Months => 28 * 24 * 60 * 60,
+        };
+        chrono::Duration::seconds(i64::from(self.count) * unit_seconds)
+    }
+
+    /// The start of the `n`th bucket after the one starting at `from`.
+    pub(crate) fn advance(self, from: NaiveDateTime, n: u32) -> NaiveDateTime {
+        match self.unit {
+            TimeUnit::Months => from + chrono::Months::new(self.count * n),
+            _ => from + self.duration() * n as i32,
+        }
+    }
+}
+
+/// Where buckets start when no `origin` is given: DuckDB's own default, a
+/// Monday midnight, so hourly buckets start on the hour, daily ones at
+/// midnight and `7d` ones on Mondays.
+const DEFAULT_ORIGIN: &str = "2000-01-03 00:00:00";
+
+/// The `origin` parameter: a timestamp, or a time of day (UTC) that shifts
+/// the default origin, e.g. `09:30` for daily bars from a session open.
+pub(crate) fn parse_origin(value: Option<&str>) -> Result<Timestamp, AppError> {
+    let default = NaiveDateTime::parse_from_str(DEFAULT_ORIGIN, TIMESTAMP_FORMAT)
+        .expect("valid default origin");
+    let Some(value) = value else {
+        return Ok(Timestamp::new(default));
+    };
+    let time_of_day = chrono::NaiveTime::parse_from_str(value, "%H:%M:%S%.f")
+        .or_else(|_| chrono::NaiveTime::parse_from_str(value, "%H:%M"));
+    match time_of_day {
+        Ok(time) => Ok(Timestamp::new(default.date().and_time(time))),
+        Err(_) => parse_query_timestamp("origin", value, DayBound::Start)
+            .map(Timestamp::new)
+            .map_err(|_| {
+                bad_request(format!(
+                    "invalid origin {value:?}; expected a time of day such as 09:30 or a timestamp"
+                ))
+            }),
+    }
+}
+
+/// The most common spacing between consecutive stored candles, or `None`
+/// with fewer than two distinct timestamps.
+fn infer_interval(conn: &Connection) -> duckdb::Result<Option<chrono::Duration>> {
+    let step: Option<i64> = conn
+        .prepare_cached(
+            "SELECT mode(step) FROM (
+                SELECT epoch_ms(timestamp) - epoch_ms(lag(timestamp) OVER (ORDER BY timestamp)) AS step
+                FROM candles
+             )
+             WHERE step > 0",
+        )?
+        .query_row([], |row| row.get(0))?;
+    Ok(step.map(chrono::Duration::milliseconds))
+}
+
+/// Refuses a `timeframe` finer than the stored candles' own interval: 1m bars
+/// from 5m data would be mostly empty buckets that chart as gaps.
+async fn check_finer_than_native(state: &AppState, timeframe: Timeframe) -> Result<(), AppError> {
+    match state.db.read(infer_interval).await? {
+        Some(native) if timeframe.duration() < native => Err(bad_request(format!(
+            "timeframe {} is finer than the candles' native interval of {}",
+            timeframe.label(),
+            describe_interval(native)
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// An interval in the largest unit that divides it, e.g. `5m` or `1500ms`.
+fn describe_interval(interval: chrono::Duration) -> String {
+    let millis = interval.num_milliseconds();
+    [
+        ("d", 86_400_000),
+        ("h", 3_600_000),
+        ("m", 60_000),
+        ("s", 1_000),
+    ]
+    .into_iter()
+    .find(|(_, unit)| millis % unit == 0)
+    .map_or_else(
+        || format!("{millis}ms"),
+        |(suffix, unit)| format!("{}{suffix}", millis / unit),
+    )
+}
+
+#[derive(Deserialize)]
+pub(crate) struct IndicatorQuery {
+    /// Wrap the series as `{ data, meta }`, with data-sufficiency warnings.
+    envelope: Option<bool>,
+    /// Reject with 422 instead of warning when there are too few candles.
+    strict: Option<bool>,
+    source: Option<PriceSource>,
+    /// Comma-separated Hull Moving Average periods, each at least 2.
+    hma: Option<String>,
+    /// Comma-separated periods of extra simple moving averages.
+    sma: Option<String>,
+    /// Comma-separated periods of extra exponential moving averages.
+    ema: Option<String>,
+    /// Comma-separated periods of extra RSIs.
+    rsi: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RangeQuery {
+    start: Option<String>,
+    end: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PercentileQuery {
+    field: Option<PercentileField>,
+    start: Option<String>,
+    end: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PercentileField {
+    #[default]
+    Close,
+    Volume,
+}
+
+impl PercentileField {
+    fn column(self) -> &'static str {
+        match self {
+            PercentileField::Close => "close",
+            PercentileField::Volume => "volume",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct OverlayQuery {
+    /// Minutes per time-of-day bucket, dividing a day evenly.
+    bucket: Option<u32>,
+    /// Time of day (UTC) each session opens at, e.g. `17:00` for FX.
+    session_start: Option<String>,
+    start: Option<String>,
+    end: Option<String>,
+}
+
+/// Default `/api/intraday_overlay` bucket, in minutes.
+const OVERLAY_BUCKET: u32 = 30;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+pub(crate) async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// `200` while the database answers, `503` from its first failed watchdog
+/// probe until it does again; the body says how far recovery has got.
+pub(crate) async fn ready(State(state): State<AppState>) -> Response {
+    let readiness = state.health.readiness();
+    let status = match readiness.ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(readiness)).into_response()
+}
+
+/// One page of raw candles within optional bounds, past an optional cursor.
+/// Timestamps may repeat, so the cursor is the last row's timestamp and
+/// `rowid`, which the query selects last.
+fn raw_candles_sql(order: SortOrder) -> String {
+    raw_candles_with_quotes_sql(order, &[])
+}
+
+/// [`raw_candles_sql`] followed by the columns of
+/// [`CandleIncludes::quotes`].
+fn raw_candles_with_quotes_sql(order: SortOrder, quotes: &[(&str, &str)]) -> String {
+    let quotes = quotes
+        .iter()
+        .map(|(_, sql)| format!(", {sql}"))
+        .collect::<String>();
+    format!(
+        "SELECT timestamp, open, high, low, close, volume{quotes}, rowid
+         FROM candles
+         WHERE (? IS NULL OR timestamp >= CAST(? AS TIMESTAMP))
+           AND (? IS NULL OR timestamp <= CAST(? AS TIMESTAMP))
+           AND (? IS NULL OR timestamp {after} CAST(? AS TIMESTAMP)
+                OR (timestamp = CAST(? AS TIMESTAMP) AND rowid {after} ?))
+         ORDER BY timestamp {order}, rowid {order}
+         LIMIT ?",
+        after = order.after(),
+        order = order.sql(),
+    )
+}
+
+/// Rows fetched per query while walking a candle series. DuckDB materializes
+/// each result set in full, so paging is what keeps a long export's memory flat.
+const CANDLE_PAGE_ROWS: i64 = 10_000;
+
+/// A value bound to a placeholder, which `/api/admin/sql` shows as JSON.
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub(crate) enum SqlParam {
+    Text(String),
+    /// `null` for an open bound or a cursor not yet set.
+    Timestamp(Option<Timestamp>),
+    /// The `rowid` of a cursor, `null` before the first page.
+    Row(Option<i64>),
+    Integer(i64),
+}
+
+impl ToSql for SqlParam {
+    fn to_sql(&self) -> duckdb::Result<ToSqlOutput<'_>> {
+        match self {
+            SqlParam::Text(text) => text.to_sql(),
+            SqlParam::Timestamp(timestamp) => timestamp.to_sql(),
+            SqlParam::Row(row) => row.to_sql(),
+            SqlParam::Integer(integer) => integer.to_sql(),
+        }
+    }
+}
+
+/// A candle query, raw or resampled, read page by page with a cursor on the
+/// last row's timestamp and position.
+struct CandleSeries {
+    sql: String,
+    /// Bucket width and origin when resampling to fixed-width buckets.
+    bucket: Option<(String, Timestamp)>,
+    from: Option<Timestamp>,
+    until: Option<Timestamp>,
+    limit: i64,
+    timestamps: TimestampFormat,
+    volume_precision: Option<u32>,
+    /// Names of the quote columns selected after `volume`.
+    quotes: Vec<&'static str>,
+}
+
+impl CandleSeries {
+    /// What a page of rows past `after` binds, in placeholder order.
+    fn params(&self, after: Option<(Timestamp, i64)>, page: i64) -> Vec<SqlParam> {
+        let (from, until) = (self.from, self.until);
+        let bucket = self.bucket.iter().flat_map(|(interval, origin)| {
+            [
+                SqlParam::Text(interval.clone()),
+                SqlParam::Timestamp(Some(*origin)),
+            ]
+        });
+        let cursor = after.map(|(timestamp, _)| timestamp);
+        bucket
+            .chain([from, from, until, until, cursor, cursor, cursor].map(SqlParam::Timestamp))
+            .chain([
+                SqlParam::Row(after.map(|(_, row)| row)),
+                SqlParam::Integer(page),
+            ])
+            .collect()
+    }
+
+    /// Calls `f` with each candle in order until it returns `false`.
+    fn for_each(&self, conn: &Connection, mut f: impl FnMut(Candle) -> bool) -> duckdb::Result<()> {
+        let mut stmt = conn.prepare_cached(&self.sql)?;
+        let mut after = None;
+        let mut remaining = self.limit;
+        while remaining > 0 {
+            let page = remaining.min(CANDLE_PAGE_ROWS);
+            let mut rows = stmt.query(params_from_iter(self.params(after, page)))?;
+            let mut fetched = 0;
+            while let Some(row) = rows.next()? {
+                let mut candle = candle_from_row(row)?;
+                for (i, name) in self.quotes.iter().enumerate() {
+                    candle.quotes.0.push((name, row.get(6 + i)?));
+                }
+                fetched += 1;
+                after = Some((candle.timestamp, row.get(6 + self.quotes.len())?));
+                candle.timestamp.format = self.timestamps;
+                candle.volume = candle.volume.with_precision(self.volume_precision);
+                if !f(candle) {
+                    return Ok(());
+                }
+            }
+            if fetched < page {
+                break;
+            }
+            remaining -= fetched;
+        }
+        Ok(())
+    }
+}
+
+/// A validated `/api/candles` request: the series it reads and what it
+/// does with the rows.
+struct CandleRequest {
+    includes: CandleIncludes,
+    volume_colors: bool,
+    order: SortOrder,
+    project: u32,
+    timeframe: Option<Timeframe>,
+    series: CandleSeries,
+}
+
+async fn candle_request(
+    state: &AppState,
+    query: CandleQuery,
+    timestamps: TimestampFormat,
+    format: Format,
+) -> Result<CandleRequest, AppError> {
+    let mut limit = query.limit.unwrap_or(500) as i64;
+    if format == Format::Html {
+        limit = limit.min(MAX_HTML_ROWS as i64 + 1);
+    }
+    let includes = CandleIncludes::parse(query.include.as_deref()).map_err(bad_request)?;
+    let timeframe = query
+        .timeframe
+        .as_deref()
+        .map(|value| {
+            Timeframe::parse(value).ok_or_else(|| {
+                bad_request(format!(
+                    "invalid timeframe {value:?}; expected a count and unit like 30s, 15m, 4h \
+                     or 1d, or 1w or 1M for calendar weeks and months"
+                ))
+            })
+        })
+        .transpose()?;
+    if timeframe.is_none() && query.origin.is_some() {
+        return Err(bad_request("origin requires a timeframe"));
+    }
+    if timeframe.and_then(Timeframe::calendar).is_some() && query.origin.is_some() {
+        return Err(bad_request(
+            "origin only applies to fixed-width timeframes; 1w and 1M follow the calendar",
+        ));
+    }
+    let origin = parse_origin(query.origin.as_deref())?;
+    let order = query.order.unwrap_or_default();
+    let bound = |name, value: &Option<String>| {
+        value
+            .as_deref()
+            .map(|value| parse_query_timestamp(name, value, DayBound::End).map(Timestamp::new))
+            .transpose()
+    };
+    let (from, end) = parse_range(query.start.as_deref(), query.end.as_deref())?;
+    let until = match (end, bound("as_of", &query.as_of)?) {
+        (Some(end), Some(as_of)) => Some(if end.at < as_of.at { end } else { as_of }),
+        (end, as_of) => end.or(as_of),
+    };
+    let project = query.project.unwrap_or(0).min(MAX_PROJECTED_BARS);
+    if project > 0 && order == SortOrder::Desc {
+        return Err(bad_request("project requires ascending order"));
+    }
+    if format == Format::Lwc && query.include.is_some() {
+        return Err(bad_request(
+            "include is not available with format=lwc, which sends bars and volume only",
+        ));
+    }
+    if matches!(format, Format::Csv | Format::Bin | Format::Html) {
+        for (name, included) in [
+            ("events", includes.events),
+            ("bidask", includes.bidask),
+            ("spread", includes.spread),
+        ] {
+            if included {
+                return Err(bad_request(format!(
+                    "include={name} is only available with format=json, ndjson or msgpack"
+                )));
+            }
+        }
+    }
+    let quotes = if includes.wants_quotes() {
+        includes.quotes(&state.db.read(crate::db::quote_columns).await?)
+    } else {
+        Vec::new()
+    };
+    let quote_names = quotes.iter().map(|(name, _)| *name).collect();
+    let series = match timeframe {
+        None => {
+            if query.open.is_some()
+                || query.high.is_some()
+                || query.low.is_some()
+                || query.close.is_some()
+                || query.volume.is_some()
+            {
+                return Err(bad_request("aggregation overrides require a timeframe"));
+            }
+            CandleSeries {
+                sql: raw_candles_with_quotes_sql(order, &quotes),
+                bucket: None,
+                from,
+                until,
+                limit,
+                timestamps,
+                volume_precision: state.config.volume_precision,
+                quotes: quote_names,
+            }
+        }
+        Some(timeframe) => {
+            let (sql, bucket) = resampled_candles_sql(
+                timeframe,
+                origin,
+                order,
+                [
+                    query.open.unwrap_or(Aggregation::First),
+                    query.high.unwrap_or(Aggregation::Max),
+                    query.low.unwrap_or(Aggregation::Min),
+                    query.close.unwrap_or(Aggregation::Last),
+                    query.volume.unwrap_or(Aggregation::Sum),
+                ],
+                &quotes,
+            );
+            // Resampling reads every candle in the range however few buckets
+            // it returns, so the range is capped; raw series are capped by
+            // `limit` instead.
+            check_range(state, from, until).await?;
+            check_finer_than_native(state, timeframe).await?;
+            CandleSeries {
+                sql,
+                bucket,
+                from,
+                until,
+                limit,
+                timestamps,
+                volume_precision: state.config.volume_precision,
+                quotes: quote_names,
+            }
+        }
+    };
+    Ok(CandleRequest {
+        includes,
+        volume_colors: query.volume_colors.unwrap_or(false),
+        order,
+        project,
+        timeframe,
+        series,
+    })
+}
+
+/// Streamed straight from the cursor unless the format or an extra needs
+/// the whole series, so never through a [`DataResponse`] but for
+/// MessagePack.
+pub(crate) async fn get_candles(
+    State(state): State<AppState>,
+    timestamps: TimestampFormat,
+    deadline: Deadline,
+    negotiated: ResponseFormat,
+    Query(query): Query<CandleQuery>,
+) -> Result<Response, AppError> {
+    let timestamps = negotiated.timestamps(timestamps);
+    let CandleRequest {
+        includes,
+        volume_colors,
+        order,
+        project,
+        timeframe,
+        series,
+    } = candle_request(&state, query, timestamps, negotiated.format).await?;
+    let streamed = CandleFormat::streamed(negotiated.format);
+
+    // Without extras that need the whole series, rows go straight from the
+    // cursor into the body instead of through a Vec<Candle>.
+    if let Some(format) = streamed.filter(|_| !includes.events && project == 0) {
+        let body = state
+            .db
+            .read_stream(deadline.remaining(), move |conn, out| {
+                // Preparing first lets a bad query still become a 500.
+                conn.prepare_cached(&series.sql)?;
+                out.write(|buf| format.begin(buf));
+                out.flush();
+                let mut index = 0;
+                series.for_each(conn, |candle| {
+                    let row = CandleRow::Candle(candle);
+                    let open = out.write(|buf| format.row(buf, index, &row));
+                    index += 1;
+                    open
+                })?;
+                out.write(|buf| format.end(buf));
+                Ok(())
+            })
+            .await?;
+        return Ok(format.response(body));
+    }
+
+    // Checked per row so a timed-out request hands its reader back promptly.
+    let Deadline { at, limit } = deadline;
+    let candles = state
+        .db
+        .read(move |conn| {
+            let mut candles = Vec::new();
+            let mut in_time = true;
+            series.for_each(conn, |candle| {
+                candles.push(candle);
+                in_time = Instant::now() < at;
+                in_time
+            })?;
+            Ok::<_, duckdb::Error>(in_time.then_some(candles))
+        })
+        .await?;
+    let Some(mut candles) = candles else {
+        return Err(gateway_timeout(limit));
+    };
+
+    if includes.events {
+        if order == SortOrder::Desc {
+            candles.reverse();
+        }
+        if let (Some(first), Some(last)) = (candles.first(), candles.last()) {
+            let range = (first.timestamp, last.timestamp);
+            let events = state
+                .db
+                .read(move |conn| {
+                    let mut stmt = conn.prepare_cached(EVENTS_SQL)?;
+                    let events = stmt
+                        .query_map(params![range.0, range.1], event_from_row)?
+                        .collect::<duckdb::Result<Vec<_>>>();
+                    events
+                })
+                .await?;
+            let events = events.into_iter().map(|mut event| {
+                event.timestamp.format = timestamps;
+                event
+            });
+            attach_events(&mut candles, events);
+        }
+        if order == SortOrder::Desc {
+            candles.reverse();
+        }
+    }
+
+    let respond = |rows: Vec<CandleRow>| match (streamed, negotiated.format) {
+        (Some(format), _) => Ok(format.encode(rows)),
+        (None, Format::Lwc) => {
+            let rows = rows
+                .iter()
+                .map(serde_json::to_value)
+                .collect::<Result<_, _>>()
+                .map_err(internal_error)?;
+            Ok(Json(lwc::candle_series(rows, volume_colors)).into_response())
+        }
+        (None, _) => Ok(DataResponse::new(negotiated.clone(), rows).into_response()),
+    };
+    let Some(last) = candles
+        .last()
+        .map(|candle| candle.timestamp.at)
+        .filter(|_| project > 0)
+    else {
+        return respond(candles.into_iter().map(CandleRow::Candle).collect());
+    };
+    let nth_after: Box<dyn Fn(u32) -> NaiveDateTime + Send> = match timeframe {
+        Some(timeframe) => Box::new(move |n| timeframe.advance(last, n)),
+        None => {
+            let Some(step) = state.db.read(infer_interval).await? else {
+                return Err(bad_request(
+                    "cannot project bars without at least two candles to infer the interval",
+                ));
+            };
+            Box::new(move |n| last + step * n as i32)
+        }
+    };
+    let projected = (1..=project).map(|n| {
+        CandleRow::Projected(ProjectedBar::at(
+            Timestamp::new(nth_after(n)).with_format(timestamps),
+        ))
+    });
+    respond(
+        candles
+            .into_iter()
+            .map(CandleRow::Candle)
+            .chain(projected)
+            .collect(),
+    )
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AdaptiveCandleQuery {
+    start: Option<String>,
+    end: Option<String>,
+    /// How long the answer should take; `GRAPH_CANDLE_BUDGET_MS` by default.
+    budget_ms: Option<u64>,
+    /// The most candles to send, however much time there is.
+    max_points: Option<u32>,
+}
+
+/// Default and upper bound of `max_points` for `/api/adaptive_candles`.
+const ADAPTIVE_POINTS: u32 = 2000;
+pub(crate) const MAX_ADAPTIVE_POINTS: u32 = 10_000;
+
+/// Timeframes `/api/adaptive_candles` resamples to, finest first.
+const ADAPTIVE_TIMEFRAMES: &[&str] = &[
+    "1s", "5s", "15s", "30s", "1m", "5m", "15m", "30m", "1h", "2h", "4h", "12h", "1d", "1w", "1M",
+];
+
+/// Candles in a range, raw when there are few enough to send within the
+/// time budget and `max_points`, or else resampled to the finest timeframe
+/// that brings them under both, with `meta` saying which.
+pub(crate) async fn get_adaptive_candles(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<AdaptiveCandleQuery>,
+) -> Result<DataResponse<AdaptiveCandles>, AppError> {
+    let max_points = query.max_points.unwrap_or(ADAPTIVE_POINTS);
+    if !(1..=MAX_ADAPTIVE_POINTS).contains(&max_points) {
+        return Err(bad_request(format!(
+            "max_points must be from 1 to {MAX_ADAPTIVE_POINTS}"
+        )));
+    }
+    let budget = query
+        .budget_ms
+        .map_or(state.config.candle_budget, Duration::from_millis);
+    if budget.is_zero() {
+        return Err(bad_request("budget_ms must be at least 1"));
+    }
+    let (from, until) = parse_range(query.start.as_deref(), query.end.as_deref())?;
+    let affordable = budget.as_secs_f64() * state.config.candle_rows_per_sec as f64;
+    let points = max_points.min(affordable.clamp(1.0, f64::from(u32::MAX)) as u32);
+    Ok(DataResponse::new(
+        format,
+        fit_candles(&state, from, until, points, timestamps).await?,
+    ))
+}
+
+/// The candles between `from` and `until`, raw if there are at most
+/// `points` of them, or else resampled with the default aggregations to the
+/// finest timeframe that brings them under it.
+pub(crate) async fn fit_candles(
+    state: &AppState,
+    from: Option<Timestamp>,
+    until: Option<Timestamp>,
+    points: u32,
+    timestamps: TimestampFormat,
+) -> Result<AdaptiveCandles, AppError> {
+    let (rows, first, last) = state
+        .db
+        .read(move |conn| candle_extent(conn, from, until))
+        .await?;
+    let timeframe = match (first, last) {
+        (Some(first), Some(last)) if rows > u64::from(points) => {
+            let native = state.db.read(infer_interval).await?;
+            Some(coarse_enough(last.at - first.at, native, points))
+        }
+        _ => None,
+    };
+
+    let (sql, bucket) = match timeframe {
+        Some(timeframe) => {
+            check_range(state, from, until).await?;
+            resampled_candles_sql(
+                timeframe,
+                parse_origin(None)?,
+                SortOrder::Asc,
+                [
+                    Aggregation::First,
+                    Aggregation::Max,
+                    Aggregation::Min,
+                    Aggregation::Last,
+                    Aggregation::Sum,
+                ],
+                &[],
+            )
+        }
+        None => (raw_candles_sql(SortOrder::Asc), None),
+    };
+    let series = CandleSeries {
+        sql,
+        bucket,
+        from,
+        until,
+        limit: i64::from(points),
+        timestamps,
+        volume_precision: state.config.volume_precision,
+        quotes: Vec::new(),
+    };
+    let data = state
+        .db
+        .read(move |conn| {
+            let mut candles = Vec::new();
+            series.for_each(conn, |candle| {
+                candles.push(candle);
+                true
+            })?;
+            Ok::<_, duckdb::Error>(candles)
+        })
+        .await?;
+    let meta = AdaptiveMeta {
+        count: data.len(),
+        rows,
+        points,
+        resampled: timeframe.is_some(),
+        timeframe: timeframe.map(Timeframe::label),
+    };
+    Ok(AdaptiveCandles { data, meta })
+}
+
+/// How many candles are stored between `from` and `until`, and the first
+/// and last of their timestamps.
+pub(crate) fn candle_extent(
+    conn: &Connection,
+    from: Option<Timestamp>,
+    until: Option<Timestamp>,
+) -> duckdb::Result<(u64, Option<Timestamp>, Option<Timestamp>)> {
+    conn.prepare_cached(
+        "SELECT count(*), min(timestamp), max(timestamp)
+         FROM candles
+         WHERE (? IS NULL OR timestamp >= CAST(? AS TIMESTAMP))
+           AND (? IS NULL OR timestamp <= CAST(? AS TIMESTAMP))",
+    )?
+    .query_row(params![from, from, until, until], |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })
+}
+
+/// Up to `limit` raw candles between `from` and `until`, oldest first, as
+/// `/api/candles` sends them.
+pub(crate) fn raw_candles(
+    conn: &Connection,
+    from: Option<Timestamp>,
+    until: Option<Timestamp>,
+    limit: i64,
+    volume_precision: Option<u32>,
+) -> duckdb::Result<Vec<Candle>> {
+    let series = CandleSeries {
+        sql: raw_candles_sql(SortOrder::Asc),
+        bucket: None,
+        from,
+        until,
+        limit,
+        timestamps: TimestampFormat::default(),
+        volume_precision,
+        quotes: Vec::new(),
+    };
+    let mut candles = Vec::new();
+    series.for_each(conn, |candle| {
+        candles.push(candle);
+        true
+    })?;
+    Ok(candles)
+}
+
+/// The finest of [`ADAPTIVE_TIMEFRAMES`], no finer than the `native`
+/// interval, that cuts `span` into at most `points` buckets; the coarsest
+/// when none does, whose buckets past `points` are then left out.
+fn coarse_enough(
+    span: chrono::Duration,
+    native: Option<chrono::Duration>,
+    points: u32,
+) -> Timeframe {
+    let timeframes = ADAPTIVE_TIMEFRAMES
+        .iter()
+        .map(|label| Timeframe::parse(label).expect("adaptive timeframes parse"))
+        .filter(|timeframe| native.is_none_or(|native| timeframe.duration() >= native))
+        .collect::<Vec<_>>();
+    let coarsest = *timeframes
+        .last()
+        .expect("no candle interval is longer than a month");
+    timeframes
+        .into_iter()
+        .find(|timeframe| {
+            let buckets = span.num_seconds() / timeframe.duration().num_seconds() + 1;
+            buckets <= i64::from(points)
+        })
+        .unwrap_or(coarsest)
+}
+
+/// The query resampling candles into `timeframe` buckets with the `open`,
+/// `high`, `low`, `close` and `volume` aggregations, in that order, and the
+/// width and origin it binds first for fixed-width buckets. Its remaining
+/// parameters and columns line up with [`raw_candles_sql`]'s; a bucket
+/// never repeats, so the position it selects last is always `0`.
+fn resampled_candles_sql(
+    timeframe: Timeframe,
+    origin: Timestamp,
+    order: SortOrder,
+    [open, high, low, close, volume]: [Aggregation; 5],
+    quotes: &[(&str, &str)],
+) -> (String, Option<(String, Timestamp)>) {
+    // Calendar buckets bind nothing, so their parameters line up with
+    // a raw series'. `date_trunc` gives week and month starts as dates.
+    let (bucket_sql, bucket) = match timeframe.calendar() {
+        Some(period) => (
+            format!("CAST(date_trunc('{period}', timestamp) AS TIMESTAMP)"),
+            None,
+        ),
+        None => (
+            "time_bucket(CAST(? AS INTERVAL), timestamp, CAST(? AS TIMESTAMP))".to_string(),
+            Some((timeframe.sql_interval(), origin)),
+        ),
+    };
+    // A bucket's quotes are those of its last candle, like `close`.
+    let quotes = quotes
+        .iter()
+        .map(|(_, sql)| format!(", arg_max({sql}, timestamp)"))
+        .collect::<String>();
+    let sql = format!(
+        "SELECT
+            bucket, {open}, {high}, {low}, {close}, {volume}{quotes}, 0
+         FROM (
+            SELECT {bucket_sql} AS bucket, *
+            FROM candles
+            WHERE (? IS NULL OR timestamp >= CAST(? AS TIMESTAMP))
+              AND (? IS NULL OR timestamp <= CAST(? AS TIMESTAMP))
+         )
+         WHERE ? IS NULL OR bucket {after} CAST(? AS TIMESTAMP)
+            OR (bucket = CAST(? AS TIMESTAMP) AND 0 {after} ?)
+         GROUP BY bucket
+         ORDER BY bucket {order}
+         LIMIT ?",
+        after = order.after(),
+        order = order.sql(),
+        open = open.sql("open"),
+        high = high.sql("high"),
+        low = low.sql("low"),
+        close = close.sql("close"),
+        volume = volume.sql("volume"),
+    );
+    (sql, bucket)
+}
+
+/// Upper bound on `backfill` for `/api/ws`.
+pub(crate) const MAX_BACKFILL: u32 = 10_000;
+
+#[derive(Deserialize)]
+pub(crate) struct StreamQuery {
+    /// Send the latest this many candles before any live ones.
+    backfill: Option<u32>,
+    /// Replay stored candles from this moment instead of streaming live ones.
+    replay_from: Option<String>,
+    /// End the replay after the candles at this moment.
+    replay_until: Option<String>,
+    /// Replay pace: a multiple of real time, or `max`.
+    speed: Option<String>,
+}
+
+/// Candles a replay reads per query.
+const REPLAY_CHUNK: i64 = 1000;
+
+/// The pause between candles replayed at `speed=max`.
+const MAX_SPEED_DELAY: Duration = Duration::from_millis(1);
+
+/// How fast `/api/ws?replay_from=` plays history back.
+#[derive(Clone, Copy)]
+enum ReplaySpeed {
+    /// A multiple of real time: at 60, an hour of candles takes a minute.
+    Times(f64),
+    Max,
+}
+
+impl ReplaySpeed {
+    fn parse(value: &str) -> Result<Self, AppError> {
+        if value == "max" {
+            return Ok(ReplaySpeed::Max);
+        }
+        value
+            .parse()
+            .ok()
+            .filter(|speed: &f64| speed.is_finite() && *speed > 0.0)
+            .map(ReplaySpeed::Times)
+            .ok_or_else(|| {
+                bad_request(format!(
+                    "invalid speed {value:?}; expected a positive number or max"
+                ))
+            })
+    }
+
+    /// How long to wait before sending a candle stamped `at` when the
+    /// previous one was stamped `previous`.
+    fn delay(self, previous: Option<NaiveDateTime>, at: NaiveDateTime) -> Duration {
+        match (self, previous) {
+            (ReplaySpeed::Max, _) => MAX_SPEED_DELAY,
+            (ReplaySpeed::Times(_), None) => Duration::ZERO,
+            (ReplaySpeed::Times(speed), Some(previous)) => {
+                let gap = (at - previous).to_std().unwrap_or_default();
+                Duration::try_from_secs_f64(gap.as_secs_f64() / speed).unwrap_or(Duration::MAX)
+            }
+        }
+    }
+}
+
+/// What a `/api/ws` client may send.
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum StreamCommand {
+    /// Only replays can be paused.
+    Pause,
+    Resume,
+    Subscribe(IndicatorSubscription),
+}
+
+/// Most indicators one stream can subscribe to.
+const MAX_SUBSCRIBED: usize = 16;
+
+/// Indicators to send after each candle, such as
+/// `{"ema": [21], "rsi": [14]}`; none stops them.
+#[derive(Deserialize)]
+struct IndicatorSubscription {
+    #[serde(default)]
+    indicators: BTreeMap<PeriodIndicator, Vec<usize>>,
+    #[serde(default)]
+    source: PriceSource,
+}
+
+impl IndicatorSubscription {
+    /// The requested indicators in `sma`, `ema`, `rsi` order, without repeats.
+    fn checked(self) -> Result<Vec<(PeriodIndicator, usize)>, AppError> {
+        let mut subscribed = Vec::new();
+        for (indicator, periods) in self.indicators {
+            for period in periods {
+                check_indicator_period(indicator, period)?;
+                if !subscribed.contains(&(indicator, period)) {
+                    subscribed.push((indicator, period));
+                }
+            }
+        }
+        if subscribed.len() > MAX_SUBSCRIBED {
+            return Err(bad_request(format!(
+                "at most {MAX_SUBSCRIBED} indicators per stream"
+            )));
+        }
+        Ok(subscribed)
+    }
+}
+
+/// A stream's indicator subscription and how far it has got.
+#[derive(Default)]
+struct StreamIndicators {
+    feed: Option<IndicatorFeed>,
+    /// The last candle sent, which a new subscription catches up to.
+    last_sent: Option<Timestamp>,
+}
+
+impl StreamIndicators {
+    /// Replaces the subscription, seeding it with the stored candles up to
+    /// the last one sent, or up to `unsent` (all of them when `None`) if none
+    /// has been. Says why when the subscription is refused.
+    async fn subscribe(
+        &mut self,
+        db: &Arc<Db>,
+        subscription: IndicatorSubscription,
+        unsent: Option<Timestamp>,
+    ) -> Option<Message> {
+        let source = subscription.source;
+        let subscribed = match subscription.checked() {
+            Ok(subscribed) => subscribed,
+            Err(err) => {
+                let message = err.message().to_owned();
+                return Some(text_message(&StreamMessage::Error { message }));
+            }
+        };
+        self.feed = None;
+        if subscribed.is_empty() {
+            return None;
+        }
+        let through = self.last_sent.or(unsent);
+        self.start(db, IndicatorFeed::new(source, subscribed), through)
+            .await
+    }
+
+    /// Seeds `feed` with the stored candles up to `through` and makes it the
+    /// stream's, or says it could not.
+    async fn start(
+        &mut self,
+        db: &Arc<Db>,
+        mut feed: IndicatorFeed,
+        through: Option<Timestamp>,
+    ) -> Option<Message> {
+        let seeded = db
+            .read(move |conn| feed.seed(conn, through).map(|()| feed))
+            .await;
+        match seeded {
+            Ok(feed) => {
+                self.feed = Some(feed);
+                None
+            }
+            Err(err) => {
+                tracing::warn!("seeding stream indicators failed: {err}");
+                let message = "indicators are unavailable; subscribe again later".to_owned();
+                Some(text_message(&StreamMessage::Error { message }))
+            }
+        }
+    }
+}
+
+/// Why a replay stopped before its last candle.
+enum Interrupted {
+    Left,
+    ShuttingDown,
+}
+
+pub(crate) async fn stream_candles(
+    State(state): State<AppState>,
+    timestamps: TimestampFormat,
+    Query(query): Query<StreamQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let backfill = query.backfill.unwrap_or(0);
+    if backfill > MAX_BACKFILL {
+        return Err(bad_request(format!(
+            "backfill must be at most {MAX_BACKFILL}"
+        )));
+    }
+    let limit = state.config.max_websockets;
+    let Some(slot) = state.websockets.try_open(limit) else {
+        return Err(AppError::Unavailable(format!(
+            "already serving the most WebSocket connections allowed ({}); try again later",
+            limit.unwrap_or_default()
+        )));
+    };
+    let stream = CandleStream {
+        timestamps,
+        volume_precision: state.config.volume_precision,
+    };
+    if let Some(from) = query.replay_from.as_deref() {
+        if query.backfill.is_some() {
+            return Err(bad_request("backfill cannot be combined with replay_from"));
+        }
+        let from = Timestamp::new(parse_query_timestamp("replay_from", from, DayBound::Start)?);
+        let until = query
+            .replay_until
+            .as_deref()
+            .map(|until| parse_query_timestamp("replay_until", until, DayBound::End))
+            .transpose()?
+            .map(Timestamp::new);
+        if let Some(until) = until.filter(|until| until.at < from.at) {
+            return Err(bad_request(format!(
+                "replay_from {from} is after replay_until {until}"
+            )));
+        }
+        let speed = ReplaySpeed::parse(query.speed.as_deref().unwrap_or("1"))?;
+        let (db, closing) = (Arc::clone(&state.db), state.hub.closing());
+        return Ok(ws.on_upgrade(move |socket| async move {
+            stream
+                .replay(socket, db, (from, until), speed, closing)
+                .await;
+            drop(slot);
+        }));
+    }
+    if query.speed.is_some() {
+        return Err(bad_request("speed requires replay_from"));
+    }
+    if query.replay_until.is_some() {
+        return Err(bad_request("replay_until requires replay_from"));
+    }
+    // Subscribing before reading the backfill means nothing published in
+    // between is missed; the overlap is skipped when forwarding.
+    let candles = state.hub.subscribe();
+    let closing = state.hub.closing();
+    let recent = match backfill {
+        0 => Vec::new(),
+        count => {
+            state
+                .db
+                .read(move |conn| latest_candles(conn, count))
+                .await?
+        }
+    };
+    let db = Arc::clone(&state.db);
+    Ok(ws.on_upgrade(move |socket| async move {
+        stream.forward(socket, db, recent, candles, closing).await;
+        drop(slot);
+    }))
+}
+
+/// Request header carrying the id of the last `/api/sse` event a
+/// reconnecting `EventSource` received.
+const LAST_EVENT_ID: &str = "last-event-id";
+
+/// How often `/api/sse` sends a comment so idle proxies keep the connection.
+const SSE_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Deserialize)]
+pub(crate) struct ReplayQuery {
+    /// Replay stored candles from this moment.
+    start: Option<String>,
+    /// End the replay after the candles at this moment.
+    end: Option<String>,
+    /// Replay pace: a multiple of real time, or `max`.
+    speed: Option<String>,
+}
+
+/// `/ws/replay`, the same replay as `/api/ws?replay_from=` with `start` and
+/// `end` for `replay_from` and `replay_until`.
+pub(crate) async fn replay_candles(
+    state: State<AppState>,
+    timestamps: TimestampFormat,
+    Query(query): Query<ReplayQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    let start = query
+        .start
+        .ok_or_else(|| bad_request("start is required"))?;
+    let query = StreamQuery {
+        backfill: None,
+        replay_from: Some(start),
+        replay_until: query.end,
+        speed: query.speed,
+    };
+    stream_candles(state, timestamps, Query(query), ws).await
+}
+
+/// `candle` and `data_changed` events for `EventSource` clients, resuming
+/// after `Last-Event-ID` from the hub's recent updates. A client that cannot
+/// resume, or falls behind, gets a `data_changed` or `gap` event and should
+/// refetch what it shows.
+pub(crate) async fn stream_events(
+    State(state): State<AppState>,
+    timestamps: TimestampFormat,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    // An id that does not parse was never ours, so nothing after it is
+    // retained: the same as resuming from one that is too old.
+    let last_id = headers.get(LAST_EVENT_ID).map(|value| {
+        value
+            .to_str()
+            .ok()
+            .and_then(|id| id.trim().parse().ok())
+            .unwrap_or(u64::MAX)
+    });
+    let Subscription {
+        replay,
+        missed,
+        live,
+    } = state.hub.subscribe_after(last_id);
+    let stream = CandleStream {
+        timestamps,
+        volume_precision: state.config.volume_precision,
+    };
+    let resync = missed.then(|| sse::Event::default().event("data_changed").data("{}"));
+    let replay = resync.into_iter().chain(
+        replay
+            .into_iter()
+            .map(move |published| stream.event(published)),
+    );
+    let live = stream::unfold(
+        (live, state.hub.closing()),
+        move |(mut live, mut closing)| async move {
+            let event = tokio::select! {
+                received = live.recv() => match received {
+                    Ok(published) => stream.event(published),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::debug!("event stream client lagged by {missed} updates");
+                        sse::Event::default()
+                            .event("gap")
+                            .json_data(serde_json::json!({ "missed": missed }))
+                            .expect("gap events always serialize")
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                _ = closing.wait_for(|closing| *closing) => return None,
+            };
+            Some((event, (live, closing)))
+        },
+    );
+    Sse::new(stream::iter(replay).chain(live).map(Ok))
+        .keep_alive(KeepAlive::new().interval(SSE_KEEP_ALIVE))
+}
+
+/// The current rows of the latest `count` timestamps, oldest first.
+fn latest_candles(conn: &Connection, count: u32) -> duckdb::Result<Vec<Candle>> {
+    let mut candles = conn
+        .prepare_cached(
+            "SELECT timestamp, open, high, low, close, volume
+             FROM candles
+             WHERE timestamp >= (
+                SELECT min(timestamp) FROM (
+                    SELECT DISTINCT timestamp FROM candles ORDER BY timestamp DESC LIMIT ?
+                )
+             )
+             ORDER BY timestamp, rowid DESC",
+        )?
+        .query_map([count], candle_from_row)?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+    candles.dedup_by_key(|candle| candle.timestamp);
+    Ok(candles)
+}
+
+/// How one `/api/ws` or `/api/sse` client wants its candles rendered.
+#[derive(Clone, Copy)]
+struct CandleStream {
+    timestamps: TimestampFormat,
+    volume_precision: Option<u32>,
+}
+
+impl CandleStream {
+    /// Sends `recent`, then every published candle, until the client leaves,
+    /// the hub goes away or the server shuts down. A client too slow to keep
+    /// up loses the oldest candles and is told how many with a `gap`, so it
+    /// never holds up the poller or other clients.
+    async fn forward(
+        self,
+        mut socket: WebSocket,
+        db: Arc<Db>,
+        recent: Vec<Candle>,
+        mut candles: broadcast::Receiver<Published>,
+        mut closing: watch::Receiver<bool>,
+    ) {
+        let backfilled = recent.last().map(|candle| candle.timestamp.at);
+        let mut indicators = StreamIndicators::default();
+        for candle in recent {
+            if self
+                .send(&mut socket, &mut indicators, candle)
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        loop {
+            let message = tokio::select! {
+                received = candles.recv() => match received.map(|published| published.update) {
+                    // Already sent as part of the backfill.
+                    Ok(Update::Candle(candle))
+                        if backfilled.is_some_and(|last| candle.timestamp.at < last) =>
+                    {
+                        continue;
+                    }
+                    Ok(Update::Candle(candle)) => {
+                        if self.send(&mut socket, &mut indicators, candle).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    Ok(Update::DataChanged) => {
+                        // What the indicators were seeded with may be gone.
+                        if let Some(feed) = indicators.feed.take() {
+                            indicators.last_sent = None;
+                            if let Some(failed) = indicators.start(&db, feed.restarted(), None).await {
+                                if socket.send(failed).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                        text_message(&StreamMessage::DataChanged)
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::debug!("websocket client lagged by {missed} candles");
+                        text_message(&StreamMessage::Gap { missed })
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                incoming = socket.recv() => match incoming {
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => return,
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                        Ok(StreamCommand::Subscribe(subscription)) => {
+                            match indicators.subscribe(&db, subscription, None).await {
+                                Some(refused) => refused,
+                                None => continue,
+                            }
+                        }
+                        Ok(StreamCommand::Pause | StreamCommand::Resume) => {
+                            tracing::debug!("ignoring {text:?}: only replays pause");
+                            continue;
+                        }
+                        Err(err) => {
+                            tracing::debug!("ignoring stream command {text:?}: {err}");
+                            continue;
+                        }
+                    },
+                    Some(Ok(_)) => continue,
+                },
+                () = shutting_down(&mut closing) => break,
+            };
+            if socket.send(message).await.is_err() {
+                return;
+            }
+        }
+        let goodbye = CloseFrame {
+            code: close_code::AWAY,
+            reason: "server shutting down".into(),
+        };
+        let _ = socket.send(Message::Close(Some(goodbye))).await;
+    }
+
+    /// Sends the stored candles from `from` on, through `until` when given,
+    /// paced by their timestamps at `speed` and read a chunk at a time, then
+    /// `replay_done`. The client
+    /// can pause and resume; the server shutting down ends the replay early.
+    async fn replay(
+        self,
+        mut socket: WebSocket,
+        db: Arc<Db>,
+        (from, until): (Timestamp, Option<Timestamp>),
+        speed: ReplaySpeed,
+        mut closing: watch::Receiver<bool>,
+    ) {
+        // The last candle read and its rowid, as `CandleSeries` pages.
+        let mut after: Option<(Timestamp, i64)> = None;
+        let mut previous = None;
+        let mut paused = false;
+        let mut indicators = StreamIndicators::default();
+        let mut subscription = None;
+        // Until a candle is sent, subscriptions catch up to just before `from`.
+        let before_from = Timestamp::new(from.at - chrono::TimeDelta::microseconds(1));
+        let goodbye = 'replay: loop {
+            let chunk = db
+                .read(move |conn| {
+                    let mut stmt = conn.prepare_cached(&raw_candles_sql(SortOrder::Asc))?;
+                    let (cursor, row) = after.unzip();
+                    let candles = stmt
+                        .query_map(
+                            params![
+                                from,
+                                from,
+                                until,
+                                until,
+                                cursor,
+                                cursor,
+                                cursor,
+                                row,
+                                REPLAY_CHUNK
+                            ],
+                            |row| Ok((candle_from_row(row)?, row.get::<_, i64>(6)?)),
+                        )?
+                        .collect::<duckdb::Result<Vec<_>>>();
+                    candles
+                })
+                .await;
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    tracing::warn!("replay query failed: {err}");
+                    break CloseFrame {
+                        code: close_code::ERROR,
+                        reason: "replay query failed".into(),
+                    };
+                }
+            };
+            let last_chunk = (chunk.len() as i64) < REPLAY_CHUNK;
+            for (candle, row) in chunk {
+                let at = candle.timestamp.at;
+                let wait = speed.delay(previous, at);
+                previous = Some(at);
+                after = Some((candle.timestamp, row));
+                let paced = pace(
+                    &mut socket,
+                    &mut closing,
+                    wait,
+                    &mut paused,
+                    &mut subscription,
+                )
+                .await;
+                match paced {
+                    Ok(()) => {}
+                    Err(Interrupted::Left) => return,
+                    Err(Interrupted::ShuttingDown) => {
+                        break 'replay CloseFrame {
+                            code: close_code::AWAY,
+                            reason: "server shutting down".into(),
+                        }
+                    }
+                }
+                if let Some(requested) = subscription.take() {
+                    let refused = indicators
+                        .subscribe(&db, requested, Some(before_from))
+                        .await;
+                    if let Some(refused) = refused {
+                        if socket.send(refused).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                if self
+                    .send(&mut socket, &mut indicators, candle)
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            if last_chunk {
+                if socket
+                    .send(text_message(&StreamMessage::ReplayDone))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                break CloseFrame {
+                    code: close_code::NORMAL,
+                    reason: "replay done".into(),
+                };
+            }
+        };
+        let _ = socket.send(Message::Close(Some(goodbye))).await;
+    }
+
+    fn candle(&self, candle: Candle) -> Message {
+        text_message(&StreamMessage::Candle(&self.render(candle)))
+    }
+
+    /// Sends `candle`, then the subscribed indicators at it.
+    async fn send(
+        &self,
+        socket: &mut WebSocket,
+        indicators: &mut StreamIndicators,
+        candle: Candle,
+    ) -> Result<(), axum::Error> {
+        indicators.last_sent = Some(candle.timestamp);
+        let values = indicators
+            .feed
+            .as_mut()
+            .and_then(|feed| feed.update(&candle));
+        let timestamp = candle.timestamp.with_format(self.timestamps);
+        socket.send(self.candle(candle)).await?;
+        if let Some(values) = values {
+            let update = IndicatorUpdate { timestamp, values };
+            socket
+                .send(text_message(&StreamMessage::Indicator(&update)))
+                .await?;
+        }
+        Ok(())
+    }
+
+    fn event(&self, published: Published) -> sse::Event {
+        let event = match published.update {
+            Update::Candle(candle) => sse::Event::default()
+                .event("candle")
+                .json_data(self.render(candle))
+                .expect("candles always serialize"),
+            Update::DataChanged => sse::Event::default().event("data_changed").data("{}"),
+        };
+        event.id(published.id.to_string())
+    }
+
+    fn render(&self, mut candle: Candle) -> Candle {
+        candle.timestamp.format = self.timestamps;
+        candle.volume = candle.volume.with_precision(self.volume_precision);
+        candle
+    }
+}
+
+/// Waits out `wait`, not counting time spent paused, while answering the
+/// client's replay commands. A subscription is left in `subscription` for
+/// the replay to apply before its next candle.
+async fn pace(
+    socket: &mut WebSocket,
+    closing: &mut watch::Receiver<bool>,
+    wait: Duration,
+    paused: &mut bool,
+    subscription: &mut Option<IndicatorSubscription>,
+) -> Result<(), Interrupted> {
+    let mut remaining = wait;
+    loop {
+        let (was_paused, started) = (*paused, Instant::now());
+        let due = async move {
+            match was_paused {
+                true => std::future::pending().await,
+                false => tokio::time::sleep(remaining).await,
+            }
+        };
+        tokio::select! {
+            () = due => return Ok(()),
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_)) | Err(_)) | None => return Err(Interrupted::Left),
+                Some(Ok(message)) => {
+                    if !was_paused {
+                        remaining = remaining.saturating_sub(started.elapsed());
+                    }
+                    if let Message::Text(text) = message {
+                        match serde_json::from_str(&text) {
+                            Ok(StreamCommand::Pause) => *paused = true,
+                            Ok(StreamCommand::Resume) => *paused = false,
+                            Ok(StreamCommand::Subscribe(requested)) => *subscription = Some(requested),
+                            Err(err) => tracing::debug!("ignoring replay command {text:?}: {err}"),
+                        }
+                    }
+                }
+            },
+            _ = closing.wait_for(|closing| *closing) => return Err(Interrupted::ShuttingDown),
+        }
+    }
+}
+
+/// Resolves once the server starts shutting down.
+async fn shutting_down(closing: &mut watch::Receiver<bool>) {
+    let _ = closing.wait_for(|closing| *closing).await;
+}
+
+fn text_message(message: &StreamMessage<'_>) -> Message {
+    Message::Text(serde_json::to_string(message).expect("stream messages always serialize"))
+}
+
+pub(crate) fn candle_from_row(row: &duckdb::Row) -> duckdb::Result<Candle> {
+    Ok(Candle {
+        timestamp: row.get(0)?,
+        open: row.get(1)?,
+        high: row.get(2)?,
+        low: row.get(3)?,
+        close: row.get(4)?,
+        volume: row.get(5)?,
+        events: None,
+        quotes: QuoteValues::default(),
+    })
+}
+
+fn event_from_row(row: &duckdb::Row) -> duckdb::Result<Event> {
+    Ok(Event {
+        timestamp: row.get(0)?,
+        kind: row.get(1)?,
+        label: row.get(2)?,
+    })
+}
+
+const EVENTS_SQL: &str = "SELECT timestamp, type, label
+     FROM events
+     WHERE timestamp BETWEEN CAST(? AS TIMESTAMP) AND CAST(? AS TIMESTAMP)
+     ORDER BY timestamp";
+
+/// Attaches every event between the first and last candle to the candle
+/// nearest in time, ties going to the earlier candle. Candles must be sorted
+/// by timestamp.
+fn attach_events(candles: &mut [Candle], events: impl IntoIterator<Item = Event>) {
+    let times: Vec<_> = candles.iter().map(|candle| candle.timestamp.at).collect();
+    for candle in candles.iter_mut() {
+        candle.events = Some(Vec::new());
+    }
+    for event in events {
+        let at = event.timestamp.at;
+        let after = times.partition_point(|&time| time < at);
+        let nearest = match (after.checked_sub(1), times.get(after)) {
+            (Some(before), Some(&next)) if at - times[before] > next - at => after,
+            (Some(before), _) => before,
+            (None, Some(_)) => after,
+            (None, None) => continue,
+        };
+        if let Some(events) = &mut candles[nearest].events {
+            events.push(event);
+        }
+    }
+}
+
+/// Which end of its day a bare `YYYY-MM-DD` stands for: `start`-like bounds
+/// take midnight, `end`-like ones the last microsecond, so `end=2024-01-02`
+/// includes all of the 2nd.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DayBound {
+    Start,
+    End,
+}
+
+const TIMESTAMP_FORMATS: &str = "YYYY-MM-DD, YYYY-MM-DD HH:MM:SS[.fff], RFC 3339 such as \
+     2024-01-01T09:30:00.250Z, or unix seconds or milliseconds";
+
+/// Digits-only values in these ranges are unix seconds and milliseconds
+/// respectively: both cover 1973 to 2286 and they do not overlap, so the
+/// magnitude decides the unit. Anything else, such as `20240101`, is refused
+/// rather than guessed at.
+const UNIX_SECONDS: std::ops::RangeInclusive<i64> = 100_000_000..=9_999_999_999;
+const UNIX_MILLIS: std::ops::RangeInclusive<i64> = 100_000_000_000..=9_999_999_999_999;
+
+/// A timestamp query parameter, in UTC like the stored candles: a full
+/// `YYYY-MM-DD HH:MM:SS` with an optional fraction, a bare date at the `bound` end of its day, RFC 3339
+/// with its offset applied, or unix seconds or milliseconds.
+pub(crate) fn parse_query_timestamp(
+    name: &str,
+    value: &str,
+    bound: DayBound,
+) -> Result<NaiveDateTime, AppError> {
+    let invalid = |detail: &str| {
+        bad_request(format!(
+            "invalid {name} {value:?}{detail}; expected {TIMESTAMP_FORMATS}"
+        ))
+    };
+    if !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit()) {
+        let number: i64 = value
+            .parse()
+            .map_err(|_| invalid(": too large for a unix timestamp"))?;
+        let at = if UNIX_SECONDS.contains(&number) {
+            chrono::DateTime::from_timestamp(number, 0)
+        } else if UNIX_MILLIS.contains(&number) {
+            chrono::DateTime::from_timestamp_millis(number)
+        } else {
+            None
+        };
+        return at.map(|at| at.naive_utc()).ok_or_else(|| {
+            invalid(": digits must be unix seconds or milliseconds between 1973 and 2286")
+        });
+    }
+    NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT)
+        .ok()
+        .or_else(|| {
+            let date = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+            match bound {
+                DayBound::Start => date.and_hms_opt(0, 0, 0),
+                DayBound::End => date.and_hms_micro_opt(23, 59, 59, 999_999),
+            }
+        })
+        .or_else(|| {
+            chrono::DateTime::parse_from_rfc3339(value)
+                .ok()
+                .map(|at| at.naive_utc())
+        })
+        .ok_or_else(|| invalid(""))
+}
+
+/// Optional `start` and `end` parameters, rejected when `start` is later.
+pub(crate) fn parse_range(
+    start: Option<&str>,
+    end: Option<&str>,
+) -> Result<(Option<Timestamp>, Option<Timestamp>), AppError> {
+    let parse = |name, value: Option<&str>, bound| {
+        value
+            .map(|value| parse_query_timestamp(name, value, bound).map(Timestamp::new))
+            .transpose()
+    };
+    let (start, end) = (
+        parse("start", start, DayBound::Start)?,
+        parse("end", end, DayBound::End)?,
+    );
+    if let (Some(start), Some(end)) = (start, end) {
+        if start.at > end.at {
+            return Err(bad_request(format!("start {start} is after end {end}")));
+        }
+    }
+    Ok((start, end))
+}
+
+/// The default-period indicators on `source`: from the materialized table when
+/// it is current, otherwise from the incrementally maintained in-memory state.
+pub(crate) fn indicator_points(
+    conn: &Connection,
+    cached: &Mutex<HashMap<PriceSource, IndicatorState>>,
+    source: PriceSource,
+) -> duckdb::Result<Vec<IndicatorPoint>> {
+    if source == PriceSource::Close {
+        if let Some(points) = indicators::table_points(conn)? {
+            return Ok(points);
+        }
+    }
+    let mut cached = cached.lock().expect("indicator state poisoned");
+    let series = cached
+        .entry(source)
+        .or_insert_with(|| IndicatorState::new(source));
+    series.refresh(conn)?;
+    Ok(series.points().to_vec())
+}
+
+/// The periods an `/api/indicators` request asks for beyond the defaults,
+/// each once.
+struct IndicatorChoices {
+    hma_periods: Vec<usize>,
+    chosen: Vec<(PeriodIndicator, usize)>,
+}
+
+fn indicator_choices(query: &IndicatorQuery) -> Result<IndicatorChoices, AppError> {
+    let mut hma_periods = Vec::new();
+    for part in query
+        .hma
+        .as_deref()
+        .unwrap_or_default()
+        .split_terminator(',')
+    {
+        match part.trim().parse::<usize>() {
+            Ok(period) if period >= 2 => {
+                if !hma_periods.contains(&period) {
+                    hma_periods.push(period);
+                }
+            }
+            _ => {
+                return Err(bad_request(format!(
+                    "invalid hma period {part:?}; expected whole numbers of at least 2"
+                )))
+            }
+        }
+    }
+    let mut chosen = Vec::new();
+    for (indicator, list) in [
+        (PeriodIndicator::Sma, &query.sma),
+        (PeriodIndicator::Ema, &query.ema),
+        (PeriodIndicator::Rsi, &query.rsi),
+    ] {
+        for part in list.as_deref().unwrap_or_default().split_terminator(',') {
+            let period = part.trim().parse().map_err(|_| {
+                bad_request(format!(
+                    "invalid {} period {part:?}; expected whole numbers from 1 to {}",
+                    indicator.name(),
+                    formula::MAX_PERIOD
+                ))
+            })?;
+            check_indicator_period(indicator, period)?;
+            // The default columns already hold the default period.
+            if period != indicators::PERIOD && !chosen.contains(&(indicator, period)) {
+                chosen.push((indicator, period));
+            }
+        }
+    }
+    Ok(IndicatorChoices {
+        hma_periods,
+        chosen,
+    })
+}
+
+pub(crate) async fn get_indicators(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<IndicatorQuery>,
+) -> Result<Response, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let envelope = query.envelope.unwrap_or(false);
+    if envelope && matches!(format.format, Format::Ndjson | Format::Csv | Format::Html) {
+        return Err(bad_request(format!(
+            "envelope is not available with format={}",
+            format.format.name()
+        )));
+    }
+    let source = query.source.unwrap_or_default();
+    let IndicatorChoices {
+        hma_periods,
+        chosen,
+    } = indicator_choices(&query)?;
+    let cached = Arc::clone(&state.indicators);
+    let (periods, extra) = (hma_periods.clone(), chosen.clone());
+    let mut points = state
+        .db
+        .read(move |conn| {
+            let mut points = indicator_points(conn, &cached, source)?;
+            indicators::add_hull_averages(conn, source, &periods, &mut points)?;
+            indicators::add_period_indicators(conn, source, &extra, &mut points)?;
+            Ok::<_, duckdb::Error>(points)
+        })
+        .await?;
+    for point in &mut points {
+        point.timestamp.format = timestamps;
+    }
+    let warnings = indicators::insufficient_data_warnings(points.len(), &hma_periods, &chosen);
+    if query.strict.unwrap_or(false) && !warnings.is_empty() {
+        return Err(AppError::Unprocessable(warnings.join("; ")));
+    }
+    if envelope {
+        let meta = Meta {
+            count: points.len(),
+            warnings,
+        };
+        return Ok(DataResponse::new(format, Envelope { data: points, meta }).into_response());
+    }
+    Ok(DataResponse::new(format, points).into_response())
+}
+
+/// Bounds the period of an indicator chosen on `/api/indicators` or in a
+/// stream subscription, as `/api/formula` bounds its series.
+pub(crate) fn check_indicator_period(
+    indicator: PeriodIndicator,
+    period: usize,
+) -> Result<(), AppError> {
+    if (1..=formula::MAX_PERIOD).contains(&period) {
+        return Ok(());
+    }
+    Err(bad_request(format!(
+        "invalid {} period {period}; expected whole numbers from 1 to {}",
+        indicator.name(),
+        formula::MAX_PERIOD
+    )))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ZScoreQuery {
+    field: Option<ZScoreField>,
+    /// Trailing values the mean and standard deviation are taken over.
+    window: Option<usize>,
+}
+
+/// A candle column or an indicator output to standardize.
+#[derive(Clone, Copy, Default, Deserialize)]
+enum ZScoreField {
+    #[default]
+    #[serde(rename = "close")]
+    Close,
+    #[serde(rename = "volume")]
+    Volume,
+    #[serde(rename = "sma_14")]
+    Sma14,
+    #[serde(rename = "ema_14")]
+    Ema14,
+    #[serde(rename = "rsi_14")]
+    Rsi14,
+}
+
+/// Default z-score window.
+const ZSCORE_WINDOW: usize = 20;
+
+pub(crate) async fn get_zscore(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<ZScoreQuery>,
+) -> Result<DataResponse<Vec<ZScorePoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let field = query.field.unwrap_or_default();
+    let window = query.window.unwrap_or(ZSCORE_WINDOW);
+    if window < 2 {
+        return Err(bad_request("window must be at least 2"));
+    }
+    let cached = Arc::clone(&state.indicators);
+    let series: Vec<(Timestamp, Option<f64>)> = state
+        .db
+        .read(move |conn| {
+            let column = match field {
+                ZScoreField::Close => "close",
+                ZScoreField::Volume => "volume",
+                ZScoreField::Sma14 | ZScoreField::Ema14 | ZScoreField::Rsi14 => {
+                    let points = indicator_points(conn, &cached, PriceSource::Close)?;
+                    return Ok(points
+                        .into_iter()
+                        .map(|point| {
+                            let value = match field {
+                                ZScoreField::Sma14 => point.sma_14,
+                                ZScoreField::Ema14 => point.ema_14,
+                                _ => point.rsi_14,
+                            };
+                            (point.timestamp, value)
+                        })
+                        .collect());
+                }
+            };
+            conn.prepare_cached(&format!(
+                "SELECT timestamp, {column}
+                 FROM candles
+                 QUALIFY row_number() OVER (PARTITION BY timestamp ORDER BY rowid DESC) = 1
+                 ORDER BY timestamp"
+            ))?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<duckdb::Result<Vec<_>>>()
+        })
+        .await?;
+
+    let values = series.iter().map(|(_, value)| *value).collect::<Vec<_>>();
+    let zscores = indicators::rolling_zscore(&values, window);
+    Ok(DataResponse::new(
+        format,
+        series
+            .into_iter()
+            .zip(zscores)
+            .map(|((timestamp, value), zscore)| ZScorePoint {
+                timestamp: timestamp.with_format(timestamps),
+                value,
+                zscore,
+            })
+            .collect(),
+    ))
+}
+
+/// Bounds a named window query parameter of a dedicated indicator endpoint.
+fn check_period(name: &str, value: usize, min: usize) -> Result<(), AppError> {
+    if (min..=formula::MAX_PERIOD).contains(&value) {
+        return Ok(());
+    }
+    Err(bad_request(format!(
+        "{name} must be from {min} to {}",
+        formula::MAX_PERIOD
+    )))
+}
+
+/// Reads a series and renders its timestamps as the request asked.
+async fn read_stamped<P, F>(
+    state: &AppState,
+    timestamps: TimestampFormat,
+    read: F,
+) -> Result<Vec<P>, AppError>
+where
+    P: Stamped + Send + 'static,
+    F: FnOnce(&Connection) -> duckdb::Result<Vec<P>> + Send + 'static,
+{
+    let mut points = state.db.read(read).await?;
+    for point in &mut points {
+        point.timestamp_mut().format = timestamps;
+    }
+    Ok(points)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct StdDevQuery {
+    /// Trailing candles each value is taken over.
+    period: Option<usize>,
+    source: Option<PriceSource>,
+}
+
+/// Default standard deviation window, the usual Bollinger length.
+const STDDEV_PERIOD: usize = 20;
+
+pub(crate) async fn get_stddev(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<StdDevQuery>,
+) -> Result<DataResponse<Vec<StdDevPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let period = query.period.unwrap_or(STDDEV_PERIOD);
+    check_period("period", period, 2)?;
+    let source = query.source.unwrap_or_default();
+    let points = read_stamped(&state, timestamps, move |conn| {
+        indicators::standard_deviation(conn, source, period)
+    })
+    .await?;
+    Ok(DataResponse::new(format, points))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RollingPriceQuery {
+    /// Trailing candles each value is taken over.
+    window: Option<usize>,
+    /// The price averaged; the typical price, `hlc3`, by default.
+    source: Option<PriceSource>,
+}
+
+/// Default `/api/rolling_price` window.
+const ROLLING_PRICE_WINDOW: usize = 20;
+
+pub(crate) async fn get_rolling_price(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<RollingPriceQuery>,
+) -> Result<DataResponse<Vec<RollingPricePoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let window = query.window.unwrap_or(ROLLING_PRICE_WINDOW);
+    check_period("window", window, 1)?;
+    let source = query.source.unwrap_or(PriceSource::Hlc3);
+    let points = read_stamped(&state, timestamps, move |conn| {
+        indicators::rolling_price(conn, source, window)
+    })
+    .await?;
+    Ok(DataResponse::new(format, points))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CmoQuery {
+    /// Trailing price changes each value is taken over.
+    period: Option<usize>,
+    source: Option<PriceSource>,
+}
+
+pub(crate) async fn get_cmo(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<CmoQuery>,
+) -> Result<DataResponse<Vec<CmoPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let period = query.period.unwrap_or(indicators::PERIOD);
+    check_period("period", period, 1)?;
+    let source = query.source.unwrap_or_default();
+    let points = read_stamped(&state, timestamps, move |conn| {
+        indicators::momentum_oscillator(conn, source, period)
+    })
+    .await?;
+    Ok(DataResponse::new(format, points))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct KamaQuery {
+    /// Bars the efficiency ratio is measured over.
+    efficiency: Option<usize>,
+    /// EMA period the smoothing approaches in a clean trend.
+    fast: Option<usize>,
+    /// EMA period the smoothing approaches in noise.
+    slow: Option<usize>,
+    source: Option<PriceSource>,
+}
+
+pub(crate) async fn get_kama(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<KamaQuery>,
+) -> Result<DataResponse<Vec<KamaPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let defaults = KamaPeriods::default();
+    let periods = KamaPeriods {
+        efficiency: query.efficiency.unwrap_or(defaults.efficiency),
+        fast: query.fast.unwrap_or(defaults.fast),
+        slow: query.slow.unwrap_or(defaults.slow),
+    };
+    for (name, period) in [
+        ("efficiency", periods.efficiency),
+        ("fast", periods.fast),
+        ("slow", periods.slow),
+    ] {
+        check_period(name, period, 1)?;
+    }
+    if periods.fast >= periods.slow {
+        return Err(bad_request("fast must be shorter than slow"));
+    }
+    let source = query.source.unwrap_or_default();
+    let points = read_stamped(&state, timestamps, move |conn| {
+        indicators::adaptive_moving_average(conn, source, periods)
+    })
+    .await?;
+    Ok(DataResponse::new(format, points))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct StcQuery {
+    /// Period of the MACD line's fast EMA.
+    fast: Option<usize>,
+    /// Period of the MACD line's slow EMA.
+    slow: Option<usize>,
+    /// Bars each stochastic is taken over.
+    cycle: Option<usize>,
+    source: Option<PriceSource>,
+}
+
+pub(crate) async fn get_stc(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<StcQuery>,
+) -> Result<DataResponse<Vec<StcPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let defaults = StcPeriods::default();
+    let periods = StcPeriods {
+        fast: query.fast.unwrap_or(defaults.fast),
+        slow: query.slow.unwrap_or(defaults.slow),
+        cycle: query.cycle.unwrap_or(defaults.cycle),
+    };
+    for (name, period, min) in [
+        ("fast", periods.fast, 1),
+        ("slow", periods.slow, 1),
+        ("cycle", periods.cycle, 2),
+    ] {
+        check_period(name, period, min)?;
+    }
+    if periods.fast >= periods.slow {
+        return Err(bad_request("fast must be shorter than slow"));
+    }
+    let source = query.source.unwrap_or_default();
+    let points = read_stamped(&state, timestamps, move |conn| {
+        indicators::trend_cycle(conn, source, periods)
+    })
+    .await?;
+    Ok(DataResponse::new(format, points))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DpoQuery {
+    /// Candles the moving average is taken over; the price is compared
+    /// `period / 2 + 1` candles back.
+    period: Option<usize>,
+    source: Option<PriceSource>,
+}
+
+/// Default `/api/dpo` period.
+const DPO_PERIOD: usize = 20;
+
+pub(crate) async fn get_dpo(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<DpoQuery>,
+) -> Result<DataResponse<Vec<DpoPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let period = query.period.unwrap_or(DPO_PERIOD);
+    check_period("period", period, 1)?;
+    let source = query.source.unwrap_or_default();
+    let points = read_stamped(&state, timestamps, move |conn| {
+        indicators::detrended_price_oscillator(conn, source, period)
+    })
+    .await?;
+    Ok(DataResponse::new(format, points))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SpreadQuery {
+    /// Defaults to `Config::default_symbol`.
+    a: Option<String>,
+    b: Option<String>,
+    mode: Option<SpreadMode>,
+    /// Bars the rolling mean and z-score are taken over.
+    window: Option<usize>,
+    start: Option<String>,
+    end: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SpreadMode {
+    #[default]
+    Diff,
+    Ratio,
+}
+
+pub(crate) async fn get_spread(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<SpreadQuery>,
+) -> Result<DataResponse<Vec<SpreadPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let Some(a) = query.a.or_else(|| state.config.default_symbol.clone()) else {
+        return Err(bad_request(
+            "a is required when no default symbol is configured",
+        ));
+    };
+    let Some(b) = query.b else {
+        return Err(bad_request("b is required"));
+    };
+    let mode = query.mode.unwrap_or_default();
+    let window = query.window.unwrap_or(ZSCORE_WINDOW);
+    if window < 2 {
+        return Err(bad_request("window must be at least 2"));
+    }
+    let (start, end) = parse_range(query.start.as_deref(), query.end.as_deref())?;
+    check_symbols(&state, vec![a.clone(), b.clone()]).await?;
+    // Bars only count where both symbols have one.
+    let pairs: Vec<(Timestamp, f64, f64)> = state
+        .db
+        .read(move |conn| {
+            conn.prepare_cached(
+                "SELECT a.timestamp, a.close, b.close
+                 FROM symbol_candles a
+                 JOIN symbol_candles b ON a.timestamp = b.timestamp
+                 WHERE a.symbol = ? AND b.symbol = ?
+                   AND (? IS NULL OR a.timestamp >= ?)
+                   AND (? IS NULL OR a.timestamp <= ?)
+                 ORDER BY a.timestamp",
+            )?
+            .query_map(params![a, b, start, start, end, end], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<duckdb::Result<Vec<_>>>()
+        })
+        .await?;
+
+    let spreads = pairs
+        .iter()
+        .map(|&(_, a, b)| match mode {
+            SpreadMode::Diff => Some(a - b),
+            SpreadMode::Ratio => (b != 0.0).then(|| a / b),
+        })
+        .collect::<Vec<_>>();
+    let stats = indicators::rolling_mean_zscore(&spreads, window);
+    Ok(DataResponse::new(
+        format,
+        pairs
+            .into_iter()
+            .zip(spreads)
+            .zip(stats)
+            .map(
+                |(((timestamp, _, _), spread), (mean, zscore))| SpreadPoint {
+                    timestamp: timestamp.with_format(timestamps),
+                    spread,
+                    mean,
+                    zscore,
+                },
+            )
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ContinuousQuery {
+    /// Comma-separated contract symbols, oldest first.
+    contracts: Option<String>,
+    /// Comma-separated roll timestamps, one between each pair of contracts.
+    rolls: Option<String>,
+    adjust: Option<Adjustment>,
+}
+
+/// Upper bound on contracts in one continuous series.
+const MAX_CONTRACTS: usize = 64;
+
+pub(crate) async fn get_continuous(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<ContinuousQuery>,
+) -> Result<DataResponse<ContinuousSeries>, AppError> {
+    let list = |value: Option<&str>| {
+        value
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    let symbols = list(query.contracts.as_deref());
+    if symbols.is_empty() || symbols.len() > MAX_CONTRACTS {
+        return Err(bad_request(format!(
+            "contracts must list 1 to {MAX_CONTRACTS} symbols, oldest first"
+        )));
+    }
+    let rolls = list(query.rolls.as_deref())
+        .iter()
+        .map(|roll| parse_query_timestamp("roll", roll, DayBound::Start).map(Timestamp::new))
+        .collect::<Result<Vec<_>, _>>()?;
+    if rolls.len() + 1 != symbols.len() {
+        return Err(bad_request(format!(
+            "{} contracts need {} rolls, got {}",
+            symbols.len(),
+            symbols.len() - 1,
+            rolls.len()
+        )));
+    }
+    if rolls.windows(2).any(|pair| pair[0].at >= pair[1].at) {
+        return Err(bad_request("rolls must be strictly increasing"));
+    }
+    check_symbols(&state, symbols.clone()).await?;
+
+    // Each contract is only needed up to its own roll; earlier bars serve as
+    // the overlap for measuring the previous roll's gap.
+    let bounds = rolls.clone();
+    let contracts = state
+        .db
+        .read(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT timestamp, open, high, low, close, volume
+                 FROM symbol_candles
+                 WHERE symbol = ? AND (? IS NULL OR timestamp < ?)
+                 ORDER BY timestamp",
+            )?;
+            symbols
+                .into_iter()
+                .enumerate()
+                .map(|(index, symbol)| {
+                    let until = bounds.get(index).copied();
+                    let bars = stmt
+                        .query_map(params![symbol, until, until], |row| {
+                            Ok(Bar {
+                                timestamp: row.get(0)?,
+                                open: row.get(1)?,
+                                high: row.get(2)?,
+                                low: row.get(3)?,
+                                close: row.get(4)?,
+                                volume: row.get(5)?,
+                            })
+                        })?
+                        .collect::<duckdb::Result<Vec<_>>>()?;
+                    Ok(Contract { symbol, bars })
+                })
+                .collect::<duckdb::Result<Vec<_>>>()
+        })
+        .await?;
+    let mut series = continuous::stitch(&contracts, &rolls, query.adjust.unwrap_or_default())
+        .map_err(AppError::Unprocessable)?;
+    for candle in &mut series.candles {
+        candle.timestamp.format = timestamps;
+    }
+    for roll in &mut series.rolls {
+        roll.timestamp.format = timestamps;
+        roll.reference.format = timestamps;
+    }
+    Ok(DataResponse::new(format, series))
+}
+
+pub(crate) async fn get_symbols(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+) -> Result<DataResponse<Vec<SymbolInfo>>, AppError> {
+    let symbols = state
+        .db
+        .read(move |conn| {
+            conn.prepare_cached(
+                "SELECT symbol, min(timestamp), max(timestamp), count(*)
+                 FROM symbol_candles
+                 GROUP BY symbol
+                 ORDER BY symbol",
+            )?
+            .query_map([], |row| {
+                Ok(SymbolInfo {
+                    symbol: row.get(0)?,
+                    first: row.get::<_, Timestamp>(1)?.with_format(timestamps),
+                    last: row.get::<_, Timestamp>(2)?.with_format(timestamps),
+                    candles: row.get(3)?,
+                })
+            })?
+            .collect::<duckdb::Result<Vec<_>>>()
+        })
+        .await?;
+    Ok(DataResponse::new(format, symbols))
+}
+
+/// Candles [`get_meta`] infers its hints from, the most recent first.
+pub(crate) const META_SAMPLE: usize = 10_000;
+
+/// How to display prices and volumes, inferred from the latest candles.
+pub(crate) async fn get_meta(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+) -> Result<DataResponse<DisplayHints>, AppError> {
+    let (prices, volumes) = state
+        .db
+        .read(|conn| {
+            let mut prices = Vec::new();
+            let mut volumes = Vec::new();
+            let mut stmt = conn.prepare_cached(
+                "SELECT open, high, low, close, volume FROM candles
+                 ORDER BY timestamp DESC LIMIT ?",
+            )?;
+            let mut rows = stmt.query([META_SAMPLE as i64])?;
+            while let Some(row) = rows.next()? {
+                for index in 0..4 {
+                    prices.push(row.get::<_, f64>(index)?);
+                }
+                volumes.push(row.get::<_, f64>(4)?);
+            }
+            Ok::<_, duckdb::Error>((prices, volumes))
+        })
+        .await?;
+    if volumes.is_empty() {
+        return Ok(DataResponse::new(format, DisplayHints::default()));
+    }
+    let decimals = precision::decimals(&prices, precision::MAX_DECIMALS);
+    let tick = precision::tick_units(&prices, decimals);
+    let volume_decimals = state
+        .config
+        .volume_precision
+        .unwrap_or_else(|| precision::decimals(&volumes, precision::MAX_DECIMALS));
+    Ok(DataResponse::new(
+        format,
+        DisplayHints {
+            sampled: volumes.len(),
+            price_decimals: Some(decimals),
+            tick_size: tick.map(|tick| tick as f64 / 10f64.powi(decimals as i32)),
+            pricescale: Some(10u64.pow(decimals)),
+            min_move: tick,
+            volume_decimals: Some(volume_decimals),
+        },
+    ))
+}
+
+/// Answers 404 for the first symbol with no candles, so an unknown symbol is
+/// not mistaken for an empty range.
+async fn check_symbols(state: &AppState, symbols: Vec<String>) -> Result<(), AppError> {
+    let missing = state
+        .db
+        .read(move |conn| {
+            let mut stmt = conn
+                .prepare_cached("SELECT EXISTS (SELECT 1 FROM symbol_candles WHERE symbol = ?)")?;
+            for symbol in symbols {
+                if !stmt.query_row([&symbol], |row| row.get::<_, bool>(0))? {
+                    return Ok(Some(symbol));
+                }
+            }
+            Ok::<_, duckdb::Error>(None)
+        })
+        .await?;
+    match missing {
+        Some(symbol) => Err(AppError::NotFound(format!(
+            "unknown symbol {symbol:?}; see /api/symbols"
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct VolumeIndicatorQuery {
+    /// EMA period for the Force Index; raw values when omitted.
+    force_period: Option<usize>,
+    /// Averaging period for Ease of Movement.
+    eom_period: Option<usize>,
+}
+
+pub(crate) async fn get_volume_indicators(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<VolumeIndicatorQuery>,
+) -> Result<DataResponse<Vec<VolumeIndicatorPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let eom_period = query.eom_period.unwrap_or(indicators::PERIOD);
+    if eom_period == 0 || query.force_period == Some(0) {
+        return Err(bad_request("periods must be at least 1"));
+    }
+    let volume_precision = state.config.volume_precision;
+    let mut points = state
+        .db
+        .read(move |conn| {
+            indicators::volume_indicators(conn, query.force_period, eom_period, volume_precision)
+        })
+        .await?;
+    for point in &mut points {
+        point.timestamp.format = timestamps;
+    }
+    Ok(DataResponse::new(format, points))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AdxQuery {
+    /// Smoothing period for the DIs and ADX.
+    period: Option<usize>,
+    /// How many bars back ADXR reaches for the earlier ADX; defaults to
+    /// `period`.
+    adxr_period: Option<usize>,
+}
+
+pub(crate) async fn get_adx(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<AdxQuery>,
+) -> Result<DataResponse<Vec<AdxPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let period = query.period.unwrap_or(indicators::PERIOD);
+    let adxr_period = query.adxr_period.unwrap_or(period);
+    if period == 0 || adxr_period == 0 {
+        return Err(bad_request("periods must be at least 1"));
+    }
+    let mut points = state
+        .db
+        .read(move |conn| indicators::directional_movement(conn, period, adxr_period))
+        .await?;
+    for point in &mut points {
+        point.timestamp.format = timestamps;
+    }
+    Ok(DataResponse::new(format, points))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct VortexQuery {
+    /// Trailing bars the movements and true ranges are summed over.
+    period: Option<usize>,
+}
+
+pub(crate) async fn get_vortex(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<VortexQuery>,
+) -> Result<DataResponse<Vec<VortexPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let period = query.period.unwrap_or(indicators::PERIOD);
+    check_period("period", period, 1)?;
+    let points = read_stamped(&state, timestamps, move |conn| {
+        indicators::vortex_indicator(conn, period)
+    })
+    .await?;
+    Ok(DataResponse::new(format, points))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PnfQuery {
+    /// Price span of one box; required, since no size suits every series.
+    box_size: Option<f64>,
+    /// Boxes the price must cover against a column to start the next one.
+    reversal: Option<u32>,
+}
+
+/// Boxes a point-and-figure chart may draw in all, across every column.
+const MAX_PNF_BOXES: usize = 100_000;
+
+pub(crate) async fn get_pnf(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Query(query): Query<PnfQuery>,
+) -> Result<DataResponse<Vec<PnfColumn>>, AppError> {
+    let box_size = query
+        .box_size
+        .ok_or_else(|| bad_request("box_size is required"))?;
+    if !(box_size.is_finite() && box_size > 0.0) {
+        return Err(bad_request(format!(
+            "box_size must be a positive number, not {box_size}"
+        )));
+    }
+    let reversal = query.reversal.unwrap_or(3);
+    if reversal == 0 {
+        return Err(bad_request("reversal must be at least 1"));
+    }
+    let closes = state
+        .db
+        .read(|conn| {
+            conn.prepare_cached(
+                "SELECT close
+                 FROM candles
+                 QUALIFY row_number() OVER (PARTITION BY timestamp ORDER BY rowid DESC) = 1
+                 ORDER BY timestamp",
+            )?
+            .query_map([], |row| row.get::<_, f64>(0))?
+            .collect::<duckdb::Result<Vec<_>>>()
+        })
+        .await?;
+    pnf::columns(closes, box_size, reversal, MAX_PNF_BOXES)
+        .map(|columns| DataResponse::new(format, columns))
+        .map_err(AppError::BadRequest)
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FormulaQuery {
+    /// Arithmetic over candle columns and indicator series, such as
+    /// `(close - sma_20) / atr_14`.
+    expr: Option<String>,
+}
+
+pub(crate) async fn get_formula(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<FormulaQuery>,
+) -> Result<DataResponse<Vec<FormulaPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let text = query
+        .expr
+        .ok_or_else(|| bad_request("expr is required, e.g. (close - sma_20) / atr_14"))?;
+    let formula = Formula::parse(&text).map_err(|err| bad_request(format!("expr: {err}")))?;
+    let (stamps, candles) = state.db.read(formula_columns).await?;
+    let values = formula.evaluate(&candles);
+    Ok(DataResponse::new(
+        format,
+        stamps
+            .into_iter()
+            .zip(values)
+            .map(|(timestamp, value)| FormulaPoint {
+                timestamp: timestamp.with_format(timestamps),
+                value,
+            })
+            .collect(),
+    ))
+}
+
+/// The current candles, oldest first, as formulas read them.
+fn formula_columns(conn: &Connection) -> duckdb::Result<(Vec<Timestamp>, Columns)> {
+    let mut rows = conn
+        .prepare_cached(
+            "SELECT timestamp, open, high, low, close, volume
+             FROM candles
+             ORDER BY timestamp, rowid DESC",
+        )?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, Timestamp>(0)?,
+                [
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ],
+            ))
+        })?
+        .collect::<duckdb::Result<Vec<(Timestamp, [f64; 5])>>>()?;
+    rows.dedup_by_key(|(timestamp, _)| *timestamp);
+    let mut candles = Columns::default();
+    let mut timestamps = Vec::with_capacity(rows.len());
+    for (timestamp, [open, high, low, close, volume]) in rows {
+        timestamps.push(timestamp);
+        candles.open.push(open);
+        candles.high.push(high);
+        candles.low.push(low);
+        candles.close.push(close);
+        candles.volume.push(volume);
+    }
+    Ok((timestamps, candles))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct CorrelationQuery {
+    /// A formula, as `/api/formula` takes, or `forward_return_N`.
+    a: Option<String>,
+    b: Option<String>,
+    /// Trailing bars each correlation is taken over.
+    window: Option<usize>,
+}
+
+/// Default `/api/rolling_correlation` window.
+const CORRELATION_WINDOW: usize = 20;
+
+/// One side of a rolling correlation.
+enum CorrelationSeries {
+    Formula(Formula),
+    /// The return over the next this many bars.
+    ForwardReturn(usize),
+}
+
+impl CorrelationSeries {
+    fn parse(name: &str, text: &str) -> Result<Self, AppError> {
+        if let Some(horizon) = text.trim().strip_prefix("forward_return_") {
+            return match horizon.parse::<usize>() {
+                Ok(horizon @ 1..=formula::MAX_PERIOD) => Ok(Self::ForwardReturn(horizon)),
+                _ => Err(bad_request(format!(
+                    "{name}: the forward_return horizon must be a whole number from 1 to {}",
+                    formula::MAX_PERIOD
+                ))),
+            };
+        }
+        Formula::parse(text)
+            .map(Self::Formula)
+            .map_err(|err| bad_request(format!("{name}: {err}")))
+    }
+
+    fn evaluate(&self, candles: &Columns) -> Vec<Option<f64>> {
+        match self {
+            Self::Formula(formula) => formula.evaluate(candles),
+            Self::ForwardReturn(horizon) => indicators::forward_returns(&candles.close, *horizon),
+        }
+    }
+}
+
+pub(crate) async fn get_rolling_correlation(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<CorrelationQuery>,
+) -> Result<DataResponse<Vec<CorrelationPoint>>, AppError> {
+    let timestamps = format.timestamps(timestamps);
+    let (Some(a), Some(b)) = (query.a, query.b) else {
+        return Err(bad_request(
+            "a and b are required, e.g. a=rsi_14&b=forward_return_5",
+        ));
+    };
+    let (a, b) = (
+        CorrelationSeries::parse("a", &a)?,
+        CorrelationSeries::parse("b", &b)?,
+    );
+    let window = query.window.unwrap_or(CORRELATION_WINDOW);
+    check_period("window", window, 2)?;
+    let (stamps, correlations) = state
+        .db
+        .read(move |conn| {
+            let (stamps, candles) = formula_columns(conn)?;
+            let (a, b) = (a.evaluate(&candles), b.evaluate(&candles));
+            let correlations = indicators::rolling_correlation(conn, &a, &b, window)?;
+            Ok::<_, duckdb::Error>((stamps, correlations))
+        })
+        .await?;
+    Ok(DataResponse::new(
+        format,
+        stamps
+            .into_iter()
+            .zip(correlations)
+            .map(|(timestamp, correlation)| CorrelationPoint {
+                timestamp: timestamp.with_format(timestamps),
+                correlation,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Serialize)]
+pub(crate) struct AdminStats {
+    candles: i64,
+    indicators: RefreshStatus,
+    /// Requests let in with each API key so far.
+    api_keys: Vec<KeyUsage>,
+    websockets: WebSocketStats,
+}
+
+#[derive(Serialize)]
+pub(crate) struct WebSocketStats {
+    open: usize,
+    /// `max_websockets`, or `None` when unlimited.
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ExplainQuery {
+    endpoint: Option<String>,
+    source: Option<PriceSource>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Explained {
+    endpoint: String,
+    queries: Vec<ExplainedQuery>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ExplainedQuery {
+    name: &'static str,
+    sql: String,
+    /// DuckDB's own measurement of the whole query.
+    #[serde(serialize_with = "fin_or_null")]
+    total_seconds: Option<f64>,
+    /// The `EXPLAIN ANALYZE` operator tree with per-operator row counts and
+    /// timings. This DuckDB version's JSON profiler output is empty, so the
+    /// tree is DuckDB's rendered text.
+    plan: String,
+}
+
+/// Runs the SQL behind an endpoint under `EXPLAIN ANALYZE`. Off unless
+/// `GRAPH_EXPLAIN_ENABLED` is set.
+pub(crate) async fn explain(
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(query): Query<ExplainQuery>,
+) -> Result<Json<Explained>, AppError> {
+    if !state.config.explain_enabled {
+        return Err(api_not_found(uri).await);
+    }
+    let endpoint = query
+        .endpoint
+        .ok_or_else(|| bad_request("endpoint is required; expected indicators"))?;
+    let queries = match endpoint.as_str() {
+        "indicators" => indicators::request_queries(query.source.unwrap_or_default()),
+        other => {
+            return Err(bad_request(format!(
+                "cannot explain {other:?}; expected indicators"
+            )))
+        }
+    };
+    let queries = state
+        .db
+        .read(move |conn| {
+            queries
+                .into_iter()
+                .map(|(name, sql)| {
+                    // Not cached: DuckDB cannot bind parameters under EXPLAIN,
+                    // so these are one-off statements with values inlined.
+                    let plan = conn
+                        .prepare(&format!("EXPLAIN ANALYZE {sql}"))?
+                        .query_row([], |row| row.get::<_, String>(1))?;
+                    Ok(ExplainedQuery {
+                        name,
+                        sql,
+                        total_seconds: total_seconds(&plan),
+                        plan,
+                    })
+                })
+                .collect::<duckdb::Result<Vec<_>>>()
+        })
+        .await?;
+    Ok(Json(Explained { endpoint, queries }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BoundSqlQuery {
+    endpoint: Option<String>,
+    /// The format of the candles request, which caps `format=html` pages.
+    format: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct BoundSql {
+    endpoint: String,
+    queries: Vec<BoundQuery>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct BoundQuery {
+    name: &'static str,
+    sql: String,
+    params: Vec<SqlParam>,
+}
+
+/// The statements an endpoint would run for the rest of the query string,
+/// with the values bound to their placeholders, without running them. Off
+/// unless `GRAPH_EXPLAIN_ENABLED` is set.
+pub(crate) async fn get_bound_sql(
+    State(state): State<AppState>,
+    timestamps: TimestampFormat,
+    uri: Uri,
+    Query(query): Query<BoundSqlQuery>,
+) -> Result<Json<BoundSql>, AppError> {
+    if !state.config.explain_enabled {
+        return Err(api_not_found(uri).await);
+    }
+    let endpoint = query
+        .endpoint
+        .ok_or_else(|| bad_request("endpoint is required; expected candles or indicators"))?;
+    let format = match query.format.as_deref() {
+        None => Format::Json,
+        Some(name) => {
+            Format::named(name).ok_or_else(|| bad_request(format!("unknown format {name:?}")))?
+        }
+    };
+    let queries = match endpoint.as_str() {
+        "candles" => {
+            let Query(query) = Query::<CandleQuery>::try_from_uri(&uri)
+                .map_err(|rejection| bad_request(rejection.body_text()))?;
+            let CandleRequest { series, .. } =
+                candle_request(&state, query, timestamps, format).await?;
+            // The first page; later ones bind the last timestamp and rowid read.
+            let params = series.params(None, series.limit.min(CANDLE_PAGE_ROWS));
+            vec![BoundQuery {
+                name: "candles",
+                sql: series.sql,
+                params,
+            }]
+        }
+        "indicators" => {
+            let Query(query) = Query::<IndicatorQuery>::try_from_uri(&uri)
+                .map_err(|rejection| bad_request(rejection.body_text()))?;
+            let source = query.source.unwrap_or_default();
+            let IndicatorChoices {
+                hma_periods,
+                chosen,
+            } = indicator_choices(&query)?;
+            let cursor = state
+                .indicators
+                .lock()
+                .expect("indicator state poisoned")
+                .get(&source)
+                .and_then(IndicatorState::last_timestamp);
+            let through = match hma_periods.is_empty() && chosen.is_empty() {
+                true => None,
+                false => Some(
+                    state
+                        .db
+                        .read(|conn| {
+                            conn.query_row("SELECT max(timestamp) FROM candles", [], |row| {
+                                row.get(0)
+                            })
+                        })
+                        .await?,
+                ),
+            };
+            indicators::bound_request_queries(source, cursor, through)
+                .into_iter()
+                .map(|(name, sql, params)| BoundQuery {
+                    name,
+                    sql,
+                    params: params.into_iter().map(SqlParam::Timestamp).collect(),
+                })
+                .collect()
+        }
+        other => {
+            return Err(bad_request(format!(
+                "cannot show the SQL of {other:?}; expected candles or indicators"
+            )))
+        }
+    };
+    Ok(Json(BoundSql { endpoint, queries }))
+}
+
+/// The `Total Time: 0.0123s` figure from a rendered plan.
+fn total_seconds(plan: &str) -> Option<f64> {
+    let (_, rest) = plan.split_once("Total Time: ")?;
+    let (seconds, _) = rest.split_once('s')?;
+    seconds.trim().parse().ok()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct GenerateQuery {
+    rows: Option<usize>,
+    /// Bar width such as `1m` or `1d`.
+    interval: Option<String>,
+    seed: Option<u64>,
+    start_price: Option<f64>,
+    volatility: Option<f64>,
+}
+
+/// Upper bound on `rows` for generated data.
+pub(crate) const MAX_GENERATED_ROWS: usize = 5_000_000;
+
+#[derive(Serialize)]
+pub(crate) struct Generated {
+    rows: usize,
+}
+
+/// Replaces every candle with a reproducible synthetic series.
+pub(crate) async fn generate_demo_data(
+    State(state): State<AppState>,
+    Query(query): Query<GenerateQuery>,
+) -> Result<Json<Generated>, AppError> {
+    let defaults = DemoSpec::default();
+    let interval = match query.interval.as_deref() {
+        Some(value) => Timeframe::parse(value)
+            .filter(|interval| interval.calendar().is_none())
+            .ok_or_else(|| bad_request(format!("invalid interval {value:?}")))?
+            .duration(),
+        None => defaults.interval,
+    };
+    let rows = query.rows.unwrap_or(defaults.rows);
+    if rows > MAX_GENERATED_ROWS {
+        return Err(bad_request(format!(
+            "rows must be at most {MAX_GENERATED_ROWS}"
+        )));
+    }
+    let start_price = query.start_price.unwrap_or(defaults.start_price);
+    let volatility = query.volatility.unwrap_or(defaults.volatility);
+    if !(start_price > 0.0 && (0.0..1.0).contains(&volatility)) {
+        return Err(bad_request(
+            "start_price must be positive and volatility in [0, 1)",
+        ));
+    }
+    let spec = DemoSpec {
+        rows,
+        interval,
+        start_price,
+        volatility,
+        seed: query.seed.unwrap_or(defaults.seed),
+        ..defaults
+    };
+    let mut watermark = state.hub.hold_watermark().await;
+    let (rows, latest) = state
+        .db
+        .write(move |conn| Ok::<_, duckdb::Error>((demo::load(conn, &spec)?, latest_candle(conn)?)))
+        .await?;
+    state.hub.mark_replaced(&mut watermark, latest);
+    Ok(Json(Generated { rows }))
+}
+
+pub(crate) async fn get_admin_stats(
+    State(state): State<AppState>,
+) -> Result<Json<AdminStats>, AppError> {
+    let api_keys = state.keys.usage();
+    let websockets = WebSocketStats {
+        open: state.websockets.open(),
+        limit: state.config.max_websockets,
+    };
+    state
+        .db
+        .read(|conn| {
+            Ok::<_, duckdb::Error>(AdminStats {
+                candles: conn
+                    .prepare_cached("SELECT count(*) FROM candles")?
+                    .query_row([], |row| row.get(0))?,
+                indicators: indicators::refresh_status(conn)?,
+                api_keys,
+                websockets,
+            })
+        })
+        .await
+        .map(Json)
+        .map_err(AppError::from)
+}
+
+/// Duplicated candle timestamps, which `POST` removes.
+pub(crate) async fn get_integrity(
+    State(state): State<AppState>,
+) -> Result<Json<IntegrityReport>, AppError> {
+    let report = state.db.read(db::integrity_report).await?;
+    Ok(Json(report))
+}
+
+#[derive(Serialize)]
+pub(crate) struct Repaired {
+    removed: usize,
+    integrity: IntegrityReport,
+}
+
+/// Keeps the last-ingested row of each duplicated timestamp.
+pub(crate) async fn repair_integrity(
+    State(state): State<AppState>,
+) -> Result<Json<Repaired>, AppError> {
+    let mut watermark = state.hub.hold_watermark().await;
+    let (removed, integrity, latest) = state
+        .db
+        .write(|conn| {
+            Ok::<_, duckdb::Error>((
+                db::repair_duplicates(conn)?,
+                db::integrity_report(conn)?,
+                latest_candle(conn)?,
+            ))
+        })
+        .await?;
+    if removed > 0 {
+        state.hub.mark_replaced(&mut watermark, latest);
+    }
+    Ok(Json(Repaired { removed, integrity }))
+}
+
+/// Most ticks accepted in one `/api/ticks` request.
+pub(crate) const MAX_TICK_BATCH: usize = 100_000;
+
+#[derive(Deserialize)]
+pub(crate) struct TickInput {
+    /// Ticks without one build the main candles.
+    symbol: Option<String>,
+    /// Any query timestamp format, or fractional unix seconds.
+    timestamp: serde_json::Value,
+    price: f64,
+    #[serde(default)]
+    size: f64,
+}
+
+impl TickInput {
+    fn parse(self, index: usize) -> Result<Tick, AppError> {
+        let at = json_timestamp(&format!("ticks[{index}].timestamp"), &self.timestamp)?;
+        if !self.price.is_finite() {
+            return Err(bad_request(format!("ticks[{index}].price must be finite")));
+        }
+        if !(self.size.is_finite() && self.size >= 0.0) {
+            return Err(bad_request(format!(
+                "ticks[{index}].size must be finite and not negative"
+            )));
+        }
+        if self.symbol.as_deref() == Some("") {
+            return Err(bad_request(format!(
+                "ticks[{index}].symbol must not be empty"
+            )));
+        }
+        Ok(Tick {
+            symbol: self.symbol,
+            at,
+            price: self.price,
+            size: self.size,
+        })
+    }
+}
+
+/// A timestamp in a JSON body: a string in any query timestamp format, or
+/// unix seconds or milliseconds, the seconds possibly fractional.
+pub(crate) fn json_timestamp(
+    name: &str,
+    value: &serde_json::Value,
+) -> Result<NaiveDateTime, AppError> {
+    match value {
+        serde_json::Value::String(value) => parse_query_timestamp(name, value, DayBound::Start),
+        serde_json::Value::Number(number) if number.is_i64() || number.is_u64() => {
+            parse_query_timestamp(name, &number.to_string(), DayBound::Start)
+        }
+        serde_json::Value::Number(number) => number
+            .as_f64()
+            .filter(|seconds| UNIX_SECONDS.contains(&(*seconds as i64)))
+            .and_then(|seconds| {
+                chrono::DateTime::from_timestamp_micros((seconds * 1e6).round() as i64)
+            })
+            .map(|at| at.naive_utc())
+            .ok_or_else(|| bad_request(format!("invalid {name} {number}"))),
+        other => Err(bad_request(format!("invalid {name} {other}"))),
+    }
+}
+
+/// Folds posted trades into candles of `GRAPH_TICK_INTERVAL_MS`, in the
+/// order given. A batch with any invalid tick is refused whole.
+pub(crate) async fn post_ticks(
+    State(state): State<AppState>,
+    Json(ticks): Json<Vec<TickInput>>,
+) -> Result<Json<TickReport>, AppError> {
+    if ticks.len() > MAX_TICK_BATCH {
+        return Err(bad_request(format!(
+            "at most {MAX_TICK_BATCH} ticks per request"
+        )));
+    }
+    let ticks = ticks
+        .into_iter()
+        .enumerate()
+        .map(|(index, tick)| tick.parse(index))
+        .collect::<Result<Vec<_>, _>>()?;
+    let report = state.ticks.ingest(&state.db, &state.hub, ticks).await?;
+    Ok(Json(report))
+}
+
+pub(crate) async fn get_fib(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Query(query): Query<RangeQuery>,
+) -> Result<DataResponse<FibLevels>, AppError> {
+    let (start, end) = parse_range(query.start.as_deref(), query.end.as_deref())?;
+    check_range(&state, start, end).await?;
+    let (low, high): (Option<f64>, Option<f64>) = state
+        .db
+        .read(move |conn| {
+            conn.prepare_cached(
+                "SELECT min(low), max(high) FROM candles
+                 WHERE (? IS NULL OR timestamp >= ?) AND (? IS NULL OR timestamp <= ?)",
+            )?
+            .query_row(params![start, start, end, end], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+        })
+        .await?;
+    let (Some(low), Some(high)) = (low, high) else {
+        return Err(no_data(match (start, end) {
+            (None, None) => "no candles stored",
+            _ => "no candles in the requested range",
+        }));
+    };
+
+    let levels = [0.0, 0.236, 0.382, 0.5, 0.618, 0.786, 1.0]
+        .into_iter()
+        .map(|ratio| FibLevel {
+            ratio,
+            value: high - (high - low) * ratio,
+        })
+        .collect();
+
+    Ok(DataResponse::new(format, FibLevels { low, high, levels }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct FibTimeQuery {
+    anchor: Option<String>,
+    /// Number of zones to return, starting from 1 bar.
+    count: Option<usize>,
+}
+
+/// Upper bound on `count`; the 40th zone is already ~100M bars out.
+pub(crate) const MAX_FIB_TIME_ZONES: usize = 40;
+
+pub(crate) async fn get_fib_time(
+    State(state): State<AppState>,
+    negotiated: ResponseFormat,
+    format: TimestampFormat,
+    Query(query): Query<FibTimeQuery>,
+) -> Result<DataResponse<FibTimeZones>, AppError> {
+    let anchor = query
+        .anchor
+        .ok_or_else(|| bad_request("anchor is required"))?;
+    let anchor = parse_query_timestamp("anchor", &anchor, DayBound::Start)?;
+    let bars = fibonacci_bars(query.count.unwrap_or(10).min(MAX_FIB_TIME_ZONES));
+    let furthest = bars.last().copied().unwrap_or(0);
+
+    // Real candles cover the near zones; fetching more is pointless since the
+    // rest are projected from the last one anyway.
+    let fetch = furthest.min(100_000) as i64 + 1;
+    let anchor = Timestamp::new(anchor);
+    let (timestamps, step) = state
+        .db
+        .read(move |conn| {
+            let timestamps = conn
+                .prepare_cached(
+                    "SELECT timestamp
+                     FROM candles
+                     WHERE timestamp >= ?
+                     QUALIFY row_number() OVER (PARTITION BY timestamp ORDER BY rowid DESC) = 1
+                     ORDER BY timestamp
+                     LIMIT ?",
+                )?
+                .query_map(params![anchor, fetch], |row| row.get::<_, Timestamp>(0))?
+                .collect::<duckdb::Result<Vec<_>>>()?;
+            Ok::<_, duckdb::Error>((timestamps, infer_interval(conn)?))
+        })
+        .await?;
+
+    let Some(anchor) = timestamps.first() else {
+        return Err(no_data("no candles at or after the anchor"));
+    };
+    let last_index = timestamps.len() as u64 - 1;
+    let last = timestamps[last_index as usize].at;
+    let mut zones = Vec::with_capacity(bars.len());
+    for bars in bars {
+        if bars <= last_index {
+            zones.push(FibTimeZone {
+                bars,
+                timestamp: timestamps[bars as usize].with_format(format),
+                projected: false,
+            });
+            continue;
+        }
+        let Some(step) = step else {
+            return Err(bad_request(
+                "cannot project zones without at least two candles to infer the interval",
+            ));
+        };
+        let ahead = i32::try_from(bars - last_index).map_err(internal_error)?;
+        let timestamp = step
+            .checked_mul(ahead)
+            .and_then(|offset| last.checked_add_signed(offset))
+            .ok_or_else(|| bad_request("Fibonacci time zone falls outside the calendar"))?;
+        zones.push(FibTimeZone {
+            bars,
+            timestamp: Timestamp::new(timestamp).with_format(format),
+            projected: true,
+        });
+    }
+    Ok(DataResponse::new(
+        negotiated,
+        FibTimeZones {
+            anchor: anchor.with_format(format),
+            zones,
+        },
+    ))
+}
+
+/// The first `count` distinct Fibonacci numbers from 1: 1, 2, 3, 5, 8, ...
+fn fibonacci_bars(count: usize) -> Vec<u64> {
+    let (mut a, mut b) = (1u64, 2u64);
+    (0..count)
+        .map(|_| {
+            let bars = a;
+            (a, b) = (b, a + b);
+            bars
+        })
+        .collect()
+}
+
+pub(crate) async fn get_events(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    timestamps: TimestampFormat,
+    Query(query): Query<RangeQuery>,
+) -> Result<DataResponse<Vec<Event>>, AppError> {
+    let (start, end) = parse_range(query.start.as_deref(), query.end.as_deref())?;
+    let mut events = state
+        .db
+        .read(move |conn| {
+            let mut stmt = conn.prepare_cached(
+                "SELECT timestamp, type, label
+                 FROM events
+                 WHERE (? IS NULL OR timestamp >= ?) AND (? IS NULL OR timestamp <= ?)
+                 ORDER BY timestamp",
+            )?;
+            let events = stmt
+                .query_map(params![start, start, end, end], event_from_row)?
+                .collect::<duckdb::Result<Vec<_>>>();
+            events
+        })
+        .await?;
+    for event in &mut events {
+        event.timestamp.format = timestamps;
+    }
+    Ok(DataResponse::new(format, events))
+}
+
+pub(crate) async fn get_percentile(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Query(query): Query<PercentileQuery>,
+) -> Result<DataResponse<Percentiles>, AppError> {
+    let column = query.field.unwrap_or_default().column();
+    let percentiles = state.config.percentiles.clone();
+    let quantile_columns = percentiles
+        .iter()
+        .map(|percent| format!("quantile_cont(series.value, {:?})", percent / 100.0))
+        .collect::<Vec<_>>();
+    let (start, end) = parse_range(query.start.as_deref(), query.end.as_deref())?;
+    check_range(&state, start, end).await?;
+    let sql = format!(
+        "WITH series AS (
+            SELECT timestamp, {column} AS value
+            FROM candles
+            WHERE (? IS NULL OR timestamp >= ?) AND (? IS NULL OR timestamp <= ?)
+        ),
+        latest AS (
+            SELECT arg_max(value, timestamp) AS value FROM series
+        )
+        SELECT
+            {quantiles}latest.value,
+            100.0 * count(series.value) FILTER (WHERE series.value <= latest.value)
+                / NULLIF(count(series.value), 0)
+        FROM latest
+        LEFT JOIN series ON true
+        GROUP BY latest.value",
+        quantiles = quantile_columns
+            .iter()
+            .map(|column| format!("{column}, "))
+            .collect::<String>(),
+    );
+
+    let (values, latest, latest_rank): (Vec<Option<f64>>, Option<f64>, Option<f64>) = state
+        .db
+        .read(move |conn| {
+            let mut stmt = conn.prepare_cached(&sql)?;
+            let map_row = |row: &duckdb::Row| {
+                let values = (0..percentiles.len())
+                    .map(|index| row.get::<_, Option<f64>>(index))
+                    .collect::<duckdb::Result<Vec<_>>>()?;
+                Ok((
+                    values,
+                    row.get(percentiles.len())?,
+                    row.get(percentiles.len() + 1)?,
+                ))
+            };
+            stmt.query_row(params![start, start, end, end], map_row)
+        })
+        .await?;
+    // The rank is only NULL when the series has no values at all.
+    if latest_rank.is_none() {
+        return Err(no_data(match (start, end) {
+            (None, None) => "no candles stored",
+            _ => "no candles in the requested range",
+        }));
+    }
+
+    let quantiles = state
+        .config
+        .percentiles
+        .iter()
+        .zip(values)
+        .map(|(percent, value)| (format!("p{percent}"), value))
+        .collect();
+    Ok(DataResponse::new(
+        format,
+        Percentiles {
+            quantiles: Quantiles(quantiles),
+            latest,
+            latest_rank,
+        },
+    ))
+}
+
+/// Folds every session onto one day: each session's closes as a change from
+/// its first open, the last one in each bucket, summarized per bucket across
+/// the sessions.
+pub(crate) async fn get_intraday_overlay(
+    State(state): State<AppState>,
+    format: ResponseFormat,
+    Query(query): Query<OverlayQuery>,
+) -> Result<DataResponse<Vec<OverlayPoint>>, AppError> {
+    let bucket = query.bucket.unwrap_or(OVERLAY_BUCKET);
+    if bucket == 0 || !MINUTES_PER_DAY.is_multiple_of(bucket) {
+        return Err(bad_request(format!(
+            "bucket must be a number of minutes dividing {MINUTES_PER_DAY}, such as 5, 30 or 60"
+        )));
+    }
+    let session_start = match query.session_start.as_deref() {
+        None => 0,
+        Some(value) => chrono::NaiveTime::parse_from_str(value, "%H:%M")
+            .map(|time| time.hour() * 60 + time.minute())
+            .map_err(|_| {
+                bad_request(format!(
+                    "invalid session_start {value:?}; expected a time of day such as 17:00"
+                ))
+            })?,
+    };
+    let (start, end) = parse_range(query.start.as_deref(), query.end.as_deref())?;
+    check_range(&state, start, end).await?;
+    // Shifting by the session start makes every session one calendar day.
+    let sql = format!(
+        "WITH bars AS (
+            SELECT timestamp - INTERVAL {session_start} MINUTE AS shifted, open, close
+            FROM candles
+            WHERE (? IS NULL OR timestamp >= ?) AND (? IS NULL OR timestamp <= ?)
+        ),
+        bucketed AS (
+            SELECT
+                CAST(shifted AS DATE) AS session,
+                (hour(shifted) * 60 + minute(shifted)) // {bucket} AS bucket,
+                shifted,
+                close,
+                first_value(open) OVER (
+                    PARTITION BY CAST(shifted AS DATE) ORDER BY shifted
+                ) AS session_open
+            FROM bars
+        ),
+        paths AS (
+            SELECT
+                session,
+                bucket,
+                100.0 * (arg_max(close, shifted) - session_open) / NULLIF(session_open, 0)
+                    AS change
+            FROM bucketed
+            GROUP BY session, bucket, session_open
+        )
+        SELECT bucket, avg(change), quantile_cont(change, 0.25), quantile_cont(change, 0.75)
+        FROM paths
+        WHERE isfinite(change)
+        GROUP BY bucket
+        ORDER BY bucket"
+    );
+    let rows: Vec<(i64, f64, f64, f64)> = state
+        .db
+        .read(move |conn| {
+            let mut stmt = conn.prepare_cached(&sql)?;
+            let map_row =
+                |row: &duckdb::Row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?));
+            stmt.query_map(params![start, start, end, end], map_row)?
+                .collect::<duckdb::Result<_>>()
+        })
+        .await?;
+    let points = rows
+        .into_iter()
+        .map(|(index, mean, p25, p75)| {
+            let minutes =
+                (i64::from(session_start) + index * i64::from(bucket)) % i64::from(MINUTES_PER_DAY);
+            OverlayPoint {
+                time_of_day: format!("{:02}:{:02}", minutes / 60, minutes % 60),
+                mean,
+                p25,
+                p75,
+            }
+        })
+        .collect();
+    Ok(DataResponse::new(format, points))
+}
+
+/// Rejects a range wider than `Config::max_range`. Missing bounds stand for
+/// the first and last candle, since that is what the query will scan.
+pub(crate) async fn check_range(
+    state: &AppState,
+    start: Option<Timestamp>,
+    end: Option<Timestamp>,
+) -> Result<(), AppError> {
+    let Some(max_range) = state.config.max_range else {
+        return Ok(());
+    };
+    let seconds: Option<i64> = state
+        .db
+        .read(move |conn| {
+            conn.prepare_cached(
+                "SELECT date_diff(
+                    'second',
+                    coalesce(?, (SELECT min(timestamp) FROM candles)),
+                    coalesce(?, (SELECT max(timestamp) FROM candles))
+                 )",
+            )?
+            .query_row(params![start, end], |row| row.get(0))
+        })
+        .await?;
+    match seconds {
+        Some(seconds) if seconds > max_range.as_secs() as i64 => Err(bad_request(format!(
+            "requested range spans {:.1} days, more than the {:.1} allowed; narrow start/end \
+             and page through the data, or resample with a coarser timeframe",
+            seconds as f64 / 86_400.0,
+            max_range.as_secs_f64() / 86_400.0,
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use axum::body::Bytes;
+    use axum::extract::Request;
+    use axum::http::header::AUTHORIZATION;
+    use axum::http::StatusCode;
+    use futures_util::StreamExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::bus::DataEvent;
+    use crate::config::{ApiKey, CsvMode};
+    use crate::db::{initialize_db, initialize_events, initialize_symbols};
+    use crate::test_support::*;
+    use crate::{build_router, Config};
+
+    #[test]
+    fn timeframes_parse_to_intervals() {
+        let interval = |value| Timeframe::parse(value).map(Timeframe::sql_interval);
+        assert_eq!(interval("15m").as_deref(), Some("15 minutes"));
+        assert_eq!(interval("1d").as_deref(), Some("1 days"));
+        assert_eq!(interval("0h"), None);
+        assert_eq!(interval("h"), None);
+        assert_eq!(interval("5y"), None);
+        assert_eq!(interval("1M").as_deref(), Some("1 months"));
+        assert_eq!(interval("2w"), None);
+        assert_eq!(Timeframe::parse("1w").unwrap().calendar(), Some("week"));
+        assert_eq!(
+            Timeframe::parse("4h").unwrap().duration(),
+            chrono::Duration::hours(4)
+        );
+    }
+
+    #[tokio::test]
+    async fn resampling_honours_aggregation_overrides() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 5, 1, 2, 10),
+             ('2024-01-01 01:00:00', 2, 6, 2, 3, 20),
+             ('2024-01-01 02:00:00', 3, 4, 0, 10, 30),
+             ('2024-01-02 00:00:00', 9, 9, 9, 9, 40)",
+        ));
+
+        let default = get_json(&app, "/api/candles?timeframe=1d").await;
+        assert_eq!(default.as_array().unwrap().len(), 2);
+        let day = &default[0];
+        assert_eq!(day["timestamp"], "2024-01-01 00:00:00");
+        assert_eq!(
+            [
+                &day["open"],
+                &day["high"],
+                &day["low"],
+                &day["close"],
+                &day["volume"]
+            ],
+            [1.0, 6.0, 0.0, 10.0, 60.0]
+        );
+
+        let custom = get_json(&app, "/api/candles?timeframe=1d&close=median&volume=mean").await;
+        assert_eq!([&custom[0]["close"], &custom[0]["volume"]], [3.0, 20.0]);
+    }
+
+    #[tokio::test]
+    async fn buckets_align_to_the_clock_or_to_an_origin() {
+        let app = build_router(seeded_state(
+            "('2024-01-02 01:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-02 02:00:00', 1, 1, 1, 2, 1),
+             ('2024-01-02 09:00:00', 1, 1, 1, 3, 1),
+             ('2024-01-02 10:00:00', 1, 1, 1, 4, 1),
+             ('2024-01-03 09:00:00', 1, 1, 1, 5, 1)",
+        ));
+        let stamps = |rows: serde_json::Value| {
+            rows.as_array()
+                .unwrap()
+                .iter()
+                .map(|row| row["timestamp"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        // On even hours, not two hours on from the first candle.
+        let two_hourly = get_json(&app, "/api/candles?timeframe=2h").await;
+        assert_eq!(
+            stamps(two_hourly)[..3],
+            [
+                "2024-01-02 00:00:00",
+                "2024-01-02 02:00:00",
+                "2024-01-02 08:00:00"
+            ]
+        );
+        // Daily bars from a 09:30 session open.
+        let sessions = get_json(&app, "/api/candles?timeframe=1d&origin=09:30").await;
+        assert_eq!(
+            stamps(sessions.clone()),
+            ["2024-01-01 09:30:00", "2024-01-02 09:30:00"]
+        );
+        assert_eq!([&sessions[0]["close"], &sessions[1]["close"]], [3.0, 5.0]);
+        let shifted = get_json(
+            &app,
+            "/api/candles?timeframe=2h&origin=2024-01-02%2001:00:00",
+        )
+        .await;
+        assert_eq!(stamps(shifted)[0], "2024-01-02 01:00:00");
+
+        for uri in [
+            "/api/candles?origin=09:30",
+            "/api/candles?timeframe=1d&origin=half%20past%20nine",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn overrides_without_timeframe_are_rejected() {
+        let app = build_router(seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)"));
+        let response = get_uri(&app, "/api/candles?close=median").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = get_uri(&app, "/api/candles?timeframe=5y").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn timeframes_finer_than_the_data_are_rejected() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:05:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:10:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:30:00', 1, 1, 1, 1, 1)",
+        ));
+        for uri in ["/api/candles?timeframe=1m", "/api/candles?timeframe=299s"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let message = error["error"]["message"].as_str().unwrap();
+            assert!(message.ends_with("native interval of 5m"), "{message}");
+        }
+        for timeframe in ["5m", "300s", "7m", "1h"] {
+            let uri = format!("/api/candles?timeframe={timeframe}");
+            assert_eq!(
+                get_uri(&app, &uri).await.status(),
+                StatusCode::OK,
+                "GET {uri}"
+            );
+        }
+        assert_eq!(
+            describe_interval(chrono::Duration::milliseconds(1500)),
+            "1500ms"
+        );
+        assert_eq!(describe_interval(chrono::Duration::hours(48)), "2d");
+    }
+
+    #[tokio::test]
+    async fn calendar_timeframes_bucket_by_week_and_month() {
+        // Tuesday 30 January to Monday 5 February 2024.
+        let app = build_router(seeded_state(
+            "('2024-01-31 00:00:00', 2, 3, 1, 2.5, 10),
+             ('2024-01-30 00:00:00', 1, 2, 1, 1.5, 10),
+             ('2024-02-01 00:00:00', 3, 4, 2, 3.5, 10),
+             ('2024-02-05 00:00:00', 4, 5, 3, 4.5, 10)",
+        ));
+        let bars = |body: serde_json::Value| {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|bar| {
+                    let fields = ["timestamp", "open", "high", "low", "close", "volume"];
+                    serde_json::Value::from_iter(fields.map(|name| bar[name].clone()))
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            bars(get_json(&app, "/api/candles?timeframe=1w").await),
+            [
+                serde_json::json!(["2024-01-29 00:00:00", 1.0, 4.0, 1.0, 3.5, 30.0]),
+                serde_json::json!(["2024-02-05 00:00:00", 4.0, 5.0, 3.0, 4.5, 10.0]),
+            ]
+        );
+        // A month is the calendar month, not 30 days from some origin, and
+        // projected bars keep to month starts.
+        assert_eq!(
+            bars(get_json(&app, "/api/candles?timeframe=1M&project=2").await),
+            [
+                serde_json::json!(["2024-01-01 00:00:00", 1.0, 3.0, 1.0, 2.5, 20.0]),
+                serde_json::json!(["2024-02-01 00:00:00", 3.0, 5.0, 2.0, 4.5, 20.0]),
+                serde_json::json!(["2024-03-01 00:00:00", null, null, null, null, null]),
+                serde_json::json!(["2024-04-01 00:00:00", null, null, null, null, null]),
+            ]
+        );
+
+        for uri in [
+            "/api/candles?timeframe=2w",
+            "/api/candles?timeframe=3M",
+            "/api/candles?timeframe=1M&origin=09:30",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn projection_appends_empty_future_bars() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:05:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:10:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:30:00', 1, 1, 1, 1, 1)",
+        ));
+
+        let rows = get_json(&app, "/api/candles?project=2").await;
+        let rows = rows.as_array().unwrap();
+        assert_eq!(rows.len(), 6);
+        assert_eq!(rows[4]["timestamp"], "2024-01-01 00:35:00");
+        assert_eq!(rows[5]["timestamp"], "2024-01-01 00:40:00");
+        assert!(rows[5]["close"].is_null() && rows[5]["volume"].is_null());
+        assert_eq!(rows[3]["close"], 1.0);
+
+        let rows = get_json(&app, "/api/candles?timeframe=1h&project=1").await;
+        assert_eq!(rows[1]["timestamp"], "2024-01-01 01:00:00");
+    }
+
+    #[tokio::test]
+    async fn fib_time_zones_count_bars_from_the_anchor() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:01:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:02:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:03:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:04:00', 1, 1, 1, 1, 1)",
+        ));
+
+        let zones = get_json(&app, "/api/fib_time?anchor=2024-01-01%2000:01:30&count=5").await;
+        assert_eq!(zones["anchor"], "2024-01-01 00:02:00");
+        let zones: Vec<_> = zones["zones"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|zone| {
+                (
+                    zone["bars"].as_u64().unwrap(),
+                    zone["timestamp"].as_str().unwrap().to_string(),
+                    zone["projected"].as_bool().unwrap(),
+                )
+            })
+            .collect();
+        let expected = [
+            (1, "2024-01-01 00:03:00", false),
+            (2, "2024-01-01 00:04:00", false),
+            (3, "2024-01-01 00:05:00", true),
+            (5, "2024-01-01 00:07:00", true),
+            (8, "2024-01-01 00:10:00", true),
+        ]
+        .map(|(bars, ts, projected)| (bars, ts.to_string(), projected));
+        assert_eq!(zones, expected);
+
+        for uri in ["/api/fib_time", "/api/fib_time?anchor=yesterday"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+        // No candles to anchor on is missing data, not a bad request.
+        let response = get_uri(&app, "/api/fib_time?anchor=2025-01-01").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn percentile_reports_quantiles_and_latest_rank() {
+        let state = seeded_state(
+            "('2024-01-01 00:00:00', 0, 0, 0, 10, 500),
+             ('2024-01-02 00:00:00', 0, 0, 0, 20, 100),
+             ('2024-01-03 00:00:00', 0, 0, 0, 40, 300),
+             ('2024-01-04 00:00:00', 0, 0, 0, 30, 200)",
+        );
+        let app = build_router(state);
+
+        let response = get_uri(&app, "/api/percentile").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["quantiles"]["p50"], 25.0);
+        assert_eq!(body["quantiles"]["p10"], 13.0);
+        assert_eq!(body["latest"], 30.0);
+        assert_eq!(body["latest_rank"], 75.0);
+
+        let response = get_uri(
+            &app,
+            "/api/percentile?field=volume&start=2024-01-02%2000:00:00&end=2024-01-04%2000:00:00",
+        )
+        .await;
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["quantiles"]["p50"], 200.0);
+        assert_eq!(body["latest"], 200.0);
+        assert!((body["latest_rank"].as_f64().unwrap() - 200.0 / 3.0).abs() < 1e-9);
+
+        // A lone bound leaves the other end open.
+        let body = get_json(&app, "/api/percentile?start=2024-01-03").await;
+        assert_eq!(body["quantiles"]["p50"], 35.0);
+        assert_eq!(body["latest_rank"], 50.0);
+        let body = get_json(&app, "/api/percentile?end=2024-01-02").await;
+        assert_eq!(body["quantiles"]["p50"], 15.0);
+        assert_eq!(body["latest"], 20.0);
+        assert_eq!(body["latest_rank"], 100.0);
+
+        let response = get_uri(&app, "/api/percentile?field=open").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn intraday_overlay_folds_sessions_onto_one_day() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 10, 0, 0, 11, 1),
+             ('2024-01-01 00:30:00', 11, 0, 0, 12, 1),
+             ('2024-01-01 01:00:00', 12, 0, 0, 9, 1),
+             ('2024-01-02 00:00:00', 20, 0, 0, 20, 1),
+             ('2024-01-02 01:00:00', 20, 0, 0, 22, 1)",
+        ));
+        let body = get_json(&app, "/api/intraday_overlay?bucket=60").await;
+        assert_eq!(
+            body,
+            serde_json::json!([
+                { "time_of_day": "00:00", "mean": 10.0, "p25": 5.0, "p75": 15.0 },
+                { "time_of_day": "01:00", "mean": 0.0, "p25": -5.0, "p75": 5.0 },
+            ])
+        );
+
+        // Sessions opening at 00:30 put each midnight bar at the end of the
+        // session before.
+        let body = get_json(&app, "/api/intraday_overlay?bucket=60&session_start=00:30").await;
+        let times = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|point| point["time_of_day"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(times, ["00:30", "23:30"]);
+
+        // Either bound alone narrows the fold to the sessions on its side.
+        let body = get_json(&app, "/api/intraday_overlay?bucket=60&start=2024-01-02").await;
+        assert_eq!(
+            body,
+            serde_json::json!([
+                { "time_of_day": "00:00", "mean": 0.0, "p25": 0.0, "p75": 0.0 },
+                { "time_of_day": "01:00", "mean": 10.0, "p25": 10.0, "p75": 10.0 },
+            ])
+        );
+        let body = get_json(&app, "/api/intraday_overlay?bucket=60&end=2024-01-01").await;
+        assert_eq!(body[0]["mean"], 20.0);
+        assert_eq!(body[1]["mean"], -10.0);
+        let body = get_json(
+            &app,
+            "/api/intraday_overlay?start=2025-01-01&end=2025-01-02",
+        )
+        .await;
+        assert_eq!(body, serde_json::json!([]));
+        for uri in [
+            "/api/intraday_overlay?bucket=0",
+            "/api/intraday_overlay?bucket=7",
+            "/api/intraday_overlay?session_start=25:00",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn candles_stream_as_ndjson_and_csv() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 2, 0.5, 1.5, 10),
+             ('2024-01-01 00:01:00', 1.5, 3, 1, 2, 20)",
+        ));
+
+        let response = get_uri(&app, "/api/candles?format=ndjson").await;
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/x-ndjson");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let lines: Vec<serde_json::Value> = body
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["close"], 2.0);
+
+        let response = get_uri(&app, "/api/candles?format=csv&project=1").await;
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/csv");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            "timestamp,open,high,low,close,volume\n\
+             2024-01-01 00:00:00,1,2,0.5,1.5,10\n\
+             2024-01-01 00:01:00,1.5,3,1,2,20\n\
+             2024-01-01 00:02:00,,,,,\n"
+        );
+
+        let response = get_uri(&app, "/api/candles?format=csv&include=events").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn candles_render_as_an_html_table_capped_at_a_page() {
+        let rows = (0..=MAX_HTML_ROWS)
+            .map(|minute| {
+                format!("(TIMESTAMP '2024-01-01' + to_minutes({minute}), 1, 2, 0.5, 1.5, 10)")
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let app = build_router(seeded_state(&rows));
+        let html = |uri| {
+            let app = app.clone();
+            async move {
+                let response = get_uri(&app, uri).await;
+                assert_eq!(response.status(), StatusCode::OK, "GET {uri}");
+                assert_eq!(response.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        let page = html("/api/candles?format=html&limit=2&project=1").await;
+        assert!(page.starts_with("<!doctype html>"));
+        assert!(page.ends_with("</tbody></table></body></html>\n"));
+        assert!(page.contains(
+            "<tr><td>2024-01-01 00:01:00</td><td>1</td><td>2</td><td>0.5</td><td>1.5</td>\
+             <td>10</td></tr>\n<tr><td>2024-01-01 00:02:00</td><td></td><td></td><td></td>\
+             <td></td><td></td></tr>"
+        ));
+        assert!(!page.contains("notice\">"));
+
+        let page = html("/api/candles?format=html&limit=100000&timeframe=1m").await;
+        assert_eq!(page.matches("<tr><td>").count(), MAX_HTML_ROWS);
+        assert!(page.contains("Only the first 5000 rows are shown"));
+
+        let response = get_uri(&app, "/api/candles?format=html&include=events").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let mut escaped = Vec::new();
+        crate::models::push_html_escaped(&mut escaped, "<b>\"AT&T\"</b> isn't");
+        assert_eq!(
+            String::from_utf8(escaped).unwrap(),
+            "&lt;b&gt;&quot;AT&amp;T&quot;&lt;/b&gt; isn&#39;t"
+        );
+    }
+
+    #[tokio::test]
+    async fn candles_carry_quote_columns_from_the_csv() {
+        let path = std::env::temp_dir().join(format!("graph-quotes-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "timestamp,open,high,low,close,volume,bid,ask\n\
+             2024-01-01 00:00:00,1,2,0.5,1.5,10,1.49,1.51\n\
+             2024-01-01 00:01:00,1.5,3,1,2,20,,2.01\n\
+             2024-01-01 00:02:00,2,2,2,2,5,1.98,2.02\n",
+        )
+        .unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        initialize_db(&conn, &path, CsvMode::Strict).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let app = build_router(AppState::new(
+            Arc::new(Db::new(conn, 1).unwrap()),
+            Config::default(),
+        ));
+
+        let plain = get_json(&app, "/api/candles").await;
+        assert!(plain[0].get("bid").is_none());
+        let candles = get_json(&app, "/api/candles?include=bidask,spread").await;
+        assert_eq!(candles[0]["bid"], 1.49);
+        assert_eq!(candles[0]["ask"], 1.51);
+        let spread = candles[0]["spread"].as_f64().unwrap();
+        assert!((spread - 0.02).abs() < 1e-9, "{spread}");
+        assert!(candles[1]["bid"].is_null());
+        assert!(candles[1]["spread"].is_null());
+        let spread = get_json(&app, "/api/candles?include=spread").await;
+        assert!(spread[0].get("bid").is_none());
+        assert!(spread[0]["spread"].is_number());
+        // A bucket quotes its last candle.
+        let buckets = get_json(&app, "/api/candles?timeframe=1h&include=bidask").await;
+        assert_eq!(buckets[0]["bid"], 1.98);
+        assert_eq!(buckets[0]["ask"], 2.02);
+
+        let response = get_uri(&app, "/api/candles?format=csv&include=spread").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Data without quotes simply has none to include.
+        let app = build_router(seeded_state("('2024-01-01 00:00:00', 1, 2, 0.5, 1.5, 10)"));
+        let candles = get_json(&app, "/api/candles?include=bidask,spread").await;
+        assert!(candles[0].get("bid").is_none());
+        assert!(candles[0].get("spread").is_none());
+    }
+
+    #[tokio::test]
+    async fn candles_pack_into_binary_records() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 2, 0.5, 1.5, 10),
+             ('2024-01-01 00:01:00.25', 1.5, 3, 1, 2, 20)",
+        ));
+        let decode = |body: &[u8]| {
+            assert_eq!(body[..8], BINARY_HEADER);
+            let records = body[8..].chunks(48);
+            records
+                .map(|record| {
+                    assert_eq!(record.len(), 48);
+                    let micros = i64::from_le_bytes(record[..8].try_into().unwrap());
+                    let values = record[8..]
+                        .chunks(8)
+                        .map(|value| f64::from_le_bytes(value.try_into().unwrap()))
+                        .collect::<Vec<_>>();
+                    (micros, values)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Streamed straight from the cursor; the timestamp ignores ts_format.
+        let response = get_uri(&app, "/api/candles?format=bin&ts_format=unix&tz=%2B01:00").await;
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/octet-stream");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 8 + 2 * 48);
+        assert_eq!(
+            decode(&body),
+            [
+                (1_704_067_200_000_000, vec![1.0, 2.0, 0.5, 1.5, 10.0]),
+                (1_704_067_260_250_000, vec![1.5, 3.0, 1.0, 2.0, 20.0]),
+            ]
+        );
+
+        // Projected bars are NaN throughout.
+        let response = get_uri(&app, "/api/candles?format=bin&project=1").await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let records = decode(&body);
+        assert_eq!(records.len(), 3);
+        assert!(records[2].1.iter().all(|value| value.is_nan()));
+
+        let response = get_uri(&app, "/api/candles?format=bin&include=events").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn abandoned_stream_releases_its_reader() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE candles AS
+             SELECT TIMESTAMP '2020-01-01' + to_minutes(i) AS timestamp,
+                    1.0 AS open, 2.0 AS high, 0.5 AS low, 1.5 AS close, 100.0 AS volume
+             FROM range(200000) t(i);",
+        )
+        .unwrap();
+        let db = Arc::new(Db::new(conn, 1).unwrap());
+        let app = build_router(AppState::new(Arc::clone(&db), Config::default()));
+
+        let response = get_uri(&app, "/api/candles?limit=200000").await;
+        let mut chunks = response.into_body().into_data_stream();
+        assert_eq!(&chunks.next().await.unwrap().unwrap()[..], b"[");
+        drop(chunks);
+
+        let one: i64 = tokio::time::timeout(
+            Duration::from_secs(5),
+            db.read(|conn| conn.query_row("SELECT 1", [], |row| row.get(0)).unwrap()),
+        )
+        .await
+        .expect("the streaming query should give its reader back");
+        assert_eq!(one, 1);
+    }
+
+    #[tokio::test]
+    async fn long_series_are_read_across_pages() {
+        let state = seeded_state("('2020-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles
+                     SELECT TIMESTAMP '2020-01-01' + to_minutes(i + 1), 1, 2, 0.5, 1.5, 1
+                     FROM range(24999) t(i);",
+                )
+                .unwrap()
+            })
+            .await;
+        let app = build_router(state);
+
+        let lines = |body: Bytes| {
+            body.split(|&b| b == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(|line| serde_json::from_slice::<serde_json::Value>(line).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let fetch = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = get_uri(&app, uri).await;
+                axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let raw = lines(fetch("/api/candles?format=ndjson&limit=30000").await);
+        assert_eq!(raw.len(), 25000);
+        assert_eq!(raw[10000]["timestamp"], "2020-01-07 22:40:00");
+        assert_eq!(raw[24999]["timestamp"], "2020-01-18 08:39:00");
+
+        let resampled = lines(fetch("/api/candles?format=ndjson&timeframe=1m&limit=20001").await);
+        assert_eq!(resampled.len(), 20001);
+        assert_eq!(resampled[20000]["timestamp"], "2020-01-14 21:20:00");
+
+        let capped = get_json(&app, "/api/candles?limit=12345").await;
+        assert_eq!(capped.as_array().unwrap().len(), 12345);
+    }
+
+    #[tokio::test]
+    async fn repeated_timestamps_survive_a_page_boundary() {
+        let state = seeded_state("('2020-01-01 00:00:00', 0, 0, 0, -1, 0)");
+        // Rows 9,999 to 10,004 share a timestamp, across the end of the
+        // first page.
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles
+                     SELECT TIMESTAMP '2020-01-01' + to_minutes(least(i, 9998) + 1), 1, 1, 1, i, 1
+                     FROM range(10004) t(i);",
+                )
+                .unwrap()
+            })
+            .await;
+        let app = build_router(state);
+
+        for uri in [
+            "/api/candles?format=ndjson&limit=20000",
+            "/api/candles?format=ndjson&limit=20000&order=desc",
+        ] {
+            let response = get_uri(&app, uri).await;
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let mut closes = body
+                .split(|&b| b == b'\n')
+                .filter(|line| !line.is_empty())
+                .map(|line| {
+                    serde_json::from_slice::<serde_json::Value>(line).unwrap()["close"]
+                        .as_f64()
+                        .unwrap() as i64
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(closes.len(), CANDLE_PAGE_ROWS as usize + 5, "{uri}");
+            closes.sort_unstable();
+            closes.dedup();
+            assert_eq!(closes.len(), CANDLE_PAGE_ROWS as usize + 5, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn events_snap_to_the_nearest_candle() {
+        let state = seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-02 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-03 00:00:00', 1, 1, 1, 1, 1)",
+        );
+        state
+            .db
+            .write(|conn| {
+                initialize_events(conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+                conn.execute_batch(
+                    "INSERT INTO events VALUES
+                        ('2023-12-31 00:00:00', 'news', 'before the window'),
+                        ('2024-01-01 11:00:00', 'news', 'morning'),
+                        ('2024-01-01 13:00:00', 'earnings', 'afternoon'),
+                        ('2024-01-03 00:00:00', 'dividend', 'exact');",
+                )
+            })
+            .await
+            .unwrap();
+        let app = build_router(state);
+
+        let response = get_uri(&app, "/api/candles?include=events").await;
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        let labels = |index: usize| {
+            body[index]["events"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|event| event["label"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(0), ["morning"]);
+        assert_eq!(labels(1), ["afternoon"]);
+        assert_eq!(labels(2), ["exact"]);
+        assert_eq!(body[2]["events"][0]["type"], "dividend");
+
+        let plain = get_uri(&app, "/api/candles").await;
+        let plain: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(plain.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(plain[0].get("events").is_none());
+
+        let response = get_uri(&app, "/api/events?start=2024-01-01&end=2024-01-02").await;
+        let events: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(events.as_array().unwrap().len(), 2);
+
+        let response = get_uri(&app, "/api/candles?include=nonsense").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn indicators_warn_about_too_few_candles() {
+        let rows = (1..=10)
+            .map(|day| format!("('2024-01-{day:02} 00:00:00', 1, 1, 1, {day}, 1)"))
+            .collect::<Vec<_>>()
+            .join(",");
+        let app = build_router(seeded_state(&rows));
+
+        let response = get_uri(&app, "/api/indicators?envelope=true").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["meta"]["count"], 10);
+        assert_eq!(body["data"].as_array().unwrap().len(), 10);
+        let warnings = body["meta"]["warnings"].as_array().unwrap();
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(warnings[2], "RSI (14): only 10 of 15 required candles");
+
+        let plain = get_uri(&app, "/api/indicators").await;
+        let plain: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(plain.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(plain.is_array());
+
+        let strict = get_uri(&app, "/api/indicators?strict=true").await;
+        assert_eq!(strict.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn indicators_envelope_has_no_warnings_with_enough_data() {
+        let conn = Connection::open_in_memory().unwrap();
+        initialize_db(&conn, Path::new("data/stocks.csv"), CsvMode::Lenient).unwrap();
+        let app = build_router(AppState::new(
+            Arc::new(Db::new(conn, 1).unwrap()),
+            Config::default(),
+        ));
+        let response = get_uri(&app, "/api/indicators?envelope=true&strict=true").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(
+            &axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(body["meta"]["count"], 20);
+        assert!(body["meta"].get("warnings").is_none());
+    }
+
+    #[tokio::test]
+    async fn indicators_run_on_the_requested_price_source() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 4, 2, 3, 1),
+             ('2024-01-01 00:01:00', 2, 8, 4, 6, 1)",
+        ));
+        let sma = |points: &serde_json::Value| {
+            points
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["sma_14"].as_f64().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sma(&get_json(&app, "/api/indicators").await), [3.0, 4.5]);
+        assert_eq!(
+            sma(&get_json(&app, "/api/indicators?source=open").await),
+            [1.0, 1.5]
+        );
+        assert_eq!(
+            sma(&get_json(&app, "/api/indicators?source=hl2").await),
+            [3.0, 4.5]
+        );
+        assert_eq!(
+            sma(&get_json(&app, "/api/indicators?source=ohlc4").await),
+            [2.5, 3.75]
+        );
+
+        // HMA(2) = WMA(2 * WMA(1) - WMA(2), 1): 2 * 6 - 5 on closes 3, 6.
+        let hma = get_json(&app, "/api/indicators?hma=2").await;
+        assert_eq!(hma[0]["hma_2"], serde_json::Value::Null);
+        assert_eq!(hma[1]["hma_2"], 7.0);
+        let hma = get_json(&app, "/api/indicators?source=open&hma=2").await;
+        assert!((hma[1]["hma_2"].as_f64().unwrap() - 7.0 / 3.0).abs() < 1e-9);
+        // Chosen periods warm up fully; the default period stays as it is.
+        let chosen = get_json(&app, "/api/indicators?sma=2,14&ema=1&rsi=1").await;
+        assert_eq!(chosen[0]["sma_2"], serde_json::Value::Null);
+        assert_eq!(chosen[1]["sma_2"], 4.5);
+        assert_eq!(chosen[1]["ema_1"], 6.0);
+        assert_eq!(chosen[1]["rsi_1"], serde_json::Value::Null);
+        assert_eq!(chosen[1]["sma_14"], 4.5);
+
+        for uri in [
+            "/api/indicators?source=volume",
+            "/api/indicators?hma=1",
+            "/api/indicators?hma=9,x",
+            "/api/indicators?sma=0",
+            "/api/indicators?rsi=1001",
+            "/api/indicators?ema=x",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn caller_mistakes_are_400s_and_engine_failures_500s() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 2, 1, 1, 1)");
+        let app = build_router(state.clone());
+        for uri in [
+            "/api/fib?start=yesterday&end=2024-01-02",
+            "/api/percentile?start=2024-01-01&end=2024-13-45",
+            "/api/candles?start=soon",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+
+        state
+            .db
+            .write(|conn| conn.execute_batch("DROP TABLE candles"))
+            .await
+            .unwrap();
+        let response = get_uri(&app, "/api/fib?start=2024-01-01&end=2024-01-02").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn range_parameters_are_validated_before_querying() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 2, 1, 1, 1),
+             ('2024-01-02 00:00:00', 1, 4, 1, 1, 1)",
+        ));
+        for endpoint in ["/api/fib", "/api/percentile", "/api/events", "/api/candles"] {
+            for (query, names) in [
+                ("start=banana&end=2024-01-02", "start \"banana\""),
+                ("start=2024-01-01&end=2024-02-30", "end \"2024-02-30\""),
+                ("start=2024-01-01T00:00:00&end=2024-01-02", "start"),
+                ("start=2024-01-02&end=2024-01-01", "is after end"),
+            ] {
+                let uri = format!("{endpoint}?{query}");
+                let response = get_uri(&app, &uri).await;
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let message = serde_json::from_slice::<serde_json::Value>(&body).unwrap()["error"]
+                    ["message"]
+                    .as_str()
+                    .unwrap()
+                    .to_owned();
+                assert!(message.contains(names), "GET {uri}: {message}");
+                if !names.contains("after") {
+                    assert!(message.contains("RFC 3339"), "GET {uri}: {message}");
+                }
+            }
+        }
+
+        // RFC 3339 bounds are accepted and shifted to UTC.
+        let fib = get_json(
+            &app,
+            "/api/fib?start=2024-01-01T05:00:00%2B05:00&end=2024-01-01T23:00:00Z",
+        )
+        .await;
+        assert_eq!(fib["high"], 2.0);
+        let fib = get_json(&app, "/api/fib?start=2024-01-01&end=2024-01-02").await;
+        assert_eq!(fib["high"], 4.0);
+        // Either bound alone leaves the other end open.
+        let fib = get_json(&app, "/api/fib?start=2024-01-02").await;
+        assert_eq!(fib["high"], 4.0);
+        let fib = get_json(&app, "/api/fib?end=2024-01-01").await;
+        assert_eq!(fib["high"], 2.0);
+    }
+
+    #[test]
+    fn timestamp_parameters_accept_each_documented_form() {
+        let at = |text: &str| NaiveDateTime::parse_from_str(text, TIMESTAMP_FORMAT).unwrap();
+        let parse = |value, bound| parse_query_timestamp("start", value, bound).unwrap();
+        let midnight = at("2024-01-02 00:00:00");
+        assert_eq!(parse("2024-01-02", DayBound::Start), midnight);
+        assert_eq!(
+            parse("2024-01-02", DayBound::End),
+            midnight + chrono::Duration::days(1) - chrono::Duration::microseconds(1)
+        );
+        // Everything but a bare date names one instant whichever the bound.
+        for bound in [DayBound::Start, DayBound::End] {
+            let noon = at("2024-01-02 12:30:00");
+            assert_eq!(parse("2024-01-02 12:30:00", bound), noon);
+            assert_eq!(parse("2024-01-02T12:30:00Z", bound), noon);
+            assert_eq!(parse("2024-01-02T14:30:00+02:00", bound), noon);
+            assert_eq!(parse("1704198600", bound), noon);
+            assert_eq!(parse("1704198600000", bound), noon);
+        }
+        assert_eq!(
+            parse("1704198600250", DayBound::Start)
+                .and_utc()
+                .timestamp_subsec_millis(),
+            250
+        );
+        let quarter = at("2024-01-02 12:30:00.250");
+        assert_eq!(quarter.and_utc().timestamp_subsec_millis(), 250);
+        assert_eq!(parse("2024-01-02 12:30:00.250", DayBound::End), quarter);
+        assert_eq!(parse("2024-01-02T12:30:00.25Z", DayBound::End), quarter);
+
+        for (value, detail) in [
+            ("", ""),
+            ("soon", ""),
+            ("2024-02-30", ""),
+            ("2024-01-02T12:30:00", ""),
+            ("2024-01-02 25:00:00", ""),
+            ("-1704198600", ""),
+            // Eight digits could be YYYYMMDD or seconds in 1970; neither is guessed.
+            ("20240102", "unix seconds or milliseconds"),
+            ("17041986000", "unix seconds or milliseconds"),
+            ("1704198600000000", "unix seconds or milliseconds"),
+            ("99999999999999999999", "too large"),
+        ] {
+            let error = parse_query_timestamp("end", value, DayBound::End).unwrap_err();
+            assert_eq!(error.status(), StatusCode::BAD_REQUEST, "{value:?}");
+            let message = error.message();
+            assert!(message.contains(detail), "{value:?}: {message}");
+            assert!(message.contains(TIMESTAMP_FORMATS), "{value:?}: {message}");
+        }
+    }
+
+    #[tokio::test]
+    async fn zscores_standardize_the_requested_series() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 10),
+             ('2024-01-01 00:01:00', 1, 1, 1, 2, 10),
+             ('2024-01-01 00:02:00', 1, 1, 1, 3, 40)",
+        ));
+        let zscores = |points: serde_json::Value| {
+            points
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["zscore"].as_f64())
+                .collect::<Vec<_>>()
+        };
+        let close = get_json(&app, "/api/zscore?window=3").await;
+        assert_eq!(close[2]["value"], 3.0);
+        assert_eq!(zscores(close), [None, None, Some(1.0)]);
+        let volume = zscores(get_json(&app, "/api/zscore?field=volume&window=2").await);
+        assert_eq!(volume[1], None);
+        assert!((volume[2].unwrap() - 15.0 / 450f64.sqrt()).abs() < 1e-12);
+        // The closes only rise, so RSI is undefined until a loss and so is its z-score.
+        let rsi = get_json(&app, "/api/zscore?field=rsi_14&window=2").await;
+        assert_eq!(zscores(rsi), [None, None, None]);
+
+        for uri in ["/api/zscore?window=1", "/api/zscore?field=open"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn standard_deviations_warm_up_over_the_period() {
+        // The second row for 00:03 is the newer one and the one counted.
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 2, 2, 2, 1, 1),
+             ('2024-01-01 00:01:00', 2, 2, 2, 2, 1),
+             ('2024-01-01 00:02:00', 2, 2, 2, 3, 1),
+             ('2024-01-01 00:03:00', 4, 4, 4, 100, 1),
+             ('2024-01-01 00:03:00', 4, 4, 4, 7, 1)",
+        ));
+        let field = |points: &serde_json::Value, name: &str| {
+            points
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p[name].as_f64())
+                .collect::<Vec<_>>()
+        };
+        let close = get_json(&app, "/api/stddev?period=3").await;
+        assert_eq!(
+            field(&close, "variance"),
+            [None, None, Some(1.0), Some(7.0)]
+        );
+        assert_eq!(field(&close, "stddev")[2], Some(1.0));
+        assert!((field(&close, "stddev")[3].unwrap() - 7f64.sqrt()).abs() < 1e-12);
+        let open = get_json(&app, "/api/stddev?period=2&source=open").await;
+        assert_eq!(
+            field(&open, "variance"),
+            [None, Some(0.0), Some(0.0), Some(2.0)]
+        );
+        // Formulas read the same series.
+        let formula = get_json(&app, "/api/formula?expr=var_3").await;
+        assert_eq!(field(&formula, "value"), field(&close, "variance"));
+
+        for uri in ["/api/stddev?period=1", "/api/stddev?period=1001"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rolling_prices_center_on_the_typical_price() {
+        // Typical prices 2, 3, 10 and 5; the newer 00:03 row is the one read.
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 0, 3, 1, 2, 1),
+             ('2024-01-01 00:01:00', 0, 4, 2, 3, 1),
+             ('2024-01-01 00:02:00', 0, 11, 9, 10, 1),
+             ('2024-01-01 00:03:00', 0, 9, 9, 9, 1),
+             ('2024-01-01 00:03:00', 0, 6, 4, 5, 1)",
+        ));
+        let field = |points: &serde_json::Value, name: &str| {
+            points
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p[name].as_f64())
+                .collect::<Vec<_>>()
+        };
+        let typical = get_json(&app, "/api/rolling_price?window=3").await;
+        assert_eq!(
+            field(&typical, "avg_price"),
+            [None, None, Some(5.0), Some(6.0)]
+        );
+        assert_eq!(
+            field(&typical, "median_price"),
+            [None, None, Some(3.0), Some(5.0)]
+        );
+        let close = get_json(&app, "/api/rolling_price?window=2&source=close").await;
+        assert_eq!(
+            field(&close, "median_price"),
+            [None, Some(2.5), Some(6.5), Some(7.5)]
+        );
+
+        for uri in [
+            "/api/rolling_price?window=0",
+            "/api/rolling_price?window=1001",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn adaptive_candles_resample_what_the_budget_cannot_read() {
+        let rows = (0..10)
+            .map(|minute| format!("('2024-01-01 00:0{minute}:00', {minute}, 10, 0, {minute}, 1)"))
+            .collect::<Vec<_>>()
+            .join(",");
+        let state = seeded_state(&rows);
+        let config = Config {
+            candle_rows_per_sec: 1000,
+            ..Config::default()
+        };
+        let app = build_router(AppState::new(state.db, config));
+
+        let raw = get_json(&app, "/api/adaptive_candles").await;
+        assert_eq!(raw["meta"]["resampled"], false);
+        assert_eq!(raw["meta"]["timeframe"], serde_json::Value::Null);
+        assert_eq!(raw["meta"]["count"], 10);
+
+        // Four points, or five milliseconds at a thousand rows a second: ten
+        // minutes fit neither as 1m candles but fit both as 5m ones.
+        for uri in [
+            "/api/adaptive_candles?max_points=4",
+            "/api/adaptive_candles?budget_ms=5",
+        ] {
+            let body = get_json(&app, uri).await;
+            assert_eq!(body["meta"]["resampled"], true, "GET {uri}");
+            assert_eq!(body["meta"]["timeframe"], "5m", "GET {uri}");
+            assert_eq!(body["meta"]["rows"], 10, "GET {uri}");
+            assert_eq!(body["data"][0]["open"], 0.0, "GET {uri}");
+            assert_eq!(body["data"][0]["close"], 4.0, "GET {uri}");
+            assert_eq!(body["data"][1]["volume"], 5.0, "GET {uri}");
+            assert_eq!(body["meta"]["count"], 2, "GET {uri}");
+        }
+        let narrow = get_json(
+            &app,
+            "/api/adaptive_candles?max_points=4&start=2024-01-01%2000:06:00",
+        )
+        .await;
+        assert_eq!(narrow["meta"]["resampled"], false);
+        assert_eq!(narrow["meta"]["count"], 4);
+
+        for uri in [
+            "/api/adaptive_candles?max_points=0",
+            "/api/adaptive_candles?max_points=10001",
+            "/api/adaptive_candles?budget_ms=0",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn meta_infers_display_precision_from_the_latest_candles() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 100.05, 100.25, 99.95, 100.1, 1.5),
+             ('2024-01-01 00:01:00', 100.1, 100.15, 100.0, 100.05, 2)",
+        ));
+        assert_eq!(
+            get_json(&app, "/api/meta").await,
+            serde_json::json!({
+                "sampled": 2,
+                "price_decimals": 2,
+                "tick_size": 0.05,
+                "pricescale": 100,
+                "min_move": 5,
+                "volume_decimals": 1,
+            })
+        );
+
+        let app = build_router(AppState::new(
+            seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)").db,
+            Config {
+                volume_precision: Some(3),
+                ..Config::default()
+            },
+        ));
+        let meta = get_json(&app, "/api/meta").await;
+        assert_eq!(meta["price_decimals"], 0);
+        assert!(meta["tick_size"].is_null() && meta["min_move"].is_null());
+        assert_eq!(meta["volume_decimals"], 3);
+    }
+
+    #[tokio::test]
+    async fn vortex_sums_movements_against_true_range() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 2, 1, 1.5, 1),
+             ('2024-01-01 00:01:00', 2, 3, 2, 2.5, 1),
+             ('2024-01-01 00:02:00', 3, 4, 3, 3.5, 1),
+             ('2024-01-01 00:03:00', 3, 3, 2, 2.5, 1)",
+        ));
+        let points = get_json(&app, "/api/vortex?period=2").await;
+        let vortex = points
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|point| (point["vi_plus"].as_f64(), point["vi_minus"].as_f64()))
+            .collect::<Vec<_>>();
+        assert_eq!(points[2]["timestamp"], "2024-01-01 00:02:00");
+        assert_eq!(
+            vortex,
+            [
+                (None, None),
+                (None, None),
+                (Some(4.0 / 3.0), Some(0.0)),
+                (Some(2.0 / 3.0), Some(2.0 / 3.0))
+            ]
+        );
+        assert!(get_json(&app, "/api/vortex").await[3]["vi_plus"].is_null());
+
+        for uri in ["/api/vortex?period=0", "/api/vortex?period=1001"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn cmo_balances_gains_against_losses() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 0, 1, 0, 1, 1),
+             ('2024-01-01 00:01:00', 0, 2, 0, 2, 1),
+             ('2024-01-01 00:02:00', 0, 2, 0, 4, 1),
+             ('2024-01-01 00:03:00', 0, 2, 0, 3, 1)",
+        ));
+        let cmo = |body: serde_json::Value| {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|point| point["cmo"].as_f64())
+                .collect::<Vec<_>>()
+        };
+        let closes = get_json(&app, "/api/cmo?period=2").await;
+        assert_eq!(closes[0]["timestamp"], "2024-01-01 00:00:00");
+        assert_eq!(cmo(closes), [None, None, Some(100.0), Some(100.0 / 3.0)]);
+        // The highs stop moving.
+        let highs = get_json(&app, "/api/cmo?period=2&source=high").await;
+        assert_eq!(cmo(highs), [None, None, Some(100.0), None]);
+        assert_eq!(
+            get_json(&app, "/api/cmo").await[3]["cmo"],
+            serde_json::Value::Null
+        );
+
+        for uri in ["/api/cmo?period=0", "/api/cmo?period=1001"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn kama_follows_the_chosen_source_and_periods() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 10, 1, 1, 1),
+             ('2024-01-01 00:01:00', 1, 20, 1, 2, 1),
+             ('2024-01-01 00:02:00', 1, 30, 1, 4, 1),
+             ('2024-01-01 00:03:00', 1, 40, 1, 3, 1)",
+        ));
+        let kama = |points: serde_json::Value| {
+            points
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["kama"].as_f64())
+                .collect::<Vec<_>>()
+        };
+        let periods = KamaPeriods {
+            efficiency: 2,
+            fast: 2,
+            slow: 10,
+        };
+        let expected = indicators::kaufman_adaptive_moving_average(&[1.0, 2.0, 4.0, 3.0], periods);
+        let close = get_json(&app, "/api/kama?efficiency=2&fast=2&slow=10").await;
+        assert_eq!(close[2]["timestamp"], "2024-01-01 00:02:00");
+        assert_eq!(kama(close), expected);
+        let high = get_json(&app, "/api/kama?efficiency=2&fast=2&slow=10&source=high").await;
+        let expected =
+            indicators::kaufman_adaptive_moving_average(&[10.0, 20.0, 30.0, 40.0], periods);
+        assert_eq!(kama(high), expected);
+        // The defaults need 10 bars before the first value.
+        assert_eq!(kama(get_json(&app, "/api/kama").await), [None; 4]);
+
+        for uri in [
+            "/api/kama?efficiency=0",
+            "/api/kama?slow=1001",
+            "/api/kama?fast=30&slow=30",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn dpo_compares_the_shifted_price_with_its_average() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 10, 1, 1, 1),
+             ('2024-01-01 00:01:00', 1, 20, 1, 2, 1),
+             ('2024-01-01 00:02:00', 1, 15, 1, 4, 1),
+             ('2024-01-01 00:02:00', 1, 15, 1, 4, 1),
+             ('2024-01-01 00:03:00', 1, 40, 1, 3, 1),
+             ('2024-01-01 00:04:00', 1, 30, 1, 5, 1),
+             ('2024-01-01 00:05:00', 1, 30, 1, 8, 1)",
+        ));
+        let dpo = |points: serde_json::Value| {
+            points
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["dpo"].as_f64())
+                .collect::<Vec<_>>()
+        };
+        // Period 4 compares each close three candles back with the average
+        // of the last four: 1 - 2.5, 2 - 3.5 and 4 - 5.
+        let close = get_json(&app, "/api/dpo?period=4").await;
+        assert_eq!(close[3]["timestamp"], "2024-01-01 00:03:00");
+        assert_eq!(
+            dpo(close),
+            [None, None, None, Some(-1.5), Some(-1.5), Some(-1.0)]
+        );
+        // Period 1 shifts by one candle, before any window is full.
+        let high = get_json(&app, "/api/dpo?period=1&source=high").await;
+        assert_eq!(
+            dpo(high),
+            [
+                None,
+                Some(-10.0),
+                Some(5.0),
+                Some(-25.0),
+                Some(10.0),
+                Some(0.0)
+            ]
+        );
+        assert_eq!(dpo(get_json(&app, "/api/dpo").await), [None; 6]);
+
+        for uri in ["/api/dpo?period=0", "/api/dpo?period=1001"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn stc_follows_the_chosen_source_and_periods() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 10, 1, 1, 1),
+             ('2024-01-01 00:01:00', 1, 20, 1, 3, 1),
+             ('2024-01-01 00:02:00', 1, 15, 1, 2, 1),
+             ('2024-01-01 00:03:00', 1, 40, 1, 5, 1),
+             ('2024-01-01 00:04:00', 1, 30, 1, 4, 1)",
+        ));
+        let stc = |points: serde_json::Value| {
+            points
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["stc"].as_f64())
+                .collect::<Vec<_>>()
+        };
+        let periods = StcPeriods {
+            fast: 1,
+            slow: 2,
+            cycle: 2,
+        };
+        let expected = indicators::schaff_trend_cycle(&[1.0, 3.0, 2.0, 5.0, 4.0], periods);
+        assert!(expected[3].is_some());
+        let close = get_json(&app, "/api/stc?fast=1&slow=2&cycle=2").await;
+        assert_eq!(close[3]["timestamp"], "2024-01-01 00:03:00");
+        assert_eq!(stc(close), expected);
+        let high = get_json(&app, "/api/stc?fast=1&slow=2&cycle=2&source=high").await;
+        let expected = indicators::schaff_trend_cycle(&[10.0, 20.0, 15.0, 40.0, 30.0], periods);
+        assert_eq!(stc(high), expected);
+        assert_eq!(stc(get_json(&app, "/api/stc").await), [None; 5]);
+
+        for uri in [
+            "/api/stc?cycle=1",
+            "/api/stc?slow=1001",
+            "/api/stc?fast=50&slow=50",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn spreads_align_two_symbols_by_timestamp() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        state
+            .db
+            .write(|conn| {
+                initialize_symbols(conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+                conn.execute_batch(
+                    "INSERT INTO symbol_candles VALUES
+                        ('AAA', '2024-01-01 00:00:00', 0, 0, 0, 10, 1),
+                        ('AAA', '2024-01-01 00:01:00', 0, 0, 0, 12, 1),
+                        ('AAA', '2024-01-01 00:02:00', 0, 0, 0, 9, 1),
+                        ('AAA', '2024-01-01 00:03:00', 0, 0, 0, 8, 1),
+                        ('BBB', '2024-01-01 00:00:00', 0, 0, 0, 5, 1),
+                        ('BBB', '2024-01-01 00:02:00', 0, 0, 0, 3, 1),
+                        ('BBB', '2024-01-01 00:03:00', 0, 0, 0, 4, 1);",
+                )
+            })
+            .await
+            .unwrap();
+        let app = build_router(state);
+
+        // 00:01 has no BBB bar, so it is left out.
+        let diff = get_json(&app, "/api/spread?a=AAA&b=BBB&window=2").await;
+        let spreads = diff
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["spread"].as_f64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(spreads, [5.0, 6.0, 4.0]);
+        assert_eq!(diff[0]["zscore"], serde_json::Value::Null);
+        assert_eq!(diff[1]["mean"], 5.5);
+        // Window 6, 4: mean 5, sample std sqrt(2).
+        assert_eq!(diff[2]["mean"], 5.0);
+        assert!((diff[2]["zscore"].as_f64().unwrap() + 1.0 / 2f64.sqrt()).abs() < 1e-12);
+
+        let ratio = get_json(&app, "/api/spread?a=AAA&b=BBB&mode=ratio").await;
+        assert_eq!(ratio[1]["spread"], 3.0);
+        assert_eq!(ratio[1]["timestamp"], "2024-01-01 00:02:00");
+
+        for uri in ["/api/spread?a=AAA", "/api/spread?a=AAA&b=BBB&window=1"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn an_empty_database_answers_every_route_consistently() {
+        let conn = Connection::open_in_memory().unwrap();
+        initialize_db(&conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+        initialize_events(&conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+        initialize_symbols(&conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+        let app = build_router(AppState::new(
+            Arc::new(Db::new(conn, 1).unwrap()),
+            keyed_config(),
+        ));
+
+        let response = get_uri(&app, "/healthz").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        for uri in [
+            "/api/candles",
+            "/api/candles?timeframe=1h",
+            "/api/candles?include=events",
+            "/api/candles?project=5",
+            "/api/candles?order=desc&start=2024-01-01",
+            "/api/events",
+            "/api/indicators",
+            "/api/indicators?source=hlc3&hma=9",
+            "/api/volume_indicators?force_period=13",
+            "/api/adx",
+            "/api/pnf?box_size=1",
+            "/api/formula?expr=close%20-%20sma_20",
+            "/api/zscore?field=rsi_14",
+            "/api/stddev",
+            "/api/symbols",
+        ] {
+            assert_eq!(
+                get_json(&app, uri).await,
+                serde_json::json!([]),
+                "GET {uri}"
+            );
+        }
+        let response = get_uri(&app, "/api/candles?format=ndjson").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let envelope = get_json(&app, "/api/indicators?envelope=true").await;
+        assert_eq!(envelope["data"], serde_json::json!([]));
+        assert_eq!(envelope["meta"]["count"], 0);
+        assert_eq!(admin_json(&app, "/api/admin/stats").await["candles"], 0);
+
+        for uri in [
+            "/api/fib",
+            "/api/fib?start=2024-01-01&end=2024-01-02",
+            "/api/fib_time?anchor=2024-01-01",
+            "/api/percentile",
+            "/api/percentile?field=volume&start=2024-01-01&end=2024-01-02",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "GET {uri}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let error = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            assert_eq!(error["error"]["code"], "not_found", "GET {uri}");
+            let message = error["error"]["message"].as_str().unwrap();
+            assert!(message.starts_with("no data"), "GET {uri}: {message}");
+        }
+        // Symbols are checked before data, as with a populated database.
+        let response = get_uri(&app, "/api/spread?a=AAA&b=BBB").await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn continuous_series_stitch_contracts_at_their_rolls() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        state
+            .db
+            .write(|conn| {
+                initialize_symbols(conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+                conn.execute_batch(
+                    "INSERT INTO symbol_candles VALUES
+                        ('ESH24', '2024-03-07 00:00:00', 100, 101, 99, 100, 5),
+                        ('ESH24', '2024-03-08 00:00:00', 100, 101, 99, 101, 5),
+                        ('ESH24', '2024-03-11 00:00:00', 100, 101, 99, 102, 5),
+                        ('ESM24', '2024-03-08 00:00:00', 104, 105, 103, 104, 7),
+                        ('ESM24', '2024-03-11 00:00:00', 104, 105, 103, 105, 7);",
+                )
+            })
+            .await
+            .unwrap();
+        let app = build_router(state);
+
+        let series = get_json(
+            &app,
+            "/api/continuous?contracts=ESH24,ESM24&rolls=2024-03-11&ts_format=unix",
+        )
+        .await;
+        let candles = series["candles"].as_array().unwrap();
+        let closes = candles.iter().map(|c| &c["close"]).collect::<Vec<_>>();
+        // Measured on the 8th, ESM24 trades 3 above ESH24.
+        assert_eq!(closes, [103.0, 104.0, 105.0]);
+        assert_eq!(candles[0]["contract"], "ESH24");
+        assert_eq!(candles[2]["contract"], "ESM24");
+        assert_eq!(series["rolls"][0]["gap"], 3.0);
+        assert_eq!(series["rolls"][0]["reference"], 1_709_856_000);
+
+        let raw = get_json(
+            &app,
+            "/api/continuous?contracts=ESH24,ESM24&rolls=2024-03-11&adjust=none",
+        )
+        .await;
+        assert_eq!(raw["candles"][0]["close"], 100.0);
+
+        for (uri, status) in [
+            ("/api/continuous", StatusCode::BAD_REQUEST),
+            (
+                "/api/continuous?contracts=ESH24,ESM24",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "/api/continuous?contracts=ESH24,ESM24,ESU24&rolls=2024-03-11,2024-03-01",
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                "/api/continuous?contracts=ESH24,ESU24&rolls=2024-03-11",
+                StatusCode::NOT_FOUND,
+            ),
+            // ESM24 has no bar before a roll on the 8th to measure against.
+            (
+                "/api/continuous?contracts=ESH24,ESM24&rolls=2024-03-08",
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            assert_eq!(get_uri(&app, uri).await.status(), status, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_symbols_are_404s_and_empty_ranges_are_not() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        state
+            .db
+            .write(|conn| {
+                initialize_symbols(conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+                conn.execute_batch(
+                    "INSERT INTO symbol_candles VALUES
+                        ('AAA', '2024-01-01 00:00:00', 0, 0, 0, 10, 1),
+                        ('AAA', '2024-01-01 00:01:00', 0, 0, 0, 12, 1),
+                        ('BBB', '2024-01-01 00:00:00', 0, 0, 0, 5, 1);",
+                )
+            })
+            .await
+            .unwrap();
+        let config = Config {
+            default_symbol: Some("AAA".to_owned()),
+            ..Config::default()
+        };
+        let app = build_router(AppState::new(Arc::clone(&state.db), config));
+
+        let symbols = get_json(&app, "/api/symbols").await;
+        assert_eq!(symbols[0]["symbol"], "AAA");
+        assert_eq!(symbols[0]["candles"], 2);
+        assert_eq!(symbols[0]["last"], "2024-01-01 00:01:00");
+        assert_eq!(symbols[1]["symbol"], "BBB");
+
+        // `a` falls back to the default symbol.
+        let spread = get_json(&app, "/api/spread?b=BBB").await;
+        assert_eq!(spread[0]["spread"], 5.0);
+        let empty = get_json(&app, "/api/spread?b=BBB&start=2025-01-01").await;
+        assert_eq!(empty, serde_json::json!([]));
+
+        for uri in ["/api/spread?a=ZZZ&b=BBB", "/api/spread?b=ZZZ"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "GET {uri}");
+        }
+        // Without a configured default, `a` is required.
+        let app = build_router(state);
+        let response = get_uri(&app, "/api/spread?b=BBB").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn admin_stats_report_the_indicator_refresh() {
+        let state = seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:01:00', 1, 1, 1, 2, 1)",
+        );
+        let app = build_router(AppState::new(Arc::clone(&state.db), keyed_config()));
+        let stats = admin_json(&app, "/api/admin/stats").await;
+        assert_eq!(stats["candles"], 2);
+        assert_eq!(stats["indicators"]["rows"], 0);
+
+        state.db.write(indicators::refresh_table).await.unwrap();
+        let stats = admin_json(&app, "/api/admin/stats").await;
+        assert_eq!(stats["indicators"]["rows"], 2);
+        assert_eq!(stats["indicators"]["last_timestamp"], "2024-01-01 00:01:00");
+        assert!(stats["indicators"]["refreshed_at"].is_string());
+        let points = get_json(&app, "/api/indicators").await;
+        assert_eq!(points[1]["sma_14"], 1.5);
+    }
+
+    #[tokio::test]
+    async fn explain_is_off_by_default_and_needs_an_api_key() {
+        let state = seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:01:00', 1, 1, 1, 2, 1)",
+        );
+        let uri = "/api/admin/explain?endpoint=indicators";
+        let auth = [(AUTHORIZATION, "Bearer s3cret")];
+        let config = || Config {
+            api_keys: vec![ApiKey::new("admin", "s3cret")],
+            ..Config::default()
+        };
+        let app = build_router(AppState::new(Arc::clone(&state.db), config()));
+        let response = get_with(&app, uri, &auth).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let config = Config {
+            explain_enabled: true,
+            ..config()
+        };
+        let app = build_router(AppState::new(Arc::clone(&state.db), config));
+        let response = get_uri(&app, uri).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["www-authenticate"], "Bearer");
+        let wrong = get_with(&app, uri, &[(AUTHORIZATION, "Bearer s3creT")]).await;
+        assert_eq!(wrong.status(), StatusCode::FORBIDDEN);
+
+        let response = get_with(&app, uri, &auth).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let explained: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let queries = explained["queries"].as_array().unwrap();
+        let names = queries.iter().map(|q| &q["name"]).collect::<Vec<_>>();
+        assert_eq!(names, ["table_current", "table_points", "compute_scan"]);
+        for query in queries {
+            assert!(query["plan"].as_str().unwrap().contains("SEQ_SCAN"));
+            assert!(query["total_seconds"].as_f64().unwrap() >= 0.0);
+        }
+
+        let other = "/api/admin/explain?endpoint=candles";
+        let response = get_with(&app, other, &auth).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn bound_sql_shows_the_parameters_a_request_binds() {
+        let state = seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:01:00', 1, 1, 1, 2, 1),
+             ('2024-01-01 00:02:00', 1, 1, 1, 3, 1)",
+        );
+        let auth = [(AUTHORIZATION, "Bearer s3cret")];
+        let config = || Config {
+            api_keys: vec![ApiKey::new("admin", "s3cret")],
+            ..Config::default()
+        };
+        let uri = "/api/admin/sql?endpoint=candles&start=2024-01-01T00:01:00Z&limit=5";
+        let app = build_router(AppState::new(Arc::clone(&state.db), config()));
+        let response = get_with(&app, uri, &auth).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let config = Config {
+            explain_enabled: true,
+            ..config()
+        };
+        let app = build_router(AppState::new(Arc::clone(&state.db), config));
+        async fn json(app: &axum::Router, uri: &str) -> (StatusCode, serde_json::Value) {
+            let response = get_with(app, uri, &[(AUTHORIZATION, "Bearer s3cret")]).await;
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, serde_json::from_slice(&body).unwrap())
+        }
+        let (status, bound) = json(&app, uri).await;
+        assert_eq!(status, StatusCode::OK, "{bound}");
+        let [candles] = bound["queries"].as_array().unwrap().as_slice() else {
+            panic!("{bound}");
+        };
+        let start = "2024-01-01 00:01:00";
+        assert_eq!(
+            candles["params"],
+            serde_json::json!([start, start, null, null, null, null, null, null, 5])
+        );
+        assert!(candles["sql"].as_str().unwrap().contains("LIMIT ?"));
+        let (_, bound) = json(
+            &app,
+            "/api/admin/sql?endpoint=candles&timeframe=2m&origin=2024-01-01",
+        )
+        .await;
+        let params = &bound["queries"][0]["params"];
+        assert_eq!(params[0], "2 minutes");
+        assert_eq!(params[1], "2024-01-01 00:00:00");
+
+        let names = |bound: &serde_json::Value| {
+            bound["queries"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|query| query["name"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        let uri = "/api/admin/sql?endpoint=indicators&source=open&sma=2";
+        let (_, bound) = json(&app, uri).await;
+        assert_eq!(names(&bound), ["compute_scan", "chosen_prices"]);
+        assert_eq!(
+            bound["queries"][0]["params"],
+            serde_json::json!([null, null])
+        );
+        let last = "2024-01-01 00:02:00";
+        assert_eq!(
+            bound["queries"][1]["params"],
+            serde_json::json!([last, last])
+        );
+        get_json(&app, "/api/indicators?source=open").await;
+        let (_, bound) = json(&app, uri).await;
+        assert_eq!(
+            names(&bound),
+            ["history_check", "compute_scan", "chosen_prices"]
+        );
+        assert_eq!(
+            bound["queries"][1]["params"],
+            serde_json::json!([last, last])
+        );
+
+        for wrong in [
+            "/api/admin/sql",
+            "/api/admin/sql?endpoint=percentile",
+            "/api/admin/sql?endpoint=candles&timeframe=7q",
+            "/api/admin/sql?endpoint=indicators&hma=1",
+        ] {
+            assert_eq!(
+                json(&app, wrong).await.0,
+                StatusCode::BAD_REQUEST,
+                "{wrong}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn as_of_hides_later_candles() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:30:00', 1, 1, 1, 2, 1),
+             ('2024-01-01 01:00:00', 1, 1, 1, 3, 1),
+             ('2024-01-01 01:30:00', 1, 1, 1, 4, 1)",
+        ));
+        let closes = |candles: serde_json::Value| {
+            candles
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|c| c["close"].as_f64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let latest = get_json(
+            &app,
+            "/api/candles?as_of=2024-01-01%2001:00:00&order=desc&limit=2",
+        );
+        assert_eq!(closes(latest.await), [3.0, 2.0]);
+        let all = get_json(&app, "/api/candles?as_of=2024-01-01%2001:00:00").await;
+        assert_eq!(closes(all), [1.0, 2.0, 3.0]);
+        // The 01:00 hourly bucket only holds what was known at 01:00.
+        let hourly = get_json(
+            &app,
+            "/api/candles?as_of=2024-01-01%2001:00:00&timeframe=1h",
+        )
+        .await;
+        assert_eq!(closes(hourly), [2.0, 3.0]);
+        let hourly = get_json(&app, "/api/candles?timeframe=1h&order=desc").await;
+        assert_eq!(closes(hourly), [4.0, 2.0]);
+
+        for uri in [
+            "/api/candles?as_of=soon",
+            "/api/candles?order=desc&project=2",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ranges_wider_than_the_maximum_are_rejected() {
+        let seeded = seeded_state(
+            "('2024-01-01 00:00:00', 1, 2, 1, 1, 1),
+             ('2024-01-02 00:00:00', 1, 3, 1, 2, 1),
+             ('2024-01-05 00:00:00', 1, 4, 1, 3, 1)",
+        );
+        let config = Config {
+            max_range: Some(Duration::from_secs(2 * 86_400)),
+            ..Config::default()
+        };
+        let app = build_router(AppState::new(Arc::clone(&seeded.db), config));
+
+        for uri in [
+            "/api/candles?timeframe=1d",
+            "/api/candles?timeframe=1d&start=2024-01-02",
+            "/api/fib",
+            "/api/fib?start=2024-01-01&end=2024-01-04",
+            "/api/percentile?field=close",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let message = String::from_utf8(body.to_vec()).unwrap();
+            assert!(message.contains("resample"), "{message}");
+        }
+
+        let daily = get_json(&app, "/api/candles?timeframe=1d&end=2024-01-02").await;
+        assert_eq!(daily.as_array().unwrap().len(), 2);
+        let daily = get_json(&app, "/api/candles?timeframe=1d&start=2024-01-03").await;
+        assert_eq!(daily[0]["close"], 3.0);
+        let fib = get_json(&app, "/api/fib?start=2024-01-01&end=2024-01-02").await;
+        assert_eq!(fib["high"], 3.0);
+        // Raw candles are bounded by `limit`, not by the range.
+        let raw = get_json(&app, "/api/candles?start=2024-01-01&end=2024-01-05").await;
+        assert_eq!(raw.as_array().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn volume_precision_rounds_candles_and_volume_indicator_inputs() {
+        let state = seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 12345.0000001),
+             ('2024-01-01 00:01:00', 1, 1, 1, 2, 10.25)",
+        );
+        let body = |app: axum::Router, uri: &'static str| async move {
+            let response = get_uri(&app, uri).await;
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+        let with_precision = |precision| {
+            build_router(AppState::new(
+                Arc::clone(&state.db),
+                Config {
+                    volume_precision: precision,
+                    ..Config::default()
+                },
+            ))
+        };
+
+        let stored = body(with_precision(None), "/api/candles").await;
+        assert!(stored.contains(r#""volume":12345.0000001"#), "{stored}");
+        let whole = body(with_precision(Some(0)), "/api/candles").await;
+        assert!(whole.contains(r#""volume":12345}"#), "{whole}");
+        assert!(whole.contains(r#""volume":10}"#), "{whole}");
+        let csv = body(with_precision(Some(0)), "/api/candles?format=csv").await;
+        assert!(
+            csv.ends_with(",12345\n2024-01-01 00:01:00,1,1,1,2,10\n"),
+            "{csv}"
+        );
+        let tenths = body(with_precision(Some(1)), "/api/candles?timeframe=1h").await;
+        assert!(tenths.contains(r#""volume":12355.3}"#), "{tenths}");
+
+        // The Force Index sees the same rounded volume: (2 - 1) * 10.
+        let app = with_precision(Some(0));
+        let points = get_json(&app, "/api/volume_indicators").await;
+        assert_eq!(points[1]["force_index"], 10.0);
+        let points = get_json(&with_precision(None), "/api/volume_indicators").await;
+        assert_eq!(points[1]["force_index"], 10.25);
+    }
+
+    #[tokio::test]
+    async fn volume_indicators_follow_their_definitions() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 10, 12, 8, 10, 100),
+             ('2024-01-01 00:01:00', 10, 14, 10, 13, 200),
+             ('2024-01-01 00:02:00', 13, 13, 13, 13, 50),
+             ('2024-01-01 00:03:00', 13, 15, 11, 11, 0),
+             ('2024-01-01 00:04:00', 11, 16, 12, 15, 400)",
+        ));
+        let series = |body: serde_json::Value, field: &str| {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p[field].as_f64())
+                .collect::<Vec<_>>()
+        };
+
+        let raw = get_json(&app, "/api/volume_indicators?eom_period=1").await;
+        assert_eq!(
+            series(raw.clone(), "force_index"),
+            [None, Some(600.0), Some(0.0), Some(0.0), Some(1600.0)]
+        );
+        // Zero range at 00:02 and zero volume at 00:03 have no EoM.
+        assert_eq!(
+            series(raw, "ease_of_movement"),
+            [None, Some(0.04), None, None, Some(0.01)]
+        );
+
+        let smoothed = get_json(&app, "/api/volume_indicators?force_period=3&eom_period=2").await;
+        assert_eq!(
+            series(smoothed.clone(), "force_index"),
+            [None, Some(600.0), Some(300.0), Some(150.0), Some(875.0)]
+        );
+        assert_eq!(
+            series(smoothed, "ease_of_movement"),
+            [None, Some(0.04), None, None, Some(0.025)]
+        );
+
+        let response = get_uri(&app, "/api/volume_indicators?eom_period=0").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn adx_and_adxr_warm_up_in_stages() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 9, 10, 8, 9, 1),
+             ('2024-01-01 00:01:00', 11, 12, 9, 11, 1),
+             ('2024-01-01 00:02:00', 12, 13, 11, 12, 1),
+             ('2024-01-01 00:03:00', 10, 12, 10, 10, 1),
+             ('2024-01-01 00:04:00', 13, 14, 11, 13, 1)",
+        ));
+        let field = |points: &serde_json::Value, name: &str| {
+            points
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|point| point[name].as_f64())
+                .collect::<Vec<_>>()
+        };
+
+        let points = get_json(&app, "/api/adx?period=2&adxr_period=1").await;
+        // Sums of the first two movements: TR 3 + 2, +DM 2 + 1, -DM 0.
+        assert_eq!(
+            field(&points, "plus_di"),
+            [None, None, Some(60.0), Some(100.0 / 3.0), Some(44.0)]
+        );
+        assert_eq!(field(&points, "minus_di")[4], Some(8.0));
+        // DX 100 then 20 average to 60; then (60 + 900 / 13) / 2.
+        let adx = field(&points, "adx");
+        assert_eq!(adx[..4], [None, None, None, Some(60.0)]);
+        assert!((adx[4].unwrap() - 840.0 / 13.0).abs() < 1e-9);
+        let adxr = field(&points, "adxr");
+        assert_eq!(adxr[..4], [None; 4]);
+        assert!((adxr[4].unwrap() - (840.0 / 13.0 + 60.0) / 2.0).abs() < 1e-9);
+
+        // ADXR reaches back `period` bars by default, past the data here.
+        let points = get_json(&app, "/api/adx?period=2").await;
+        assert_eq!(field(&points, "adxr"), [None; 5]);
+        let response = get_uri(&app, "/api/adx?period=0").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn pnf_columns_follow_the_closes_in_time_order() {
+        // Inserted out of order: the columns follow timestamps, not rows.
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:03:00', 1, 1, 1, 12, 1),
+             ('2024-01-01 00:00:00', 1, 1, 1, 10, 1),
+             ('2024-01-01 00:01:00', 1, 1, 1, 14, 1),
+             ('2024-01-01 00:02:00', 1, 1, 1, 8, 1)",
+        ));
+        let columns = get_json(&app, "/api/pnf?box_size=2&reversal=2").await;
+        assert_eq!(
+            columns,
+            serde_json::json!([
+                {"direction": "up", "boxes": [10.0, 12.0, 14.0]},
+                {"direction": "down", "boxes": [12.0, 10.0, 8.0]},
+                {"direction": "up", "boxes": [10.0, 12.0]},
+            ])
+        );
+        // At the default three-box reversal the last bounce draws nothing.
+        let columns = get_json(&app, "/api/pnf?box_size=2").await;
+        assert_eq!(columns.as_array().unwrap().len(), 2);
+
+        for uri in [
+            "/api/pnf",
+            "/api/pnf?box_size=0",
+            "/api/pnf?box_size=-1",
+            "/api/pnf?box_size=inf",
+            "/api/pnf?box_size=1&reversal=0",
+            "/api/pnf?box_size=0.00001",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn pnf_takes_the_newest_row_of_a_repeated_timestamp() {
+        // The second 00:01 row replaced the first, whose close must not count.
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 10, 1),
+             ('2024-01-01 00:01:00', 1, 1, 1, 2, 1),
+             ('2024-01-01 00:01:00', 1, 1, 1, 14, 1)",
+        ));
+        let columns = get_json(&app, "/api/pnf?box_size=2&reversal=2").await;
+        assert_eq!(
+            columns,
+            serde_json::json!([{"direction": "up", "boxes": [10.0, 12.0, 14.0]}])
+        );
+    }
+
+    #[tokio::test]
+    async fn formulas_are_evaluated_per_bar_and_never_reach_sql() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 2, 0, 1, 10),
+             ('2024-01-01 00:01:00', 1, 3, 1, 2, 10),
+             ('2024-01-01 00:02:00', 1, 4, 2, 3, 0)",
+        ));
+        let formula = get_json(
+            &app,
+            "/api/formula?expr=(close%20-%20sma_2)%20/%20atr_1&ts_format=unix",
+        )
+        .await;
+        assert_eq!(
+            formula,
+            serde_json::json!([
+                {"timestamp": 1_704_067_200, "value": null},
+                {"timestamp": 1_704_067_260, "value": 0.25},
+                {"timestamp": 1_704_067_320, "value": 0.25},
+            ])
+        );
+        // Dividing by a zero volume leaves that bar empty.
+        let formula = get_json(&app, "/api/formula?expr=close/volume").await;
+        assert_eq!(formula[1]["value"], 0.2);
+        assert_eq!(formula[2]["value"], serde_json::Value::Null);
+
+        for uri in [
+            "/api/formula",
+            "/api/formula?expr=close%27);DROP%20TABLE%20candles;--",
+            "/api/formula?expr=read_csv(1)",
+            "/api/formula?expr=sma_0",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+        assert_eq!(
+            get_json(&app, "/api/candles")
+                .await
+                .as_array()
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn rolling_correlations_match_duckdb_corr() {
+        let rows = [(1, 10), (3, 12), (2, 9), (5, 20), (4, 18), (6, 17), (8, 30)]
+            .iter()
+            .enumerate()
+            .map(|(i, (close, volume))| {
+                format!("('2024-01-01 00:0{i}:00', 1, 9, 0, {close}, {volume})")
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let state = seeded_state(&rows);
+        let expected: Vec<Option<f64>> = state
+            .db
+            .read(|conn| {
+                conn.prepare(
+                    "SELECT CASE WHEN count(*) OVER w = 3 THEN corr(close, volume) OVER w END
+                     FROM candles
+                     WINDOW w AS (ORDER BY timestamp ROWS BETWEEN 2 PRECEDING AND CURRENT ROW)
+                     ORDER BY timestamp",
+                )?
+                .query_map([], |row| row.get(0))?
+                .collect::<duckdb::Result<Vec<_>>>()
+            })
+            .await
+            .unwrap();
+        let app = build_router(state);
+
+        let points = get_json(&app, "/api/rolling_correlation?a=close&b=volume&window=3").await;
+        let points = points.as_array().unwrap();
+        assert_eq!(points.len(), expected.len());
+        for (point, expected) in points.iter().zip(expected) {
+            match expected {
+                Some(expected) => {
+                    let correlation = point["correlation"].as_f64().unwrap();
+                    assert!((correlation - expected).abs() < 1e-9, "{point}");
+                }
+                None => assert!(point["correlation"].is_null(), "{point}"),
+            }
+        }
+
+        // The next bar's return runs out one bar before the data does.
+        let forward = get_json(
+            &app,
+            "/api/rolling_correlation?a=close%20*%202&b=forward_return_1&window=2",
+        )
+        .await;
+        assert!(forward[0]["correlation"].is_null());
+        // Closes 1 then 3 against returns of +200% then -33%.
+        let correlation = forward[1]["correlation"].as_f64().unwrap();
+        assert!((correlation + 1.0).abs() < 1e-9, "{correlation}");
+        assert!(forward[6]["correlation"].is_null());
+
+        for uri in [
+            "/api/rolling_correlation?a=close",
+            "/api/rolling_correlation?a=close&b=volume&window=1",
+            "/api/rolling_correlation?a=close&b=forward_return_0",
+            "/api/rolling_correlation?a=nonsense(1)&b=close",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn non_finite_values_are_sent_as_null() {
+        let state = seeded_state(
+            "('2024-01-01 00:00:00', 1, 2, 1, 1, 10),
+             ('2024-01-01 00:01:00', 1, 'inf', 1, 'nan', 10),
+             ('2024-01-01 00:02:00', 1, 2, 1, 2, '-inf')",
+        );
+        state
+            .db
+            .write(|conn| {
+                initialize_symbols(conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+                conn.execute_batch(
+                    "INSERT INTO symbol_candles VALUES
+                        ('AAA', '2024-01-01 00:00:00', 0, 0, 0, 'inf', 1),
+                        ('BBB', '2024-01-01 00:00:00', 0, 0, 0, 1, 1);",
+                )
+            })
+            .await
+            .unwrap();
+        let app = build_router(state);
+        let null = serde_json::Value::Null;
+
+        // `get_json` fails on anything that is not valid JSON.
+        let candles = get_json(&app, "/api/candles").await;
+        assert_eq!((&candles[1]["high"], &candles[1]["close"]), (&null, &null));
+        assert_eq!(candles[2]["volume"], null);
+        assert_eq!(candles[0]["close"], 1.0);
+        let ndjson = get_uri(&app, "/api/candles?format=ndjson").await;
+        let ndjson = axum::body::to_bytes(ndjson.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        for line in String::from_utf8(ndjson.to_vec()).unwrap().lines() {
+            serde_json::from_str::<serde_json::Value>(line).unwrap();
+        }
+        let csv = get_uri(&app, "/api/candles?format=csv").await;
+        let csv = axum::body::to_bytes(csv.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let csv = String::from_utf8(csv.to_vec()).unwrap();
+        assert_eq!(csv.lines().nth(2), Some("2024-01-01 00:01:00,1,,1,,10"));
+        assert_eq!(csv.lines().nth(3), Some("2024-01-01 00:02:00,1,2,1,2,"));
+
+        let hma = get_json(&app, "/api/indicators?hma=2").await;
+        assert_eq!(hma[1]["hma_2"], null);
+        let volume = get_json(&app, "/api/volume_indicators").await;
+        assert_eq!(volume[1]["force_index"], null);
+        assert_eq!(volume[2]["force_index"], null);
+        let adx = get_json(&app, "/api/adx?period=1").await;
+        assert_eq!(adx[1]["plus_di"], null);
+        let zscores = get_json(&app, "/api/zscore?field=close&window=2").await;
+        assert_eq!(zscores[1]["value"], null);
+        let spread = get_json(&app, "/api/spread?a=AAA&b=BBB").await;
+        assert_eq!(spread[0]["spread"], null);
+        let fib = get_json(&app, "/api/fib").await;
+        assert_eq!(fib["high"], null);
+        assert_eq!(fib["levels"][1]["value"], null);
+        get_json(&app, "/api/percentile?field=volume").await;
+        get_json(&app, "/api/pnf?box_size=1&reversal=1").await;
+    }
+
+    #[tokio::test]
+    async fn generated_demo_data_replaces_the_candles() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let state = AppState::new(state.db, keyed_config());
+        let mut events = state.bus.subscribe();
+        let app = build_router(state.clone());
+        let generate = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let request = Request::post(uri).header("x-api-key", TEST_KEY);
+                let response = app
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, body)
+            }
+        };
+
+        let (status, body) = generate("/api/admin/generate?rows=50&interval=1h&seed=3").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(&body[..], br#"{"rows":50}"#);
+        let first = get_json(&app, "/api/candles?limit=100").await;
+        let candles = first.as_array().unwrap();
+        assert_eq!(candles.len(), 50);
+        assert_eq!(candles[1]["timestamp"], "2024-01-01 01:00:00");
+        assert_eq!(events.try_recv().unwrap(), DataEvent::Reloaded);
+        // The new candles are not reported again as appended.
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 0);
+
+        generate("/api/admin/generate?rows=50&interval=1h&seed=3").await;
+        assert_eq!(get_json(&app, "/api/candles?limit=100").await, first);
+
+        let (status, _) = generate("/api/admin/generate?interval=soon").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn duplicate_timestamps_are_reported_and_never_repeat_in_series() {
+        let rows = "('2024-01-01 00:00:00', 1, 2, 0.5, 1, 10),
+             ('2024-01-01 00:01:00', 1, 3, 0.5, 2, 10),
+             ('2024-01-01 00:02:00', 1, 4, 0.5, 3, 10)";
+        let clean = build_router(seeded_state(rows));
+        let state = seeded_state(rows);
+        // A bad copy of the middle candle, then the corrected one again.
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles VALUES
+                        ('2024-01-01 00:01:00', 1, 9, 0.5, 9, 10),
+                        ('2024-01-01 00:01:00', 1, 3, 0.5, 2, 10);",
+                )
+            })
+            .await
+            .unwrap();
+        let state = AppState::new(state.db, keyed_config());
+        let mut events = state.bus.subscribe();
+        let app = build_router(state);
+        for uri in [
+            "/api/indicators?hma=2",
+            "/api/volume_indicators",
+            "/api/adx?period=1",
+        ] {
+            assert_eq!(
+                get_json(&app, uri).await,
+                get_json(&clean, uri).await,
+                "{uri}"
+            );
+        }
+
+        let report = admin_json(&app, "/api/admin/integrity").await;
+        assert_eq!(
+            report["tables"],
+            serde_json::json!([{
+                "table": "candles",
+                "duplicate_keys": 1,
+                "extra_rows": 2,
+                "samples": [{"timestamp": "2024-01-01 00:01:00", "rows": 3}],
+            }])
+        );
+        let request = Request::post("/api/admin/integrity").header("x-api-key", TEST_KEY);
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let repaired: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(repaired["removed"], 2);
+        assert_eq!(repaired["integrity"]["tables"][0]["duplicate_keys"], 0);
+        assert_eq!(events.try_recv().unwrap(), DataEvent::Reloaded);
+        assert!(events.try_recv().is_err());
+        assert_eq!(
+            get_json(&app, "/api/candles").await,
+            get_json(&clean, "/api/candles").await
+        );
+    }
+
+    #[tokio::test]
+    async fn timestamps_follow_ts_format_and_tz() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+             ('2024-01-01 00:01:00', 1, 1, 1, 2, 1)",
+        ));
+        let first = |body: serde_json::Value| body[0]["timestamp"].clone();
+
+        let text = get_json(&app, "/api/candles").await;
+        assert_eq!(first(text), "2024-01-01 00:00:00");
+        let shifted = get_json(&app, "/api/candles?tz=%2B05:30").await;
+        assert_eq!(first(shifted), "2024-01-01 05:30:00");
+        let iso = get_json(&app, "/api/candles?ts_format=iso&tz=-04:00").await;
+        assert_eq!(first(iso), "2023-12-31T20:00:00-04:00");
+        let utc = get_json(&app, "/api/indicators?ts_format=iso").await;
+        assert_eq!(first(utc), "2024-01-01T00:00:00Z");
+        let unix = get_json(&app, "/api/candles?ts_format=unix&tz=%2B01:00").await;
+        assert_eq!(first(unix), 1_704_067_200);
+        let millis = get_json(&app, "/api/volume_indicators?ts_format=unix_ms").await;
+        assert_eq!(first(millis), 1_704_067_200_000i64);
+
+        let csv = get_uri(&app, "/api/candles?format=csv&ts_format=unix").await;
+        let body = axum::body::to_bytes(csv.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.starts_with(b"timestamp,open,high,low,close,volume\n1704067200,1,"));
+
+        for uri in ["/api/candles?tz=Mars", "/api/candles?ts_format=julian"] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn sub_second_timestamps_keep_their_fraction() {
+        let app = build_router(seeded_state(
+            "('2024-03-15 09:30:00', 1, 1, 1, 1, 1),
+             ('2024-03-15 09:30:00.5', 1, 1, 1, 2, 1),
+             ('2024-03-15 09:30:01.25', 1, 1, 1, 3, 1)",
+        ));
+        let timestamps = |body: serde_json::Value| {
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|candle| candle["timestamp"].clone())
+                .collect::<Vec<_>>()
+        };
+
+        let text = get_json(&app, "/api/candles").await;
+        assert_eq!(
+            timestamps(text),
+            [
+                "2024-03-15 09:30:00",
+                "2024-03-15 09:30:00.500",
+                "2024-03-15 09:30:01.250"
+            ]
+        );
+        let iso = get_json(&app, "/api/candles?ts_format=iso").await;
+        assert_eq!(timestamps(iso)[1], "2024-03-15T09:30:00.500Z");
+        let unix = get_json(&app, "/api/candles?ts_format=unix").await;
+        assert_eq!(
+            timestamps(unix),
+            [
+                serde_json::json!(1_710_495_000),
+                serde_json::json!(1_710_495_000.5),
+                serde_json::json!(1_710_495_001.25),
+            ]
+        );
+        let millis = get_json(&app, "/api/indicators?ts_format=unix_ms").await;
+        assert_eq!(timestamps(millis)[1], 1_710_495_000_500i64);
+
+        // Fractional bounds select within a second.
+        let range = get_json(
+            &app,
+            "/api/candles?start=2024-03-15%2009:30:00.250&end=2024-03-15%2009:30:01.250",
+        )
+        .await;
+        assert_eq!(range.as_array().unwrap().len(), 2);
+        assert_eq!(range[0]["close"], 2.0);
+    }
+
+    #[tokio::test]
+    async fn websocket_backfills_then_pushes_tagged_messages() {
+        use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+
+        let state = AppState {
+            hub: crate::hub::Hub::new(2, crate::bus::Bus::new(crate::bus::BUS_CAPACITY)),
+            ..seeded_state(
+                "('2024-01-01 00:00:00', 1, 1, 1, 1, 1),
+                 ('2024-01-01 00:01:00', 2, 2, 2, 2, 2),
+                 ('2024-01-01 00:02:00', 3, 3, 3, 3, 3)",
+            )
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let insert = |rows: &'static str| {
+            state.db.write(move |conn| {
+                conn.execute_batch(&format!("INSERT INTO candles VALUES {rows}"))
+                    .unwrap()
+            })
+        };
+        state.hub.start(&state.db).await.unwrap();
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{addr}/api/ws?backfill=2&ts_format=unix"
+        ))
+        .await
+        .unwrap();
+        for (timestamp, close) in [(1_704_067_260, 2.0), (1_704_067_320, 3.0)] {
+            let message = next_json(&mut socket).await;
+            assert_eq!(message["type"], "candle");
+            assert_eq!(message["data"]["timestamp"], timestamp);
+            assert_eq!(message["data"]["close"], close);
+        }
+
+        // A newer row for the last bar is an update; a later bar is new.
+        insert("('2024-01-01 00:02:00', 3, 4, 3, 3.5, 5), ('2024-01-01 00:03:00', 4, 4, 4, 4, 4)")
+            .await;
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 2);
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 3.5);
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 4.0);
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 0);
+
+        // Five candles into a two-slot channel: the stream says what it lost.
+        insert(
+            "('2024-01-01 00:04:00', 5, 5, 5, 5, 5), ('2024-01-01 00:05:00', 6, 6, 6, 6, 6),
+             ('2024-01-01 00:06:00', 7, 7, 7, 7, 7), ('2024-01-01 00:07:00', 8, 8, 8, 8, 8),
+             ('2024-01-01 00:08:00', 9, 9, 9, 9, 9)",
+        )
+        .await;
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 5);
+        assert_eq!(
+            next_json(&mut socket).await,
+            serde_json::json!({"type": "gap", "data": {"missed": 3}})
+        );
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 8.0);
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 9.0);
+
+        state.hub.close_streams();
+        match socket.next().await.unwrap().unwrap() {
+            WsMessage::Close(Some(frame)) => assert_eq!(u16::from(frame.code), close_code::AWAY),
+            other => panic!("expected a close frame, got {other:?}"),
+        }
+        tokio::time::timeout(Duration::from_secs(1), state.hub.streams_closed())
+            .await
+            .unwrap();
+
+        let refused =
+            tokio_tungstenite::connect_async(format!("ws://{addr}/api/ws?backfill=10001")).await;
+        let Err(WsError::Http(response)) = refused else {
+            panic!("expected an HTTP error");
+        };
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn websocket_upgrades_past_the_cap_are_refused() {
+        use tokio_tungstenite::tungstenite::Error as WsError;
+
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let config = Config {
+            max_websockets: Some(1),
+            ..keyed_config()
+        };
+        let state = AppState::new(state.db, config);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let connect = || tokio_tungstenite::connect_async(format!("ws://{addr}/api/ws"));
+
+        let (mut first, _) = connect().await.unwrap();
+        let Err(WsError::Http(response)) = connect().await else {
+            panic!("expected an HTTP error");
+        };
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let stats = admin_json(&build_router(state.clone()), "/api/admin/stats").await;
+        assert_eq!(
+            stats["websockets"],
+            serde_json::json!({"open": 1, "limit": 1})
+        );
+
+        first.close(None).await.unwrap();
+        while first.next().await.is_some() {}
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while state.websockets.open() > 0 {
+            assert!(
+                Instant::now() < deadline,
+                "the closed socket is still counted"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        connect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn websocket_replays_history_at_the_requested_pace() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+
+        // One more candle than a chunk, a minute apart from 1 March 2024.
+        let state = seeded_state("('2024-02-29 23:59:00', 0, 0, 0, 0, 0)");
+        state
+            .db
+            .write(|conn| {
+                conn.execute(
+                    "INSERT INTO candles
+                     SELECT TIMESTAMP '2024-03-01' + INTERVAL (i) MINUTE, i, i, i, i, i
+                     FROM range(?) AS t(i)",
+                    [REPLAY_CHUNK + 1],
+                )
+                .unwrap()
+            })
+            .await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let connect =
+            |query: &str| tokio_tungstenite::connect_async(format!("ws://{addr}/api/ws?{query}"));
+
+        let (mut socket, _) = connect("replay_from=2024-03-01&speed=max").await.unwrap();
+        for close in 0..=REPLAY_CHUNK {
+            let message = next_json(&mut socket).await;
+            assert_eq!(message["type"], "candle");
+            assert_eq!(message["data"]["close"], close as f64);
+        }
+        assert_eq!(
+            next_json(&mut socket).await,
+            serde_json::json!({"type": "replay_done"})
+        );
+        match socket.next().await.unwrap().unwrap() {
+            WsMessage::Close(Some(frame)) => assert_eq!(u16::from(frame.code), close_code::NORMAL),
+            other => panic!("expected a close frame, got {other:?}"),
+        }
+
+        // Through the candle at 00:01, inclusive.
+        let (mut socket, _) =
+            connect("replay_from=2024-03-01&replay_until=2024-03-01T00:01:00Z&speed=max")
+                .await
+                .unwrap();
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 0.0);
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 1.0);
+        assert_eq!(next_json(&mut socket).await["type"], "replay_done");
+
+        // `/ws/replay` names the same bounds `start` and `end`.
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{addr}/ws/replay?start=2024-03-01&end=2024-03-01T00:01:00Z&speed=max"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 0.0);
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 1.0);
+        assert_eq!(next_json(&mut socket).await["type"], "replay_done");
+        let Err(WsError::Http(response)) =
+            tokio_tungstenite::connect_async(format!("ws://{addr}/ws/replay?speed=max")).await
+        else {
+            panic!("expected an HTTP error without start");
+        };
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Two more candles at the last timestamp of the first chunk are
+        // replayed from the next one.
+        state
+            .db
+            .write(|conn| {
+                conn.execute(
+                    "INSERT INTO candles
+                     SELECT TIMESTAMP '2024-03-01' + INTERVAL (?) MINUTE, c, c, c, c, c
+                     FROM (VALUES (-1), (-2)) AS t(c)",
+                    [REPLAY_CHUNK - 1],
+                )
+                .unwrap()
+            })
+            .await;
+        let (mut socket, _) = connect("replay_from=2024-03-01&speed=max").await.unwrap();
+        let mut closes = Vec::new();
+        loop {
+            let message = next_json(&mut socket).await;
+            match message["type"].as_str() {
+                Some("candle") => closes.push(message["data"]["close"].as_f64().unwrap()),
+                _ => break,
+            }
+        }
+        assert_eq!(closes.len() as i64, REPLAY_CHUNK + 3);
+        assert_eq!(
+            closes[REPLAY_CHUNK as usize..],
+            [-1.0, -2.0, REPLAY_CHUNK as f64]
+        );
+
+        // At 600x the minute between candles takes 100ms, and none of it
+        // passes while paused.
+        let (mut socket, _) = connect("replay_from=2024-03-01&speed=600").await.unwrap();
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 0.0);
+        socket
+            .send(WsMessage::Text(r#"{"cmd":"pause"}"#.into()))
+            .await
+            .unwrap();
+        let paused = tokio::time::timeout(Duration::from_millis(300), socket.next()).await;
+        assert!(paused.is_err(), "sent while paused: {paused:?}");
+        socket
+            .send(WsMessage::Text(r#"{"cmd":"resume"}"#.into()))
+            .await
+            .unwrap();
+        let started = Instant::now();
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 1.0);
+        assert_eq!(next_json(&mut socket).await["data"]["close"], 2.0);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        state.hub.close_streams();
+        loop {
+            match socket.next().await.unwrap().unwrap() {
+                WsMessage::Close(Some(frame)) => {
+                    assert_eq!(u16::from(frame.code), close_code::AWAY);
+                    break;
+                }
+                WsMessage::Text(_) => continue,
+                other => panic!("expected a close frame, got {other:?}"),
+            }
+        }
+
+        for query in [
+            "replay_from=2024-03-01&backfill=5",
+            "replay_from=2024-03-01&speed=0",
+            "replay_from=2024-03-01&speed=fast",
+            "replay_from=soon",
+            "replay_from=2024-03-02&replay_until=2024-03-01",
+            "replay_until=2024-03-01",
+            "speed=10",
+        ] {
+            let Err(WsError::Http(response)) = connect(query).await else {
+                panic!("expected an HTTP error for {query}");
+            };
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed_indicators_match_the_batch_endpoint() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        const SUBSCRIBE: &str =
+            r#"{"cmd": "subscribe", "indicators": {"rsi": [14], "ema": [21], "sma": [5]}}"#;
+        const KEYS: [&str; 3] = ["sma_5", "ema_21", "rsi_14"];
+
+        // A jagged series, a minute apart, with history before the replay.
+        let state = seeded_state("('2024-02-29 23:00:00', 100, 100, 100, 100, 1)");
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles
+                     SELECT TIMESTAMP '2024-02-29 23:01:00' + INTERVAL (i) MINUTE,
+                            c, c + 1, c - 1, c, 1
+                     FROM (SELECT i, 100 + (i * 7) % 11 - (i % 3) * 2.5 AS c FROM range(90) t(i))",
+                )
+            })
+            .await
+            .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = build_router(state.clone());
+        tokio::spawn({
+            let app = app.clone();
+            async move { axum::serve(listener, app).await.unwrap() }
+        });
+        let connect =
+            |query: &str| tokio_tungstenite::connect_async(format!("ws://{addr}/api/ws?{query}"));
+        let batch = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                get_json(&app, uri)
+                    .await
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|point| {
+                        (
+                            point["timestamp"].as_str().unwrap().to_owned(),
+                            point.clone(),
+                        )
+                    })
+                    .collect::<HashMap<_, _>>()
+            }
+        };
+        let matches = |batch: &HashMap<String, serde_json::Value>, streamed: &serde_json::Value| {
+            let expected = &batch[streamed["timestamp"].as_str().unwrap()];
+            for key in KEYS {
+                assert_eq!(streamed[key], expected[key], "{key} at {streamed}");
+            }
+        };
+
+        // Replayed from midway, however far the replay has got when the
+        // subscription arrives, every indicator message follows its candle
+        // and matches the batch values.
+        let expected = batch("/api/indicators?sma=5&ema=21").await;
+        let (mut socket, _) = connect("replay_from=2024-03-01&speed=max").await.unwrap();
+        socket
+            .send(WsMessage::Text(SUBSCRIBE.into()))
+            .await
+            .unwrap();
+        let (mut candles, mut checked) = (0, 0);
+        let mut last_candle = None;
+        loop {
+            let message = next_json(&mut socket).await;
+            match message["type"].as_str().unwrap() {
+                "candle" => {
+                    candles += 1;
+                    last_candle = Some(message["data"]["timestamp"].clone());
+                }
+                "indicator" => {
+                    assert_eq!(Some(&message["data"]["timestamp"]), last_candle.as_ref());
+                    matches(&expected, &message["data"]);
+                    checked += 1;
+                }
+                "replay_done" => break,
+                other => panic!("unexpected {other}"),
+            }
+        }
+        assert_eq!(candles, 31);
+        assert!(checked > 20, "only {checked} indicator messages");
+
+        // Live, a new candle and then a newer row for it each bring values.
+        let (mut socket, _) = connect("").await.unwrap();
+        socket
+            .send(WsMessage::Text(
+                r#"{"cmd": "subscribe", "indicators": {"sma": [0]}}"#.into(),
+            ))
+            .await
+            .unwrap();
+        let refused = next_json(&mut socket).await;
+        assert_eq!(refused["type"], "error");
+        assert!(
+            refused["data"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("sma period 0"),
+            "{refused}"
+        );
+        socket
+            .send(WsMessage::Text(SUBSCRIBE.into()))
+            .await
+            .unwrap();
+        state.hub.start(&state.db).await.unwrap();
+        for close in [90.0, 120.0] {
+            state
+                .db
+                .write(move |conn| {
+                    conn.execute(
+                        "INSERT INTO candles VALUES ('2024-03-01 00:30:00', ?, ?, ?, ?, 1)",
+                        [close, close, close, close],
+                    )
+                })
+                .await
+                .unwrap();
+            // The subscription is read before the first update arrives.
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            state.hub.poll(&state.db).await.unwrap();
+            assert_eq!(next_json(&mut socket).await["data"]["close"], close);
+            let streamed = next_json(&mut socket).await;
+            assert_eq!(streamed["type"], "indicator");
+            matches(
+                &batch("/api/indicators?sma=5&ema=21").await,
+                &streamed["data"],
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn server_sent_events_resume_after_the_last_event_id() {
+        /// The `event`, `id` and `data` lines of the next event, skipping
+        /// keep-alive comments.
+        async fn next_event(
+            body: &mut (impl futures_util::Stream<Item = Result<Bytes, axum::Error>> + Unpin),
+        ) -> (String, Option<String>, serde_json::Value) {
+            loop {
+                let chunk = body.next().await.unwrap().unwrap();
+                let text = std::str::from_utf8(&chunk).unwrap();
+                let field = |name: &str| {
+                    text.lines()
+                        .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+                        .map(str::to_string)
+                };
+                if let Some(event) = field("event") {
+                    let data = serde_json::from_str(&field("data").unwrap()).unwrap();
+                    return (event, field("id"), data);
+                }
+            }
+        }
+
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = build_router(state.clone());
+        state.hub.start(&state.db).await.unwrap();
+        let response = get_uri(&app, "/api/sse?ts_format=unix").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/event-stream");
+        let mut body = response.into_body().into_data_stream();
+
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles VALUES ('2024-01-01 00:01:00', 2, 2, 2, 2, 2)",
+                )
+                .unwrap()
+            })
+            .await;
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 1);
+        let (event, candle_id, data) = next_event(&mut body).await;
+        assert_eq!(event, "candle");
+        assert_eq!(data["timestamp"], 1_704_067_260);
+        assert_eq!(data["close"], 2.0);
+        state
+            .hub
+            .mark_replaced(&mut *state.hub.hold_watermark().await, None);
+        let (event, changed_id, data) = next_event(&mut body).await;
+        assert_eq!(
+            (event.as_str(), data),
+            ("data_changed", serde_json::json!({}))
+        );
+
+        // Reconnecting replays only what came after the id it last saw.
+        let candle_id = candle_id.unwrap();
+        let response = get_with(
+            &app,
+            "/api/sse",
+            &[(LAST_EVENT_ID.parse().unwrap(), &candle_id)],
+        )
+        .await;
+        let mut resumed = response.into_body().into_data_stream();
+        let (event, id, _) = next_event(&mut resumed).await;
+        assert_eq!((event.as_str(), id), ("data_changed", changed_id));
+
+        // An id it cannot resume from means refetching everything.
+        let response = get_with(
+            &app,
+            "/api/sse",
+            &[(LAST_EVENT_ID.parse().unwrap(), "nonsense")],
+        )
+        .await;
+        let mut stale = response.into_body().into_data_stream();
+        let (event, id, _) = next_event(&mut stale).await;
+        assert_eq!((event.as_str(), id), ("data_changed", None));
+
+        // Shutdown ends every stream instead of waiting on it.
+        state.hub.close_streams();
+        for stream in [&mut body, &mut resumed, &mut stale] {
+            assert!(stream.next().await.is_none());
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! The outbound HTTP client behind backfill and alert webhooks.
+//!
+//! It speaks plain HTTP/1.1 over TCP, one connection per request, closed
+//! once the answer is read, straight to the URL's host. There is no TLS in
+//! this build and no proxy support: to reach an `https` service the operator
+//! runs a TLS-terminating forwarder, such as stunnel in client mode, and
+//! points the `http://` URL at it.
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// An answer from the other side.
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    /// `Retry-After`, when given in seconds.
+    pub(crate) retry_after: Option<Duration>,
+    pub(crate) body: Vec<u8>,
+}
+
+/// How long an exchange may take and how large its answer may be.
+#[derive(Clone, Copy)]
+pub(crate) struct Limits {
+    pub(crate) timeout: Duration,
+    pub(crate) max_bytes: u64,
+}
+
+/// `GET url`, asking for JSON.
+pub(crate) async fn get(
+    url: &str,
+    authorization: Option<&str>,
+    limits: Limits,
+) -> Result<Response, String> {
+    let mut headers = "Accept: application/json\r\n".to_owned();
+    if let Some(authorization) = authorization {
+        headers.push_str(&format!("Authorization: {authorization}\r\n"));
+    }
+    exchange("GET", url, &headers, &[], limits).await
+}
+
+/// `POST url` with a JSON `body`.
+pub(crate) async fn post_json(url: &str, body: &[u8], limits: Limits) -> Result<Response, String> {
+    let headers = format!(
+        "Content-Type: application/json\r\nContent-Length: {}\r\n",
+        body.len()
+    );
+    exchange("POST", url, &headers, body, limits).await
+}
+
+/// Checks that `url` is one this client can reach.
+pub(crate) fn check_url(url: &str) -> Result<(), String> {
+    target(url).map(|_| ())
+}
+
+/// The `host:port` to connect to, the `Host` header and the request target
+/// of an `http://` URL.
+fn target(url: &str) -> Result<(String, &str, String), String> {
+    let rest = url.strip_prefix("http://").ok_or("not an http:// URL")?;
+    // Written into the request line and `Host` as is, where a line break
+    // would start a header of its own.
+    if rest.contains(|c: char| c.is_whitespace() || c.is_control()) {
+        return Err("the URL contains whitespace or control characters".to_owned());
+    }
+    let (authority, target) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    if authority.is_empty() || authority.contains('@') {
+        return Err("the URL has no usable host".to_owned());
+    }
+    let target = match target {
+        "" => "/".to_owned(),
+        query if query.starts_with('?') => format!("/{query}"),
+        path => path.to_owned(),
+    };
+    // A port follows the last `:`, unless that is inside an IPv6 address.
+    let address = match authority.rfind(':') > authority.rfind(']') {
+        true => authority.to_owned(),
+        false => format!("{authority}:80"),
+    };
+    Ok((address, authority, target))
+}
+
+async fn exchange(
+    method: &str,
+    url: &str,
+    headers: &str,
+    body: &[u8],
+    limits: Limits,
+) -> Result<Response, String> {
+    let (address, authority, target) = target(url)?;
+    let mut request = format!(
+        "{method} {target} HTTP/1.1\r\nHost: {authority}\r\n{headers}Connection: close\r\n\r\n"
+    )
+    .into_bytes();
+    request.extend_from_slice(body);
+
+    let Limits { timeout, max_bytes } = limits;
+    let exchange = async {
+        let mut stream = TcpStream::connect(&address).await?;
+        stream.write_all(&request).await?;
+        let mut response = Vec::new();
+        stream
+            .take(max_bytes + 1)
+            .read_to_end(&mut response)
+            .await?;
+        Ok::<_, std::io::Error>(response)
+    };
+    let response = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| format!("no answer within {timeout:?}"))?
+        .map_err(|err| err.to_string())?;
+    if response.len() as u64 > max_bytes {
+        return Err(format!("the answer is over {max_bytes} bytes"));
+    }
+    parse_response(&response)
+}
+
+/// Splits a raw HTTP/1.1 answer into its status, `Retry-After` and body,
+/// undoing chunked transfer encoding.
+fn parse_response(response: &[u8]) -> Result<Response, String> {
+    let head_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or("the answer has no end of headers")?;
+    let head = std::str::from_utf8(&response[..head_end]).map_err(|_| "headers are not UTF-8")?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or("malformed status line")?;
+    let (mut length, mut chunked, mut retry_after) = (None, false, None);
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => length = value.parse::<usize>().ok(),
+            "transfer-encoding" => chunked = value.to_ascii_lowercase().contains("chunked"),
+            "retry-after" => retry_after = value.parse().ok().map(Duration::from_secs),
+            _ => {}
+        }
+    }
+    let rest = &response[head_end + 4..];
+    let body = match (chunked, length) {
+        (true, _) => dechunk(rest)?,
+        (false, Some(length)) => rest.get(..length).ok_or("the answer ended early")?.to_vec(),
+        (false, None) => rest.to_vec(),
+    };
+    Ok(Response {
+        status,
+        retry_after,
+        body,
+    })
+}
+
+/// The body a chunked transfer encoding carries.
+fn dechunk(mut data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or("truncated chunked body")?;
+        let size = std::str::from_utf8(&data[..line_end])
+            .ok()
+            .and_then(|line| line.split(';').next())
+            .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+            .ok_or("malformed chunk size")?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        body.extend_from_slice(data.get(..size).ok_or("truncated chunked body")?);
+        data = data.get(size + 2..).ok_or("truncated chunked body")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunked_answers_are_reassembled() {
+        let raw = b"HTTP/1.1 503 Service Unavailable\r\nTransfer-Encoding: chunked\r\n\
+                    Retry-After: 7\r\n\r\n4\r\n[1, \r\n3;ext=1\r\n2]\n\r\n0\r\n\r\n";
+        let response = parse_response(raw).unwrap();
+        assert_eq!(response.status, 503);
+        assert_eq!(response.retry_after, Some(Duration::from_secs(7)));
+        assert_eq!(response.body, b"[1, 2]\n");
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n[]").is_err());
+    }
+
+    #[test]
+    fn urls_split_into_address_host_and_target() {
+        let split =
+            |url| target(url).map(|(address, host, target)| (address, host.to_owned(), target));
+        assert_eq!(
+            split("http://example.com?a=1"),
+            Ok((
+                "example.com:80".to_owned(),
+                "example.com".to_owned(),
+                "/?a=1".to_owned()
+            ))
+        );
+        assert_eq!(
+            split("http://[::1]:9000/hook").unwrap().0,
+            "[::1]:9000".to_owned()
+        );
+        assert!(check_url("https://example.com/hook").is_err());
+        assert!(check_url("http:///hook").is_err());
+        for url in [
+            "http://example.com/hook\r\nX-Injected: 1",
+            "http://example.com/hook?a=1\nb",
+            "http://example.com/a b",
+            "http://exa mple.com/hook",
+            "http://example.com/hook\t",
+            "http://example.com/\u{7f}",
+        ] {
+            assert!(check_url(url).is_err(), "{url:?}");
+        }
+    }
+}
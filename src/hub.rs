@@ -0,0 +1,436 @@
+//! Polls for new and updated candles and fans them out to streaming clients.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use duckdb::{params, Connection};
+use tokio::sync::{broadcast, watch, MutexGuard};
+use tokio::time::MissedTickBehavior;
+
+use crate::bus::{Bus, DataEvent};
+use crate::db::Db;
+use crate::handlers::candle_from_row;
+use crate::models::Candle;
+
+/// Messages buffered per subscriber before a slow client starts lagging.
+pub(crate) const HUB_CAPACITY: usize = 1024;
+
+/// Published updates kept for clients that reconnect with the last id they saw.
+pub(crate) const RECENT_UPDATES: usize = 256;
+
+/// What streaming clients are told about.
+#[derive(Clone, PartialEq)]
+pub(crate) enum Update {
+    /// A new candle, or a newer row for the latest one.
+    Candle(Candle),
+    /// Candles were replaced or repaired in bulk; anything derived from them
+    /// should be fetched again.
+    DataChanged,
+}
+
+/// An [`Update`] and its place in the sequence.
+#[derive(Clone, PartialEq)]
+pub(crate) struct Published {
+    pub(crate) id: u64,
+    pub(crate) update: Update,
+}
+
+/// Where a subscriber starts: the retained updates it asked to resume after,
+/// and the live ones that follow them without overlap.
+pub(crate) struct Subscription {
+    pub(crate) replay: Vec<Published>,
+    /// The updates after the requested id are no longer all retained (or the
+    /// id was never handed out by this process), so some were missed.
+    pub(crate) missed: bool,
+    pub(crate) live: broadcast::Receiver<Published>,
+}
+
+struct Recent {
+    next_id: u64,
+    updates: VecDeque<Published>,
+}
+
+/// Fans newly arrived candles out to every streaming client.
+///
+/// A single poller watches the candles table and publishes each new row once;
+/// the WebSocket and SSE handlers only subscribe, so DB load stays constant no
+/// matter how many clients are connected.
+#[derive(Clone)]
+pub(crate) struct Hub {
+    updates: broadcast::Sender<Published>,
+    /// Held while publishing, so a subscriber sees each update exactly once
+    /// across its replay and its receiver.
+    recent: Arc<Mutex<Recent>>,
+    /// Told about every change to the stored candles.
+    bus: Bus,
+    /// The newest main-series candle published, which polling looks past.
+    watermark: Arc<tokio::sync::Mutex<Option<Candle>>>,
+    /// Set on shutdown; every open stream holds a receiver until it is done.
+    closing: Arc<watch::Sender<bool>>,
+}
+
+impl Hub {
+    pub(crate) fn new(capacity: usize, bus: Bus) -> Self {
+        let (updates, _) = broadcast::channel(capacity);
+        let (closing, _) = watch::channel(false);
+        // Ids start at the clock, so ones handed out before a restart are
+        // older than any retained and never mistaken for a resumable position.
+        let next_id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        Self {
+            updates,
+            recent: Arc::new(Mutex::new(Recent {
+                next_id,
+                updates: VecDeque::with_capacity(RECENT_UPDATES),
+            })),
+            bus,
+            watermark: Arc::default(),
+            closing: Arc::new(closing),
+        }
+    }
+
+    /// Held by every write to the candles tables from the write until its
+    /// changes are published, so polling never reports a row the writer
+    /// reports too and events go out in commit order.
+    pub(crate) async fn hold_watermark(&self) -> MutexGuard<'_, Option<Candle>> {
+        self.watermark.lock().await
+    }
+
+    /// Publishes candles just stored for `symbol` (`None` for the main
+    /// table), each flagged with whether it replaced a stored row. Streaming
+    /// clients get the main-series ones that are not older than `watermark`,
+    /// which then advances to the newest of them.
+    pub(crate) fn stored(
+        &self,
+        watermark: &mut Option<Candle>,
+        symbol: Option<&str>,
+        candles: Vec<(Candle, bool)>,
+    ) {
+        self.bus.publish(DataEvent::stored(
+            symbol,
+            candles.iter().map(|(candle, replaced)| (candle, *replaced)),
+        ));
+        if symbol.is_some() {
+            return;
+        }
+        for (candle, _) in candles {
+            let since = watermark.as_ref().map(|candle| candle.timestamp.at);
+            if since.is_some_and(|since| candle.timestamp.at < since) {
+                continue;
+            }
+            *watermark = Some(candle.clone());
+            self.publish(Update::Candle(candle));
+        }
+    }
+
+    /// Tells subscribers the data changed wholesale, after a bulk load or
+    /// repair made under `watermark`, which becomes `latest`.
+    pub(crate) fn mark_replaced(&self, watermark: &mut Option<Candle>, latest: Option<Candle>) {
+        *watermark = latest;
+        self.bus.publish([DataEvent::Reloaded]);
+        self.publish(Update::DataChanged);
+    }
+
+    /// Sends streaming clients a bar that is not stored yet, such as one
+    /// still forming from posted ticks.
+    pub(crate) fn push(&self, candle: Candle) {
+        self.publish(Update::Candle(candle));
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Published> {
+        self.updates.subscribe()
+    }
+
+    /// Subscribes, first replaying the retained updates after `last_id`.
+    /// Without a `last_id` there is nothing to replay.
+    pub(crate) fn subscribe_after(&self, last_id: Option<u64>) -> Subscription {
+        let recent = self.recent.lock().expect("hub history poisoned");
+        let live = self.updates.subscribe();
+        let Some(last_id) = last_id else {
+            return Subscription {
+                replay: Vec::new(),
+                missed: false,
+                live,
+            };
+        };
+        let oldest = recent
+            .updates
+            .front()
+            .map_or(recent.next_id, |update| update.id);
+        Subscription {
+            replay: recent
+                .updates
+                .iter()
+                .filter(|update| update.id > last_id)
+                .cloned()
+                .collect(),
+            missed: last_id.saturating_add(1) < oldest || last_id >= recent.next_id,
+            live,
+        }
+    }
+
+    fn publish(&self, update: Update) {
+        let mut recent = self.recent.lock().expect("hub history poisoned");
+        let published = Published {
+            id: recent.next_id,
+            update,
+        };
+        recent.next_id += 1;
+        if recent.updates.len() == RECENT_UPDATES {
+            recent.updates.pop_front();
+        }
+        recent.updates.push_back(published.clone());
+        // No subscribers is fine; the update is simply not delivered.
+        let _ = self.updates.send(published);
+    }
+
+    /// Held by each stream; changes to `true` once the server is shutting down.
+    pub(crate) fn closing(&self) -> watch::Receiver<bool> {
+        self.closing.subscribe()
+    }
+
+    /// Asks every open stream to say goodbye and finish.
+    pub(crate) fn close_streams(&self) {
+        self.closing.send_replace(true);
+    }
+
+    /// Resolves once every stream has dropped its [`closing`](Self::closing)
+    /// receiver.
+    pub(crate) async fn streams_closed(&self) {
+        self.closing.closed().await;
+    }
+
+    /// Polls for changes after the latest candle present at startup until
+    /// the process exits.
+    pub(crate) async fn run(self, db: Arc<Db>, every: Duration) {
+        if let Err(err) = self.start(&db).await {
+            tracing::error!("hub failed to read initial watermark: {err}");
+        }
+        let mut ticker = tokio::time::interval(every);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = self.poll(&db).await {
+                tracing::warn!("hub poll failed: {err}");
+            }
+        }
+    }
+
+    /// Sets the watermark to the latest stored candle, so polling only
+    /// publishes what arrives from now on.
+    pub(crate) async fn start(&self, db: &Arc<Db>) -> duckdb::Result<()> {
+        let mut watermark = self.hold_watermark().await;
+        *watermark = db.read(latest_candle).await?;
+        Ok(())
+    }
+
+    /// Publishes every candle after the watermark, the last one published,
+    /// and the watermark's own timestamp again if a newer row has revised
+    /// it, then advances it. Re-reading the last bar is what pushes updates
+    /// to a bar that is still forming; revisions of older bars are not
+    /// looked for.
+    pub(crate) async fn poll(&self, db: &Arc<Db>) -> duckdb::Result<usize> {
+        let mut watermark = self.hold_watermark().await;
+        let since = watermark.as_ref().map(|candle| candle.timestamp);
+        let mut fresh = db
+            .read(move |conn| {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT timestamp, open, high, low, close, volume
+                     FROM candles
+                     WHERE ? IS NULL OR timestamp >= ?
+                     ORDER BY timestamp, rowid DESC",
+                )?;
+                let candles = stmt
+                    .query_map(params![since, since], candle_from_row)?
+                    .collect::<duckdb::Result<Vec<_>>>();
+                candles
+            })
+            .await?;
+        // The last-ingested row of each timestamp is the current one.
+        fresh.dedup_by_key(|candle| candle.timestamp);
+        if fresh
+            .first()
+            .is_some_and(|first| Some(first) == watermark.as_ref())
+        {
+            fresh.remove(0);
+        }
+        let count = fresh.len();
+        let fresh = fresh
+            .into_iter()
+            .map(|candle| {
+                let revised = since.is_some_and(|since| since.at == candle.timestamp.at);
+                (candle, revised)
+            })
+            .collect();
+        self.stored(&mut watermark, None, fresh);
+        Ok(count)
+    }
+}
+
+/// Open WebSocket connections, so a cap can refuse upgrades past it.
+#[derive(Debug, Default)]
+pub(crate) struct WebSockets {
+    open: AtomicUsize,
+}
+
+impl WebSockets {
+    pub(crate) fn open(&self) -> usize {
+        self.open.load(Ordering::Relaxed)
+    }
+
+    /// Counts one more connection unless `limit` are already open. The
+    /// connection is counted until the returned guard is dropped.
+    pub(crate) fn try_open(self: &Arc<Self>, limit: Option<usize>) -> Option<WebSocketSlot> {
+        self.open
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                limit.is_none_or(|limit| open < limit).then_some(open + 1)
+            })
+            .ok()?;
+        Some(WebSocketSlot(Arc::clone(self)))
+    }
+}
+
+/// One connection counted in [`WebSockets`].
+pub(crate) struct WebSocketSlot(Arc<WebSockets>);
+
+impl Drop for WebSocketSlot {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The current row of the newest timestamp, where polling starts.
+pub(crate) fn latest_candle(conn: &Connection) -> duckdb::Result<Option<Candle>> {
+    conn.prepare_cached(
+        "SELECT timestamp, open, high, low, close, volume
+         FROM candles
+         ORDER BY timestamp DESC, rowid DESC
+         LIMIT 1",
+    )?
+    .query_map([], candle_from_row)?
+    .next()
+    .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+    use crate::bus::BUS_CAPACITY;
+    use crate::test_support::*;
+
+    #[tokio::test]
+    async fn hub_publishes_each_new_candle_once_to_every_subscriber() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        state.hub.start(&state.db).await.unwrap();
+        let mut first = state.hub.subscribe();
+        let mut second = state.hub.subscribe();
+
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 0);
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles VALUES
+                        ('2024-01-01 00:01:00', 2, 2, 2, 2, 2),
+                        ('2024-01-01 00:02:00', 3, 3, 3, 3, 3);",
+                )
+                .unwrap()
+            })
+            .await;
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 2);
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 0);
+
+        for subscriber in [&mut first, &mut second] {
+            for timestamp in ["2024-01-01 00:01:00", "2024-01-01 00:02:00"] {
+                let Update::Candle(candle) = subscriber.recv().await.unwrap().update else {
+                    panic!("expected a candle");
+                };
+                assert_eq!(candle.timestamp.to_string(), timestamp);
+            }
+            assert!(subscriber.try_recv().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn polling_publishes_appends_and_revisions_of_the_last_bar() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        state.hub.start(&state.db).await.unwrap();
+        let mut events = state.bus.subscribe();
+        let at = |value| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S").unwrap();
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles VALUES
+                        ('2024-01-01 00:00:00', 1, 2, 1, 2, 1),
+                        ('2024-01-01 00:01:00', 2, 2, 2, 2, 2),
+                        ('2024-01-01 00:02:00', 3, 3, 3, 3, 3);",
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 3);
+        assert_eq!(state.bus.data_version(), 1);
+        assert_eq!(
+            events.recv().await.unwrap(),
+            DataEvent::CandlesAppended {
+                symbol: None,
+                from: at("2024-01-01 00:01:00"),
+                to: at("2024-01-01 00:02:00"),
+                count: 2,
+            }
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            DataEvent::CandlesModified {
+                symbol: None,
+                from: at("2024-01-01 00:00:00"),
+                to: at("2024-01-01 00:00:00"),
+                count: 1,
+            }
+        );
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 0);
+        assert!(events.try_recv().is_err());
+        assert_eq!(state.bus.data_version(), 1);
+    }
+
+    #[tokio::test]
+    async fn subscribers_resume_after_the_last_id_they_saw() {
+        let hub = Hub::new(HUB_CAPACITY, Bus::new(BUS_CAPACITY));
+        let fresh = hub.subscribe_after(None);
+        assert!(fresh.replay.is_empty() && !fresh.missed);
+        for _ in 0..RECENT_UPDATES + 2 {
+            hub.mark_replaced(&mut None, None);
+        }
+        let ids: Vec<u64> = hub
+            .subscribe_after(Some(0))
+            .replay
+            .iter()
+            .map(|published| published.id)
+            .collect();
+        assert_eq!(ids.len(), RECENT_UPDATES);
+        let (oldest, newest) = (ids[0], ids[ids.len() - 1]);
+        assert!(ids.windows(2).all(|pair| pair[1] == pair[0] + 1));
+
+        let resumed = hub.subscribe_after(Some(newest - 2));
+        assert_eq!(resumed.replay.len(), 2);
+        assert!(!resumed.missed);
+        assert!(!hub.subscribe_after(Some(oldest - 1)).missed);
+        assert!(hub.subscribe_after(Some(newest)).replay.is_empty());
+        // Evicted, or from before a restart, or never handed out.
+        assert!(hub.subscribe_after(Some(oldest - 2)).missed);
+        assert!(hub.subscribe_after(Some(0)).missed);
+        assert!(hub.subscribe_after(Some(newest + 1)).missed);
+
+        let mut live = resumed.live;
+        hub.mark_replaced(&mut None, None);
+        let next = live.recv().await.unwrap();
+        assert_eq!(next.id, newest + 1);
+        assert!(next.update == Update::DataChanged);
+    }
+}
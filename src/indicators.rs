@@ -0,0 +1,1930 @@
+//! Moving averages and oscillators over the candles table.
+//!
+//! Databases loaded before ingest deduplicated can hold several candles for
+//! one timestamp (see [`crate::db::integrity_report`]). Every scan here reads
+//! them newest first, ordered by `timestamp, rowid DESC`, and keeps only the
+//! first of each run, so a series never repeats a timestamp.
+
+use std::collections::VecDeque;
+
+use std::sync::Arc;
+
+use duckdb::{params, Connection};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+
+use crate::bus::DataEvent;
+use crate::db::Db;
+use crate::models::{
+    AdxPoint, Candle, CmoPoint, DpoPoint, HullAverages, IndicatorPoint, KamaPoint, PeriodIndicator,
+    PeriodValues, RollingPricePoint, StcPoint, StdDevPoint, Timestamp, VolumeIndicatorPoint,
+    VortexPoint,
+};
+
+/// Indicator windows served by `/api/indicators` and the candles each needs
+/// before it produces a full-period value (RSI needs `period` price changes).
+const REQUIREMENTS: [(&str, usize); 3] = [
+    ("SMA (14)", PERIOD),
+    ("EMA (14)", PERIOD),
+    ("RSI (14)", PERIOD + 1),
+];
+
+/// Lookback window shared by every indicator.
+pub const PERIOD: usize = 14;
+
+/// One warning per indicator, including the Hull Moving Averages for
+/// `hma_periods` and the chosen-period `periods`, that `available` candles
+/// cannot fully warm up.
+pub fn insufficient_data_warnings(
+    available: usize,
+    hma_periods: &[usize],
+    periods: &[(PeriodIndicator, usize)],
+) -> Vec<String> {
+    let hma = hma_periods.iter().map(|&period| {
+        (
+            format!("HMA ({period})"),
+            period + hull_smoothing(period) - 1,
+        )
+    });
+    let chosen = periods.iter().map(|&(indicator, period)| {
+        let name = indicator.name().to_uppercase();
+        let required = match indicator {
+            PeriodIndicator::Rsi => period + 1,
+            PeriodIndicator::Sma | PeriodIndicator::Ema => period,
+        };
+        (format!("{name} ({period})"), required)
+    });
+    REQUIREMENTS
+        .iter()
+        .map(|&(name, required)| (name.to_owned(), required))
+        .chain(hma)
+        .chain(chosen)
+        .filter(|(_, required)| available < *required)
+        .map(|(name, required)| format!("{name}: only {available} of {required} required candles"))
+        .collect()
+}
+
+/// EMA smoothing for `PERIOD`, at the precision the original SQL used.
+const EMA_ALPHA: f64 = 0.133333333333;
+const EMA_DECAY: f64 = 0.866666666667;
+
+/// The price series an indicator is computed on.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceSource {
+    #[default]
+    Close,
+    Open,
+    High,
+    Low,
+    /// (high + low) / 2
+    Hl2,
+    /// (high + low + close) / 3
+    Hlc3,
+    /// (open + high + low + close) / 4
+    Ohlc4,
+}
+
+impl PriceSource {
+    fn sql(self) -> &'static str {
+        match self {
+            PriceSource::Close => "close",
+            PriceSource::Open => "open",
+            PriceSource::High => "high",
+            PriceSource::Low => "low",
+            PriceSource::Hl2 => "(high + low) / 2",
+            PriceSource::Hlc3 => "(high + low + close) / 3",
+            PriceSource::Ohlc4 => "(open + high + low + close) / 4",
+        }
+    }
+
+    /// The price of `candle`, computed as [`sql`](Self::sql) computes it.
+    fn of(self, candle: &Candle) -> f64 {
+        let Candle {
+            open,
+            high,
+            low,
+            close,
+            ..
+        } = *candle;
+        match self {
+            PriceSource::Close => close,
+            PriceSource::Open => open,
+            PriceSource::High => high,
+            PriceSource::Low => low,
+            PriceSource::Hl2 => (high + low) / 2.0,
+            PriceSource::Hlc3 => (high + low + close) / 3.0,
+            PriceSource::Ohlc4 => (open + high + low + close) / 4.0,
+        }
+    }
+}
+
+/// SMA, EMA and RSI for every candle, oldest first, computed from scratch.
+pub fn compute(conn: &Connection, source: PriceSource) -> duckdb::Result<Vec<IndicatorPoint>> {
+    let mut state = IndicatorState::new(source);
+    state.refresh(conn)?;
+    Ok(state.points)
+}
+
+/// The default-period indicator series plus the rolling windows needed to
+/// extend it, so new candles cost O(new) instead of a full recomputation.
+///
+/// [`refresh`](Self::refresh) folds in candles newer than the last one seen.
+/// It also checks a row count and an order-independent digest of everything
+/// up to that point; if the history behind the cursor was edited, inserted
+/// into or deleted from, the state is rebuilt from scratch.
+#[derive(Default)]
+pub struct IndicatorState {
+    source: PriceSource,
+    points: Vec<IndicatorPoint>,
+    windows: DefaultWindows,
+    last_timestamp: Option<Timestamp>,
+    rows: i64,
+    digest: u64,
+}
+
+impl IndicatorState {
+    pub fn new(source: PriceSource) -> Self {
+        Self {
+            source,
+            ..Self::default()
+        }
+    }
+
+    pub fn points(&self) -> &[IndicatorPoint] {
+        &self.points
+    }
+
+    /// The latest candle folded in, past which the next refresh scans.
+    pub fn last_timestamp(&self) -> Option<Timestamp> {
+        self.last_timestamp
+    }
+
+    /// Brings the state up to date with the candles table.
+    pub fn refresh(&mut self, conn: &Connection) -> duckdb::Result<()> {
+        let price = self.source.sql();
+        if let Some(last) = &self.last_timestamp {
+            let (rows, digest): (i64, Option<u64>) = conn
+                .prepare_cached(&history_sql(price))?
+                .query_row([last], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            if rows != self.rows || digest.unwrap_or(0) != self.digest {
+                tracing::debug!("candle history changed; rebuilding indicators");
+                *self = Self::new(self.source);
+            }
+        }
+
+        let mut stmt = conn.prepare_cached(&scan_sql(price, "?"))?;
+        let after = self.last_timestamp;
+        let mut rows = stmt.query(params![after, after])?;
+        while let Some(row) = rows.next()? {
+            let timestamp = row.get(0)?;
+            // The digest covers duplicates too, matching the check above.
+            self.rows += 1;
+            self.digest ^= row.get::<_, u64>(2)?;
+            if self.last_timestamp != Some(timestamp) {
+                self.push(timestamp, row.get(1)?);
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, timestamp: Timestamp, close: f64) {
+        let (sma, ema, rsi) = self.windows.push(close);
+        self.points.push(IndicatorPoint {
+            timestamp,
+            sma_14: Some(sma),
+            ema_14: Some(ema),
+            rsi_14: rsi,
+            hma: HullAverages::default(),
+            periods: PeriodValues::default(),
+        });
+        self.last_timestamp = Some(timestamp);
+    }
+}
+
+/// The trailing windows behind `sma_14`, `ema_14` and `rsi_14`.
+#[derive(Clone, Default)]
+struct DefaultWindows {
+    closes: VecDeque<f64>,
+    gains: VecDeque<f64>,
+    losses: VecDeque<f64>,
+    ema: Option<f64>,
+    last_close: Option<f64>,
+}
+
+impl DefaultWindows {
+    /// SMA, EMA and RSI once `close` is added.
+    fn push(&mut self, close: f64) -> (f64, f64, Option<f64>) {
+        let ema = match self.ema {
+            Some(prev) => close * EMA_ALPHA + prev * EMA_DECAY,
+            None => close,
+        };
+        let delta = self.last_close.map_or(0.0, |prev| close - prev);
+        push_window(&mut self.closes, close);
+        push_window(&mut self.gains, delta.max(0.0));
+        push_window(&mut self.losses, (-delta).max(0.0));
+        let avg_loss = mean(&self.losses);
+        let rsi = (avg_loss != 0.0).then(|| 100.0 - 100.0 / (1.0 + mean(&self.gains) / avg_loss));
+        self.ema = Some(ema);
+        self.last_close = Some(close);
+        (mean(&self.closes), ema, rsi)
+    }
+
+    /// The default-period value of `indicator` from [`push`](Self::push)'s
+    /// result.
+    fn pick(values: (f64, f64, Option<f64>), indicator: PeriodIndicator) -> Option<f64> {
+        let (sma, ema, rsi) = values;
+        match indicator {
+            PeriodIndicator::Sma => Some(sma),
+            PeriodIndicator::Ema => Some(ema),
+            PeriodIndicator::Rsi => rsi,
+        }
+    }
+}
+
+/// Indicator values for one `/api/ws` client, extended with each candle it
+/// is sent. Periods of [`PERIOD`] follow `sma_14`, `ema_14` and `rsi_14`;
+/// others follow [`simple_moving_average`], [`exponential_moving_average`]
+/// and [`relative_strength_index`], as `/api/indicators?sma=` does.
+pub struct IndicatorFeed {
+    source: PriceSource,
+    subscribed: Vec<(PeriodIndicator, usize)>,
+    state: FeedState,
+    /// The state before the last candle, so a newer row for it replaces it.
+    before_last: FeedState,
+    last: Option<Timestamp>,
+}
+
+#[derive(Clone)]
+struct FeedState {
+    defaults: DefaultWindows,
+    /// One per subscribed indicator whose period is not [`PERIOD`].
+    chosen: Vec<Rolling>,
+}
+
+impl IndicatorFeed {
+    pub fn new(source: PriceSource, subscribed: Vec<(PeriodIndicator, usize)>) -> Self {
+        let state = FeedState {
+            defaults: DefaultWindows::default(),
+            chosen: subscribed
+                .iter()
+                .filter(|(_, period)| *period != PERIOD)
+                .map(|&(indicator, period)| Rolling::new(indicator, period))
+                .collect(),
+        };
+        Self {
+            source,
+            subscribed,
+            before_last: state.clone(),
+            state,
+            last: None,
+        }
+    }
+
+    /// The same subscription, with nothing seen yet.
+    pub fn restarted(&self) -> Self {
+        Self::new(self.source, self.subscribed.clone())
+    }
+
+    /// Folds in the stored candles up to `through`, or all of them.
+    pub fn seed(&mut self, conn: &Connection, through: Option<Timestamp>) -> duckdb::Result<()> {
+        for (timestamp, price) in prices_through(conn, self.source, through)? {
+            self.push(timestamp, price);
+        }
+        Ok(())
+    }
+
+    /// The subscribed values once `candle` is added, or `None` if it is
+    /// older than the last candle seen.
+    pub fn update(&mut self, candle: &Candle) -> Option<PeriodValues> {
+        self.push(candle.timestamp, self.source.of(candle))
+    }
+
+    fn push(&mut self, timestamp: Timestamp, price: f64) -> Option<PeriodValues> {
+        match self.last {
+            Some(last) if timestamp.at < last.at => return None,
+            Some(last) if timestamp.at == last.at => self.state = self.before_last.clone(),
+            _ => self.before_last = self.state.clone(),
+        }
+        self.last = Some(timestamp);
+        let defaults = self.state.defaults.push(price);
+        let mut chosen = self.state.chosen.iter_mut();
+        let values = self
+            .subscribed
+            .iter()
+            .map(|&(indicator, period)| {
+                let value = match period {
+                    PERIOD => DefaultWindows::pick(defaults, indicator),
+                    _ => chosen.next().expect("one per chosen period").push(price),
+                };
+                (indicator, period, value)
+            })
+            .collect();
+        Some(PeriodValues(values))
+    }
+}
+
+/// Candles after `after` (a placeholder or a literal) with the hash that
+/// [`IndicatorState`] digests.
+fn scan_sql(price: &str, after: &str) -> String {
+    format!(
+        "SELECT timestamp, {price}, hash(timestamp, {price})
+         FROM candles
+         WHERE {after} IS NULL OR timestamp > {after}
+         ORDER BY timestamp, rowid DESC"
+    )
+}
+
+/// The count and hash digest of the candles up to a placeholder, which
+/// tell [`IndicatorState`] whether the history it folded in has changed.
+fn history_sql(price: &str) -> String {
+    format!(
+        "SELECT count(*), bit_xor(hash(timestamp, {price}))
+         FROM candles
+         WHERE timestamp <= ?"
+    )
+}
+
+/// The `price` of every candle up to a placeholder, or of all of them.
+fn prices_sql(price: &str) -> String {
+    format!(
+        "SELECT timestamp, {price} FROM candles
+         WHERE ? IS NULL OR timestamp <= ?
+         ORDER BY timestamp, rowid DESC"
+    )
+}
+
+/// Whether the materialized table matches the candles it was computed from.
+const TABLE_CURRENT_SQL: &str =
+    "SELECT (SELECT count(*) FROM candles) = (SELECT count(*) FROM indicators)
+        AND (SELECT bit_xor(hash(timestamp, close)) FROM candles)
+            IS NOT DISTINCT FROM (SELECT bit_xor(candle_hash) FROM indicators)";
+
+const TABLE_POINTS_SQL: &str = "SELECT timestamp, sma_14, ema_14, rsi_14
+     FROM indicators
+     ORDER BY timestamp";
+
+/// The statements a default `/api/indicators` request can run, named and
+/// with parameters inlined: the freshness check and table read it normally
+/// answers from, and the full scan it falls back to while the table lags.
+pub fn request_queries(source: PriceSource) -> Vec<(&'static str, String)> {
+    vec![
+        ("table_current", TABLE_CURRENT_SQL.to_owned()),
+        ("table_points", TABLE_POINTS_SQL.to_owned()),
+        ("compute_scan", scan_sql(source.sql(), "NULL")),
+    ]
+}
+
+/// The statements an `/api/indicators` request on `source` runs, each
+/// with the values it binds in placeholder order. `cursor` is the latest
+/// candle the request's cached series has folded in, and `through` the one
+/// chosen periods are read up to, when any are chosen. The table check and
+/// read apply to `close` alone, and the scans only while the table lags.
+pub fn bound_request_queries(
+    source: PriceSource,
+    cursor: Option<Timestamp>,
+    through: Option<Option<Timestamp>>,
+) -> Vec<(&'static str, String, Vec<Option<Timestamp>>)> {
+    let price = source.sql();
+    let mut queries = Vec::new();
+    if source == PriceSource::Close {
+        queries.push(("table_current", TABLE_CURRENT_SQL.to_owned(), vec![]));
+        queries.push(("table_points", TABLE_POINTS_SQL.to_owned(), vec![]));
+    }
+    if cursor.is_some() {
+        queries.push(("history_check", history_sql(price), vec![cursor]));
+    }
+    queries.push(("compute_scan", scan_sql(price, "?"), vec![cursor, cursor]));
+    if let Some(through) = through {
+        queries.push(("chosen_prices", prices_sql(price), vec![through, through]));
+    }
+    queries
+}
+
+/// The materialized `indicators` table as of its last refresh.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RefreshStatus {
+    pub refreshed_at: Option<Timestamp>,
+    /// Latest candle covered by the table.
+    pub last_timestamp: Option<Timestamp>,
+    pub rows: i64,
+    /// Rows the last refresh had to compute; appends touch only the new ones.
+    pub recomputed: i64,
+}
+
+/// Brings the materialized `indicators` table (default periods on `close`) in
+/// line with the candles table.
+///
+/// Each stored row carries the hash of the candle it came from, so one join
+/// finds the earliest timestamp that was edited, inserted, deleted or newly
+/// appended. Only rows from there on are recomputed: the `PERIOD + 1` candles
+/// before it refill the rolling windows and the stored EMA at that point seeds
+/// the average, which reproduces a full computation bit for bit.
+pub fn refresh_table(conn: &Connection) -> duckdb::Result<RefreshStatus> {
+    let changed_from: Option<Timestamp> = conn
+        .prepare_cached(
+            "SELECT min(coalesce(c.timestamp, i.timestamp))
+             FROM (SELECT timestamp, hash(timestamp, close) AS candle_hash FROM candles) c
+             FULL OUTER JOIN indicators i ON i.timestamp = c.timestamp
+             WHERE c.candle_hash IS DISTINCT FROM i.candle_hash",
+        )?
+        .query_row([], |row| row.get(0))?;
+
+    let tx = conn.unchecked_transaction()?;
+    let recomputed = match changed_from {
+        Some(from) => recompute_table_from(&tx, from)?,
+        None => 0,
+    };
+    tx.execute_batch(&format!(
+        "DELETE FROM indicator_refresh;
+         INSERT INTO indicator_refresh
+         SELECT now(), max(timestamp), count(*), {recomputed} FROM indicators;"
+    ))?;
+    tx.commit()?;
+    refresh_status(conn)
+}
+
+fn recompute_table_from(conn: &Connection, from: Timestamp) -> duckdb::Result<i64> {
+    let mut state = IndicatorState::new(PriceSource::Close);
+    let mut seed = conn.prepare_cached(
+        "SELECT timestamp, close
+         FROM candles
+         WHERE timestamp < ?
+         ORDER BY timestamp DESC, rowid DESC
+         LIMIT ?",
+    )?;
+    // Duplicates count against the limit, so fetch more until the windows
+    // can be filled or the candles run out.
+    let mut limit = PERIOD + 1;
+    let seeds = loop {
+        let mut seeds = seed
+            .query_map(params![from, limit as i64], |row| {
+                Ok((row.get::<_, Timestamp>(0)?, row.get::<_, f64>(1)?))
+            })?
+            .collect::<duckdb::Result<Vec<_>>>()?;
+        let fetched = seeds.len();
+        seeds.dedup_by_key(|(timestamp, _)| *timestamp);
+        if seeds.len() > PERIOD || fetched < limit {
+            seeds.truncate(PERIOD + 1);
+            break seeds;
+        }
+        limit *= 2;
+    };
+    for (timestamp, close) in seeds.into_iter().rev() {
+        state.push(timestamp, close);
+    }
+    if let Some(last) = &state.last_timestamp {
+        state.windows.ema = conn
+            .prepare_cached("SELECT ema_14 FROM indicators WHERE timestamp = ?")?
+            .query_row([last], |row| row.get(0))?;
+    }
+    state.points.clear();
+
+    let mut hashes = Vec::new();
+    let mut stmt = conn.prepare_cached(
+        "SELECT timestamp, close, hash(timestamp, close)
+         FROM candles
+         WHERE timestamp >= ?
+         ORDER BY timestamp, rowid DESC",
+    )?;
+    let mut rows = stmt.query([from])?;
+    while let Some(row) = rows.next()? {
+        let timestamp = row.get(0)?;
+        if state.last_timestamp == Some(timestamp) {
+            continue;
+        }
+        state.push(timestamp, row.get(1)?);
+        hashes.push(row.get::<_, u64>(2)?);
+    }
+
+    conn.prepare_cached("DELETE FROM indicators WHERE timestamp >= ?")?
+        .execute([from])?;
+    // Row-at-a-time INSERTs take about a minute for 500k candles; the
+    // appender loads them in bulk.
+    let mut appender = conn.appender("indicators")?;
+    for (point, hash) in state.points.iter().zip(hashes) {
+        appender.append_row(params![
+            point.timestamp,
+            point.sma_14,
+            point.ema_14,
+            point.rsi_14,
+            hash
+        ])?;
+    }
+    appender.flush()?;
+    Ok(state.points.len() as i64)
+}
+
+pub fn refresh_status(conn: &Connection) -> duckdb::Result<RefreshStatus> {
+    let status = conn
+        .prepare_cached(
+            "SELECT refreshed_at,
+                    last_timestamp, rows, recomputed
+             FROM indicator_refresh",
+        )?
+        .query_row([], |row| {
+            Ok(RefreshStatus {
+                refreshed_at: row.get(0)?,
+                last_timestamp: row.get(1)?,
+                rows: row.get(2)?,
+                recomputed: row.get(3)?,
+            })
+        });
+    match status {
+        Err(duckdb::Error::QueryReturnedNoRows) => Ok(RefreshStatus::default()),
+        status => status,
+    }
+}
+
+/// The materialized series, or `None` while it lags behind the candles table
+/// (until the next refresh) and callers should compute instead.
+pub fn table_points(conn: &Connection) -> duckdb::Result<Option<Vec<IndicatorPoint>>> {
+    let current: bool = conn
+        .prepare_cached(TABLE_CURRENT_SQL)?
+        .query_row([], |row| row.get(0))?;
+    if !current {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare_cached(TABLE_POINTS_SQL)?;
+    let points = stmt
+        .query_map([], |row| {
+            Ok(IndicatorPoint {
+                timestamp: row.get(0)?,
+                sma_14: row.get(1)?,
+                ema_14: row.get(2)?,
+                rsi_14: row.get(3)?,
+                hma: HullAverages::default(),
+                periods: PeriodValues::default(),
+            })
+        })?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+    Ok(Some(points))
+}
+
+/// Refreshes the indicators table at startup and after every change to the
+/// main candles, once per burst of changes.
+pub(crate) async fn maintain_table(db: Arc<Db>, mut events: broadcast::Receiver<DataEvent>) {
+    loop {
+        match db.write(refresh_table).await {
+            Ok(status) => tracing::debug!(
+                "indicators table refreshed: {} rows, {} recomputed",
+                status.rows,
+                status.recomputed
+            ),
+            Err(err) => tracing::error!("indicators table refresh failed: {err}"),
+        }
+        // A lagged receiver missed events, which may have touched the main
+        // candles, so it refreshes too.
+        loop {
+            match events.recv().await {
+                Ok(event) if !event.touches_main() => continue,
+                Ok(_) | Err(RecvError::Lagged(_)) => break,
+                Err(RecvError::Closed) => return,
+            }
+        }
+        while !matches!(
+            events.try_recv(),
+            Err(TryRecvError::Empty | TryRecvError::Closed)
+        ) {}
+    }
+}
+
+/// Elder's Force Index, `(close - prev_close) * volume`, EMA-smoothed over
+/// `force_period` when given, and Ease of Movement,
+/// `(mid - prev_mid) / (volume / (high - low))`, averaged over `eom_period`.
+///
+/// The first candle has no previous one and gets neither value. Candles with
+/// zero volume or zero range have no Ease of Movement and are left out of its
+/// average. Volumes are rounded to `volume_precision` places first, as
+/// `/api/candles` returns them.
+pub fn volume_indicators(
+    conn: &Connection,
+    force_period: Option<usize>,
+    eom_period: usize,
+    volume_precision: Option<u32>,
+) -> duckdb::Result<Vec<VolumeIndicatorPoint>> {
+    let places = volume_precision.map(|places| places.min(15));
+    let mut candles = conn
+        .prepare_cached(
+            "SELECT timestamp, high, low, close,
+                CASE WHEN ? IS NULL THEN volume ELSE round(volume, ?) END
+             FROM candles
+             ORDER BY timestamp, rowid DESC",
+        )?
+        .query_map(params![places, places], |row| {
+            Ok((
+                row.get::<_, Timestamp>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, f64>(3)?,
+                row.get::<_, f64>(4)?,
+            ))
+        })?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+    candles.dedup_by_key(|candle| candle.0);
+    let mut force_ema = force_period.map(Ema::new);
+    let mut eom_window = VecDeque::new();
+    let mut previous: Option<(f64, f64)> = None;
+    let points = candles
+        .into_iter()
+        .map(|(timestamp, high, low, close, volume)| {
+            let midpoint = (high + low) / 2.0;
+            let range = high - low;
+            let moves = previous.replace((close, midpoint));
+            let force = moves.map(|(prev_close, _)| (close - prev_close) * volume);
+            let midpoint_move = moves.map(|(_, prev_midpoint)| midpoint - prev_midpoint);
+            let force_index = match (&mut force_ema, force) {
+                (Some(ema), Some(force)) => Some(ema.push(force)),
+                (None, force) => force,
+                (Some(_), None) => None,
+            };
+            let eom = midpoint_move
+                .filter(|_| volume != 0.0 && range != 0.0)
+                .map(|midpoint_move| midpoint_move * range / volume);
+            let ease_of_movement = eom.map(|eom| {
+                if eom_window.len() == eom_period {
+                    eom_window.pop_front();
+                }
+                eom_window.push_back(eom);
+                mean(&eom_window)
+            });
+            VolumeIndicatorPoint {
+                timestamp,
+                force_index,
+                ease_of_movement,
+            }
+        })
+        .collect();
+    Ok(points)
+}
+
+/// Wilder's Directional Movement System over `period` bars: `+DI` and `-DI`
+/// from Wilder-smoothed true range and directional movement, `ADX` as the
+/// Wilder average of `DX = 100 * |+DI - -DI| / (+DI + -DI)`, and
+/// `ADXR = (ADX + ADX adxr_lag bars earlier) / 2`.
+///
+/// Each stage warms up on the previous one: the first candle has no
+/// movement, the DIs need `period` movements, ADX `period` DX values and
+/// ADXR another `adxr_lag` bars, so they start at the `period + 1`th,
+/// `2 * period`th and `2 * period + adxr_lag`th candle. A bar with no
+/// directional movement on either side has a DX of 0.
+pub fn directional_movement(
+    conn: &Connection,
+    period: usize,
+    adxr_lag: usize,
+) -> duckdb::Result<Vec<AdxPoint>> {
+    let candles = high_low_closes(conn)?;
+    let n = period as f64;
+    let mut previous: Option<(f64, f64, f64)> = None;
+    let mut movements = 0;
+    let (mut smoothed_range, mut plus_dm, mut minus_dm) = (0.0, 0.0, 0.0);
+    let (mut dx_count, mut dx_sum) = (0, 0.0);
+    let mut adx: Option<f64> = None;
+    let mut recent_adx = VecDeque::with_capacity(adxr_lag + 1);
+    let points = candles.into_iter().map(|(timestamp, high, low, close)| {
+        let di =
+            previous
+                .replace((high, low, close))
+                .and_then(|(prev_high, prev_low, prev_close)| {
+                    let range = true_range(high, low, prev_close);
+                    let (up, down) = (high - prev_high, prev_low - low);
+                    let plus = if up > down && up > 0.0 { up } else { 0.0 };
+                    let minus = if down > up && down > 0.0 { down } else { 0.0 };
+                    movements += 1;
+                    // Sums over the first period, then Wilder's running smoothing.
+                    let decay = if movements > period { 1.0 / n } else { 0.0 };
+                    smoothed_range += range - smoothed_range * decay;
+                    plus_dm += plus - plus_dm * decay;
+                    minus_dm += minus - minus_dm * decay;
+                    (movements >= period).then(|| {
+                        if smoothed_range == 0.0 {
+                            (0.0, 0.0)
+                        } else {
+                            (
+                                100.0 * plus_dm / smoothed_range,
+                                100.0 * minus_dm / smoothed_range,
+                            )
+                        }
+                    })
+                });
+        let current_adx = di.and_then(|(plus_di, minus_di)| {
+            let sum = plus_di + minus_di;
+            let dx = if sum == 0.0 {
+                0.0
+            } else {
+                100.0 * (plus_di - minus_di).abs() / sum
+            };
+            dx_count += 1;
+            adx = match adx {
+                Some(previous) => Some((previous * (n - 1.0) + dx) / n),
+                None if dx_count == period => Some((dx_sum + dx) / n),
+                None => {
+                    dx_sum += dx;
+                    None
+                }
+            };
+            adx
+        });
+        if recent_adx.len() > adxr_lag {
+            recent_adx.pop_front();
+        }
+        recent_adx.push_back(current_adx);
+        let adxr = match (recent_adx.front(), current_adx) {
+            (Some(Some(earlier)), Some(adx)) if recent_adx.len() > adxr_lag => {
+                Some((adx + earlier) / 2.0)
+            }
+            _ => None,
+        };
+        AdxPoint {
+            timestamp,
+            plus_di: di.map(|(plus_di, _)| plus_di),
+            minus_di: di.map(|(_, minus_di)| minus_di),
+            adx: current_adx,
+            adxr,
+        }
+    });
+    Ok(points.collect())
+}
+
+/// The Vortex Indicator over `period` bars: `VI+` and `VI-` as the summed
+/// vortex movements `|high - previous low|` and `|low - previous high|` over
+/// the summed true range of the trailing `period` bars. Both are `None`
+/// until `period` movements have been seen, from the `period + 1`th candle,
+/// and over a window whose true range is 0.
+pub fn vortex(
+    highs: &[f64],
+    lows: &[f64],
+    closes: &[f64],
+    period: usize,
+) -> Vec<(Option<f64>, Option<f64>)> {
+    let mut previous: Option<(f64, f64, f64)> = None;
+    let mut window = VecDeque::with_capacity(period + 1);
+    highs
+        .iter()
+        .zip(lows)
+        .zip(closes)
+        .map(|((&high, &low), &close)| {
+            let Some((prev_high, prev_low, prev_close)) = previous.replace((high, low, close))
+            else {
+                return (None, None);
+            };
+            window.push_back((
+                (high - prev_low).abs(),
+                (low - prev_high).abs(),
+                true_range(high, low, prev_close),
+            ));
+            if window.len() > period {
+                window.pop_front();
+            }
+            let range = window.iter().map(|(_, _, range)| range).sum::<f64>();
+            if window.len() < period || range == 0.0 {
+                return (None, None);
+            }
+            let plus = window.iter().map(|(plus, _, _)| plus).sum::<f64>();
+            let minus = window.iter().map(|(_, minus, _)| minus).sum::<f64>();
+            (Some(plus / range), Some(minus / range))
+        })
+        .collect()
+}
+
+/// [`vortex`] over every candle.
+pub fn vortex_indicator(conn: &Connection, period: usize) -> duckdb::Result<Vec<VortexPoint>> {
+    let candles = high_low_closes(conn)?;
+    let highs = candles.iter().map(|candle| candle.1).collect::<Vec<_>>();
+    let lows = candles.iter().map(|candle| candle.2).collect::<Vec<_>>();
+    let closes = candles.iter().map(|candle| candle.3).collect::<Vec<_>>();
+    let vortex = vortex(&highs, &lows, &closes, period);
+    Ok(candles
+        .into_iter()
+        .zip(vortex)
+        .map(|((timestamp, ..), (vi_plus, vi_minus))| VortexPoint {
+            timestamp,
+            vi_plus,
+            vi_minus,
+        })
+        .collect())
+}
+
+/// Every candle's timestamp, high, low and close, oldest first.
+fn high_low_closes(conn: &Connection) -> duckdb::Result<Vec<(Timestamp, f64, f64, f64)>> {
+    let mut candles = conn
+        .prepare_cached(
+            "SELECT timestamp, high, low, close FROM candles ORDER BY timestamp, rowid DESC",
+        )?
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, Timestamp>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, f64>(3)?,
+            ))
+        })?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+    candles.dedup_by_key(|candle| candle.0);
+    Ok(candles)
+}
+
+/// The sample standard deviation and variance of `source` over the trailing
+/// `period` candles, `None` until `period` have been seen. The windows are
+/// DuckDB's `stddev_samp` and `var_samp`, so `period` must be at least 2.
+pub fn standard_deviation(
+    conn: &Connection,
+    source: PriceSource,
+    period: usize,
+) -> duckdb::Result<Vec<StdDevPoint>> {
+    // Both are whole numbers chosen here, never request text.
+    let sql = format!(
+        "SELECT timestamp,
+            CASE WHEN count(*) OVER recent = {period} THEN stddev_samp(price) OVER recent END,
+            CASE WHEN count(*) OVER recent = {period} THEN var_samp(price) OVER recent END
+         FROM (
+            SELECT timestamp, {source} AS price
+            FROM candles
+            QUALIFY row_number() OVER (PARTITION BY timestamp ORDER BY rowid DESC) = 1
+         )
+         WINDOW recent AS (ORDER BY timestamp ROWS BETWEEN {preceding} PRECEDING AND CURRENT ROW)
+         ORDER BY timestamp",
+        source = source.sql(),
+        preceding = period - 1,
+    );
+    // `prepare`, not `prepare_cached`: every period is a different statement.
+    let points = conn
+        .prepare(&sql)?
+        .query_map([], |row| {
+            Ok(StdDevPoint {
+                timestamp: row.get(0)?,
+                stddev: row.get(1)?,
+                variance: row.get(2)?,
+            })
+        })?
+        .collect();
+    points
+}
+
+/// The mean and median of `source` over the trailing `window` candles,
+/// `None` until `window` have been seen: smooth centrelines, the median
+/// barely moved by a single spike.
+pub fn rolling_price(
+    conn: &Connection,
+    source: PriceSource,
+    window: usize,
+) -> duckdb::Result<Vec<RollingPricePoint>> {
+    // Both are whole numbers chosen here, never request text.
+    let sql = format!(
+        "SELECT timestamp,
+            CASE WHEN count(*) OVER recent = {window} THEN avg(price) OVER recent END,
+            CASE WHEN count(*) OVER recent = {window} THEN median(price) OVER recent END
+         FROM (
+            SELECT timestamp, {source} AS price
+            FROM candles
+            QUALIFY row_number() OVER (PARTITION BY timestamp ORDER BY rowid DESC) = 1
+         )
+         WINDOW recent AS (ORDER BY timestamp ROWS BETWEEN {preceding} PRECEDING AND CURRENT ROW)
+         ORDER BY timestamp",
+        source = source.sql(),
+        preceding = window - 1,
+    );
+    // `prepare`, not `prepare_cached`: every window is a different statement.
+    let points = conn
+        .prepare(&sql)?
+        .query_map([], |row| {
+            Ok(RollingPricePoint {
+                timestamp: row.get(0)?,
+                avg_price: row.get(1)?,
+                median_price: row.get(2)?,
+            })
+        })?
+        .collect();
+    points
+}
+
+/// The Detrended Price Oscillator of `source`: the price `period / 2 + 1`
+/// candles back less the simple moving average of the trailing `period`,
+/// which takes the trend out and leaves its cycles. `None` until both the
+/// shifted price and a full window exist.
+pub fn detrended_price_oscillator(
+    conn: &Connection,
+    source: PriceSource,
+    period: usize,
+) -> duckdb::Result<Vec<DpoPoint>> {
+    // All are whole numbers chosen here, never request text.
+    let sql = format!(
+        "SELECT timestamp,
+            CASE WHEN count(*) OVER recent = {period}
+                THEN lag(price, {shift}) OVER (ORDER BY timestamp) - avg(price) OVER recent
+            END
+         FROM (
+            SELECT timestamp, {source} AS price
+            FROM candles
+            QUALIFY row_number() OVER (PARTITION BY timestamp ORDER BY rowid DESC) = 1
+         )
+         WINDOW recent AS (ORDER BY timestamp ROWS BETWEEN {preceding} PRECEDING AND CURRENT ROW)
+         ORDER BY timestamp",
+        source = source.sql(),
+        shift = period / 2 + 1,
+        preceding = period - 1,
+    );
+    // `prepare`, not `prepare_cached`: every period is a different statement.
+    let points = conn
+        .prepare(&sql)?
+        .query_map([], |row| {
+            Ok(DpoPoint {
+                timestamp: row.get(0)?,
+                dpo: row.get(1)?,
+            })
+        })?
+        .collect();
+    points
+}
+
+/// Kaufman's Adaptive Moving Average of `source` over every candle; see
+/// [`kaufman_adaptive_moving_average`].
+pub fn adaptive_moving_average(
+    conn: &Connection,
+    source: PriceSource,
+    periods: KamaPeriods,
+) -> duckdb::Result<Vec<KamaPoint>> {
+    let (timestamps, prices): (Vec<_>, Vec<_>) =
+        prices_through(conn, source, None)?.into_iter().unzip();
+    let kama = kaufman_adaptive_moving_average(&prices, periods);
+    Ok(timestamps
+        .into_iter()
+        .zip(kama)
+        .map(|(timestamp, kama)| KamaPoint { timestamp, kama })
+        .collect())
+}
+
+/// The windows of a KAMA: `efficiency` bars for the efficiency ratio, and
+/// the EMA periods its smoothing moves between.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KamaPeriods {
+    pub efficiency: usize,
+    pub fast: usize,
+    pub slow: usize,
+}
+
+impl Default for KamaPeriods {
+    /// Kaufman's own 10, 2 and 30.
+    fn default() -> Self {
+        Self {
+            efficiency: 10,
+            fast: 2,
+            slow: 30,
+        }
+    }
+}
+
+/// Kaufman's Adaptive Moving Average: an EMA whose smoothing constant is
+/// `(er * (fast_alpha - slow_alpha) + slow_alpha)^2`, with `alpha = 2 / (n +
+/// 1)` for the fast and slow periods. The efficiency ratio `er` is the net
+/// change over the last `efficiency` bars divided by the sum of the absolute
+/// bar-to-bar changes, 1 in a straight trend and near 0 in noise (0 when the
+/// window is flat). Seeded with the price `efficiency` bars in, so the first
+/// `efficiency` values are `None`.
+pub fn kaufman_adaptive_moving_average(prices: &[f64], periods: KamaPeriods) -> Vec<Option<f64>> {
+    let n = periods.efficiency;
+    let alpha = |period: usize| 2.0 / (period as f64 + 1.0);
+    let (fast, slow) = (alpha(periods.fast), alpha(periods.slow));
+    let mut kama: Option<f64> = None;
+    (0..prices.len())
+        .map(|i| {
+            if n == 0 || i < n {
+                return None;
+            }
+            let change = (prices[i] - prices[i - n]).abs();
+            let volatility = prices[i - n..=i]
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).abs())
+                .sum::<f64>();
+            let ratio = if volatility > 0.0 {
+                change / volatility
+            } else {
+                0.0
+            };
+            let smoothing = (ratio * (fast - slow) + slow).powi(2);
+            let previous = kama.unwrap_or(prices[i - 1]);
+            let next = previous + smoothing * (prices[i] - previous);
+            kama = Some(next);
+            kama
+        })
+        .collect()
+}
+
+/// The Schaff Trend Cycle of `source` over every candle; see
+/// [`schaff_trend_cycle`].
+pub fn trend_cycle(
+    conn: &Connection,
+    source: PriceSource,
+    periods: StcPeriods,
+) -> duckdb::Result<Vec<StcPoint>> {
+    let (timestamps, prices): (Vec<_>, Vec<_>) =
+        prices_through(conn, source, None)?.into_iter().unzip();
+    let stc = schaff_trend_cycle(&prices, periods);
+    Ok(timestamps
+        .into_iter()
+        .zip(stc)
+        .map(|(timestamp, stc)| StcPoint { timestamp, stc })
+        .collect())
+}
+
+/// The windows of a Schaff Trend Cycle: the `fast` and `slow` EMAs its MACD
+/// line is the difference of, and the `cycle` bars each stochastic covers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StcPeriods {
+    pub fast: usize,
+    pub slow: usize,
+    pub cycle: usize,
+}
+
+impl Default for StcPeriods {
+    /// Schaff's own 23, 50 and 10.
+    fn default() -> Self {
+        Self {
+            fast: 23,
+            slow: 50,
+            cycle: 10,
+        }
+    }
+}
+
+/// How far each stochastic's smoothing moves toward its new value.
+const STC_FACTOR: f64 = 0.5;
+
+/// The Schaff Trend Cycle: the MACD line `ema(fast) - ema(slow)`, its
+/// stochastic `100 * (macd - lowest) / (highest - lowest)` over the trailing
+/// `cycle` values, smoothed by moving halfway to each new value, then the
+/// same stochastic and smoothing again over that. A flat window repeats the
+/// stochastic before it (0 at the start), so the result stays within 0 to
+/// 100. The MACD line counts from the `slow`th price, when the slow EMA has
+/// seen a full period, and each stochastic needs `cycle` values, so the
+/// first `slow + 2 * cycle - 3` values are `None`.
+pub fn schaff_trend_cycle(prices: &[f64], periods: StcPeriods) -> Vec<Option<f64>> {
+    let StcPeriods { fast, slow, cycle } = periods;
+    let (mut fast, mut slow_ema) = (Ema::new(fast), Ema::new(slow));
+    let (mut macds, mut smoothed) = (VecDeque::new(), VecDeque::new());
+    let (mut macd_k, mut smoothed_k) = (None, None);
+    let (mut first, mut second) = (None, None);
+    let smooth = |state: &mut Option<f64>, value: f64| {
+        let next = match *state {
+            Some(prev) => prev + STC_FACTOR * (value - prev),
+            None => value,
+        };
+        *state = Some(next);
+        next
+    };
+    prices
+        .iter()
+        .enumerate()
+        .map(|(i, &price)| {
+            let macd = fast.push(price) - slow_ema.push(price);
+            if cycle == 0 || i + 1 < slow {
+                return None;
+            }
+            trail(&mut macds, macd, cycle);
+            if macds.len() < cycle {
+                return None;
+            }
+            let value = smooth(&mut first, stochastic(&macds, &mut macd_k));
+            trail(&mut smoothed, value, cycle);
+            if smoothed.len() < cycle {
+                return None;
+            }
+            Some(smooth(&mut second, stochastic(&smoothed, &mut smoothed_k)))
+        })
+        .collect()
+}
+
+/// Keeps the trailing `len` values of `window`.
+fn trail(window: &mut VecDeque<f64>, value: f64, len: usize) {
+    if window.len() == len {
+        window.pop_front();
+    }
+    window.push_back(value);
+}
+
+/// Where the newest value of `window` lies between its lowest and highest,
+/// from 0 to 100; `previous` when the window is flat, and then kept as the
+/// previous value for the next window.
+fn stochastic(window: &VecDeque<f64>, previous: &mut Option<f64>) -> f64 {
+    let newest = *window.back().expect("the window is not empty");
+    let low = window.iter().copied().fold(f64::INFINITY, f64::min);
+    let high = window.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let value = if high > low {
+        100.0 * (newest - low) / (high - low)
+    } else {
+        previous.unwrap_or(0.0)
+    };
+    *previous = Some(value);
+    value
+}
+
+/// Fills in `points[..].hma` with a Hull Moving Average per period, computed
+/// on `source` over the same candles the points were computed from.
+pub fn add_hull_averages(
+    conn: &Connection,
+    source: PriceSource,
+    periods: &[usize],
+    points: &mut [IndicatorPoint],
+) -> duckdb::Result<()> {
+    let Some(last) = points.last().map(|point| point.timestamp) else {
+        return Ok(());
+    };
+    let prices = prices_through(conn, source, Some(last))?
+        .into_iter()
+        .map(|(_, price)| price)
+        .collect::<Vec<_>>();
+    for &period in periods {
+        let hma = hull_moving_average(&prices, period);
+        for (point, value) in points.iter_mut().zip(hma) {
+            point.hma.0.push((period, value));
+        }
+    }
+    Ok(())
+}
+
+/// Fills in `points[..].periods` with each chosen-period indicator, computed
+/// on `source` over the same candles the points were computed from.
+pub fn add_period_indicators(
+    conn: &Connection,
+    source: PriceSource,
+    periods: &[(PeriodIndicator, usize)],
+    points: &mut [IndicatorPoint],
+) -> duckdb::Result<()> {
+    let Some(last) = points.last().map(|point| point.timestamp) else {
+        return Ok(());
+    };
+    if periods.is_empty() {
+        return Ok(());
+    }
+    let prices = prices_through(conn, source, Some(last))?;
+    for &(indicator, period) in periods {
+        let mut rolling = Rolling::new(indicator, period);
+        for (point, (_, price)) in points.iter_mut().zip(&prices) {
+            point
+                .periods
+                .0
+                .push((indicator, period, rolling.push(*price)));
+        }
+    }
+    Ok(())
+}
+
+/// The current `source` price of every candle up to `through`, or of all of
+/// them, oldest first.
+fn prices_through(
+    conn: &Connection,
+    source: PriceSource,
+    through: Option<Timestamp>,
+) -> duckdb::Result<Vec<(Timestamp, f64)>> {
+    let mut prices = conn
+        .prepare_cached(&prices_sql(source.sql()))?
+        .query_map([through, through], |row| {
+            Ok((row.get::<_, Timestamp>(0)?, row.get::<_, f64>(1)?))
+        })?
+        .collect::<duckdb::Result<Vec<_>>>()?;
+    prices.dedup_by_key(|(timestamp, _)| *timestamp);
+    Ok(prices)
+}
+
+/// `WMA(2 * WMA(price, n / 2) - WMA(price, n), round(sqrt(n)))`, with `n / 2`
+/// rounded down. The first `n + round(sqrt(n)) - 2` values are `None`, and a
+/// period below 2 yields no values at all.
+pub fn hull_moving_average(prices: &[f64], period: usize) -> Vec<Option<f64>> {
+    if period < 2 {
+        return vec![None; prices.len()];
+    }
+    let prices = prices.iter().copied().map(Some);
+    let half = weighted_moving_average(prices.clone(), period / 2);
+    let full = weighted_moving_average(prices, period);
+    let spread = half
+        .into_iter()
+        .zip(full)
+        .map(|(half, full)| Some(2.0 * half? - full?));
+    weighted_moving_average(spread, hull_smoothing(period))
+}
+
+/// Period of the outer WMA of a Hull Moving Average.
+fn hull_smoothing(period: usize) -> usize {
+    ((period as f64).sqrt().round() as usize).max(1)
+}
+
+/// A linearly weighted moving average over the trailing `period` values, the
+/// newest weighted `period` and the oldest 1. A window containing `None` has
+/// no average.
+fn weighted_moving_average(
+    values: impl IntoIterator<Item = Option<f64>>,
+    period: usize,
+) -> Vec<Option<f64>> {
+    let weights = (period * (period + 1) / 2) as f64;
+    let mut window = VecDeque::with_capacity(period);
+    values
+        .into_iter()
+        .map(|value| {
+            let Some(value) = value else {
+                window.clear();
+                return None;
+            };
+            if window.len() == period {
+                window.pop_front();
+            }
+            window.push_back(value);
+            (window.len() == period).then(|| {
+                window
+                    .iter()
+                    .zip(1..)
+                    .map(|(value, weight)| value * weight as f64)
+                    .sum::<f64>()
+                    / weights
+            })
+        })
+        .collect()
+}
+
+/// The mean of the trailing `period` values, `None` until `period` have been
+/// seen.
+pub fn simple_moving_average(values: &[f64], period: usize) -> Vec<Option<f64>> {
+    let mut sma = Rolling::new(PeriodIndicator::Sma, period);
+    values.iter().map(|&value| sma.push(value)).collect()
+}
+
+/// The sample variance of the trailing `period` values, `None` until
+/// `period` have been seen; the same as [`standard_deviation`] squared.
+pub fn rolling_variance(values: &[f64], period: usize) -> Vec<Option<f64>> {
+    let mut window = VecDeque::with_capacity(period);
+    values
+        .iter()
+        .map(|&value| {
+            if window.len() == period {
+                window.pop_front();
+            }
+            window.push_back(value);
+            (window.len() == period && period > 1).then(|| {
+                let mean = mean(&window);
+                window
+                    .iter()
+                    .map(|value| (value - mean).powi(2))
+                    .sum::<f64>()
+                    / (period - 1) as f64
+            })
+        })
+        .collect()
+}
+
+/// The Pearson correlation of `a` and `b` over the trailing `window` bars,
+/// by DuckDB's `corr()` window function. The two series are derived in Rust,
+/// from formulas or forward returns, so they are loaded into a temporary
+/// table on `conn` for the query. `None` until `window` bars have been seen,
+/// while any bar in the window lacks either value, and where either side has
+/// no spread.
+pub fn rolling_correlation(
+    conn: &Connection,
+    a: &[Option<f64>],
+    b: &[Option<f64>],
+    window: usize,
+) -> duckdb::Result<Vec<Option<f64>>> {
+    conn.execute_batch(
+        "CREATE OR REPLACE TEMP TABLE correlation_series (bar BIGINT, a DOUBLE, b DOUBLE)",
+    )?;
+    let finite = |value: Option<f64>| value.filter(|value| value.is_finite());
+    let mut appender = conn.appender("correlation_series")?;
+    for (bar, (&a, &b)) in a.iter().zip(b).enumerate() {
+        appender.append_row(params![bar as i64, finite(a), finite(b)])?;
+    }
+    drop(appender);
+    // A whole number chosen here, never request text.
+    let sql = format!(
+        "SELECT CASE WHEN count(a + b) OVER recent = {window} THEN corr(a, b) OVER recent END
+         FROM correlation_series
+         WINDOW recent AS (ORDER BY bar ROWS BETWEEN {preceding} PRECEDING AND CURRENT ROW)
+         ORDER BY bar",
+        preceding = window.saturating_sub(1),
+    );
+    let correlations = conn
+        .prepare(&sql)?
+        .query_map([], |row| row.get::<_, Option<f64>>(0).map(finite))?
+        .collect();
+    conn.execute_batch("DROP TABLE correlation_series")?;
+    correlations
+}
+
+/// The return from each close to the one `horizon` bars later, `None` for
+/// the last `horizon` bars and after a zero close.
+pub fn forward_returns(closes: &[f64], horizon: usize) -> Vec<Option<f64>> {
+    (0..closes.len())
+        .map(|i| {
+            let later = *closes.get(i + horizon)?;
+            (closes[i] != 0.0).then(|| later / closes[i] - 1.0)
+        })
+        .collect()
+}
+
+/// An EMA with `alpha = 2 / (period + 1)`, seeded with the first value.
+pub fn exponential_moving_average(values: &[f64], period: usize) -> Vec<Option<f64>> {
+    let mut ema = Rolling::new(PeriodIndicator::Ema, period);
+    values.iter().map(|&value| ema.push(value)).collect()
+}
+
+/// RSI over the trailing `period` changes, averaged simply as for `rsi_14`.
+/// `None` until `period` changes have been seen and while none of them is a
+/// loss.
+pub fn relative_strength_index(values: &[f64], period: usize) -> Vec<Option<f64>> {
+    let mut rsi = Rolling::new(PeriodIndicator::Rsi, period);
+    values.iter().map(|&value| rsi.push(value)).collect()
+}
+
+/// One chosen-period indicator, extended a value at a time.
+#[derive(Clone)]
+enum Rolling {
+    Sma {
+        period: usize,
+        window: VecDeque<f64>,
+    },
+    Ema(Ema),
+    Rsi(Changes),
+}
+
+impl Rolling {
+    fn new(indicator: PeriodIndicator, period: usize) -> Self {
+        match indicator {
+            PeriodIndicator::Sma => Rolling::Sma {
+                period,
+                window: VecDeque::with_capacity(period),
+            },
+            PeriodIndicator::Ema => Rolling::Ema(Ema::new(period)),
+            PeriodIndicator::Rsi => Rolling::Rsi(Changes::new(period)),
+        }
+    }
+
+    fn push(&mut self, value: f64) -> Option<f64> {
+        match self {
+            Rolling::Sma { period, window } => {
+                if window.len() == *period {
+                    window.pop_front();
+                }
+                window.push_back(value);
+                (window.len() == *period).then(|| mean(window))
+            }
+            Rolling::Ema(ema) => Some(ema.push(value)),
+            Rolling::Rsi(changes) => {
+                let full = changes.push(value)?;
+                let avg_loss = mean(&changes.losses);
+                (full && avg_loss != 0.0)
+                    .then(|| 100.0 - 100.0 / (1.0 + mean(&changes.gains) / avg_loss))
+            }
+        }
+    }
+}
+
+/// The gains and losses over the trailing `period` changes of a series, as
+/// RSI and CMO weigh them against each other.
+#[derive(Clone)]
+struct Changes {
+    period: usize,
+    previous: Option<f64>,
+    gains: VecDeque<f64>,
+    losses: VecDeque<f64>,
+}
+
+impl Changes {
+    fn new(period: usize) -> Self {
+        Self {
+            period,
+            previous: None,
+            gains: VecDeque::new(),
+            losses: VecDeque::new(),
+        }
+    }
+
+    /// Records the change to `value`: `None` for the first value, which has
+    /// none, otherwise whether `period` changes have now been seen.
+    fn push(&mut self, value: f64) -> Option<bool> {
+        let delta = value - self.previous.replace(value)?;
+        if self.gains.len() == self.period {
+            self.gains.pop_front();
+            self.losses.pop_front();
+        }
+        self.gains.push_back(delta.max(0.0));
+        self.losses.push_back((-delta).max(0.0));
+        Some(self.gains.len() == self.period)
+    }
+}
+
+/// Chande's Momentum Oscillator, `100 * (gains - losses) / (gains + losses)`
+/// summed over the trailing `period` changes: from -100 when every change is
+/// a loss to 100 when every one is a gain. `None` until `period` changes have
+/// been seen and over a window with no changes at all.
+pub fn chande_momentum_oscillator(values: &[f64], period: usize) -> Vec<Option<f64>> {
+    let mut changes = Changes::new(period);
+    values
+        .iter()
+        .map(|&value| {
+            if !changes.push(value)? {
+                return None;
+            }
+            let gains = changes.gains.iter().sum::<f64>();
+            let losses = changes.losses.iter().sum::<f64>();
+            (gains + losses != 0.0).then(|| 100.0 * (gains - losses) / (gains + losses))
+        })
+        .collect()
+}
+
+/// [`chande_momentum_oscillator`] of `source` over every candle.
+pub fn momentum_oscillator(
+    conn: &Connection,
+    source: PriceSource,
+    period: usize,
+) -> duckdb::Result<Vec<CmoPoint>> {
+    let prices = prices_through(conn, source, None)?;
+    let values = prices.iter().map(|(_, price)| *price).collect::<Vec<_>>();
+    let cmo = chande_momentum_oscillator(&values, period);
+    Ok(prices
+        .into_iter()
+        .zip(cmo)
+        .map(|((timestamp, _), cmo)| CmoPoint { timestamp, cmo })
+        .collect())
+}
+
+/// Wilder's Average True Range: the mean of the first `period` true ranges,
+/// then `(previous * (period - 1) + range) / period`. The first candle has no
+/// previous close and so no true range.
+pub fn average_true_range(
+    highs: &[f64],
+    lows: &[f64],
+    closes: &[f64],
+    period: usize,
+) -> Vec<Option<f64>> {
+    let n = period as f64;
+    let mut previous_close = None;
+    let (mut ranges, mut sum, mut atr) = (0, 0.0, None);
+    highs
+        .iter()
+        .zip(lows)
+        .zip(closes)
+        .map(|((&high, &low), &close)| {
+            let range = true_range(high, low, previous_close.replace(close)?);
+            ranges += 1;
+            atr = match atr {
+                Some(previous) => Some((previous * (n - 1.0) + range) / n),
+                None if ranges == period => Some((sum + range) / n),
+                None => {
+                    sum += range;
+                    None
+                }
+            };
+            atr
+        })
+        .collect()
+}
+
+/// The widest of the bar's own range and its gaps from the previous close.
+fn true_range(high: f64, low: f64, prev_close: f64) -> f64 {
+    (high - low)
+        .max((high - prev_close).abs())
+        .max((low - prev_close).abs())
+}
+
+/// `(value - mean) / std` of each value against the trailing `window` values,
+/// itself included, using the sample standard deviation. Missing values are
+/// skipped and stay `None`, as does everything until `window` values have been
+/// seen and any window with no spread.
+pub fn rolling_zscore(values: &[Option<f64>], window: usize) -> Vec<Option<f64>> {
+    rolling_mean_zscore(values, window)
+        .into_iter()
+        .map(|(_, zscore)| zscore)
+        .collect()
+}
+
+/// [`rolling_zscore`] together with the rolling mean it standardizes against.
+pub fn rolling_mean_zscore(
+    values: &[Option<f64>],
+    window: usize,
+) -> Vec<(Option<f64>, Option<f64>)> {
+    let mut trailing = VecDeque::with_capacity(window);
+    values
+        .iter()
+        .map(|value| {
+            let Some(value) = *value else {
+                return (None, None);
+            };
+            if trailing.len() == window {
+                trailing.pop_front();
+            }
+            trailing.push_back(value);
+            if trailing.len() < window || window < 2 {
+                return (None, None);
+            }
+            let mean = mean(&trailing);
+            let variance = trailing
+                .iter()
+                .map(|value| (value - mean).powi(2))
+                .sum::<f64>()
+                / (window - 1) as f64;
+            let std = variance.sqrt();
+            (Some(mean), (std > 0.0).then(|| (value - mean) / std))
+        })
+        .collect()
+}
+
+/// An exponential moving average over `period` values, seeded with the first.
+#[derive(Clone)]
+struct Ema {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ema {
+    fn new(period: usize) -> Self {
+        Self {
+            alpha: 2.0 / (period as f64 + 1.0),
+            value: None,
+        }
+    }
+
+    fn push(&mut self, value: f64) -> f64 {
+        let next = match self.value {
+            Some(prev) => value * self.alpha + prev * (1.0 - self.alpha),
+            None => value,
+        };
+        self.value = Some(next);
+        next
+    }
+}
+
+/// Keeps the trailing `PERIOD` values, like `ROWS BETWEEN 13 PRECEDING`.
+fn push_window(window: &mut VecDeque<f64>, value: f64) {
+    if window.len() == PERIOD {
+        window.pop_front();
+    }
+    window.push_back(value);
+}
+
+/// Summed in order each time rather than kept as a running total, so the
+/// result depends only on the window contents and never drifts.
+fn mean(window: &VecDeque<f64>) -> f64 {
+    window.iter().sum::<f64>() / window.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use duckdb::Connection;
+
+    use super::*;
+    use crate::db::migrate;
+
+    /// The set-based definition this module replaced, kept as an oracle.
+    const SQL: &str = r#"
+        WITH RECURSIVE ordered AS (
+            SELECT
+                row_number() OVER (ORDER BY timestamp) AS rn,
+                timestamp,
+                close
+            FROM candles
+        ),
+        ema AS (
+            SELECT rn, timestamp, close, close AS ema
+            FROM ordered
+            WHERE rn = 1
+            UNION ALL
+            SELECT o.rn, o.timestamp, o.close,
+                   (o.close * 0.133333333333) + (e.ema * 0.866666666667) AS ema
+            FROM ordered o
+            JOIN ema e ON o.rn = e.rn + 1
+        ),
+        deltas AS (
+            SELECT
+                timestamp,
+                close,
+                close - lag(close) OVER (ORDER BY timestamp) AS delta
+            FROM candles
+        ),
+        gains AS (
+            SELECT
+                timestamp,
+                close,
+                CASE WHEN delta > 0 THEN delta ELSE 0 END AS gain,
+                CASE WHEN delta < 0 THEN -delta ELSE 0 END AS loss
+            FROM deltas
+        ),
+        rsi_calc AS (
+            SELECT
+                timestamp,
+                close,
+                avg(gain) OVER (ORDER BY timestamp ROWS BETWEEN 13 PRECEDING AND CURRENT ROW) AS avg_gain,
+                avg(loss) OVER (ORDER BY timestamp ROWS BETWEEN 13 PRECEDING AND CURRENT ROW) AS avg_loss
+            FROM gains
+        )
+        SELECT
+            strftime(candles.timestamp, '%Y-%m-%d %H:%M:%S') AS ts,
+            avg(candles.close) OVER (ORDER BY candles.timestamp ROWS BETWEEN 13 PRECEDING AND CURRENT ROW) AS sma_14,
+            ema.ema AS ema_14,
+            CASE
+                WHEN rsi_calc.avg_loss = 0 THEN NULL
+                ELSE 100 - (100 / (1 + (rsi_calc.avg_gain / rsi_calc.avg_loss)))
+            END AS rsi_14
+        FROM candles
+        LEFT JOIN ema ON ema.timestamp = candles.timestamp
+        LEFT JOIN rsi_calc ON rsi_calc.timestamp = candles.timestamp
+        ORDER BY candles.timestamp
+    "#;
+
+    fn candles(rows: impl IntoIterator<Item = (u32, f64)>) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE candles (
+                timestamp TIMESTAMP, open DOUBLE, high DOUBLE,
+                low DOUBLE, close DOUBLE, volume DOUBLE
+            );",
+        )
+        .unwrap();
+        append(&conn, rows);
+        migrate(&conn).unwrap();
+        conn
+    }
+
+    fn append(conn: &Connection, rows: impl IntoIterator<Item = (u32, f64)>) {
+        let mut stmt = conn
+            .prepare(
+                "INSERT INTO candles
+                 VALUES (TIMESTAMP '2024-01-01' + to_minutes(?), ?, ?, ?, ?, 1)",
+            )
+            .unwrap();
+        for (minute, close) in rows {
+            stmt.execute(params![minute, close, close, close, close])
+                .unwrap();
+        }
+    }
+
+    /// A deterministic, jagged price path: ups, downs and flat stretches.
+    fn close_at(minute: u32) -> f64 {
+        100.0 + f64::from(minute % 7) * 1.37 - f64::from(minute % 11) * 0.91
+    }
+
+    fn bits(points: &[IndicatorPoint]) -> Vec<(String, [Option<u64>; 3])> {
+        points
+            .iter()
+            .map(|p| {
+                let bits = [p.sma_14, p.ema_14, p.rsi_14].map(|v| v.map(f64::to_bits));
+                (p.timestamp.to_string(), bits)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hull_moving_average_follows_its_definition() {
+        // Nested WMAs cancel the lag of a straight line exactly.
+        let line = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let hma = hull_moving_average(&line, 4);
+        assert_eq!(hma[..4], [None; 4]);
+        for (value, expected) in hma[4..].iter().zip([5.0, 6.0]) {
+            assert!((value.unwrap() - expected).abs() < 1e-9, "{value:?}");
+        }
+        // The outer WMA(2) of 2 WMA(2) - WMA(4): (4.1333 + 2 * 9.6333) / 3.
+        let jagged = hull_moving_average(&[3.0, 1.0, 4.0, 1.0, 5.0, 9.0], 4);
+        assert!((jagged[5].unwrap() - 7.8).abs() < 1e-9, "{jagged:?}");
+        assert_eq!(hull_moving_average(&line, 1), [None; 6]);
+        assert_eq!(
+            insufficient_data_warnings(20, &[9, 25], &[(PeriodIndicator::Rsi, 20)]),
+            [
+                "HMA (25): only 20 of 29 required candles",
+                "RSI (20): only 20 of 21 required candles"
+            ]
+        );
+    }
+
+    #[test]
+    fn kama_adapts_its_smoothing_to_the_efficiency_ratio() {
+        let periods = KamaPeriods {
+            efficiency: 3,
+            fast: 2,
+            slow: 30,
+        };
+        let (fast, slow) = (2.0 / 3.0, 2.0 / 31.0);
+        // A straight trend has ratio 1: the fast EMA throughout.
+        let trend = kaufman_adaptive_moving_average(&[1.0, 2.0, 3.0, 4.0, 5.0], periods);
+        assert_eq!(trend[..3], [None; 3]);
+        let first = 3.0 + fast * fast * (4.0 - 3.0);
+        assert!((trend[3].unwrap() - first).abs() < 1e-12, "{trend:?}");
+        let second = first + fast * fast * (5.0 - first);
+        assert!((trend[4].unwrap() - second).abs() < 1e-12, "{trend:?}");
+
+        // 1, 3, 1, 3 goes nowhere over 6 of movement: ratio 1/3 at the
+        // fourth bar, and a flat window leaves the slow EMA.
+        let noise = kaufman_adaptive_moving_average(&[1.0, 3.0, 1.0, 3.0, 3.0, 3.0, 3.0], periods);
+        let smoothing = ((fast - slow) / 3.0 + slow).powi(2);
+        let first = 1.0 + smoothing * (3.0 - 1.0);
+        assert!((noise[3].unwrap() - first).abs() < 1e-12, "{noise:?}");
+        let flat = noise[5].unwrap() + slow * slow * (3.0 - noise[5].unwrap());
+        assert!((noise[6].unwrap() - flat).abs() < 1e-12, "{noise:?}");
+        assert_eq!(
+            kaufman_adaptive_moving_average(&[1.0, 2.0], KamaPeriods::default()),
+            [None, None]
+        );
+    }
+
+    #[test]
+    fn stc_matches_reference_values() {
+        let periods = StcPeriods {
+            fast: 2,
+            slow: 3,
+            cycle: 3,
+        };
+        let prices = [
+            10.0, 11.0, 13.0, 12.0, 14.0, 17.0, 15.0, 16.0, 19.0, 18.0, 14.0, 13.0,
+        ];
+        // Worked through with exact fractions: the rise to 19 takes both
+        // stochastics to the top, and the fall after it halves the second
+        // smoothing each bar.
+        let expected = [
+            0.0,
+            0.0,
+            50.0,
+            49.20970881199141,
+            24.604854405995706,
+            12.302427202997853,
+        ];
+        let stc = schaff_trend_cycle(&prices, periods);
+        assert_eq!(stc[..6], [None; 6]);
+        for (value, expected) in stc[6..].iter().zip(expected) {
+            assert!((value.unwrap() - expected).abs() < 1e-9, "{stc:?}");
+        }
+        assert!(stc
+            .iter()
+            .flatten()
+            .all(|value| (0.0..=100.0).contains(value)));
+        assert_eq!(
+            schaff_trend_cycle(&prices, StcPeriods::default()),
+            [None; 12]
+        );
+    }
+
+    #[test]
+    fn cmo_weighs_summed_gains_against_summed_losses() {
+        let cmo = chande_momentum_oscillator(&[1.0, 2.0, 4.0, 3.0, 3.0, 3.0, 3.0], 3);
+        // Changes +1, +2, -1, 0, 0, 0: gains 3 against a loss of 1, then 2
+        // against 1, then only the loss, then nothing moving at all.
+        assert_eq!(
+            cmo,
+            [
+                None,
+                None,
+                None,
+                Some(50.0),
+                Some(100.0 / 3.0),
+                Some(-100.0),
+                None
+            ]
+        );
+        assert_eq!(
+            chande_momentum_oscillator(&[5.0, 4.0, 3.0], 2)[2],
+            Some(-100.0)
+        );
+    }
+
+    #[test]
+    fn vortex_divides_summed_movements_by_summed_true_range() {
+        let highs = [2.0, 3.0, 4.0, 3.0, 3.0, 3.0];
+        let lows = [1.0, 2.0, 3.0, 2.0, 3.0, 3.0];
+        let closes = [1.5, 2.5, 3.5, 2.5, 3.0, 3.0];
+        let vi = vortex(&highs, &lows, &closes, 2);
+        // Movements (VM+, VM-, TR): (2, 0, 1.5), (2, 0, 1.5), (0, 2, 1.5),
+        // (1, 0, 0.5), then a bar that moves and ranges nothing.
+        assert_eq!(vi[..2], [(None, None), (None, None)]);
+        assert_eq!(vi[2], (Some(4.0 / 3.0), Some(0.0)));
+        assert_eq!(vi[3], (Some(2.0 / 3.0), Some(2.0 / 3.0)));
+        assert_eq!(vi[4], (Some(0.5), Some(1.0)));
+        assert_eq!(vi[5], (Some(2.0), Some(0.0)));
+        let flat = [1.0; 3];
+        assert_eq!(vortex(&flat, &flat, &flat, 2)[2], (None, None));
+    }
+
+    #[test]
+    fn rolling_zscore_standardizes_against_the_trailing_window() {
+        let values = [
+            Some(1.0),
+            Some(2.0),
+            None,
+            Some(3.0),
+            Some(3.0),
+            Some(3.0),
+            Some(9.0),
+        ];
+        let z = rolling_zscore(&values, 3);
+        assert_eq!(z[..3], [None, None, None]);
+        // Window 1, 2, 3: mean 2, sample std 1.
+        assert_eq!(z[3], Some(1.0));
+        // Window 2, 3, 3: mean 8/3, sample std sqrt(1/3).
+        assert!((z[4].unwrap() - (1.0 / 3.0) / (1.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        // A flat window has no spread to standardize against.
+        assert_eq!(z[5], None);
+        // Window 3, 3, 9: mean 5, sample std sqrt(12).
+        assert!((z[6].unwrap() - 4.0 / 12f64.sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn incremental_refresh_matches_a_cold_computation() {
+        let conn = candles((0..5).map(|m| (m, close_at(m))));
+        let mut state = IndicatorState::default();
+        state.refresh(&conn).unwrap();
+        // Batches of 1, 3, 0, 14 and 40 cross every warm-up boundary.
+        let mut next = 5;
+        for batch in [1, 3, 0, 14, 40] {
+            append(&conn, (next..next + batch).map(|m| (m, close_at(m))));
+            next += batch;
+            state.refresh(&conn).unwrap();
+            assert_eq!(
+                bits(state.points()),
+                bits(&compute(&conn, PriceSource::Close).unwrap())
+            );
+        }
+        assert_eq!(state.points().len(), next as usize);
+    }
+
+    #[test]
+    fn edits_to_history_force_a_rebuild() {
+        let conn = candles((0..30).map(|m| (m, close_at(m))));
+        let mut state = IndicatorState::default();
+        state.refresh(&conn).unwrap();
+
+        for edit in [
+            "UPDATE candles SET close = close + 5 WHERE timestamp = TIMESTAMP '2024-01-01 00:10:00'",
+            "DELETE FROM candles WHERE timestamp = TIMESTAMP '2024-01-01 00:03:00'",
+            "INSERT INTO candles VALUES ('2023-12-31 23:59:00', 1, 1, 1, 1, 1)",
+        ] {
+            conn.execute_batch(edit).unwrap();
+            append(&conn, [(next_minute(&conn), 90.0)]);
+            state.refresh(&conn).unwrap();
+            assert_eq!(bits(state.points()), bits(&compute(&conn, PriceSource::Close).unwrap()), "{edit}");
+        }
+    }
+
+    fn next_minute(conn: &Connection) -> u32 {
+        conn.query_row(
+            "SELECT CAST(date_diff('minute', TIMESTAMP '2024-01-01', max(timestamp)) AS INTEGER) + 1
+             FROM candles",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn matches_the_sql_definition() {
+        let conn = candles((0..60).map(|m| (m, close_at(m))));
+        let expected: Vec<(String, [Option<f64>; 3])> = conn
+            .prepare(SQL)
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, [row.get(1)?, row.get(2)?, row.get(3)?]))
+            })
+            .unwrap()
+            .collect::<duckdb::Result<_>>()
+            .unwrap();
+        let actual = compute(&conn, PriceSource::Close).unwrap();
+        assert_eq!(actual.len(), expected.len());
+        for (point, (timestamp, values)) in actual.iter().zip(&expected) {
+            assert_eq!(&point.timestamp.to_string(), timestamp);
+            for (got, want) in [point.sma_14, point.ema_14, point.rsi_14]
+                .iter()
+                .zip(values)
+            {
+                match (got, want) {
+                    (Some(got), Some(want)) => assert!((got - want).abs() < 1e-9, "{timestamp}"),
+                    _ => assert_eq!(got, want, "{timestamp}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn table_refresh_recomputes_only_from_the_first_change() {
+        let conn = candles((0..40).map(|m| (m, close_at(m))));
+        let check = |recomputed: i64, rows: i64| {
+            let status = refresh_table(&conn).unwrap();
+            assert_eq!((status.recomputed, status.rows), (recomputed, rows));
+            let stored = table_points(&conn).unwrap().expect("table is current");
+            assert_eq!(
+                bits(&stored),
+                bits(&compute(&conn, PriceSource::Close).unwrap())
+            );
+        };
+        check(40, 40);
+        check(0, 40);
+
+        append(&conn, (40..43).map(|m| (m, close_at(m))));
+        assert!(
+            table_points(&conn).unwrap().is_none(),
+            "stale until refreshed"
+        );
+        check(3, 43);
+
+        conn.execute_batch(
+            "UPDATE candles SET close = 1 WHERE timestamp = TIMESTAMP '2024-01-01 00:30:00'",
+        )
+        .unwrap();
+        check(13, 43);
+
+        conn.execute_batch("DELETE FROM candles WHERE timestamp = TIMESTAMP '2024-01-01 00:05:00'")
+            .unwrap();
+        check(37, 42);
+    }
+}
@@ -0,0 +1,654 @@
+//! Candlestick chart server: DuckDB-backed JSON API, live WebSocket feed and
+//! the static front end.
+
+pub mod cli;
+pub mod config;
+pub mod continuous;
+pub mod db;
+pub mod demo;
+pub mod error;
+pub mod indicators;
+pub mod models;
+
+mod alerts;
+mod assets;
+mod auth;
+mod backfill;
+mod bus;
+mod cache;
+mod chart;
+mod formula;
+mod handlers;
+mod http;
+mod hub;
+mod lwc;
+mod msgpack;
+mod negotiate;
+mod openapi;
+mod pnf;
+mod precision;
+mod rate_limit;
+mod series_csv;
+mod sql;
+mod strict;
+#[cfg(test)]
+mod test_support;
+mod ticks;
+mod timeout;
+mod udf;
+mod watchdog;
+mod xlsx;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use axum::middleware;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, MethodRouter};
+use axum::Router;
+use chrono::NaiveDateTime;
+use duckdb::Connection;
+use tower_http::compression::predicate::{NotForContentType, Predicate, SizeAbove};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::services::ServeDir;
+
+pub use crate::config::Config;
+pub use crate::db::Db;
+
+use crate::auth::{require_api_key, Access, Keys};
+use crate::bus::{Bus, BUS_CAPACITY};
+use crate::cache::{cache_response, conditional_get, ResponseCache};
+use crate::config::{CorsSettings, RateLimit};
+use crate::db::{
+    check_integrity, initialize_db, initialize_demo_db, initialize_events, initialize_symbols,
+    Reopen,
+};
+use crate::demo::DemoSpec;
+use crate::error::{
+    api_not_found, internal_error, is_api_path, json_errors, method_not_allowed, REQUEST_ID,
+};
+use crate::handlers::{
+    explain, generate_demo_data, get_adaptive_candles, get_admin_stats, get_adx, get_bound_sql,
+    get_candles, get_cmo, get_continuous, get_dpo, get_events, get_fib, get_fib_time, get_formula,
+    get_indicators, get_integrity, get_intraday_overlay, get_kama, get_meta, get_percentile,
+    get_pnf, get_rolling_correlation, get_rolling_price, get_spread, get_stc, get_stddev,
+    get_symbols, get_volume_indicators, get_vortex, get_zscore, healthz, post_ticks, ready,
+    repair_integrity, replay_candles, stream_candles, stream_events,
+};
+use crate::hub::{Hub, WebSockets, HUB_CAPACITY};
+use crate::indicators::{IndicatorState, PriceSource};
+use crate::rate_limit::{limit_requests, Limit, RateLimiter};
+use crate::strict::{enforce_ohlc, OhlcCheck};
+use crate::ticks::TickAggregator;
+use crate::timeout::{enforce_timeout, REQUEST_TIMEOUT};
+use crate::udf::{get_config, get_history, get_symbol, get_time, search};
+use crate::watchdog::Health;
+
+/// Everything a request handler needs, cheap to clone into each request.
+#[derive(Clone)]
+pub struct AppState {
+    pub(crate) config: Arc<Config>,
+    pub(crate) db: Arc<Db>,
+    pub(crate) hub: Hub,
+    /// Data-changed notifications, published by every write path.
+    pub(crate) bus: Bus,
+    pub(crate) cache: Option<Arc<ResponseCache>>,
+    pub(crate) keys: Arc<Keys>,
+    /// What the database watchdog last saw, for `/ready`.
+    pub(crate) health: Arc<Health>,
+    /// Incrementally maintained indicator series, one per price source.
+    pub(crate) indicators: Arc<std::sync::Mutex<HashMap<PriceSource, IndicatorState>>>,
+    /// The last strict-mode scan of the candles, when `strict_ohlc` is on.
+    pub(crate) ohlc: Arc<OhlcCheck>,
+    /// Bars being built from ticks posted to `/api/ticks`.
+    pub(crate) ticks: Arc<TickAggregator>,
+    /// Open `/api/ws` connections, counted against `max_websockets`.
+    pub(crate) websockets: Arc<WebSockets>,
+}
+
+impl AppState {
+    pub fn new(db: Arc<Db>, config: Config) -> Self {
+        let cache = config
+            .cache_enabled
+            .then(|| Arc::new(ResponseCache::new(config.cache_max_bytes)));
+        let bus = Bus::new(BUS_CAPACITY);
+        Self {
+            keys: Arc::new(Keys::new(&config.api_keys)),
+            health: Arc::default(),
+            ohlc: Arc::default(),
+            websockets: Arc::default(),
+            ticks: Arc::new(TickAggregator::new(config.tick_interval, config.tick_grace)),
+            config: Arc::new(config),
+            db,
+            hub: Hub::new(HUB_CAPACITY, bus.clone()),
+            bus,
+            cache,
+            indicators: Arc::default(),
+        }
+    }
+}
+
+/// How long shutdown waits for WebSocket clients to get their close frames.
+const STREAM_CLOSE_GRACE: Duration = Duration::from_secs(2);
+
+/// Opens and loads the database, starts the candle poller and serves the API
+/// until the listener fails or the process is asked to stop. On Ctrl-C or
+/// SIGTERM it stops accepting connections, lets in-flight requests finish,
+/// sends WebSocket clients a close frame, stores bars still forming from
+/// ticks and checkpoints the database so the next start has no WAL to replay.
+pub async fn serve(config: Config) -> anyhow::Result<()> {
+    let reopen = Arc::new(database(&config));
+    let conn = (reopen.open)()?;
+    let db = Db::with_limits(conn, config.read_pool_size, &config.duckdb)?;
+    let candles = db
+        .write({
+            let reopen = Arc::clone(&reopen);
+            move |conn| {
+                (reopen.setup)(conn)?;
+                Ok::<_, anyhow::Error>(conn.query_row(
+                    "SELECT count(*) FROM candles",
+                    [],
+                    |row| row.get::<_, i64>(0),
+                )?)
+            }
+        })
+        .await
+        .context("init DuckDB")?;
+    if candles == 0 {
+        tracing::warn!(
+            "the candles table is EMPTY: {} was not found or had no rows, so list \
+             endpoints will answer [] and aggregates 404 until candles are stored \
+             (or start with --generate-demo-data)",
+            config.csv_path.display()
+        );
+    }
+
+    let addr = config.bind_addr;
+    let poll_interval = config.poll_interval;
+    let (watchdog_interval, watchdog_failures) =
+        (config.watchdog_interval, config.watchdog_failures);
+    let tick_interval = config.tick_interval;
+    let state = AppState::new(Arc::new(db), config);
+    tokio::spawn(state.hub.clone().run(Arc::clone(&state.db), poll_interval));
+    if watchdog_failures > 0 {
+        tokio::spawn(watchdog::run(
+            Arc::clone(&state.db),
+            Arc::clone(&state.health),
+            reopen,
+            watchdog_interval,
+            watchdog_failures,
+        ));
+    }
+    tokio::spawn(indicators::maintain_table(
+        Arc::clone(&state.db),
+        state.bus.subscribe(),
+    ));
+    tokio::spawn(alerts::run(
+        Arc::clone(&state.db),
+        state.config.webhooks.clone(),
+        state.bus.subscribe(),
+    ));
+    tokio::spawn(ticks::flush_idle(
+        Arc::clone(&state.ticks),
+        Arc::clone(&state.db),
+        state.hub.clone(),
+        tick_interval,
+    ));
+    let db = Arc::clone(&state.db);
+    let hub = state.hub.clone();
+    let tick_bars = Arc::clone(&state.ticks);
+    let app = build_router(state);
+
+    tracing::info!("listening on {addr}");
+    // Peer addresses key the rate limiter.
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(tokio::net::TcpListener::bind(addr).await?, app)
+        .with_graceful_shutdown({
+            let hub = hub.clone();
+            async move {
+                shutdown_signal().await;
+                // Upgraded sockets are not requests, so draining does not
+                // wait for them, and event streams never finish on their
+                // own; both are asked to close instead.
+                hub.close_streams();
+            }
+        })
+        .await?;
+    if tokio::time::timeout(STREAM_CLOSE_GRACE, hub.streams_closed())
+        .await
+        .is_err()
+    {
+        tracing::warn!("some streaming clients did not close within {STREAM_CLOSE_GRACE:?}");
+    }
+
+    // Bars still forming have no later ticks coming to store them.
+    match tick_bars.flush(&db, &hub, NaiveDateTime::MAX).await {
+        Ok(0) => {}
+        Ok(stored) => tracing::info!("stored {stored} bars still forming from ticks"),
+        Err(err) => tracing::error!("storing bars still forming from ticks failed: {err}"),
+    }
+    // `serve` always opens the database read-write (it creates and repairs
+    // tables at startup), so there is no read-only mode to skip this in.
+    tracing::info!("requests drained; checkpointing DuckDB");
+    db.write(|conn| conn.execute_batch("CHECKPOINT"))
+        .await
+        .context("checkpoint DuckDB")?;
+    tracing::info!("checkpoint complete");
+    Ok(())
+}
+
+/// Opening the configured database file and the idempotent setup run on it
+/// at startup and again whenever the watchdog reopens it.
+fn database(config: &Config) -> Reopen {
+    let db_path = config.db_path.clone();
+    let csv_path = config.csv_path.clone();
+    let events_csv_path = config.events_csv_path.clone();
+    let symbols_csv_path = config.symbols_csv_path.clone();
+    let csv_mode = config.csv_mode;
+    let repair_duplicates = config.repair_duplicates;
+    let demo_data = config.demo_data;
+    Reopen {
+        open: Box::new(move || Connection::open(&db_path).context("open DuckDB")),
+        setup: Box::new(move |conn| {
+            if demo_data {
+                initialize_demo_db(conn, &DemoSpec::default())?;
+            } else {
+                initialize_db(conn, &csv_path, csv_mode)?;
+            }
+            initialize_events(conn, &events_csv_path, csv_mode)?;
+            initialize_symbols(conn, &symbols_csv_path, csv_mode)?;
+            check_integrity(conn, repair_duplicates)?;
+            Ok(())
+        }),
+    }
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM where there is one.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("cannot listen for Ctrl-C: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("cannot listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+    tracing::info!("shutting down");
+}
+
+/// The full application: API routes, caching and compression layers, and the
+/// static front end.
+pub fn build_router(state: AppState) -> Router {
+    let limit = |limit| middleware::from_fn_with_state(limit, enforce_timeout);
+    let query_limit = || limit(state.config.query_timeout);
+    let rate_limit = |limit: &RateLimit| {
+        middleware::from_fn_with_state(
+            Limit {
+                limiter: Arc::new(RateLimiter::new(limit)),
+                trusted_proxy: state.config.rate_limits.trusted_proxy,
+            },
+            limit_requests,
+        )
+    };
+    // One limiter shared by the analytics routes, so a client's budget covers
+    // all of them together.
+    let expensive_limit = state.config.rate_limits.expensive.as_ref().map(rate_limit);
+    let expensive = |route: MethodRouter<AppState>| match &expensive_limit {
+        Some(layer) => route.route_layer(layer.clone()),
+        None => route,
+    };
+    let global_limit = state.config.rate_limits.global.as_ref().map(rate_limit);
+    let access = |access| middleware::from_fn_with_state((state.clone(), access), require_api_key);
+    let data = Router::new()
+        .route(
+            "/api/candles",
+            get(get_candles).route_layer(limit(state.config.export_timeout)),
+        )
+        .route(
+            "/api/export/xlsx",
+            expensive(get(xlsx::export).route_layer(limit(state.config.export_timeout))),
+        )
+        .route(
+            "/api/adaptive_candles",
+            expensive(get(get_adaptive_candles).route_layer(query_limit())),
+        )
+        .route(
+            "/api/chart.png",
+            expensive(get(chart::get_chart).route_layer(query_limit())),
+        )
+        .route(
+            "/api/indicators",
+            expensive(get(get_indicators).route_layer(query_limit())),
+        )
+        .route(
+            "/api/volume_indicators",
+            expensive(get(get_volume_indicators).route_layer(query_limit())),
+        )
+        .route(
+            "/api/adx",
+            expensive(get(get_adx).route_layer(query_limit())),
+        )
+        .route(
+            "/api/vortex",
+            expensive(get(get_vortex).route_layer(query_limit())),
+        )
+        .route(
+            "/api/pnf",
+            expensive(get(get_pnf).route_layer(query_limit())),
+        )
+        .route(
+            "/api/formula",
+            expensive(get(get_formula).route_layer(query_limit())),
+        )
+        .route(
+            "/api/zscore",
+            expensive(get(get_zscore).route_layer(query_limit())),
+        )
+        .route(
+            "/api/stddev",
+            expensive(get(get_stddev).route_layer(query_limit())),
+        )
+        .route(
+            "/api/rolling_price",
+            expensive(get(get_rolling_price).route_layer(query_limit())),
+        )
+        .route(
+            "/api/cmo",
+            expensive(get(get_cmo).route_layer(query_limit())),
+        )
+        .route(
+            "/api/kama",
+            expensive(get(get_kama).route_layer(query_limit())),
+        )
+        .route(
+            "/api/stc",
+            expensive(get(get_stc).route_layer(query_limit())),
+        )
+        .route(
+            "/api/dpo",
+            expensive(get(get_dpo).route_layer(query_limit())),
+        )
+        .route(
+            "/api/rolling_correlation",
+            expensive(get(get_rolling_correlation).route_layer(query_limit())),
+        )
+        .route(
+            "/api/spread",
+            expensive(get(get_spread).route_layer(query_limit())),
+        )
+        .route("/api/symbols", get(get_symbols).route_layer(query_limit()))
+        .route("/api/meta", get(get_meta).route_layer(query_limit()))
+        .route(
+            "/api/continuous",
+            expensive(get(get_continuous).route_layer(query_limit())),
+        )
+        .route("/api/fib", get(get_fib).route_layer(query_limit()))
+        .route(
+            "/api/fib_time",
+            get(get_fib_time).route_layer(query_limit()),
+        )
+        .route(
+            "/api/percentile",
+            expensive(get(get_percentile).route_layer(query_limit())),
+        )
+        .route(
+            "/api/intraday_overlay",
+            expensive(get(get_intraday_overlay).route_layer(query_limit())),
+        )
+        .route("/api/events", get(get_events).route_layer(query_limit()))
+        .route("/udf/config", get(get_config))
+        .route("/udf/symbols", get(get_symbol).route_layer(query_limit()))
+        .route("/udf/search", get(search).route_layer(query_limit()))
+        .route(
+            "/udf/history",
+            expensive(get(get_history).route_layer(query_limit())),
+        )
+        .route_layer(middleware::from_fn_with_state(state.clone(), enforce_ohlc))
+        // Inside `conditional_get`, which leaves the data fingerprint the
+        // cache keys entries by.
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            cache_response,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            conditional_get,
+        ))
+        .route("/api/ws", get(stream_candles))
+        .route("/ws/replay", get(replay_candles))
+        .route("/api/sse", get(stream_events))
+        // The clock, which no cached copy would tell.
+        .route("/udf/time", get(get_time))
+        .route_layer(access(Access::Read));
+    let admin = Router::new()
+        .route("/api/admin/stats", get(get_admin_stats))
+        .route("/api/admin/explain", get(explain))
+        .route("/api/admin/sql", get(get_bound_sql))
+        .route("/api/admin/generate", post(generate_demo_data))
+        .route(
+            "/api/query",
+            post(sql::post_query).route_layer(query_limit()),
+        )
+        .route("/api/ticks", post(post_ticks))
+        .route("/api/backfill", post(backfill::post_backfill))
+        .route(
+            "/api/alerts",
+            get(alerts::get_alerts).post(alerts::post_alert),
+        )
+        .route("/api/alerts/:id", delete(alerts::delete_alert))
+        .route("/api/alerts/:id/deliveries", get(alerts::get_deliveries))
+        .route(
+            "/api/admin/integrity",
+            get(get_integrity).post(repair_integrity),
+        )
+        .route_layer(access(Access::Write));
+    // The API surface is as private as the data it describes.
+    let docs = Router::new()
+        .route("/api/openapi.json", get(openapi::openapi_json))
+        .route("/docs", get(openapi::swagger_ui))
+        .route_layer(access(Access::Read));
+    let compression = state.config.compression_enabled.then(|| {
+        CompressionLayer::new()
+            .gzip(true)
+            .br(true)
+            .compress_when(compression_predicate(state.config.compression_min_bytes))
+    });
+    let cors = cors_layer(&state.config.cors);
+    let router = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/ready", get(ready))
+        .merge(docs)
+        .merge(data)
+        .merge(admin)
+        .method_not_allowed_fallback(method_not_allowed)
+        .fallback(static_files)
+        .with_state(state);
+    // Inside the JSON error layer, so 429s get the usual error body.
+    let router = match global_limit {
+        Some(limit) => router.layer(limit),
+        None => router,
+    }
+    .layer(middleware::from_fn(json_errors));
+    // Outside the JSON error layer, so a preflight is answered before any
+    // route sees it and error responses carry the CORS headers too.
+    let router = match cors {
+        Some(cors) => router.layer(cors),
+        None => router,
+    };
+    match compression {
+        Some(compression) => router.layer(compression),
+        None => router,
+    }
+}
+
+/// Everything no route matched: the front end, except under `/api`, where a
+/// mistyped endpoint gets the JSON 404 instead of an HTML page or whatever
+/// file the static directory happens to have at that path. Without a static
+/// directory, the copy built into the binary.
+async fn static_files(State(state): State<AppState>, request: Request) -> Response {
+    if is_api_path(request.uri().path()) {
+        return api_not_found(request.uri().clone()).await.into_response();
+    }
+    let Some(dir) = &state.config.static_dir else {
+        return assets::serve(request).await;
+    };
+    let navigation = assets::is_navigation(request.headers(), request.uri().path());
+    let response = match ServeDir::new(dir).try_call(request).await {
+        Ok(response) => response.map(Body::new),
+        Err(err) => return internal_error(err).into_response(),
+    };
+    // A page of the front end's own, which it routes from `index.html`, as
+    // the embedded copy does.
+    if response.status() == StatusCode::NOT_FOUND && navigation {
+        let index = Request::get("/")
+            .body(Body::empty())
+            .expect("a valid request");
+        return ServeDir::new(dir)
+            .try_call(index)
+            .await
+            .map_err(internal_error)
+            .into_response();
+    }
+    response
+}
+
+/// The configured CORS policy, or `None` when no origins are allowed.
+fn cors_layer(settings: &CorsSettings) -> Option<CorsLayer> {
+    let origins = match settings.allowed_origins.as_slice() {
+        [] => return None,
+        [any] if any == "*" => AllowOrigin::any(),
+        origins => AllowOrigin::list(
+            origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin).expect("origins are validated")),
+        ),
+    };
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods([
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+            .allow_headers([
+                header::ACCEPT,
+                header::AUTHORIZATION,
+                header::CACHE_CONTROL,
+                header::CONTENT_TYPE,
+                header::IF_NONE_MATCH,
+                HeaderName::from_static("x-api-key"),
+                REQUEST_ID,
+                REQUEST_TIMEOUT,
+            ])
+            .expose_headers([
+                header::ETAG,
+                HeaderName::from_static("cache-status"),
+                REQUEST_ID,
+            ])
+            .max_age(settings.max_age),
+    )
+}
+
+/// Compress anything above the size floor except content that is already
+/// compressed or must stream unbuffered (server-sent events).
+fn compression_predicate(min_bytes: u16) -> impl Predicate {
+    SizeAbove::new(min_bytes)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::SSE)
+        .and(NotForContentType::const_new("application/gzip"))
+        .and(NotForContentType::const_new("application/zip"))
+        .and(NotForContentType::const_new("application/zstd"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use axum::body::Body;
+    use axum::extract::Request;
+    use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING};
+    use axum::http::StatusCode;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::*;
+
+    #[tokio::test]
+    async fn slow_queries_do_not_stall_the_runtime() {
+        let db = memory_db(2);
+        let slow = tokio::spawn({
+            let db = Arc::clone(&db);
+            async move {
+                db.read(|conn| {
+                    conn.query_row(
+                        "SELECT count(*) FROM range(5000) a, range(5000) b, range(40) c",
+                        [],
+                        |row| row.get::<_, i64>(0),
+                    )
+                    .unwrap()
+                })
+                .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let app = build_router(AppState::new(db, Config::default()));
+        let started = Instant::now();
+        let response = app
+            .oneshot(Request::get("/healthz").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert!(!slow.is_finished(), "slow query should still be running");
+        assert_eq!(slow.await.unwrap(), 1_000_000_000);
+    }
+
+    #[tokio::test]
+    async fn responses_are_compressed_when_accepted() {
+        let app = stocks_app(Config::default());
+        let gzip = get_with(&app, "/api/candles", &[(ACCEPT_ENCODING, "gzip")]).await;
+        assert_eq!(gzip.headers()[CONTENT_ENCODING], "gzip");
+        let brotli = get_with(&app, "/api/candles", &[(ACCEPT_ENCODING, "br")]).await;
+        assert_eq!(brotli.headers()[CONTENT_ENCODING], "br");
+
+        let identity = get_uri(&app, "/api/candles").await;
+        assert!(identity.headers().get(CONTENT_ENCODING).is_none());
+        let tiny = get_with(&app, "/healthz", &[(ACCEPT_ENCODING, "gzip")]).await;
+        assert!(tiny.headers().get(CONTENT_ENCODING).is_none());
+
+        let disabled = stocks_app(Config {
+            compression_enabled: false,
+            ..Config::default()
+        });
+        let response = get_with(&disabled, "/api/candles", &[(ACCEPT_ENCODING, "gzip")]).await;
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+    }
+}
@@ -0,0 +1,168 @@
+//! `?format=lwc`: responses shaped for TradingView's lightweight-charts, so a
+//! front end can hand them straight to `series.setData()`.
+//!
+//! The request's timestamps are Unix seconds whatever `ts_format` asked for.
+//! Candles become `{time, open, high, low, close}` bars beside a separate
+//! `{time, value}` volume series, coloured by direction on request; a series
+//! of points splits into one `{time, value}` line per column. A missing
+//! value becomes whitespace, `{time}` alone, which the chart draws as a gap.
+
+use serde_json::{json, Map, Value};
+
+/// Volume bar colours with `volume_colors=true`: lightweight-charts' own
+/// defaults for rising and falling candles.
+const UP_COLOR: &str = "#26a69a";
+const DOWN_COLOR: &str = "#ef5350";
+
+/// A series of points as [`line_series`]; `envelope=true` keeps its `meta`
+/// beside the reshaped `data`.
+pub(crate) fn reshape(value: Value) -> Value {
+    match value {
+        Value::Array(points) => line_series(points),
+        Value::Object(mut envelope) => {
+            if let Some(Value::Array(points)) = envelope.remove("data") {
+                envelope.insert("data".to_owned(), line_series(points));
+            }
+            Value::Object(envelope)
+        }
+        other => other,
+    }
+}
+
+/// `{candles: [{time, open, high, low, close}], volume: [{time, value}]}`;
+/// projected bars are whitespace in both.
+pub(crate) fn candle_series(rows: Vec<Value>, colors: bool) -> Value {
+    let mut bars = Vec::with_capacity(rows.len());
+    let mut volume = Vec::with_capacity(rows.len());
+    for row in rows {
+        let Value::Object(row) = row else { continue };
+        let time = row.get("timestamp").cloned().unwrap_or_default();
+        let field = |name: &str| row.get(name).filter(|value| !value.is_null()).cloned();
+        match (field("open"), field("high"), field("low"), field("close")) {
+            (Some(open), Some(high), Some(low), Some(close)) => {
+                let rose = close.as_f64() >= open.as_f64();
+                bars.push(json!({
+                    "time": time, "open": open, "high": high, "low": low, "close": close,
+                }));
+                volume.push(match field("volume") {
+                    Some(value) if colors => json!({
+                        "time": time,
+                        "value": value,
+                        "color": if rose { UP_COLOR } else { DOWN_COLOR },
+                    }),
+                    Some(value) => json!({ "time": time, "value": value }),
+                    None => json!({ "time": time }),
+                });
+            }
+            _ => {
+                bars.push(json!({ "time": time }));
+                volume.push(json!({ "time": time }));
+            }
+        }
+    }
+    json!({ "candles": bars, "volume": volume })
+}
+
+/// One `{time, value}` series per numeric column of `points`, keyed by the
+/// column's name.
+fn line_series(points: Vec<Value>) -> Value {
+    let mut lines = Map::new();
+    for point in points {
+        let Value::Object(mut point) = point else {
+            continue;
+        };
+        let time = point.remove("timestamp").unwrap_or_default();
+        for (name, value) in point {
+            let entry = match value {
+                Value::Number(_) => json!({ "time": time, "value": value }),
+                Value::Null => json!({ "time": time }),
+                _ => continue,
+            };
+            lines
+                .entry(name)
+                .or_insert_with(|| Value::Array(Vec::new()))
+                .as_array_mut()
+                .expect("series are arrays")
+                .push(entry);
+        }
+    }
+    Value::Object(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use crate::build_router;
+    use crate::test_support::{get_json, get_uri, seeded_state};
+
+    const ROWS: &str = "('2024-01-01 00:00:00', 1, 3, 1, 2, 10),
+         ('2024-01-01 00:01:00', 2, 3, 1, 1, 20)";
+    // 2024-01-01 00:00:00 UTC.
+    const T: i64 = 1_704_067_200;
+
+    #[tokio::test]
+    async fn candles_split_into_bars_and_volume() {
+        let app = build_router(seeded_state(ROWS));
+        let body = get_json(&app, "/api/candles?format=lwc&ts_format=iso&project=1").await;
+        assert_eq!(
+            body["candles"],
+            json!([
+                { "time": T, "open": 1.0, "high": 3.0, "low": 1.0, "close": 2.0 },
+                { "time": T + 60, "open": 2.0, "high": 3.0, "low": 1.0, "close": 1.0 },
+                { "time": T + 120 },
+            ])
+        );
+        assert_eq!(
+            body["volume"],
+            json!([
+                { "time": T, "value": 10.0 },
+                { "time": T + 60, "value": 20.0 },
+                { "time": T + 120 },
+            ])
+        );
+
+        // Timestamps follow the negotiated format, however the query spells it.
+        for uri in [
+            "/api/candles?format=%6Cwc&ts_format=iso",
+            "/api/candles?format=lwc&format=json&ts_format=iso",
+        ] {
+            assert_eq!(get_json(&app, uri).await["candles"][0]["time"], T, "{uri}");
+        }
+        let body = get_json(&app, "/api/candles?format=json&format=lwc&ts_format=iso").await;
+        assert_eq!(body[0]["timestamp"], "2024-01-01T00:00:00Z");
+
+        let colored = get_json(&app, "/api/candles?format=lwc&volume_colors=true").await;
+        assert_eq!(colored["volume"][0]["color"], "#26a69a");
+        assert_eq!(colored["volume"][1]["color"], "#ef5350");
+
+        // The default shape is untouched.
+        let plain = get_json(&app, "/api/candles").await;
+        assert_eq!(plain[0]["timestamp"], "2024-01-01 00:00:00");
+        assert_eq!(plain[0]["close"], 2.0);
+
+        let response = get_uri(&app, "/api/candles?format=lwc&include=events").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn indicator_columns_become_named_series() {
+        let app = build_router(seeded_state(ROWS));
+        let body = get_json(&app, "/api/stddev?period=2&format=lwc").await;
+        assert_eq!(body["stddev"][0], json!({ "time": T }));
+        assert_eq!(body["stddev"][1]["time"], T + 60);
+        assert!(body["variance"][1]["value"].is_number());
+
+        let body = get_json(&app, "/api/indicators?format=lwc").await;
+        assert_eq!(
+            body["sma_14"],
+            json!([{ "time": T, "value": 2.0 }, { "time": T + 60, "value": 1.5 }])
+        );
+        assert_eq!(body["rsi_14"][0], json!({ "time": T }));
+
+        let body = get_json(&app, "/api/indicators?format=lwc&envelope=true").await;
+        assert_eq!(body["data"]["sma_14"][1]["value"], 1.5);
+        assert_eq!(body["meta"]["count"], 2);
+    }
+}
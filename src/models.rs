@@ -0,0 +1,864 @@
+//! Response bodies shared by the handlers and the streaming endpoints.
+
+use std::fmt;
+use std::io::Write;
+
+use chrono::{DateTime, FixedOffset, NaiveDateTime, SecondsFormat};
+use duckdb::types::ValueRef;
+use duckdb::types::{FromSql, FromSqlError, FromSqlResult, TimeUnit, ToSql, ToSqlOutput, Value};
+use serde::{Deserialize, Serialize};
+
+/// Layout of timestamps in requests and in the default `ts_format`. The
+/// fraction is optional when parsing and written only when the second has
+/// one, with as many digits (3, 6 or 9) as it needs.
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+/// A stored timestamp, read from DuckDB as a native value and only turned
+/// into text (or a number) when a response is written, in the format the
+/// request asked for. Stored timestamps are taken to be UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp {
+    pub at: NaiveDateTime,
+    pub format: TimestampFormat,
+}
+
+/// How a response renders its timestamps: `ts_format` and `tz`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimestampFormat {
+    pub style: TimestampStyle,
+    /// Shift applied to `text` and `iso`; epoch values are zone-independent.
+    pub offset: FixedOffset,
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        Self {
+            style: TimestampStyle::default(),
+            offset: FixedOffset::east_opt(0).expect("zero offset"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampStyle {
+    /// `YYYY-MM-DD HH:MM:SS`, plus `.fff` for sub-second timestamps
+    #[default]
+    Text,
+    /// RFC 3339 with the offset, e.g. `2024-01-01T09:30:00.250+05:30`
+    Iso,
+    /// Seconds since the Unix epoch, fractional for sub-second timestamps
+    Unix,
+    /// Milliseconds since the Unix epoch
+    UnixMs,
+}
+
+impl Timestamp {
+    pub fn new(at: NaiveDateTime) -> Self {
+        Self {
+            at,
+            format: TimestampFormat::default(),
+        }
+    }
+
+    pub fn with_format(self, format: TimestampFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    /// Seconds since the epoch, whole unless the timestamp splits a second
+    /// so that bars within one second stay apart.
+    fn unix_seconds(&self) -> UnixSeconds {
+        let at = self.at.and_utc();
+        match at.timestamp_subsec_micros() {
+            0 => UnixSeconds::Whole(at.timestamp()),
+            _ => UnixSeconds::Fractional(at.timestamp_micros() as f64 / 1e6),
+        }
+    }
+}
+
+enum UnixSeconds {
+    Whole(i64),
+    Fractional(f64),
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let local = self.at.and_utc().with_timezone(&self.format.offset);
+        match self.format.style {
+            TimestampStyle::Text => write!(f, "{}", local.format(TIMESTAMP_FORMAT)),
+            TimestampStyle::Iso => f.write_str(&local.to_rfc3339_opts(SecondsFormat::AutoSi, true)),
+            TimestampStyle::Unix => match self.unix_seconds() {
+                UnixSeconds::Whole(seconds) => write!(f, "{seconds}"),
+                UnixSeconds::Fractional(seconds) => write!(f, "{seconds}"),
+            },
+            TimestampStyle::UnixMs => write!(f, "{}", local.timestamp_millis()),
+        }
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.format.style {
+            TimestampStyle::Unix => match self.unix_seconds() {
+                UnixSeconds::Whole(seconds) => serializer.serialize_i64(seconds),
+                UnixSeconds::Fractional(seconds) => serializer.serialize_f64(seconds),
+            },
+            TimestampStyle::UnixMs => {
+                serializer.serialize_i64(self.at.and_utc().timestamp_millis())
+            }
+            TimestampStyle::Text | TimestampStyle::Iso => serializer.collect_str(self),
+        }
+    }
+}
+
+impl FromSql for Timestamp {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let ValueRef::Timestamp(unit, raw) = value else {
+            return Err(FromSqlError::InvalidType);
+        };
+        let micros = match unit {
+            TimeUnit::Second => raw.checked_mul(1_000_000),
+            TimeUnit::Millisecond => raw.checked_mul(1_000),
+            TimeUnit::Microsecond => Some(raw),
+            TimeUnit::Nanosecond => Some(raw.div_euclid(1_000)),
+        };
+        micros
+            .and_then(DateTime::from_timestamp_micros)
+            .map(|at| Timestamp::new(at.naive_utc()))
+            .ok_or(FromSqlError::OutOfRange(i128::from(raw)))
+    }
+}
+
+impl ToSql for Timestamp {
+    fn to_sql(&self) -> duckdb::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Owned(Value::Timestamp(
+            TimeUnit::Microsecond,
+            self.at.and_utc().timestamp_micros(),
+        )))
+    }
+}
+
+/// A float as responses carry it: itself when finite, otherwise `null`.
+///
+/// NaN and infinities come out of degenerate inputs, such as zero ranges,
+/// zero denominators or non-finite stored prices. JSON cannot express them
+/// and CSV readers disagree on their spelling, so every float in a response
+/// goes through here and reaches the client as a missing value instead:
+/// fields with `#[serde(serialize_with = "fin_or_null")]`, hand-written
+/// `Serialize` impls through [`Finite::finite`], and CSV rows through
+/// [`FinOrNull::get`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FinOrNull(pub f64);
+
+impl FinOrNull {
+    pub fn get(self) -> Option<f64> {
+        self.0.is_finite().then_some(self.0)
+    }
+}
+
+impl Serialize for FinOrNull {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.get() {
+            Some(value) => serializer.serialize_f64(value),
+            None => serializer.serialize_none(),
+        }
+    }
+}
+
+/// Floats, and containers of them, serialized through [`FinOrNull`].
+pub trait Finite {
+    type Output: Serialize;
+
+    fn finite(&self) -> Self::Output;
+}
+
+impl Finite for f64 {
+    type Output = FinOrNull;
+
+    fn finite(&self) -> FinOrNull {
+        FinOrNull(*self)
+    }
+}
+
+impl Finite for Option<f64> {
+    type Output = Option<FinOrNull>;
+
+    fn finite(&self) -> Option<FinOrNull> {
+        self.map(FinOrNull)
+    }
+}
+
+impl Finite for Vec<f64> {
+    type Output = Vec<FinOrNull>;
+
+    fn finite(&self) -> Vec<FinOrNull> {
+        self.iter().copied().map(FinOrNull).collect()
+    }
+}
+
+/// `serialize_with` for float fields; see [`FinOrNull`].
+pub fn fin_or_null<T: Finite, S: serde::Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value.finite().serialize(serializer)
+}
+
+/// A candle's volume, stored as a double and rounded on the way out when
+/// `Config::volume_precision` is set. At precision 0 whole values are written
+/// as integers, so share counts lose the `.0`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Volume {
+    pub value: f64,
+    pub precision: Option<u32>,
+}
+
+impl Volume {
+    pub fn new(value: f64) -> Self {
+        Self {
+            value,
+            precision: None,
+        }
+    }
+
+    /// Rounds to `precision` decimal places; `None` keeps the stored value.
+    pub fn with_precision(self, precision: Option<u32>) -> Self {
+        Self {
+            value: precision.map_or(self.value, |places| round_to(self.value, places)),
+            precision,
+        }
+    }
+}
+
+/// Rounds half away from zero; beyond 15 places a double has nothing left.
+pub fn round_to(value: f64, places: u32) -> f64 {
+    let scale = 10f64.powi(places.min(15) as i32);
+    (value * scale).round() / scale
+}
+
+/// Integers above this are no longer exact as doubles.
+const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+impl fmt::Display for Volume {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+impl Serialize for Volume {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.precision == Some(0) && self.value.abs() <= MAX_EXACT_INTEGER {
+            serializer.serialize_i64(self.value as i64)
+        } else {
+            FinOrNull(self.value).serialize(serializer)
+        }
+    }
+}
+
+impl FromSql for Volume {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        f64::column_result(value).map(Volume::new)
+    }
+}
+
+impl ToSql for Volume {
+    fn to_sql(&self) -> duckdb::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Owned(Value::Double(self.value)))
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Candle {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub open: f64,
+    #[serde(serialize_with = "fin_or_null")]
+    pub high: f64,
+    #[serde(serialize_with = "fin_or_null")]
+    pub low: f64,
+    #[serde(serialize_with = "fin_or_null")]
+    pub close: f64,
+    pub volume: Volume,
+    /// Events snapped to this candle, present with `include=events`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub events: Option<Vec<Event>>,
+    /// Quote columns, present with `include=bidask` or `include=spread`.
+    #[serde(flatten)]
+    pub quotes: QuoteValues,
+}
+
+/// `bid`, `ask` and `spread`, each present only when asked for and the
+/// candles table has the data for it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QuoteValues(pub Vec<(&'static str, Option<f64>)>);
+
+impl Serialize for QuoteValues {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in &self.0 {
+            map.serialize_entry(name, &value.finite())?;
+        }
+        map.end()
+    }
+}
+
+/// A message on `/api/ws`, tagged as `{"type": ..., "data": ...}` so the
+/// protocol can grow.
+#[derive(Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum StreamMessage<'a> {
+    /// A new candle, or a newer version of the last one sent.
+    Candle(&'a Candle),
+    /// The client fell behind and `missed` candles were dropped; the range
+    /// can be refetched from `/api/candles`.
+    Gap { missed: u64 },
+    /// Candles were replaced or repaired in bulk; refetch anything shown.
+    DataChanged,
+    /// A replay has sent its last candle.
+    ReplayDone,
+    /// The subscribed indicators at the candle sent just before.
+    Indicator(&'a IndicatorUpdate),
+    /// A client command was refused.
+    Error { message: String },
+}
+
+/// A row of a candle series: either stored data or a projected future slot.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum CandleRow {
+    Candle(Candle),
+    Projected(ProjectedBar),
+}
+
+/// A future timestamp with no data, so charts can extend the x-axis for
+/// projections such as Ichimoku spans or regression channels.
+#[derive(Serialize)]
+pub struct ProjectedBar {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub open: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub high: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub low: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub close: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub volume: Option<f64>,
+}
+
+/// Header of the `format=bin` candle encoding, which clients decode without
+/// parsing text. Every field is little-endian.
+///
+/// The body is the 4-byte magic `OHLC`, a `u16` version (1) and a `u16`
+/// record size (48), then one record per bar until the body ends: an `i64`
+/// timestamp in microseconds since the Unix epoch, UTC whatever `ts_format`
+/// and `tz` ask for, followed by `open`, `high`, `low`, `close` and `volume`
+/// as `f64`. Values that are missing (projected bars) or not finite are NaN.
+pub const BINARY_HEADER: [u8; 8] = [b'O', b'H', b'L', b'C', 1, 0, 48, 0];
+
+impl CandleRow {
+    fn fields(&self) -> (&Timestamp, [Option<f64>; 5]) {
+        match self {
+            CandleRow::Candle(c) => (
+                &c.timestamp,
+                [
+                    Some(c.open),
+                    Some(c.high),
+                    Some(c.low),
+                    Some(c.close),
+                    Some(c.volume.value),
+                ],
+            ),
+            CandleRow::Projected(p) => (&p.timestamp, [p.open, p.high, p.low, p.close, p.volume]),
+        }
+    }
+
+    pub(crate) fn write_csv(&self, buf: &mut Vec<u8>) {
+        let (timestamp, values) = self.fields();
+        write!(buf, "{timestamp}").expect("writing to a Vec cannot fail");
+        for value in values {
+            buf.push(b',');
+            if let Some(value) = value.and_then(|value| FinOrNull(value).get()) {
+                buf.extend_from_slice(value.to_string().as_bytes());
+            }
+        }
+        buf.push(b'\n');
+    }
+
+    /// One `<tr>` of the `format=html` table, empty cells for missing values.
+    pub(crate) fn write_html(&self, buf: &mut Vec<u8>) {
+        let (timestamp, values) = self.fields();
+        buf.extend_from_slice(b"<tr><td>");
+        push_html_escaped(buf, &timestamp.to_string());
+        buf.extend_from_slice(b"</td>");
+        for value in values {
+            buf.extend_from_slice(b"<td>");
+            if let Some(value) = value.and_then(|value| FinOrNull(value).get()) {
+                buf.extend_from_slice(value.to_string().as_bytes());
+            }
+            buf.extend_from_slice(b"</td>");
+        }
+        buf.extend_from_slice(b"</tr>\n");
+    }
+
+    /// One record of [`BINARY_HEADER`]'s layout.
+    pub(crate) fn write_binary(&self, buf: &mut Vec<u8>) {
+        let (timestamp, values) = self.fields();
+        buf.extend_from_slice(&timestamp.at.and_utc().timestamp_micros().to_le_bytes());
+        for value in values {
+            let value = value.and_then(|value| FinOrNull(value).get());
+            buf.extend_from_slice(&value.unwrap_or(f64::NAN).to_le_bytes());
+        }
+    }
+}
+
+/// Appends `text` with the characters HTML gives meaning to escaped, so any
+/// string is safe inside an element or a quoted attribute.
+pub(crate) fn push_html_escaped(buf: &mut Vec<u8>, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => buf.extend_from_slice(b"&amp;"),
+            '<' => buf.extend_from_slice(b"&lt;"),
+            '>' => buf.extend_from_slice(b"&gt;"),
+            '"' => buf.extend_from_slice(b"&quot;"),
+            '\'' => buf.extend_from_slice(b"&#39;"),
+            c => {
+                let mut utf8 = [0; 4];
+                buf.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            }
+        }
+    }
+}
+
+impl ProjectedBar {
+    pub(crate) fn at(timestamp: Timestamp) -> Self {
+        Self {
+            timestamp,
+            open: None,
+            high: None,
+            low: None,
+            close: None,
+            volume: None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize)]
+pub struct Event {
+    pub timestamp: Timestamp,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub label: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct IndicatorPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub sma_14: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub ema_14: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub rsi_14: Option<f64>,
+    #[serde(flatten)]
+    pub hma: HullAverages,
+    #[serde(flatten)]
+    pub periods: PeriodValues,
+}
+
+/// Hull Moving Averages requested with `hma=`, keyed `hma_9`, `hma_21`, ...
+/// in request order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HullAverages(pub Vec<(usize, Option<f64>)>);
+
+impl Serialize for HullAverages {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (period, value) in &self.0 {
+            map.serialize_entry(&format!("hma_{period}"), &value.finite())?;
+        }
+        map.end()
+    }
+}
+
+/// An indicator whose period is chosen per request.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum PeriodIndicator {
+    Sma,
+    Ema,
+    Rsi,
+}
+
+impl PeriodIndicator {
+    pub fn name(self) -> &'static str {
+        match self {
+            PeriodIndicator::Sma => "sma",
+            PeriodIndicator::Ema => "ema",
+            PeriodIndicator::Rsi => "rsi",
+        }
+    }
+}
+
+/// Indicators requested with `sma=`, `ema=` and `rsi=` (or subscribed to on
+/// `/api/ws`), keyed `sma_21`, `rsi_7`, ... in request order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeriodValues(pub Vec<(PeriodIndicator, usize, Option<f64>)>);
+
+impl Serialize for PeriodValues {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (indicator, period, value) in &self.0 {
+            map.serialize_entry(&format!("{}_{period}", indicator.name()), &value.finite())?;
+        }
+        map.end()
+    }
+}
+
+/// The subscribed indicators at a candle just sent on `/api/ws`.
+#[derive(Serialize)]
+pub struct IndicatorUpdate {
+    pub timestamp: Timestamp,
+    #[serde(flatten)]
+    pub values: PeriodValues,
+}
+
+#[derive(Serialize)]
+pub struct VolumeIndicatorPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub force_index: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub ease_of_movement: Option<f64>,
+}
+
+/// Wilder's Directional Movement System at one candle.
+#[derive(Serialize)]
+pub struct AdxPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub plus_di: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub minus_di: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub adx: Option<f64>,
+    /// `(adx + adx n bars earlier) / 2`.
+    #[serde(serialize_with = "fin_or_null")]
+    pub adxr: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct ZScorePoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub value: Option<f64>,
+    /// `(value - mean) / std` over the trailing window ending here.
+    #[serde(serialize_with = "fin_or_null")]
+    pub zscore: Option<f64>,
+}
+
+/// Rolling dispersion of a price source at one candle.
+#[derive(Serialize)]
+pub struct StdDevPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub stddev: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub variance: Option<f64>,
+}
+
+/// The rolling mean and median of a price source at one candle.
+#[derive(Serialize)]
+pub struct RollingPricePoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub avg_price: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub median_price: Option<f64>,
+}
+
+/// Chande's Momentum Oscillator at one candle.
+#[derive(Serialize)]
+pub struct CmoPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub cmo: Option<f64>,
+}
+
+/// The Vortex Indicator at one candle.
+#[derive(Serialize)]
+pub struct VortexPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub vi_plus: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub vi_minus: Option<f64>,
+}
+
+/// Kaufman's Adaptive Moving Average at one candle.
+#[derive(Serialize)]
+pub struct KamaPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub kama: Option<f64>,
+}
+
+/// The Schaff Trend Cycle at one candle.
+#[derive(Serialize)]
+pub struct StcPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub stc: Option<f64>,
+}
+
+/// The Detrended Price Oscillator at one candle.
+#[derive(Serialize)]
+pub struct DpoPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub dpo: Option<f64>,
+}
+
+/// A series point carrying one [`Timestamp`], so a handler can render the
+/// whole series in the request's format once it is read.
+pub trait Stamped {
+    fn timestamp_mut(&mut self) -> &mut Timestamp;
+}
+
+macro_rules! stamped {
+    ($($point:ty),*) => {
+        $(impl Stamped for $point {
+            fn timestamp_mut(&mut self) -> &mut Timestamp {
+                &mut self.timestamp
+            }
+        })*
+    };
+}
+
+stamped!(
+    StdDevPoint,
+    RollingPricePoint,
+    CmoPoint,
+    VortexPoint,
+    KamaPoint,
+    StcPoint,
+    DpoPoint
+);
+
+/// One entry of `/api/symbols`.
+#[derive(Serialize)]
+pub struct SymbolInfo {
+    pub symbol: String,
+    pub first: Timestamp,
+    pub last: Timestamp,
+    pub candles: u64,
+}
+
+/// `/api/continuous`: the stitched bars and how each roll was adjusted.
+#[derive(Serialize)]
+pub struct ContinuousSeries {
+    pub candles: Vec<ContinuousCandle>,
+    pub rolls: Vec<Roll>,
+}
+
+/// A back-adjusted bar and the contract it was taken from.
+#[derive(Serialize)]
+pub struct ContinuousCandle {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub open: f64,
+    #[serde(serialize_with = "fin_or_null")]
+    pub high: f64,
+    #[serde(serialize_with = "fin_or_null")]
+    pub low: f64,
+    #[serde(serialize_with = "fin_or_null")]
+    pub close: f64,
+    #[serde(serialize_with = "fin_or_null")]
+    pub volume: f64,
+    pub contract: String,
+}
+
+#[derive(Serialize)]
+pub struct Roll {
+    pub timestamp: Timestamp,
+    pub from: String,
+    pub to: String,
+    /// The last bar before the roll both contracts have, where `gap` is read.
+    pub reference: Timestamp,
+    /// `to - from` for additive adjustment, `to / from` for ratio, 0 for none.
+    #[serde(serialize_with = "fin_or_null")]
+    pub gap: f64,
+}
+
+#[derive(Serialize)]
+pub struct SpreadPoint {
+    pub timestamp: Timestamp,
+    /// `a - b` or `a / b` of the two closes.
+    #[serde(serialize_with = "fin_or_null")]
+    pub spread: Option<f64>,
+    /// Rolling mean of the spread over the window ending here.
+    #[serde(serialize_with = "fin_or_null")]
+    pub mean: Option<f64>,
+    #[serde(serialize_with = "fin_or_null")]
+    pub zscore: Option<f64>,
+}
+
+/// A point-and-figure column: X's drawn upward or O's drawn downward.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PnfDirection {
+    Up,
+    Down,
+}
+
+/// One bar of a user formula from `/api/formula`.
+#[derive(Serialize)]
+pub struct FormulaPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub value: Option<f64>,
+}
+
+/// One bar of `/api/rolling_correlation`.
+#[derive(Serialize)]
+pub struct CorrelationPoint {
+    pub timestamp: Timestamp,
+    #[serde(serialize_with = "fin_or_null")]
+    pub correlation: Option<f64>,
+}
+
+#[derive(Serialize)]
+pub struct PnfColumn {
+    pub direction: PnfDirection,
+    /// Box prices in the order they were drawn: rising for X's, falling for
+    /// O's.
+    #[serde(serialize_with = "fin_or_null")]
+    pub boxes: Vec<f64>,
+}
+
+/// `/api/adaptive_candles`: the candles, and how they were chosen.
+#[derive(Serialize)]
+pub struct AdaptiveCandles {
+    pub data: Vec<Candle>,
+    pub meta: AdaptiveMeta,
+}
+
+#[derive(Serialize)]
+pub struct AdaptiveMeta {
+    pub count: usize,
+    /// Candles stored in the range, which sending raw would have meant.
+    pub rows: u64,
+    /// The most candles the time budget and `max_points` allowed.
+    pub points: u32,
+    /// Whether the candles were resampled to fit, and to what.
+    pub resampled: bool,
+    pub timeframe: Option<String>,
+}
+
+/// Response wrapper selected with `envelope=true`.
+#[derive(Serialize)]
+pub struct Envelope<T> {
+    pub data: T,
+    pub meta: Meta,
+}
+
+/// How a front end might format prices and volumes, inferred from the data;
+/// every hint is `None` without candles.
+#[derive(Default, Serialize)]
+pub struct DisplayHints {
+    /// Candles the hints come from: the most recent ones.
+    pub sampled: usize,
+    /// The most decimals any sampled price was written with.
+    pub price_decimals: Option<u32>,
+    /// The step every sampled price lies on; `None` unless two differ.
+    #[serde(serialize_with = "fin_or_null")]
+    pub tick_size: Option<f64>,
+    /// `10^price_decimals`, as TradingView's `pricescale`.
+    pub pricescale: Option<u64>,
+    /// The tick in units of `1 / pricescale`, as TradingView's `minmov`.
+    pub min_move: Option<u64>,
+    /// `GRAPH_VOLUME_PRECISION`, or the most decimals any sampled volume has.
+    pub volume_decimals: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Meta {
+    pub count: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct FibLevels {
+    #[serde(serialize_with = "fin_or_null")]
+    pub low: f64,
+    #[serde(serialize_with = "fin_or_null")]
+    pub high: f64,
+    pub levels: Vec<FibLevel>,
+}
+
+#[derive(Serialize)]
+pub struct FibLevel {
+    #[serde(serialize_with = "fin_or_null")]
+    pub ratio: f64,
+    #[serde(serialize_with = "fin_or_null")]
+    pub value: f64,
+}
+
+#[derive(Serialize)]
+pub struct FibTimeZones {
+    /// The candle the zones count from: the first one at or after the
+    /// requested anchor.
+    pub anchor: Timestamp,
+    pub zones: Vec<FibTimeZone>,
+}
+
+#[derive(Serialize)]
+pub struct FibTimeZone {
+    /// Bars after the anchor, a Fibonacci number.
+    pub bars: u64,
+    pub timestamp: Timestamp,
+    /// Past the last candle, so the timestamp comes from the inferred interval.
+    pub projected: bool,
+}
+
+#[derive(Serialize)]
+pub struct Percentiles {
+    pub quantiles: Quantiles,
+    #[serde(serialize_with = "fin_or_null")]
+    pub latest: Option<f64>,
+    /// Share of values in the range at or below `latest`, in percent.
+    #[serde(serialize_with = "fin_or_null")]
+    pub latest_rank: Option<f64>,
+}
+
+/// One time-of-day bucket of `/api/intraday_overlay`: how far price had moved
+/// from its session's open by then, across the sessions that reached it.
+#[derive(Serialize)]
+pub struct OverlayPoint {
+    /// `HH:MM` (UTC) at which the bucket starts.
+    pub time_of_day: String,
+    /// Change from the session's first open to the bucket's last close, in
+    /// percent.
+    pub mean: f64,
+    pub p25: f64,
+    pub p75: f64,
+}
+
+/// Quantile values keyed `p10`, `p50`, ... in configured order.
+pub struct Quantiles(pub(crate) Vec<(String, Option<f64>)>);
+
+impl Serialize for Quantiles {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, &value.finite())?;
+        }
+        map.end()
+    }
+}
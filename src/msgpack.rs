@@ -0,0 +1,167 @@
+//! MessagePack responses for clients that send `Accept: application/msgpack`
+//! or `?format=msgpack`; [`crate::negotiate`] picks the format.
+//!
+//! The encoder covers exactly what JSON can express: integers take the
+//! smallest MessagePack form, other numbers are 64-bit floats.
+
+use serde_json::Value;
+
+/// Appends the MessagePack encoding of `value` to `out`.
+pub(crate) fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xc0),
+        Value::Bool(false) => out.push(0xc2),
+        Value::Bool(true) => out.push(0xc3),
+        Value::Number(number) => {
+            if let Some(n) = number.as_u64() {
+                encode_uint(n, out);
+            } else if let Some(n) = number.as_i64() {
+                encode_int(n, out);
+            } else {
+                out.push(0xcb);
+                out.extend_from_slice(&number.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(text) => {
+            encode_len(text.len(), [0xa0, 0xd9, 0xda, 0xdb], 32, out);
+            out.extend_from_slice(text.as_bytes());
+        }
+        Value::Array(items) => {
+            encode_len(items.len(), [0x90, 0, 0xdc, 0xdd], 16, out);
+            for item in items {
+                encode(item, out);
+            }
+        }
+        Value::Object(fields) => {
+            encode_len(fields.len(), [0x80, 0, 0xde, 0xdf], 16, out);
+            for (key, value) in fields {
+                encode(&Value::String(key.clone()), out);
+                encode(value, out);
+            }
+        }
+    }
+}
+
+fn encode_uint(n: u64, out: &mut Vec<u8>) {
+    match n {
+        0..=0x7f => out.push(n as u8),
+        0x80..=0xff => out.extend_from_slice(&[0xcc, n as u8]),
+        0x100..=0xffff => {
+            out.push(0xcd);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(0xce);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(0xcf);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+/// Negative integers only; non-negative ones go through [`encode_uint`].
+fn encode_int(n: i64, out: &mut Vec<u8>) {
+    if n >= -32 {
+        out.push(n as u8);
+    } else if n >= i64::from(i8::MIN) {
+        out.extend_from_slice(&[0xd0, n as u8]);
+    } else if n >= i64::from(i16::MIN) {
+        out.push(0xd1);
+        out.extend_from_slice(&(n as i16).to_be_bytes());
+    } else if n >= i64::from(i32::MIN) {
+        out.push(0xd2);
+        out.extend_from_slice(&(n as i32).to_be_bytes());
+    } else {
+        out.push(0xd3);
+        out.extend_from_slice(&n.to_be_bytes());
+    }
+}
+
+/// Writes a length header: the fix form below `fix_limit`, then the 8-bit
+/// (strings only, `0` when the type has none), 16-bit and 32-bit forms.
+fn encode_len(len: usize, tags: [u8; 4], fix_limit: usize, out: &mut Vec<u8>) {
+    let [fix, len8, len16, len32] = tags;
+    if len < fix_limit {
+        out.push(fix | len as u8);
+    } else if len8 != 0 && len <= 0xff {
+        out.extend_from_slice(&[len8, len as u8]);
+    } else if len <= 0xffff {
+        out.push(len16);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(len32);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::{ACCEPT, CONTENT_TYPE};
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    use super::*;
+    use crate::build_router;
+    use crate::test_support::*;
+
+    const MSGPACK: &str = "application/msgpack";
+
+    fn encoded(value: Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        encode(&value, &mut out);
+        out
+    }
+
+    #[test]
+    fn values_take_their_smallest_form() {
+        assert_eq!(encoded(json!(null)), [0xc0]);
+        assert_eq!(encoded(json!([true, false])), [0x92, 0xc3, 0xc2]);
+        assert_eq!(encoded(json!(127)), [0x7f]);
+        assert_eq!(encoded(json!(200)), [0xcc, 200]);
+        assert_eq!(encoded(json!(65_536)), [0xce, 0, 1, 0, 0]);
+        assert_eq!(encoded(json!(-1)), [0xff]);
+        assert_eq!(encoded(json!(-33)), [0xd0, 0xdf]);
+        assert_eq!(encoded(json!(-1_000)), [0xd1, 0xfc, 0x18]);
+        let mut float = vec![0xcb];
+        float.extend_from_slice(&1.5f64.to_be_bytes());
+        assert_eq!(encoded(json!(1.5)), float);
+        assert_eq!(
+            encoded(json!({"a": "bc"})),
+            [0x81, 0xa1, b'a', 0xa2, b'b', b'c']
+        );
+        let long = "x".repeat(40);
+        assert_eq!(encoded(json!(long))[..2], [0xd9, 40]);
+        let many = vec![0; 20];
+        assert_eq!(encoded(json!(many))[..3], [0xdc, 0, 20]);
+    }
+
+    #[tokio::test]
+    async fn data_endpoints_answer_in_msgpack_when_asked() {
+        let app = build_router(seeded_state("('2024-01-01 00:00:00', 1, 2, 1, 1, 1)"));
+        let response = get_with(
+            &app,
+            "/udf/config",
+            &[(ACCEPT, "application/msgpack, application/json;q=0.5")],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], MSGPACK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json = get_json(&app, "/udf/config").await;
+        assert_eq!(body, encoded(json));
+
+        for accept in ["application/json", "application/msgpack;q=0"] {
+            let response = get_with(&app, "/udf/config", &[(ACCEPT, accept)]).await;
+            assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        }
+        // `format` outranks `Accept`, and errors stay JSON.
+        let csv = get_with(&app, "/api/candles?format=csv", &[(ACCEPT, MSGPACK)]).await;
+        assert_eq!(csv.headers()[CONTENT_TYPE], "text/csv");
+        let error = get_with(&app, "/api/candles?timeframe=x", &[(ACCEPT, MSGPACK)]).await;
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+    }
+}
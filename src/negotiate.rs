@@ -0,0 +1,775 @@
+//! Content negotiation for the data routes: which format a request is
+//! answered in, and encoding a handler's result in it.
+//!
+//! [`ResponseFormat`] settles the format before the handler runs, by one
+//! precedence:
+//!
+//! 1. `?format=` names it outright.
+//! 2. Otherwise `Accept`, highest `q` first: the first media type the route
+//!    can produce wins, and `*/*` means its default. Media types that name no
+//!    format at all are passed over, so an odd `Accept` still gets JSON.
+//! 3. Otherwise the route's default, JSON.
+//!
+//! A named format the route cannot produce, by either means, is a 406 that
+//! lists the ones it can. `lwc` and `html` are only chosen by name, so a
+//! browser's `Accept: text/html` still gets data. [`offered`] says what each
+//! route produces; handlers return a [`DataResponse`], which encodes JSON,
+//! MessagePack and lightweight-charts series from any `Serialize` body, and
+//! the tabular formats from its rows through [`Table`]. A new format is a [`Format`]
+//! variant and an arm of [`DataResponse`]'s encoder. `/api/candles` streams
+//! its own JSON, NDJSON, CSV, binary and HTML and only buffers for the rest.
+
+use std::convert::Infallible;
+use std::fmt;
+
+use axum::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract::{FromRequestParts, Query};
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use futures_util::stream;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{internal_error, AppError};
+use crate::handlers::MAX_HTML_ROWS;
+use crate::models::{push_html_escaped, TimestampFormat, TimestampStyle};
+use crate::{lwc, msgpack, series_csv};
+
+/// A response encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Json,
+    /// One JSON object per line.
+    Ndjson,
+    Csv,
+    Msgpack,
+    /// `{time, value}` series for lightweight-charts; see [`crate::lwc`].
+    Lwc,
+    /// A page with one table row per point, for looking at in a browser.
+    Html,
+    /// `/api/candles`' packed records; see [`crate::models::BINARY_HEADER`].
+    Bin,
+}
+
+impl Format {
+    const ALL: [Format; 7] = [
+        Format::Json,
+        Format::Ndjson,
+        Format::Csv,
+        Format::Msgpack,
+        Format::Lwc,
+        Format::Html,
+        Format::Bin,
+    ];
+
+    /// The `?format=` value.
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+            Format::Csv => "csv",
+            Format::Msgpack => "msgpack",
+            Format::Lwc => "lwc",
+            Format::Html => "html",
+            Format::Bin => "bin",
+        }
+    }
+
+    pub(crate) fn named(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    /// The `Content-Type` the format is sent with.
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Format::Json | Format::Lwc => "application/json",
+            Format::Ndjson => "application/x-ndjson",
+            Format::Csv => "text/csv",
+            Format::Msgpack => MSGPACK,
+            Format::Html => "text/html; charset=utf-8",
+            Format::Bin => "application/octet-stream",
+        }
+    }
+
+    /// The media type `Accept` picks the format by; `lwc` and `html` have
+    /// none.
+    fn media_type(self) -> Option<&'static str> {
+        match self {
+            Format::Lwc | Format::Html => None,
+            format => Some(format.content_type()),
+        }
+    }
+
+    fn accepts(self, media: &str) -> bool {
+        self.media_type()
+            .is_some_and(|own| own.eq_ignore_ascii_case(media))
+            // The older name for MessagePack.
+            || (self == Format::Msgpack && media.eq_ignore_ascii_case("application/x-msgpack"))
+    }
+}
+
+const MSGPACK: &str = "application/msgpack";
+
+const CANDLES: &[Format] = &[
+    Format::Json,
+    Format::Ndjson,
+    Format::Csv,
+    Format::Bin,
+    Format::Html,
+    Format::Msgpack,
+    Format::Lwc,
+];
+/// Points in time with a column per value.
+const SERIES: &[Format] = &[
+    Format::Json,
+    Format::Ndjson,
+    Format::Csv,
+    Format::Html,
+    Format::Msgpack,
+    Format::Lwc,
+];
+/// Flat records that are not a series to chart.
+const RECORDS: &[Format] = &[
+    Format::Json,
+    Format::Ndjson,
+    Format::Csv,
+    Format::Html,
+    Format::Msgpack,
+];
+const DOCUMENTS: &[Format] = &[Format::Json, Format::Msgpack];
+
+/// The formats the route at `path` answers in, its default first.
+pub(crate) fn offered(path: &str) -> &'static [Format] {
+    match path {
+        "/api/candles" => CANDLES,
+        "/api/indicators"
+        | "/api/volume_indicators"
+        | "/api/adx"
+        | "/api/vortex"
+        | "/api/formula"
+        | "/api/zscore"
+        | "/api/stddev"
+        | "/api/rolling_price"
+        | "/api/cmo"
+        | "/api/kama"
+        | "/api/stc"
+        | "/api/dpo"
+        | "/api/rolling_correlation"
+        | "/api/spread" => SERIES,
+        "/api/symbols" | "/api/events" | "/api/intraday_overlay" => RECORDS,
+        _ => DOCUMENTS,
+    }
+}
+
+/// The format a request is answered in, and what a CSV download is named
+/// after.
+#[derive(Clone, Debug)]
+pub(crate) struct ResponseFormat {
+    pub(crate) format: Format,
+    /// `stddev` for `/api/stddev`.
+    endpoint: String,
+    /// A numeric `period` or `window`, which CSV columns not named for their
+    /// period take, as `stddev_20`.
+    period: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ResponseFormat {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        // A malformed query is the handler's own `Query` to report.
+        let pairs = Query::<Vec<(String, String)>>::try_from_uri(&parts.uri)
+            .map(|Query(pairs)| pairs)
+            .unwrap_or_default();
+        let param = |name: &str| {
+            pairs
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let path = parts.uri.path();
+        let offered = offered(path);
+        let format = match param("format") {
+            Some(name) => Format::named(name)
+                .filter(|format| offered.contains(format))
+                .ok_or_else(|| not_acceptable(path, &format!("format={name}"), offered))?,
+            None => by_accept(&parts.headers, offered)
+                .map_err(|media| not_acceptable(path, &media, offered))?,
+        };
+        let period = ["period", "window"]
+            .into_iter()
+            .filter_map(param)
+            .find(|value| !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()));
+        Ok(Self {
+            format,
+            endpoint: path.trim_start_matches("/api/").replace('/', "-"),
+            period: period.map(str::to_owned),
+        })
+    }
+}
+
+impl ResponseFormat {
+    /// `timestamps`, or Unix seconds when answering `lwc`: lightweight-charts
+    /// takes those and nothing else.
+    pub(crate) fn timestamps(&self, timestamps: TimestampFormat) -> TimestampFormat {
+        match self.format {
+            Format::Lwc => TimestampFormat {
+                style: TimestampStyle::Unix,
+                ..timestamps
+            },
+            _ => timestamps,
+        }
+    }
+}
+
+/// The first of `offered` that `Accept` takes, highest `q` first, or the
+/// default when it names none of the formats; `Err` with the best media type
+/// it named when that is a format the route lacks.
+fn by_accept(headers: &HeaderMap, offered: &'static [Format]) -> Result<Format, String> {
+    let mut ranges = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media = params.next().filter(|media| !media.is_empty())?;
+            let q = params
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            (q > 0.0).then_some((media, q))
+        })
+        .collect::<Vec<_>>();
+    // Stable, so equal weights keep the client's order.
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let mut refused = None;
+    for (media, _) in ranges {
+        if media == "*/*" {
+            return Ok(offered[0]);
+        }
+        if let Some(kind) = media.strip_suffix("/*") {
+            let within = offered.iter().find(|format| {
+                format
+                    .media_type()
+                    .and_then(|own| own.split('/').next())
+                    .is_some_and(|own| own.eq_ignore_ascii_case(kind))
+            });
+            if let Some(format) = within {
+                return Ok(*format);
+            }
+            continue;
+        }
+        match offered.iter().find(|format| format.accepts(media)) {
+            Some(format) => return Ok(*format),
+            None if Format::ALL.iter().any(|format| format.accepts(media)) => {
+                refused.get_or_insert_with(|| media.to_owned());
+            }
+            None => {}
+        }
+    }
+    match refused {
+        Some(media) => Err(media),
+        None => Ok(offered[0]),
+    }
+}
+
+fn not_acceptable(path: &str, asked: &str, offered: &[Format]) -> AppError {
+    let names = offered
+        .iter()
+        .map(|format| format.name())
+        .collect::<Vec<_>>()
+        .join(", ");
+    AppError::NotAcceptable(format!(
+        "{path} cannot answer with {asked}; available: {names}"
+    ))
+}
+
+/// A handler's result, sent in the format the request negotiated.
+pub(crate) struct DataResponse<T> {
+    format: ResponseFormat,
+    body: T,
+}
+
+impl<T> DataResponse<T> {
+    pub(crate) fn new(format: ResponseFormat, body: T) -> Self {
+        Self { format, body }
+    }
+}
+
+impl<T: Serialize> IntoResponse for DataResponse<T> {
+    fn into_response(self) -> Response {
+        self.encode().unwrap_or_else(IntoResponse::into_response)
+    }
+}
+
+impl<T: Serialize> DataResponse<T> {
+    fn encode(self) -> Result<Response, AppError> {
+        let ResponseFormat {
+            format,
+            endpoint,
+            period,
+        } = self.format;
+        let response = match format {
+            Format::Json => Json(self.body).into_response(),
+            Format::Msgpack => {
+                let mut out = Vec::new();
+                msgpack::encode(
+                    &serde_json::to_value(&self.body).map_err(internal_error)?,
+                    &mut out,
+                );
+                ([(CONTENT_TYPE, MSGPACK)], out).into_response()
+            }
+            Format::Lwc => Json(lwc::reshape(
+                serde_json::to_value(&self.body).map_err(internal_error)?,
+            ))
+            .into_response(),
+            Format::Ndjson => ndjson(Rows::of(&self.body)?),
+            Format::Csv => series_csv::encode(Rows::of(&self.body)?, &endpoint, period.as_deref())?,
+            Format::Html => html(Rows::of(&self.body)?, &endpoint),
+            Format::Bin => {
+                return Err(internal_error(
+                    "packed records are only written by /api/candles",
+                ))
+            }
+        };
+        Ok(response)
+    }
+}
+
+/// What the tabular formats need of a body: named columns, and rows with a
+/// cell for each.
+pub(crate) trait Table: Send + 'static {
+    fn columns(&self) -> &[String];
+
+    fn len(&self) -> usize;
+
+    /// Row `index`'s cells in column order, `null` where it has no value.
+    fn row(&self, index: usize) -> Vec<&Value>;
+}
+
+/// Rows encoded into each chunk of a streamed table.
+const CHUNK_ROWS: usize = 1000;
+
+/// `head`, then each row as `write` puts it, then `tail`, encoded a chunk at
+/// a time as the body is sent rather than all at once.
+pub(crate) fn stream_rows<T: Table>(
+    table: T,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+    write: impl Fn(&mut Vec<u8>, usize, &[String], &[&Value]) + Send + 'static,
+) -> Body {
+    let mut next = 0;
+    let rows = std::iter::from_fn(move || {
+        let mut chunk = Vec::new();
+        let end = table.len().min(next + CHUNK_ROWS);
+        for index in next..end {
+            write(&mut chunk, index, table.columns(), &table.row(index));
+        }
+        next = end;
+        (!chunk.is_empty()).then_some(chunk)
+    });
+    let chunks = std::iter::once(head)
+        .chain(rows)
+        .chain(std::iter::once(tail))
+        .filter(|chunk| !chunk.is_empty())
+        .map(|chunk| Ok::<_, Infallible>(Bytes::from(chunk)));
+    Body::from_stream(stream::iter(chunks))
+}
+
+fn ndjson(table: impl Table) -> Response {
+    let body = stream_rows(table, Vec::new(), Vec::new(), |out, _, columns, cells| {
+        out.push(b'{');
+        for (index, (column, cell)) in columns.iter().zip(cells).enumerate() {
+            if index > 0 {
+                out.push(b',');
+            }
+            serde_json::to_writer(&mut *out, column).expect("strings always serialize");
+            out.push(b':');
+            serde_json::to_writer(&mut *out, cell).expect("JSON values always serialize");
+        }
+        out.extend_from_slice(b"}\n");
+    });
+    ([(CONTENT_TYPE, Format::Ndjson.content_type())], body).into_response()
+}
+
+const HTML_STYLE: &str = "body{font-family:monospace;margin:1em}table{border-collapse:collapse}\
+th,td{padding:2px 8px}td{text-align:right}td:first-child{text-align:left}\
+tbody tr:nth-child(even){background:#f0f0f0}th{position:sticky;top:0;background:#fff;text-align:right}\
+th:first-child{text-align:left}td.notice{text-align:left;font-style:italic}";
+
+/// The page up to the first row of a `format=html` table of `columns`.
+pub(crate) fn html_head(title: &str, columns: &[impl AsRef<str>]) -> Vec<u8> {
+    let mut head = b"<!doctype html>\n<html><head><meta charset=\"utf-8\"><title>".to_vec();
+    push_html_escaped(&mut head, title);
+    head.extend_from_slice(format!("</title>\n<style>{HTML_STYLE}</style></head>\n").as_bytes());
+    head.extend_from_slice(b"<body><table><thead><tr>");
+    for column in columns {
+        head.extend_from_slice(b"<th>");
+        push_html_escaped(&mut head, column.as_ref());
+        head.extend_from_slice(b"</th>");
+    }
+    head.extend_from_slice(b"</tr></thead><tbody>\n");
+    head
+}
+
+pub(crate) const HTML_TAIL: &[u8] = b"</tbody></table></body></html>\n";
+
+/// The row saying a page stopped at [`MAX_HTML_ROWS`].
+pub(crate) fn html_notice(out: &mut Vec<u8>, columns: usize) {
+    out.extend_from_slice(
+        format!(
+            "<tr><td class=\"notice\" colspan=\"{columns}\">Only the first {MAX_HTML_ROWS} rows \
+             are shown; narrow the range to see the rest.</td></tr>\n"
+        )
+        .as_bytes(),
+    );
+}
+
+fn html(table: impl Table, endpoint: &str) -> Response {
+    let head = html_head(endpoint, table.columns());
+    let body = stream_rows(
+        table,
+        head,
+        HTML_TAIL.to_vec(),
+        |out, index, columns, cells| match index {
+            index if index < MAX_HTML_ROWS => {
+                out.extend_from_slice(b"<tr>");
+                for cell in cells {
+                    out.extend_from_slice(b"<td>");
+                    if let Some(text) = series_csv::cell_text(cell) {
+                        push_html_escaped(out, &text);
+                    }
+                    out.extend_from_slice(b"</td>");
+                }
+                out.extend_from_slice(b"</tr>\n");
+            }
+            MAX_HTML_ROWS => html_notice(out, columns.len()),
+            _ => {}
+        },
+    );
+    ([(CONTENT_TYPE, Format::Html.content_type())], body).into_response()
+}
+
+/// The [`Table`] of any body that serializes to an array of objects: a
+/// `timestamp` column if the objects have one, then one per field in the
+/// order first seen.
+pub(crate) struct Rows {
+    columns: Vec<String>,
+    rows: Vec<Row>,
+}
+
+impl Rows {
+    pub(crate) fn of(body: &impl Serialize) -> Result<Self, AppError> {
+        let rows = serde_json::to_vec(body)
+            .and_then(|json| serde_json::from_slice::<Vec<Row>>(&json))
+            .map_err(internal_error)?;
+        let mut columns = Vec::<String>::new();
+        for (field, _) in rows.iter().flat_map(|row| &row.0) {
+            if !columns.contains(field) {
+                columns.push(field.clone());
+            }
+        }
+        if let Some(index) = columns.iter().position(|column| column == "timestamp") {
+            let timestamp = columns.remove(index);
+            columns.insert(0, timestamp);
+        }
+        Ok(Self { columns, rows })
+    }
+}
+
+impl Table for Rows {
+    fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn row(&self, index: usize) -> Vec<&Value> {
+        static NULL: Value = Value::Null;
+        let row = &self.rows[index];
+        self.columns
+            .iter()
+            .map(|column| row.get(column).unwrap_or(&NULL))
+            .collect()
+    }
+}
+
+/// An object's fields in the order the endpoint wrote them, which
+/// `serde_json::Map` does not keep.
+struct Row(Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for Row {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RowVisitor;
+
+        impl<'de> Visitor<'de> for RowVisitor {
+            type Value = Row;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a point object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Row, A::Error> {
+                let mut fields = Vec::new();
+                while let Some(field) = map.next_entry()? {
+                    fields.push(field);
+                }
+                Ok(Row(fields))
+            }
+        }
+
+        deserializer.deserialize_map(RowVisitor)
+    }
+}
+
+impl Row {
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.0
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use axum::http::header::{ACCEPT, CONTENT_TYPE};
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::build_router;
+    use crate::config::CsvMode;
+    use crate::db::{initialize_events, initialize_symbols};
+    use crate::test_support::*;
+
+    /// Every route that negotiates, with a query it answers.
+    const ENDPOINTS: &[&str] = &[
+        "/api/candles",
+        "/api/adaptive_candles",
+        "/api/indicators",
+        "/api/volume_indicators",
+        "/api/adx",
+        "/api/vortex?period=1",
+        "/api/pnf?box_size=0.5&reversal=1",
+        "/api/formula?expr=close",
+        "/api/zscore",
+        "/api/stddev?period=2",
+        "/api/rolling_price?window=2",
+        "/api/cmo?period=1",
+        "/api/kama",
+        "/api/stc",
+        "/api/dpo?period=1",
+        "/api/rolling_correlation?a=close&b=volume&window=2",
+        "/api/spread?a=AAA&b=BBB&window=2",
+        "/api/symbols",
+        "/api/meta",
+        "/api/continuous?contracts=AAA,BBB&rolls=2024-01-01T00:01:00Z",
+        "/api/fib",
+        "/api/fib_time?anchor=2024-01-01%2000:01:00&count=2",
+        "/api/percentile",
+        "/api/intraday_overlay",
+        "/api/events",
+        "/udf/config",
+        "/udf/symbols?symbol=MAIN",
+        "/udf/search?query=a",
+        "/udf/history?symbol=MAIN&resolution=1&from=0&to=1800000000",
+    ];
+
+    async fn app() -> axum::Router {
+        let state = seeded_state(
+            "('2024-01-01 00:00:00', 1, 3, 1, 2, 10),
+             ('2024-01-01 00:01:00', 2, 3, 1, 1, 20),
+             ('2024-01-01 00:02:00', 2, 3, 1, 4, 20)",
+        );
+        state
+            .db
+            .write(|conn| {
+                initialize_symbols(conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+                initialize_events(conn, Path::new("missing.csv"), CsvMode::Lenient).unwrap();
+                conn.execute_batch(
+                    "INSERT INTO events VALUES ('2024-01-01 00:01:00', 'news', 'a, \"b\"');
+                     INSERT INTO symbol_candles VALUES
+                        ('AAA', '2024-01-01 00:00:00', 1, 1, 1, 10, 1),
+                        ('AAA', '2024-01-01 00:01:00', 1, 1, 1, 12, 1),
+                        ('BBB', '2024-01-01 00:00:00', 1, 1, 1, 5, 1),
+                        ('BBB', '2024-01-01 00:01:00', 1, 1, 1, 3, 1);",
+                )
+            })
+            .await
+            .unwrap();
+        build_router(state)
+    }
+
+    async fn text(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8_lossy(&body).into_owned()
+    }
+
+    /// The status and `Content-Type` of `uri`, and a refusal's message.
+    async fn answer(app: &axum::Router, uri: &str, accept: Option<&str>) -> (StatusCode, String) {
+        let headers = accept.map(|accept| (ACCEPT, accept));
+        let response = get_with(app, uri, headers.as_slice()).await;
+        let status = response.status();
+        let content_type = response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        match status {
+            // In the UDF protocol's own error shape there.
+            StatusCode::NOT_ACCEPTABLE if uri.starts_with("/udf/") => {
+                let body: Value = serde_json::from_str(&text(response).await).unwrap();
+                assert_eq!(body["s"], "error", "{uri}");
+                (status, body["errmsg"].as_str().unwrap().to_owned())
+            }
+            StatusCode::NOT_ACCEPTABLE => {
+                let body: Value = serde_json::from_str(&text(response).await).unwrap();
+                assert_eq!(body["error"]["code"], "not_acceptable", "{uri}");
+                (
+                    status,
+                    body["error"]["message"].as_str().unwrap().to_owned(),
+                )
+            }
+            _ => (status, content_type),
+        }
+    }
+
+    #[tokio::test]
+    async fn every_endpoint_answers_the_formats_it_offers_and_refuses_the_rest() {
+        let app = app().await;
+        for uri in ENDPOINTS {
+            let path = uri.split('?').next().unwrap();
+            let offered = offered(path);
+            let names = offered.iter().map(|f| f.name()).collect::<Vec<_>>();
+            let refusal = format!("available: {}", names.join(", "));
+            let separator = if uri.contains('?') { '&' } else { '?' };
+            for format in Format::ALL {
+                let request = format!("{uri}{separator}format={}", format.name());
+                let (status, detail) = answer(&app, &request, None).await;
+                if offered.contains(&format) {
+                    assert_eq!(status, StatusCode::OK, "{request}: {detail}");
+                    assert_eq!(detail, format.content_type(), "{request}");
+                } else {
+                    assert_eq!(status, StatusCode::NOT_ACCEPTABLE, "{request}");
+                    assert!(detail.ends_with(&refusal), "{request}: {detail}");
+                }
+
+                let Some(media) = format.media_type() else {
+                    continue;
+                };
+                let (status, detail) = answer(&app, uri, Some(media)).await;
+                if offered.contains(&format) {
+                    assert_eq!(status, StatusCode::OK, "{uri} as {media}: {detail}");
+                    assert_eq!(detail, media, "{uri} as {media}");
+                } else {
+                    assert_eq!(status, StatusCode::NOT_ACCEPTABLE, "{uri} as {media}");
+                    assert!(detail.contains(media), "{detail}");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn format_outranks_accept_which_goes_by_weight() {
+        let app = app().await;
+        let cases: &[(&str, Option<&str>, &str)] = &[
+            ("/api/indicators?format=csv", Some(MSGPACK), "text/csv"),
+            (
+                "/api/indicators",
+                Some("application/json;q=0.5, application/x-ndjson"),
+                "application/x-ndjson",
+            ),
+            (
+                "/api/indicators",
+                Some("text/html,application/xhtml+xml,*/*;q=0.8"),
+                "application/json",
+            ),
+            ("/api/indicators", Some("text/*"), "text/csv"),
+            ("/api/fib", Some("application/xml"), "application/json"),
+            ("/api/fib", Some("application/*"), "application/json"),
+            (
+                "/api/fib",
+                Some("text/csv, application/x-msgpack;q=0.1"),
+                MSGPACK,
+            ),
+            (
+                "/api/candles",
+                Some("application/octet-stream"),
+                "application/octet-stream",
+            ),
+            ("/api/candles?format=lwc", Some(MSGPACK), "application/json"),
+        ];
+        for (uri, accept, expected) in cases {
+            let (status, content_type) = answer(&app, uri, *accept).await;
+            assert_eq!(status, StatusCode::OK, "{uri} {accept:?}: {content_type}");
+            assert_eq!(content_type, *expected, "{uri} {accept:?}");
+        }
+
+        let (status, message) = answer(&app, "/api/fib?format=xml", None).await;
+        assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(
+            message,
+            "/api/fib cannot answer with format=xml; available: json, msgpack"
+        );
+        let (_, message) = answer(&app, "/api/fib", Some("text/csv;q=0.9, text/html")).await;
+        assert_eq!(
+            message,
+            "/api/fib cannot answer with text/csv; available: json, msgpack"
+        );
+
+        // Each format is its own cache entry.
+        let accept = |value| [(ACCEPT, value)];
+        for _ in 0..2 {
+            for (value, expected) in [(MSGPACK, MSGPACK), ("text/csv", "text/csv")] {
+                let response = get_with(&app, "/api/symbols", &accept(value)).await;
+                assert_eq!(response.headers()[CONTENT_TYPE], expected);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rows_become_lines_pages_and_columns() {
+        let app = app().await;
+        let ndjson = text(get_uri(&app, "/api/stddev?period=2&format=ndjson").await).await;
+        let mut lines = ndjson.lines();
+        assert_eq!(
+            lines.next(),
+            Some(r#"{"timestamp":"2024-01-01 00:00:00","stddev":null,"variance":null}"#)
+        );
+        assert_eq!(lines.count(), 2);
+
+        let page = text(get_uri(&app, "/api/indicators?format=html").await).await;
+        assert!(page.starts_with("<!doctype html>"));
+        assert!(page.contains("<title>indicators</title>"));
+        assert!(page.contains("<th>timestamp</th><th>sma_14</th>"));
+        assert!(page.contains("<tr><td>2024-01-01 00:00:00</td><td>2</td>"));
+        assert!(page.ends_with("</tbody></table></body></html>\n"));
+
+        let symbols = text(get_uri(&app, "/api/symbols?format=csv").await).await;
+        assert_eq!(symbols.lines().next(), Some("symbol,first,last,candles"));
+        let events = text(get_uri(&app, "/api/events?format=csv").await).await;
+        assert_eq!(
+            events,
+            "timestamp,type,label\n2024-01-01 00:01:00,news,\"a, \"\"b\"\"\"\n"
+        );
+
+        let candles = get_uri(&app, "/api/candles?format=msgpack").await;
+        let body = axum::body::to_bytes(candles.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut expected = Vec::new();
+        msgpack::encode(&get_json(&app, "/api/candles").await, &mut expected);
+        assert_eq!(body, expected);
+    }
+}
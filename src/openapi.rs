@@ -0,0 +1,1513 @@
+//! The OpenAPI document at `/api/openapi.json` and Swagger UI at `/docs`.
+//!
+//! Query parameters are read off the handlers' own query types: a probing
+//! deserializer walks each one the way `Query` would and records every field
+//! serde accepts, with its type and, for enums, the accepted values, so a new
+//! parameter shows up in the spec without touching this file. Bounds that
+//! live in handler code rather than in types are added per operation.
+//! Response schemas are spelled out here, and the tests check them against
+//! real responses and the paths against the router.
+
+use std::sync::OnceLock;
+
+use axum::response::Html;
+use axum::Json;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, MapAccess, Visitor};
+use serde_json::{json, Map, Value};
+
+use crate::alerts::MAX_MESSAGE_LEN;
+use crate::chart::{ChartQuery, MAX_HEIGHT, MAX_WIDTH, MIN_HEIGHT, MIN_WIDTH};
+use crate::db::QUOTE_COLUMNS;
+use crate::formula::{MAX_FORMULA_LEN, MAX_PERIOD};
+use crate::handlers::{
+    AdaptiveCandleQuery, AdxQuery, BoundSqlQuery, CandleQuery, CmoQuery, ContinuousQuery,
+    CorrelationQuery, DpoQuery, ExplainQuery, FibTimeQuery, FormulaQuery, GenerateQuery,
+    IndicatorQuery, KamaQuery, OverlayQuery, PercentileQuery, PnfQuery, RangeQuery, ReplayQuery,
+    RollingPriceQuery, SpreadQuery, StcQuery, StdDevQuery, StreamQuery, TimestampQuery,
+    VolumeIndicatorQuery, VortexQuery, ZScoreQuery, MAX_ADAPTIVE_POINTS, MAX_BACKFILL,
+    MAX_FIB_TIME_ZONES, MAX_GENERATED_ROWS, MAX_PROJECTED_BARS, MAX_TICK_BATCH,
+};
+use crate::negotiate::{self, Format};
+use crate::udf::{HistoryQuery, SearchQuery, SymbolQuery, MAX_HISTORY_BARS};
+use crate::xlsx::{XlsxQuery, XLSX};
+
+pub(crate) async fn openapi_json() -> Json<Value> {
+    static SPEC: OnceLock<Value> = OnceLock::new();
+    Json(SPEC.get_or_init(spec).clone())
+}
+
+pub(crate) async fn swagger_ui() -> Html<&'static str> {
+    Html(SWAGGER_UI)
+}
+
+/// Loads Swagger UI from the same CDN the chart library comes from.
+const SWAGGER_UI: &str = r##"<!doctype html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <title>graph API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5.17.14/swagger-ui-bundle.js"></script>
+    <script>
+      SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+    </script>
+  </body>
+</html>
+"##;
+
+/// A documented route: its parameters, success schema and whether it needs
+/// an API key.
+struct Operation {
+    path: &'static str,
+    method: &'static str,
+    summary: &'static str,
+    parameters: Vec<Value>,
+    response: Value,
+    media_type: &'static str,
+    /// Other encodings of the response, by `format` or `Accept`.
+    formats: &'static [Format],
+    /// The JSON a POST takes, if any.
+    request_body: Option<Value>,
+    /// The body of failures, the shared `Error` unless a route speaks
+    /// another protocol's.
+    error: Value,
+    keyed: bool,
+}
+
+impl Operation {
+    fn get(path: &'static str, summary: &'static str, response: Value) -> Self {
+        Self {
+            path,
+            method: "get",
+            summary,
+            parameters: Vec::new(),
+            response,
+            media_type: "application/json",
+            formats: &[],
+            request_body: None,
+            error: reference("Error"),
+            keyed: false,
+        }
+    }
+
+    fn query<T: DeserializeOwned>(mut self) -> Self {
+        self.parameters.extend(query_parameters::<T>());
+        self
+    }
+
+    /// Adds `ts_format` and `tz`, taken by every route returning timestamps.
+    fn timestamps(self) -> Self {
+        self.query::<TimestampQuery>()
+    }
+
+    /// Merges `constraint` into the schema of parameter `name`.
+    fn constrain(mut self, name: &str, constraint: Value) -> Self {
+        let parameter = self
+            .parameters
+            .iter_mut()
+            .find(|parameter| parameter["name"] == name)
+            .unwrap_or_else(|| panic!("{} has no parameter {name}", self.path));
+        if let (Some(schema), Value::Object(constraint)) =
+            (parameter["schema"].as_object_mut(), constraint)
+        {
+            schema.extend(constraint);
+        }
+        self
+    }
+
+    /// `format`, with the values [`negotiate::offered`] lists for the path,
+    /// and a response body for each.
+    fn negotiated(mut self) -> Self {
+        self.formats = negotiate::offered(self.path);
+        let names = self
+            .formats
+            .iter()
+            .map(|format| format.name())
+            .collect::<Vec<_>>();
+        self.parameters.push(json!({
+            "name": "format",
+            "in": "query",
+            "required": false,
+            "schema": { "type": "string", "enum": names },
+        }));
+        self
+    }
+
+    /// A success body other than JSON.
+    fn media_type(self, media_type: &'static str) -> Self {
+        Self { media_type, ..self }
+    }
+
+    fn request_body(self, schema: Value) -> Self {
+        Self {
+            request_body: Some(schema),
+            ..self
+        }
+    }
+
+    fn error(self, error: Value) -> Self {
+        Self { error, ..self }
+    }
+
+    fn keyed(self) -> Self {
+        Self {
+            keyed: true,
+            ..self
+        }
+    }
+
+    fn into_json(mut self) -> Value {
+        // `:id` segments, the only path parameters the routes take.
+        let ids = self
+            .path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix(':'))
+            .map(|name| json!({ "name": name, "in": "path", "required": true, "schema": { "type": "integer" } }))
+            .collect::<Vec<_>>();
+        self.parameters.splice(0..0, ids);
+        let mut operation = json!({
+            "summary": self.summary,
+            "parameters": self.parameters,
+            "responses": {
+                "200": {
+                    "description": "OK",
+                    "content": { self.media_type: { "schema": self.response } }
+                },
+                "default": {
+                    "description": "Error",
+                    "content": { "application/json": { "schema": self.error } }
+                }
+            }
+        });
+        for format in self.formats {
+            let schema = match format {
+                Format::Msgpack => self.response.clone(),
+                Format::Bin => json!({ "type": "string", "format": "binary" }),
+                _ => json!({ "type": "string" }),
+            };
+            let content = &mut operation["responses"]["200"]["content"];
+            if content.get(format.content_type()).is_none() {
+                content[format.content_type()] = json!({ "schema": schema });
+            }
+        }
+        if let Some(schema) = self.request_body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema } }
+            });
+        }
+        if self.keyed {
+            operation["security"] = json!([{ "bearer": [] }, { "apiKey": [] }]);
+        }
+        operation
+    }
+}
+
+/// The timeframe and interval syntax, e.g. `15m` or `1d`.
+const DURATION_PATTERN: &str = "^[1-9][0-9]*[smhd]$";
+
+/// Timeframes add calendar weeks and months, `1w` and `1M`.
+const TIMEFRAME_PATTERN: &str = "^([1-9][0-9]*[smhd]|1[wM])$";
+
+/// UDF resolutions: minutes as a bare count, or days, weeks and months.
+const UDF_RESOLUTION_PATTERN: &str = "^([1-9][0-9]*D?|D|1?[WM])$";
+
+fn operations() -> Vec<Operation> {
+    let series = |name| array(reference(name));
+    vec![
+        Operation::get("/healthz", "Liveness check", json!({ "type": "string" })),
+        Operation::get(
+            "/ready",
+            "Readiness: 503 while the database watchdog is recovering",
+            reference("Readiness"),
+        ),
+        Operation::get(
+            "/api/candles",
+            "Candles, raw or resampled into fixed buckets; also as NDJSON, CSV, packed binary or an HTML table",
+            series("CandleRow"),
+        )
+        .query::<CandleQuery>()
+        .timestamps()
+        .constrain("timeframe", json!({ "pattern": TIMEFRAME_PATTERN }))
+        .constrain(
+            "include",
+            json!({ "pattern": "^(events|bidask|spread)(,(events|bidask|spread))*$" }),
+        )
+        .constrain("project", json!({ "maximum": MAX_PROJECTED_BARS }))
+        .negotiated(),
+        Operation::get(
+            "/api/export/xlsx",
+            "Candles, indicators and a summary of a range as an Excel workbook; 413 over the row cap",
+            json!({ "type": "string", "format": "binary" }),
+        )
+        .query::<XlsxQuery>()
+        .constrain(
+            "include",
+            json!({ "pattern": "^(candles|indicators|summary)(,(candles|indicators|summary))*$" }),
+        )
+        .media_type(XLSX),
+        Operation::get(
+            "/api/adaptive_candles",
+            "Candles, resampled to a coarser timeframe when raw ones would not fit a time budget",
+            reference("AdaptiveCandles"),
+        )
+        .query::<AdaptiveCandleQuery>()
+        .timestamps()
+        .constrain("budget_ms", json!({ "minimum": 1 }))
+        .constrain(
+            "max_points",
+            json!({ "minimum": 1, "maximum": MAX_ADAPTIVE_POINTS }),
+        )
+.negotiated(),
+        Operation::get(
+            "/api/chart.png",
+            "A candlestick chart of a range as a PNG, with moving average overlays",
+            json!({ "type": "string", "format": "binary" }),
+        )
+        .query::<ChartQuery>()
+        .timestamps()
+        .constrain(
+            "indicators",
+            json!({ "pattern": "^(sma|ema|hma):[0-9]+(,(sma|ema|hma):[0-9]+)*$" }),
+        )
+        .constrain(
+            "width",
+            json!({ "minimum": MIN_WIDTH, "maximum": MAX_WIDTH }),
+        )
+        .constrain(
+            "height",
+            json!({ "minimum": MIN_HEIGHT, "maximum": MAX_HEIGHT }),
+        )
+        .media_type("image/png"),
+        Operation::get(
+            "/api/indicators",
+            "SMA, EMA and RSI over 14 bars, plus requested Hull Moving Averages",
+            json!({ "oneOf": [series("IndicatorPoint"), reference("IndicatorEnvelope")] }),
+        )
+        .query::<IndicatorQuery>()
+        .timestamps()
+        .negotiated(),
+        Operation::get(
+            "/api/volume_indicators",
+            "Force Index and Ease of Movement",
+            series("VolumeIndicatorPoint"),
+        )
+        .query::<VolumeIndicatorQuery>()
+        .timestamps()
+        .constrain("force_period", json!({ "minimum": 1 }))
+        .constrain("eom_period", json!({ "minimum": 1 }))
+        .negotiated(),
+        Operation::get(
+            "/api/adx",
+            "Wilder's +DI, -DI, ADX and ADXR",
+            series("AdxPoint"),
+        )
+        .query::<AdxQuery>()
+        .timestamps()
+        .constrain("period", json!({ "minimum": 1 }))
+        .constrain("adxr_period", json!({ "minimum": 1 }))
+        .negotiated(),
+        Operation::get(
+            "/api/vortex",
+            "Vortex Indicator VI+ and VI-",
+            series("VortexPoint"),
+        )
+        .query::<VortexQuery>()
+        .timestamps()
+        .constrain("period", json!({ "minimum": 1, "maximum": MAX_PERIOD }))
+        .negotiated(),
+        Operation::get(
+            "/api/pnf",
+            "Point-and-figure columns of the closes",
+            series("PnfColumn"),
+        )
+        .query::<PnfQuery>()
+        .constrain("box_size", json!({ "exclusiveMinimum": 0 }))
+        .constrain("reversal", json!({ "minimum": 1 }))
+.negotiated(),
+        Operation::get(
+            "/api/formula",
+            "A formula over candle columns and indicators, e.g. (close - sma_20) / atr_14",
+            series("FormulaPoint"),
+        )
+        .query::<FormulaQuery>()
+        .constrain("expr", json!({ "maxLength": MAX_FORMULA_LEN }))
+        .timestamps()
+        .negotiated(),
+        Operation::get(
+            "/api/zscore",
+            "Rolling z-score of a field",
+            series("ZScorePoint"),
+        )
+        .query::<ZScoreQuery>()
+        .timestamps()
+        .constrain("window", json!({ "minimum": 2 }))
+        .negotiated(),
+        Operation::get(
+            "/api/stddev",
+            "Rolling sample standard deviation and variance of a price source",
+            series("StdDevPoint"),
+        )
+        .query::<StdDevQuery>()
+        .timestamps()
+        .constrain("period", json!({ "minimum": 2, "maximum": MAX_PERIOD }))
+        .negotiated(),
+        Operation::get(
+            "/api/rolling_price",
+            "Rolling mean and median of a price source, the typical price by default",
+            series("RollingPricePoint"),
+        )
+        .query::<RollingPriceQuery>()
+        .timestamps()
+        .constrain("window", json!({ "minimum": 1, "maximum": MAX_PERIOD }))
+        .negotiated(),
+        Operation::get(
+            "/api/cmo",
+            "Chande Momentum Oscillator of a price source",
+            series("CmoPoint"),
+        )
+        .query::<CmoQuery>()
+        .timestamps()
+        .constrain("period", json!({ "minimum": 1, "maximum": MAX_PERIOD }))
+        .negotiated(),
+        Operation::get(
+            "/api/kama",
+            "Kaufman's Adaptive Moving Average of a price source",
+            series("KamaPoint"),
+        )
+        .query::<KamaQuery>()
+        .timestamps()
+        .constrain("efficiency", json!({ "minimum": 1, "maximum": MAX_PERIOD }))
+        .constrain("fast", json!({ "minimum": 1, "maximum": MAX_PERIOD }))
+        .constrain("slow", json!({ "minimum": 2, "maximum": MAX_PERIOD }))
+        .negotiated(),
+        Operation::get(
+            "/api/stc",
+            "Schaff Trend Cycle of a price source",
+            series("StcPoint"),
+        )
+        .query::<StcQuery>()
+        .timestamps()
+        .constrain("fast", json!({ "minimum": 1, "maximum": MAX_PERIOD }))
+        .constrain("slow", json!({ "minimum": 2, "maximum": MAX_PERIOD }))
+        .constrain("cycle", json!({ "minimum": 2, "maximum": MAX_PERIOD }))
+        .negotiated(),
+        Operation::get(
+            "/api/dpo",
+            "Detrended Price Oscillator of a price source",
+            series("DpoPoint"),
+        )
+        .query::<DpoQuery>()
+        .timestamps()
+        .constrain("period", json!({ "minimum": 1, "maximum": MAX_PERIOD }))
+        .negotiated(),
+        Operation::get(
+            "/api/rolling_correlation",
+            "Rolling correlation of two formula series, or of one with forward returns",
+            series("CorrelationPoint"),
+        )
+        .query::<CorrelationQuery>()
+        .timestamps()
+        .constrain("a", json!({ "maxLength": MAX_FORMULA_LEN }))
+        .constrain("b", json!({ "maxLength": MAX_FORMULA_LEN }))
+        .constrain("window", json!({ "minimum": 2, "maximum": MAX_PERIOD }))
+        .negotiated(),
+        Operation::get(
+            "/api/spread",
+            "Spread between two symbols' closes with its rolling mean and z-score",
+            series("SpreadPoint"),
+        )
+        .query::<SpreadQuery>()
+        .timestamps()
+        .constrain("window", json!({ "minimum": 2 }))
+        .negotiated(),
+        Operation::get(
+            "/api/meta",
+            "Display precision and tick size inferred from the latest candles",
+            reference("DisplayHints"),
+        )
+.negotiated(),
+        Operation::get(
+            "/api/symbols",
+            "Symbols with stored candles",
+            series("SymbolInfo"),
+        )
+        .timestamps()
+.negotiated(),
+        Operation::get(
+            "/api/continuous",
+            "Back-adjusted continuous futures stitched from contracts",
+            reference("ContinuousSeries"),
+        )
+        .query::<ContinuousQuery>()
+        .timestamps()
+.negotiated(),
+        Operation::get(
+            "/api/fib",
+            "Fibonacci retracement levels over a range",
+            reference("FibLevels"),
+        )
+        .query::<RangeQuery>()
+.negotiated(),
+        Operation::get(
+            "/api/fib_time",
+            "Fibonacci time zones from an anchor candle",
+            reference("FibTimeZones"),
+        )
+        .query::<FibTimeQuery>()
+        .timestamps()
+        .constrain("count", json!({ "maximum": MAX_FIB_TIME_ZONES }))
+.negotiated(),
+        Operation::get(
+            "/api/percentile",
+            "Configured quantiles and the latest value's percentile rank",
+            reference("Percentiles"),
+        )
+        .query::<PercentileQuery>()
+.negotiated(),
+        Operation::get(
+            "/api/intraday_overlay",
+            "Change from the session open by time of day, across sessions",
+            series("OverlayPoint"),
+        )
+        .query::<OverlayQuery>()
+.negotiated(),
+        Operation::get("/api/events", "Chart events", series("Event"))
+            .query::<RangeQuery>()
+            .timestamps()
+.negotiated(),
+        Operation::get(
+            "/udf/config",
+            "TradingView UDF datafeed configuration",
+            reference("UdfConfig"),
+        )
+        .error(reference("UdfError"))
+.negotiated(),
+        Operation::get(
+            "/udf/symbols",
+            "TradingView UDF symbol metadata",
+            reference("UdfSymbolInfo"),
+        )
+        .query::<SymbolQuery>()
+        .error(reference("UdfError"))
+.negotiated(),
+        Operation::get(
+            "/udf/search",
+            "TradingView UDF symbol search",
+            series("UdfSearchResult"),
+        )
+        .query::<SearchQuery>()
+        .constrain("limit", json!({ "minimum": 0 }))
+        .error(reference("UdfError"))
+.negotiated(),
+        Operation::get(
+            "/udf/history",
+            "TradingView UDF bars as column arrays, resampled to the resolution",
+            reference("UdfHistory"),
+        )
+        .query::<HistoryQuery>()
+        .constrain("resolution", json!({ "pattern": UDF_RESOLUTION_PATTERN }))
+        .constrain("countback", json!({ "maximum": MAX_HISTORY_BARS }))
+        .error(reference("UdfError"))
+.negotiated(),
+        Operation::get(
+            "/udf/time",
+            "The server clock in Unix seconds",
+            json!({ "type": "string" }),
+        )
+        .media_type("text/plain"),
+        Operation::get(
+            "/api/ws",
+            "WebSocket pushing the latest backfill candles, then each new or updated one, \
+             or replaying history from replay_from, each followed by any subscribed indicators",
+            reference("StreamMessage"),
+        )
+        .query::<StreamQuery>()
+        .timestamps()
+        .constrain("backfill", json!({ "maximum": MAX_BACKFILL })),
+        Operation::get(
+            "/ws/replay",
+            "WebSocket replaying history from start, as /api/ws does from replay_from",
+            reference("StreamMessage"),
+        )
+        .query::<ReplayQuery>()
+        .timestamps(),
+        Operation::get(
+            "/api/sse",
+            "Server-sent candle and data_changed events, resumable with Last-Event-ID",
+            json!({ "type": "string" }),
+        )
+        .timestamps()
+        .media_type("text/event-stream"),
+        Operation::get(
+            "/api/admin/stats",
+            "Candle count, indicator table state and API key usage",
+            reference("AdminStats"),
+        )
+        .keyed(),
+        Operation::get(
+            "/api/admin/explain",
+            "EXPLAIN ANALYZE the statements behind an endpoint",
+            reference("Explained"),
+        )
+        .query::<ExplainQuery>()
+        .constrain("endpoint", json!({ "enum": ["indicators"] }))
+        .keyed(),
+        Operation::get(
+            "/api/admin/sql",
+            "Show the SQL an endpoint would run for its parameters, with bound values",
+            reference("BoundSql"),
+        )
+        .query::<BoundSqlQuery>()
+        .constrain("endpoint", json!({ "enum": ["candles", "indicators"] }))
+        .keyed(),
+        Operation {
+            method: "post",
+            ..Operation::get(
+                "/api/query",
+                "Run one read-only SELECT or WITH query",
+                object(&[
+                    ("columns", json!({ "type": "array", "items": { "type": "string" } })),
+                    ("rows", json!({ "type": "array", "items": { "type": "array" } })),
+                    ("truncated", json!({ "type": "boolean" })),
+                ]),
+            )
+        }
+        .request_body(json!({
+            "type": "object",
+            "required": ["sql"],
+            "properties": {
+                "sql": { "type": "string" },
+                "params": {
+                    "type": "array",
+                    "items": { "type": ["string", "number", "boolean", "null"] }
+                }
+            }
+        }))
+        .timestamps()
+        .keyed(),
+        Operation {
+            method: "post",
+            ..Operation::get(
+                "/api/admin/generate",
+                "Replace every candle with a seeded random walk",
+                json!({
+                    "type": "object",
+                    "required": ["rows"],
+                    "properties": { "rows": { "type": "integer" } }
+                }),
+            )
+        }
+        .query::<GenerateQuery>()
+        .constrain(
+            "rows",
+            json!({ "minimum": 1, "maximum": MAX_GENERATED_ROWS }),
+        )
+        .constrain("interval", json!({ "pattern": DURATION_PATTERN }))
+        .constrain("volatility", json!({ "minimum": 0, "exclusiveMaximum": 1 }))
+        .constrain("start_price", json!({ "exclusiveMinimum": 0 }))
+        .keyed(),
+        Operation::get(
+            "/api/admin/integrity",
+            "Candle timestamps stored more than once",
+            reference("IntegrityReport"),
+        )
+        .keyed(),
+        Operation {
+            method: "post",
+            ..Operation::get(
+                "/api/admin/integrity",
+                "Delete duplicated candles, keeping the last-ingested row of each",
+                object(&[
+                    ("removed", json!({ "type": "integer" })),
+                    ("integrity", reference("IntegrityReport")),
+                ]),
+            )
+        }
+        .keyed(),
+        Operation {
+            method: "post",
+            ..Operation::get(
+                "/api/ticks",
+                "Build candles from trades",
+                reference("TickReport"),
+            )
+        }
+        .request_body(json!({
+            "type": "array",
+            "maxItems": MAX_TICK_BATCH,
+            "items": {
+                "type": "object",
+                "required": ["timestamp", "price"],
+                "properties": {
+                    "symbol": { "type": "string", "minLength": 1 },
+                    "timestamp": { "type": ["string", "number"] },
+                    "price": { "type": "number" },
+                    "size": { "type": "number", "minimum": 0, "default": 0 }
+                }
+            }
+        }))
+        .keyed(),
+        Operation {
+            method: "post",
+            ..Operation::get(
+                "/api/backfill",
+                "Fetch history from the configured upstream a page at a time and store it",
+                reference("BackfillReport"),
+            )
+        }
+        .request_body(json!({
+            "type": "object",
+            "required": ["start", "end"],
+            "properties": {
+                "symbol": { "type": "string", "minLength": 1 },
+                "start": { "type": "string" },
+                "end": { "type": "string" }
+            }
+        }))
+        .keyed(),
+        Operation::get(
+            "/api/alerts",
+            "Alert rules and where each stands",
+            array(reference("AlertRule")),
+        )
+        .timestamps()
+        .keyed(),
+        Operation {
+            method: "post",
+            ..Operation::get(
+                "/api/alerts",
+                "Create an alert rule, whose webhook is called when it fires",
+                reference("AlertRule"),
+            )
+        }
+        .request_body(json!({
+            "type": "object",
+            "required": ["condition", "level", "webhook_url"],
+            "properties": {
+                "symbol": { "type": "string", "minLength": 1 },
+                "field": {
+                    "type": "string",
+                    "enum": ["open", "high", "low", "close", "volume"],
+                    "default": "close"
+                },
+                "condition": { "type": "string", "enum": ["above", "below"] },
+                "level": { "type": "number" },
+                "webhook_url": { "type": "string", "pattern": "^http://" },
+                "message": { "type": "string", "maxLength": MAX_MESSAGE_LEN },
+                "rearm": {
+                    "type": "string",
+                    "pattern": "^(reset|never|[1-9][0-9]*[smhd]|1[wM])$",
+                    "default": "reset"
+                }
+            }
+        }))
+        .timestamps()
+        .keyed(),
+        Operation {
+            method: "delete",
+            ..Operation::get(
+                "/api/alerts/:id",
+                "Delete an alert rule and its delivery history",
+                reference("AlertRule"),
+            )
+        }
+        .timestamps()
+        .keyed(),
+        Operation::get(
+            "/api/alerts/:id/deliveries",
+            "Every attempt at an alert rule's webhook, oldest first",
+            array(reference("WebhookDelivery")),
+        )
+        .timestamps()
+        .keyed(),
+        Operation::get(
+            "/api/openapi.json",
+            "This document",
+            json!({ "type": "object" }),
+        ),
+        Operation::get("/docs", "Swagger UI", json!({ "type": "string" })),
+    ]
+}
+
+fn spec() -> Value {
+    let mut paths = Map::new();
+    for operation in operations() {
+        let (path, method) = (openapi_path(operation.path), operation.method);
+        paths.entry(path).or_insert_with(|| json!({}))[method] = operation.into_json();
+    }
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "graph",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Candles, indicators and analytics from DuckDB. Errors share the Error shape."
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer" },
+                "apiKey": { "type": "apiKey", "in": "header", "name": "X-Api-Key" }
+            }
+        }
+    })
+}
+
+/// A router path as OpenAPI writes it, `/api/alerts/{id}` for
+/// `/api/alerts/:id`.
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{name}}}"),
+            None => segment.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+fn array(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+/// An object whose properties are all present, as every model serializes.
+fn object(properties: &[(&str, Value)]) -> Value {
+    let names = properties.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    let properties = properties
+        .iter()
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect::<Map<_, _>>();
+    json!({ "type": "object", "required": names, "properties": properties })
+}
+
+fn schemas() -> Value {
+    fn number() -> Value {
+        json!({ "type": "number" })
+    }
+    fn nullable() -> Value {
+        json!({ "type": ["number", "null"] })
+    }
+    let string = || json!({ "type": "string" });
+    let timestamp = || reference("Timestamp");
+    let ohlc = |value: fn() -> Value| {
+        [
+            ("timestamp", timestamp()),
+            ("open", value()),
+            ("high", value()),
+            ("low", value()),
+            ("close", value()),
+            ("volume", value()),
+        ]
+    };
+    let mut candle = object(&ohlc(number));
+    candle["properties"]["events"] = array(reference("Event"));
+    // With `include=bidask` or `include=spread`, when the data has quotes.
+    for name in QUOTE_COLUMNS {
+        candle["properties"][name] = nullable();
+    }
+    let projected = object(&ohlc(nullable));
+    let mut indicator = object(&[
+        ("timestamp", timestamp()),
+        ("sma_14", nullable()),
+        ("ema_14", nullable()),
+        ("rsi_14", nullable()),
+    ]);
+    // `hma_<period>`, `sma_<period>`, ... for each period requested with
+    // `hma=`, `sma=`, `ema=` and `rsi=`.
+    indicator["additionalProperties"] = nullable();
+    let mut continuous_candle = object(&ohlc(number));
+    continuous_candle["required"]
+        .as_array_mut()
+        .expect("required is a list")
+        .push(json!("contract"));
+    continuous_candle["properties"]["contract"] = string();
+    let mut meta = object(&[("count", json!({ "type": "integer" }))]);
+    let mut duplicate = object(&[
+        ("timestamp", timestamp()),
+        ("rows", json!({ "type": "integer" })),
+    ]);
+    // Only for tables holding several symbols.
+    duplicate["properties"]["symbol"] = string();
+    meta["properties"]["warnings"] = array(string());
+    let boolean = || json!({ "type": "boolean" });
+    let integer = || json!({ "type": "integer" });
+    let optional_integer = || json!({ "type": ["integer", "null"] });
+    let display_hints = object(&[
+        ("sampled", integer()),
+        ("price_decimals", optional_integer()),
+        ("tick_size", nullable()),
+        ("pricescale", optional_integer()),
+        ("min_move", optional_integer()),
+        ("volume_decimals", optional_integer()),
+    ]);
+    let udf_config = object(&[
+        ("supported_resolutions", array(string())),
+        ("supports_search", boolean()),
+        ("supports_group_request", boolean()),
+        ("supports_marks", boolean()),
+        ("supports_timescale_marks", boolean()),
+        ("supports_time", boolean()),
+    ]);
+    let udf_symbol = object(&[
+        ("name", string()),
+        ("ticker", string()),
+        ("description", string()),
+        ("type", string()),
+        ("session", string()),
+        ("timezone", string()),
+        ("exchange", string()),
+        ("listed_exchange", string()),
+        ("format", json!({ "const": "price" })),
+        ("pricescale", integer()),
+        ("minmov", integer()),
+        ("has_intraday", boolean()),
+        ("has_weekly_and_monthly", boolean()),
+        ("supported_resolutions", array(string())),
+        ("volume_precision", integer()),
+        ("data_status", string()),
+    ]);
+    let udf_search = object(&[
+        ("symbol", string()),
+        ("full_name", string()),
+        ("description", string()),
+        ("exchange", string()),
+        ("ticker", string()),
+        ("type", string()),
+    ]);
+    let udf_bars = object(&[
+        ("s", json!({ "const": "ok" })),
+        ("t", array(integer())),
+        ("o", array(nullable())),
+        ("h", array(nullable())),
+        ("l", array(nullable())),
+        ("c", array(nullable())),
+        ("v", array(nullable())),
+    ]);
+    // `nextTime`, the start of the closest earlier bar, when there is one.
+    let mut udf_no_data = object(&[("s", json!({ "const": "no_data" }))]);
+    udf_no_data["properties"]["nextTime"] = integer();
+
+    let mut schemas = json!({
+        "Timestamp": {
+            "description": "YYYY-MM-DD HH:MM:SS by default, with a fraction such as .250 for sub-second timestamps; RFC 3339 with ts_format=iso, or epoch seconds (fractional below a second) or milliseconds with unix and unix_ms",
+            "type": ["string", "number"]
+        },
+        "Error": object(&[(
+            "error",
+            object(&[
+                ("code", string()),
+                ("message", string()),
+                ("request_id", string()),
+            ]),
+        )]),
+        "Candle": candle,
+        "ProjectedBar": projected,
+        "CandleRow": { "oneOf": [reference("Candle"), reference("ProjectedBar")] },
+        "StreamMessage": { "oneOf": [
+            object(&[("type", json!({ "const": "candle" })), ("data", reference("Candle"))]),
+            object(&[
+                ("type", json!({ "const": "gap" })),
+                ("data", object(&[("missed", json!({ "type": "integer" }))])),
+            ]),
+            object(&[("type", json!({ "const": "data_changed" }))]),
+            object(&[("type", json!({ "const": "replay_done" }))]),
+            object(&[
+                ("type", json!({ "const": "indicator" })),
+                ("data", {
+                    let mut values = object(&[("timestamp", timestamp())]);
+                    values["additionalProperties"] = nullable();
+                    values
+                }),
+            ]),
+            object(&[
+                ("type", json!({ "const": "error" })),
+                ("data", object(&[("message", string())])),
+            ]),
+        ] },
+        "Event": object(&[("timestamp", timestamp()), ("type", string()), ("label", string())]),
+        "IndicatorPoint": indicator,
+        "AdaptiveCandles": object(&[
+            ("data", array(reference("Candle"))),
+            (
+                "meta",
+                object(&[
+                    ("count", json!({ "type": "integer" })),
+                    ("rows", json!({ "type": "integer" })),
+                    ("points", json!({ "type": "integer" })),
+                    ("resampled", json!({ "type": "boolean" })),
+                    ("timeframe", json!({ "type": ["string", "null"] })),
+                ]),
+            ),
+        ]),
+        "IndicatorEnvelope": object(&[
+            ("data", array(reference("IndicatorPoint"))),
+            ("meta", meta),
+        ]),
+        "VolumeIndicatorPoint": object(&[
+            ("timestamp", timestamp()),
+            ("force_index", nullable()),
+            ("ease_of_movement", nullable()),
+        ]),
+        "AdxPoint": object(&[
+            ("timestamp", timestamp()),
+            ("plus_di", nullable()),
+            ("minus_di", nullable()),
+            ("adx", nullable()),
+            ("adxr", nullable()),
+        ]),
+        "VortexPoint": object(&[
+            ("timestamp", timestamp()),
+            ("vi_plus", nullable()),
+            ("vi_minus", nullable()),
+        ]),
+        "PnfColumn": object(&[
+            ("direction", json!({ "enum": ["up", "down"] })),
+            ("boxes", array(number())),
+        ]),
+        "FormulaPoint": object(&[("timestamp", timestamp()), ("value", nullable())]),
+        "KamaPoint": object(&[("timestamp", timestamp()), ("kama", nullable())]),
+        "CorrelationPoint": object(&[("timestamp", timestamp()), ("correlation", nullable())]),
+        "RollingPricePoint": object(&[
+            ("timestamp", timestamp()),
+            ("avg_price", nullable()),
+            ("median_price", nullable()),
+        ]),
+        "CmoPoint": object(&[("timestamp", timestamp()), ("cmo", nullable())]),
+        "StdDevPoint": object(&[
+            ("timestamp", timestamp()),
+            ("stddev", nullable()),
+            ("variance", nullable()),
+        ]),
+        "ZScorePoint": object(&[
+            ("timestamp", timestamp()),
+            ("value", nullable()),
+            ("zscore", nullable()),
+        ]),
+        "SpreadPoint": object(&[
+            ("timestamp", timestamp()),
+            ("spread", nullable()),
+            ("mean", nullable()),
+            ("zscore", nullable()),
+        ]),
+        "SymbolInfo": object(&[
+            ("symbol", string()),
+            ("first", timestamp()),
+            ("last", timestamp()),
+            ("candles", json!({ "type": "integer" })),
+        ]),
+        "ContinuousCandle": continuous_candle,
+        "Roll": object(&[
+            ("timestamp", timestamp()),
+            ("from", string()),
+            ("to", string()),
+            ("reference", timestamp()),
+            ("gap", number()),
+        ]),
+        "ContinuousSeries": object(&[
+            ("candles", array(reference("ContinuousCandle"))),
+            ("rolls", array(reference("Roll"))),
+        ]),
+        "FibLevels": object(&[
+            ("low", number()),
+            ("high", number()),
+            ("levels", array(object(&[("ratio", number()), ("value", number())]))),
+        ]),
+        "FibTimeZones": object(&[
+            ("anchor", timestamp()),
+            (
+                "zones",
+                array(object(&[
+                    ("bars", json!({ "type": "integer" })),
+                    ("timestamp", timestamp()),
+                    ("projected", json!({ "type": "boolean" })),
+                ])),
+            ),
+        ]),
+        "Percentiles": object(&[
+            // `p<percent>` for each configured quantile.
+            ("quantiles", json!({ "type": "object", "additionalProperties": nullable() })),
+            ("latest", nullable()),
+            ("latest_rank", nullable()),
+        ]),
+        "Readiness": object(&[
+            ("ready", json!({ "type": "boolean" })),
+            ("consecutive_failures", json!({ "type": "integer" })),
+            ("recoveries", json!({ "type": "integer" })),
+        ]),
+        "AdminStats": object(&[
+            ("candles", json!({ "type": "integer" })),
+            ("indicators", json!({ "type": "object" })),
+            (
+                "api_keys",
+                array(object(&[("id", string()), ("requests", json!({ "type": "integer" }))])),
+            ),
+            (
+                "websockets",
+                object(&[
+                    ("open", json!({ "type": "integer" })),
+                    ("limit", json!({ "type": ["integer", "null"] })),
+                ]),
+            ),
+        ]),
+        "UdfConfig": udf_config,
+        "UdfSymbolInfo": udf_symbol,
+        "UdfSearchResult": udf_search,
+        "UdfHistory": { "oneOf": [udf_bars, udf_no_data] },
+        "UdfError": object(&[("s", json!({ "const": "error" })), ("errmsg", string())]),
+        "TickReport": object(&[
+            ("accepted", json!({ "type": "integer" })),
+            ("rejected", json!({ "type": "integer" })),
+            ("written", json!({ "type": "integer" })),
+        ]),
+        "BackfillReport": object(&[
+            ("pages", json!({ "type": "integer" })),
+            ("fetched", json!({ "type": "integer" })),
+            ("inserted", json!({ "type": "integer" })),
+            ("duplicates", json!({ "type": "integer" })),
+            ("retries", json!({ "type": "integer" })),
+        ]),
+        "IntegrityReport": object(&[(
+            "tables",
+            array(object(&[
+                ("table", string()),
+                ("duplicate_keys", json!({ "type": "integer" })),
+                ("extra_rows", json!({ "type": "integer" })),
+                ("samples", array(duplicate)),
+            ])),
+        )]),
+        "Explained": object(&[
+            ("endpoint", string()),
+            (
+                "queries",
+                array(object(&[
+                    ("name", string()),
+                    ("sql", string()),
+                    ("total_seconds", nullable()),
+                    ("plan", string()),
+                ])),
+            ),
+        ]),
+    });
+    // Outside the literal, which is at `json!`'s recursion limit.
+    schemas["DisplayHints"] = display_hints;
+    schemas["BoundSql"] = object(&[
+        ("endpoint", string()),
+        (
+            "queries",
+            array(object(&[
+                ("name", string()),
+                ("sql", string()),
+                (
+                    "params",
+                    json!({ "type": "array", "items": { "type": ["string", "integer", "null"] } }),
+                ),
+            ])),
+        ),
+    ]);
+    schemas["DpoPoint"] = object(&[("timestamp", timestamp()), ("dpo", nullable())]);
+    schemas["StcPoint"] = object(&[("timestamp", timestamp()), ("stc", nullable())]);
+    schemas["OverlayPoint"] = object(&[
+        ("time_of_day", string()),
+        ("mean", number()),
+        ("p25", number()),
+        ("p75", number()),
+    ]);
+    let optional_timestamp = || json!({ "oneOf": [timestamp(), { "type": "null" }] });
+    schemas["AlertRule"] = object(&[
+        ("id", integer()),
+        ("symbol", json!({ "type": ["string", "null"] })),
+        ("field", string()),
+        ("condition", string()),
+        ("level", number()),
+        ("webhook_url", string()),
+        ("message", json!({ "type": ["string", "null"] })),
+        ("rearm", string()),
+        (
+            "state",
+            json!({ "type": "string", "enum": ["armed", "fired"] }),
+        ),
+        ("fired_at", optional_timestamp()),
+        ("checked_through", optional_timestamp()),
+        ("created_at", timestamp()),
+    ]);
+    schemas["WebhookDelivery"] = object(&[
+        ("id", integer()),
+        ("timestamp", timestamp()),
+        ("attempt", integer()),
+        ("attempted_at", timestamp()),
+        ("status", optional_integer()),
+        ("error", json!({ "type": ["string", "null"] })),
+        ("delivered", boolean()),
+    ]);
+    schemas
+}
+
+/// One OpenAPI parameter per field of `T`, as `Query<T>` would read it.
+fn query_parameters<T: DeserializeOwned>() -> Vec<Value> {
+    let mut fields = Vec::new();
+    T::deserialize(StructProbe {
+        fields: &mut fields,
+    })
+    .expect("query types are structs of plain fields");
+    fields
+        .into_iter()
+        .map(|(name, required, schema)| {
+            json!({
+                "name": name,
+                "in": "query",
+                "required": required,
+                "schema": schema,
+            })
+        })
+        .collect()
+}
+
+type ProbeError = de::value::Error;
+
+/// Answers `deserialize_struct` with every field the type declares.
+struct StructProbe<'a> {
+    fields: &'a mut Vec<(&'static str, bool, Value)>,
+}
+
+impl<'de> de::Deserializer<'de> for StructProbe<'_> {
+    type Error = ProbeError;
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        names: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        visitor.visit_map(FieldProbe {
+            names,
+            next: 0,
+            fields: self.fields,
+        })
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ProbeError> {
+        Err(de::Error::custom("only structs can be probed"))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+struct FieldProbe<'a> {
+    names: &'static [&'static str],
+    next: usize,
+    fields: &'a mut Vec<(&'static str, bool, Value)>,
+}
+
+impl<'de> MapAccess<'de> for FieldProbe<'_> {
+    type Error = ProbeError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ProbeError> {
+        let Some(name) = self.names.get(self.next) else {
+            return Ok(None);
+        };
+        seed.deserialize(name.into_deserializer()).map(Some)
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, ProbeError> {
+        let mut schema = Map::new();
+        let mut required = true;
+        let value = seed.deserialize(ValueProbe {
+            schema: &mut schema,
+            required: &mut required,
+        })?;
+        self.fields
+            .push((self.names[self.next], required, Value::Object(schema)));
+        self.next += 1;
+        Ok(value)
+    }
+}
+
+/// Records what a field asks to be deserialized as and hands back a
+/// placeholder of that type.
+struct ValueProbe<'a> {
+    schema: &'a mut Map<String, Value>,
+    required: &'a mut bool,
+}
+
+impl ValueProbe<'_> {
+    fn record(&mut self, kind: &str) {
+        self.schema.insert("type".to_owned(), json!(kind));
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueProbe<'_> {
+    type Error = ProbeError;
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        *self.required = false;
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.record("boolean");
+        visitor.visit_bool(false)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.record("integer");
+        self.schema.insert("minimum".to_owned(), json!(0));
+        visitor.visit_u64(0)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.record("integer");
+        visitor.visit_i64(0)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.record("number");
+        visitor.visit_f64(0.0)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        mut self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ProbeError> {
+        self.record("string");
+        self.schema.insert("enum".to_owned(), json!(variants));
+        visitor.visit_enum(variants[0].into_deserializer())
+    }
+
+    fn deserialize_any<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, ProbeError> {
+        self.record("string");
+        visitor.visit_str("")
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct newtype_struct
+        seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::build_router;
+    use crate::test_support::*;
+
+    #[test]
+    fn query_types_are_probed_field_by_field() {
+        let parameters = query_parameters::<CandleQuery>();
+        let names = parameters
+            .iter()
+            .map(|parameter| parameter["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(&names[..3], ["limit", "timeframe", "origin"]);
+        assert!(names.contains(&"as_of") && names.contains(&"order"));
+        assert_eq!(
+            parameters[0]["schema"],
+            json!({ "type": "integer", "minimum": 0 })
+        );
+        assert_eq!(parameters[0]["required"], false);
+        let close = parameters.iter().find(|p| p["name"] == "close").unwrap();
+        assert_eq!(
+            close["schema"]["enum"],
+            json!(["first", "last", "min", "max", "mean", "median", "sum"])
+        );
+        let styles = &query_parameters::<TimestampQuery>()[0]["schema"]["enum"];
+        assert_eq!(styles, &json!(["text", "iso", "unix", "unix_ms"]));
+    }
+
+    #[tokio::test]
+    async fn the_spec_covers_every_route_and_matches_responses() {
+        let app = build_router(seeded_state(
+            "('2024-01-01 00:00:00', 1, 2, 0.5, 1.5, 10),
+             ('2024-01-01 00:01:00', 1.5, 3, 1, 2, 20)",
+        ));
+        let spec = get_json(&app, "/api/openapi.json").await;
+        assert_eq!(spec["openapi"], "3.1.0");
+
+        // Every route the router declares is documented, and nothing else.
+        let documented = spec["paths"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        let source = include_str!("lib.rs")
+            .split("#[cfg(test)]\nmod tests")
+            .next();
+        let source = source.unwrap();
+        let routed = source
+            .split(".route(")
+            .skip(1)
+            .filter_map(|call| call.trim_start().strip_prefix('"'))
+            .filter_map(|call| call.split('"').next())
+            .filter(|path| !path.contains('*'))
+            .map(openapi_path)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(documented, routed);
+
+        // Every reference resolves.
+        let text = spec.to_string();
+        for name in text.split("#/components/schemas/").skip(1) {
+            let name = name.split('"').next().unwrap();
+            assert!(
+                spec["components"]["schemas"][name].is_object(),
+                "missing schema {name}"
+            );
+        }
+
+        // Schemas list exactly the fields the handlers send.
+        let schema = |name: &str| spec["components"]["schemas"][name].clone();
+        for (uri, name) in [
+            ("/api/candles", "Candle"),
+            ("/api/indicators", "IndicatorPoint"),
+            ("/api/volume_indicators", "VolumeIndicatorPoint"),
+            ("/api/adx", "AdxPoint"),
+            ("/api/vortex?period=1", "VortexPoint"),
+            ("/api/zscore", "ZScorePoint"),
+            ("/api/stddev?period=2", "StdDevPoint"),
+            ("/api/rolling_price?window=2", "RollingPricePoint"),
+            ("/api/cmo?period=1", "CmoPoint"),
+            ("/api/kama", "KamaPoint"),
+            ("/api/stc", "StcPoint"),
+            ("/api/dpo?period=1", "DpoPoint"),
+            (
+                "/api/rolling_correlation?a=close&b=volume&window=2",
+                "CorrelationPoint",
+            ),
+            ("/api/pnf?box_size=0.5&reversal=1", "PnfColumn"),
+            ("/api/formula?expr=close", "FormulaPoint"),
+            ("/api/intraday_overlay", "OverlayPoint"),
+        ] {
+            let body = get_json(&app, uri).await;
+            assert_fields(&body[0], &schema(name), uri);
+        }
+        assert_fields(
+            &get_json(&app, "/api/fib").await,
+            &schema("FibLevels"),
+            "fib",
+        );
+        assert_fields(
+            &get_json(&app, "/api/meta").await,
+            &schema("DisplayHints"),
+            "meta",
+        );
+        assert_fields(
+            &get_json(&app, "/ready").await,
+            &schema("Readiness"),
+            "ready",
+        );
+        assert_fields(
+            &get_json(&app, "/udf/config").await,
+            &schema("UdfConfig"),
+            "udf config",
+        );
+        assert_fields(
+            &get_json(&app, "/udf/symbols?symbol=MAIN").await,
+            &schema("UdfSymbolInfo"),
+            "udf symbols",
+        );
+        for (uri, variant) in [
+            (
+                "/udf/history?symbol=MAIN&resolution=1&from=0&to=1800000000",
+                0,
+            ),
+            ("/udf/history?symbol=MAIN&resolution=1&from=0&to=60", 1),
+        ] {
+            let history = get_json(&app, uri).await;
+            assert_fields(&history, &schema("UdfHistory")["oneOf"][variant], uri);
+        }
+        let percentiles = get_json(&app, "/api/percentile").await;
+        assert_fields(&percentiles, &schema("Percentiles"), "percentile");
+        let envelope = get_json(&app, "/api/indicators?envelope=true").await;
+        assert_fields(&envelope, &schema("IndicatorEnvelope"), "envelope");
+        let error = get_uri(&app, "/api/candles?limit=-1").await;
+        let body = axum::body::to_bytes(error.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert_fields(&error, &schema("Error"), "error");
+        assert_fields(
+            &error["error"],
+            &schema("Error")["properties"]["error"],
+            "error",
+        );
+
+        let docs = get_uri(&app, "/docs").await;
+        assert_eq!(docs.status(), StatusCode::OK);
+    }
+
+    /// `value` has every required field of `schema` and no undeclared ones
+    /// (unless the schema allows additional properties).
+    fn assert_fields(value: &Value, schema: &Value, what: &str) {
+        let fields = value.as_object().unwrap().keys().collect::<BTreeSet<_>>();
+        let declared = schema["properties"]
+            .as_object()
+            .unwrap()
+            .keys()
+            .collect::<BTreeSet<_>>();
+        let required = schema["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|name| name.as_str().unwrap())
+            .collect::<Vec<_>>();
+        for name in required {
+            assert!(value.get(name).is_some(), "{what} lacks {name}");
+        }
+        if schema.get("additionalProperties").is_none() {
+            assert!(
+                fields.is_subset(&declared),
+                "{what}: {fields:?} vs {declared:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Point-and-figure columns built from the close series.
+//!
+//! Prices map onto a grid of `box_size` boxes. A column of X's rises a box
+//! each time a close reaches the next box up and a column of O's falls the
+//! same way; moves against the column draw nothing until they cover
+//! `reversal` whole boxes, which starts the next column one box past the last
+//! one's extreme. The first column starts at the first close's box, in
+//! whichever direction the price first moves a whole box.
+
+use crate::models::{PnfColumn, PnfDirection};
+
+/// Slack, in boxes, for closes that sit on a box boundary but divide to just
+/// under it in floating point, such as `0.3 / 0.1`.
+const EPSILON: f64 = 1e-9;
+
+/// A column as box indices: where it starts and the extreme it reached.
+struct Column {
+    direction: PnfDirection,
+    from: i64,
+    to: i64,
+}
+
+impl Column {
+    fn boxes(&self) -> usize {
+        self.from.abs_diff(self.to) as usize + 1
+    }
+}
+
+/// The columns `closes` (oldest first) draw, failing once they would hold
+/// more than `max_boxes` boxes in all. Non-finite closes are skipped.
+pub fn columns(
+    closes: impl IntoIterator<Item = f64>,
+    box_size: f64,
+    reversal: u32,
+    max_boxes: usize,
+) -> Result<Vec<PnfColumn>, String> {
+    // The highest box a close reaches going up and the lowest going down.
+    let up = |price: f64| (price / box_size + EPSILON).floor() as i64;
+    let down = |price: f64| (price / box_size - EPSILON).ceil() as i64;
+    let reversal = i64::from(reversal);
+    let mut first = None;
+    let mut columns: Vec<Column> = Vec::new();
+    let mut boxes = 0;
+    for price in closes.into_iter().filter(|price| price.is_finite()) {
+        let (high, low) = (up(price), down(price));
+        let drawn = match columns.last_mut() {
+            None => {
+                let &mut (start_up, start_down) = first.get_or_insert((high, low));
+                if high > start_up {
+                    columns.push(Column {
+                        direction: PnfDirection::Up,
+                        from: start_up,
+                        to: high,
+                    });
+                } else if low < start_down {
+                    columns.push(Column {
+                        direction: PnfDirection::Down,
+                        from: start_down,
+                        to: low,
+                    });
+                } else {
+                    continue;
+                }
+                columns[0].boxes()
+            }
+            Some(column) => match column.direction {
+                PnfDirection::Up if high > column.to => {
+                    let added = high - column.to;
+                    column.to = high;
+                    added as usize
+                }
+                PnfDirection::Up if low <= column.to - reversal => {
+                    let next = Column {
+                        direction: PnfDirection::Down,
+                        from: column.to - 1,
+                        to: low,
+                    };
+                    let added = next.boxes();
+                    columns.push(next);
+                    added
+                }
+                PnfDirection::Down if low < column.to => {
+                    let added = column.to - low;
+                    column.to = low;
+                    added as usize
+                }
+                PnfDirection::Down if high >= column.to + reversal => {
+                    let next = Column {
+                        direction: PnfDirection::Up,
+                        from: column.to + 1,
+                        to: high,
+                    };
+                    let added = next.boxes();
+                    columns.push(next);
+                    added
+                }
+                _ => continue,
+            },
+        };
+        boxes += drawn;
+        if boxes > max_boxes {
+            return Err(format!(
+                "box_size {box_size} draws more than {max_boxes} boxes over these candles; \
+                 use a larger one"
+            ));
+        }
+    }
+    // Box prices are rounded to nine decimals so that e.g. 0.1-sized boxes
+    // read 0.3 rather than 0.30000000000000004.
+    let level = |index: i64| (index as f64 * box_size * 1e9).round() / 1e9;
+    Ok(columns
+        .into_iter()
+        .map(|column| PnfColumn {
+            direction: column.direction,
+            boxes: match column.direction {
+                PnfDirection::Up => (column.from..=column.to).map(level).collect(),
+                PnfDirection::Down => (column.to..=column.from).rev().map(level).collect(),
+            },
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drawn(closes: &[f64], box_size: f64, reversal: u32) -> Vec<(PnfDirection, Vec<f64>)> {
+        columns(closes.iter().copied(), box_size, reversal, 1_000)
+            .unwrap()
+            .into_iter()
+            .map(|column| (column.direction, column.boxes))
+            .collect()
+    }
+
+    #[test]
+    fn columns_extend_and_reverse_by_whole_boxes() {
+        use PnfDirection::{Down, Up};
+        let closes = [
+            10.0, 10.9, // under a box: nothing yet
+            12.3, // X's from 10 to 12
+            11.1, 10.2, // fewer than three boxes back: still X's
+            14.0, // extends to 14
+            11.0, // three boxes down: O's from 13 to 11
+            12.5, 9.0,  // extends to 9
+            12.0, // three boxes up from 9: X's from 10 to 12
+        ];
+        assert_eq!(
+            drawn(&closes, 1.0, 3),
+            [
+                (Up, vec![10.0, 11.0, 12.0, 13.0, 14.0]),
+                (Down, vec![13.0, 12.0, 11.0, 10.0, 9.0]),
+                (Up, vec![10.0, 11.0, 12.0]),
+            ]
+        );
+        // A one-box reversal flips on every whole box against the column.
+        assert_eq!(
+            drawn(&[10.0, 9.0, 10.0, 8.0], 1.0, 1),
+            [
+                (Down, vec![10.0, 9.0]),
+                (Up, vec![10.0]),
+                (Down, vec![9.0, 8.0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn fractional_boxes_land_on_their_boundaries() {
+        assert_eq!(
+            drawn(&[0.1, 0.3, f64::NAN, 0.0], 0.1, 2),
+            [
+                (PnfDirection::Up, vec![0.1, 0.2, 0.3]),
+                (PnfDirection::Down, vec![0.2, 0.1, 0.0]),
+            ]
+        );
+        assert!(drawn(&[1.0, 1.05, 0.97], 0.1, 3).is_empty());
+    }
+
+    #[test]
+    fn tiny_boxes_are_refused() {
+        let Err(err) = columns([1.0, 2.0], 0.000_001, 3, 1_000) else {
+            panic!("a millionth-sized box should draw too many");
+        };
+        assert!(err.contains("more than 1000 boxes"), "{err}");
+    }
+}
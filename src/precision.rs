@@ -0,0 +1,80 @@
+//! Display precision inferred from the stored values themselves, for front
+//! ends that would otherwise guess between cents, whole units and the many
+//! decimals of a crypto pair.
+//!
+//! Values arrive as the nearest binary fraction to what was written, such as
+//! `1.15` as `1.14999…`, so a value "has" `d` decimals when scaling it by
+//! `10^d` lands within a relative [`EPSILON`] of a whole number.
+
+/// Most decimals a value is taken to have; anything finer, such as a
+/// computed `1 / 3`, counts as this many.
+pub(crate) const MAX_DECIMALS: u32 = 10;
+
+const EPSILON: f64 = 1e-12;
+
+/// The fewest decimals, up to `max`, that write every finite value in
+/// `values` exactly.
+pub(crate) fn decimals(values: &[f64], max: u32) -> u32 {
+    (0..=max)
+        .find(|&decimals| values.iter().all(|&value| fits(value, decimals)))
+        .unwrap_or(max)
+}
+
+fn fits(value: f64, decimals: u32) -> bool {
+    let scaled = value * 10f64.powi(decimals as i32);
+    !scaled.is_finite() || (scaled - scaled.round()).abs() <= EPSILON * scaled.abs().max(1.0)
+}
+
+/// The step every value lies on, in units of `10^-decimals`: the greatest
+/// common divisor of the gaps between distinct values, so prices quoted in
+/// nickels give 5 at two decimals wherever the series starts. `None` with
+/// fewer than two distinct finite values.
+pub(crate) fn tick_units(values: &[f64], decimals: u32) -> Option<u64> {
+    let scale = 10f64.powi(decimals as i32);
+    let mut units = values
+        .iter()
+        .filter(|value| value.is_finite())
+        .map(|value| (value * scale).round() as i128)
+        .collect::<Vec<_>>();
+    units.sort_unstable();
+    units.dedup();
+    let tick = units
+        .windows(2)
+        .map(|pair| (pair[1] - pair[0]).unsigned_abs())
+        .fold(0, gcd);
+    u64::try_from(tick).ok().filter(|&tick| tick > 0)
+}
+
+fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimals_count_what_was_written_not_the_binary_fraction() {
+        assert_eq!(decimals(&[1.15, 2.0, 100.5], MAX_DECIMALS), 2);
+        assert_eq!(decimals(&[0.1 + 0.2], MAX_DECIMALS), 1);
+        assert_eq!(decimals(&[50_000.0, 49_999.0], MAX_DECIMALS), 0);
+        assert_eq!(decimals(&[0.000_012_34], MAX_DECIMALS), 8);
+        assert_eq!(decimals(&[1.0 / 3.0], MAX_DECIMALS), MAX_DECIMALS);
+        assert_eq!(decimals(&[1.0 / 3.0, f64::NAN], 4), 4);
+        assert_eq!(decimals(&[], MAX_DECIMALS), 0);
+    }
+
+    #[test]
+    fn ticks_are_the_common_step_between_prices() {
+        // Nickels, offset from zero by a cent.
+        assert_eq!(tick_units(&[100.01, 100.06, 100.21, 100.06], 2), Some(5));
+        assert_eq!(tick_units(&[0.25, 0.75, 1.5], 2), Some(25));
+        assert_eq!(tick_units(&[1.15, 1.16], 2), Some(1));
+        assert_eq!(tick_units(&[3.0, 3.0], 0), None);
+        assert_eq!(tick_units(&[3.0, f64::INFINITY], 0), None);
+    }
+}
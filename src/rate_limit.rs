@@ -0,0 +1,218 @@
+//! Per-client token buckets: one across all routes and a stricter one for
+//! the expensive analytics endpoints.
+//!
+//! Clients are keyed by IP: the peer address, or the last `X-Forwarded-For`
+//! hop when the server sits behind a trusted proxy that appends it. Requests
+//! over the limit get `429 Too Many Requests` with `Retry-After`. Health and
+//! readiness checks are never limited.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::config::RateLimit;
+use crate::error::AppError;
+
+/// Idle buckets are dropped once this many clients are tracked.
+const PRUNE_ABOVE: usize = 10_000;
+
+/// Token buckets keyed by client, refilled at `per_second` up to `burst`.
+pub(crate) struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: &RateLimit) -> Self {
+        Self {
+            per_second: limit.per_second,
+            burst: f64::from(limit.burst.max(1)),
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Takes a token for `client` at `now`, or says how long until one is
+    /// available. Taking `now` as an argument keeps the clock in the caller's
+    /// hands.
+    pub(crate) fn check(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().expect("rate limiter poisoned");
+        if buckets.len() > PRUNE_ABOVE {
+            // A bucket that has refilled completely is the same as none.
+            let full_after = Duration::from_secs_f64(self.burst / self.per_second);
+            buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < full_after);
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = bucket.updated.max(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+}
+
+/// Middleware state: the limiter and whether `X-Forwarded-For` is trusted.
+#[derive(Clone)]
+pub(crate) struct Limit {
+    pub(crate) limiter: Arc<RateLimiter>,
+    pub(crate) trusted_proxy: bool,
+}
+
+pub(crate) async fn limit_requests(
+    State(limit): State<Limit>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if matches!(request.uri().path(), "/healthz" | "/ready") {
+        return next.run(request).await;
+    }
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = client_ip(request.headers(), peer, limit.trusted_proxy);
+    match limit.limiter.check(client, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(wait) => {
+            let seconds = wait.as_secs_f64().ceil().max(1.0) as u64;
+            tracing::debug!("rate limited {client} on {}", request.uri().path());
+            let mut response =
+                AppError::TooManyRequests(format!("too many requests; retry in {seconds}s"))
+                    .into_response();
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+            response
+        }
+    }
+}
+
+/// The client a request is counted against. Without a peer address (as in
+/// tests that call the router directly) every request shares one bucket.
+fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>, trusted_proxy: bool) -> IpAddr {
+    let forwarded = trusted_proxy
+        .then(|| headers.get_all("x-forwarded-for").iter().next_back())
+        .flatten()
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|hop| hop.trim().parse().ok());
+    forwarded
+        .or(peer)
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::config::RateLimits;
+    use crate::test_support::*;
+    use crate::{build_router, AppState, Config};
+
+    #[test]
+    fn buckets_refill_at_the_configured_rate() {
+        let limiter = RateLimiter::new(&RateLimit {
+            per_second: 2.0,
+            burst: 3,
+        });
+        let (a, b) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check(a, start), Ok(()));
+        }
+        assert_eq!(limiter.check(a, start), Err(Duration::from_millis(500)));
+        // Other clients have buckets of their own.
+        assert_eq!(limiter.check(b, start), Ok(()));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.check(a, later), Ok(()));
+        assert!(limiter.check(a, later).is_err());
+        // Refills stop at the burst size.
+        let idle = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert_eq!(limiter.check(a, idle), Ok(()));
+        }
+        assert!(limiter.check(a, idle).is_err());
+    }
+
+    #[test]
+    fn forwarded_addresses_count_only_behind_a_trusted_proxy() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("1.2.3.4, 203.0.113.9"),
+        );
+        let peer = IpAddr::from([127, 0, 0, 1]);
+        assert_eq!(
+            client_ip(&headers, Some(peer), true),
+            IpAddr::from([203, 0, 113, 9])
+        );
+        assert_eq!(client_ip(&headers, Some(peer), false), peer);
+        assert_eq!(client_ip(&HeaderMap::new(), Some(peer), true), peer);
+    }
+
+    #[tokio::test]
+    async fn limited_requests_get_429_with_retry_after() {
+        let config = Config {
+            rate_limits: RateLimits {
+                global: Some(RateLimit {
+                    per_second: 0.1,
+                    burst: 3,
+                }),
+                expensive: Some(RateLimit {
+                    per_second: 0.1,
+                    burst: 1,
+                }),
+                trusted_proxy: false,
+            },
+            ..Config::default()
+        };
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = build_router(AppState::new(state.db, config));
+
+        assert_eq!(
+            get_uri(&app, "/api/indicators").await.status(),
+            StatusCode::OK
+        );
+        // The expensive bucket is empty; the global one still has tokens.
+        // (Cache hits cost nothing, so this asks for something new.)
+        let response = get_uri(&app, "/api/indicators?source=open").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "10");
+        assert_eq!(get_uri(&app, "/api/fib").await.status(), StatusCode::OK);
+        let response = get_uri(&app, "/api/fib").await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error"]["code"], "too_many_requests");
+
+        for _ in 0..5 {
+            let response = get_uri(&app, "/healthz").await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! `format=csv` for everything but `/api/candles`, which writes its own:
+//! the rows as one wide table, for notebooks and spreadsheets.
+//!
+//! The table has a column per field of the points, in the order the endpoint
+//! sends them and named as in the JSON, so `/api/indicators?sma=20` has
+//! `sma_20` beside `sma_14`; a field not named for its period takes the
+//! request's `period` or `window`, as `stddev_20`. A missing value is an
+//! empty cell.
+
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::HeaderValue;
+use axum::response::{IntoResponse, Response};
+use serde_json::Value;
+
+use crate::error::{internal_error, AppError};
+use crate::handlers::{parse_query_timestamp, DayBound};
+use crate::negotiate::{stream_rows, Format, Table};
+
+/// `table` as a CSV download named for `endpoint`, its columns and the span
+/// of its rows.
+pub(crate) fn encode(
+    table: impl Table,
+    endpoint: &str,
+    period: Option<&str>,
+) -> Result<Response, AppError> {
+    let columns = table
+        .columns()
+        .iter()
+        .map(|column| match period {
+            Some(period)
+                if column != "timestamp" && !column.ends_with(|c: char| c.is_ascii_digit()) =>
+            {
+                format!("{column}_{period}")
+            }
+            _ => column.clone(),
+        })
+        .collect::<Vec<_>>();
+    let disposition = HeaderValue::try_from(format!(
+        "attachment; filename=\"{}\"",
+        filename(endpoint, &columns, &table)
+    ))
+    .map_err(internal_error)?;
+    let mut header = String::new();
+    for (index, column) in columns.iter().enumerate() {
+        if index > 0 {
+            header.push(',');
+        }
+        push_cell(&mut header, &Value::String(column.clone()));
+    }
+    header.push('\n');
+    let body = stream_rows(
+        table,
+        header.into_bytes(),
+        Vec::new(),
+        |out, _, _, cells| {
+            let mut line = String::new();
+            for (index, cell) in cells.iter().enumerate() {
+                if index > 0 {
+                    line.push(',');
+                }
+                push_cell(&mut line, cell);
+            }
+            line.push('\n');
+            out.extend_from_slice(line.as_bytes());
+        },
+    );
+    Ok((
+        [
+            (
+                CONTENT_TYPE,
+                HeaderValue::from_static(Format::Csv.content_type()),
+            ),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// A value as text, as a cell of the CSV or HTML tables shows it; `None`
+/// for `null`.
+pub(crate) fn cell_text(value: &Value) -> Option<String> {
+    Some(match value {
+        Value::Null => return None,
+        Value::String(text) => text.clone(),
+        // Written as the candles' own CSV writes them, `2` rather than `2.0`.
+        Value::Number(number) => match number.as_i64() {
+            Some(integer) => integer.to_string(),
+            None => number.as_f64().unwrap_or(f64::NAN).to_string(),
+        },
+        other => other.to_string(),
+    })
+}
+
+/// A value as a CSV cell: nothing for `null`, and text quoted when it
+/// holds a separator, quote or line break.
+fn push_cell(out: &mut String, value: &Value) {
+    let Some(text) = cell_text(value) else {
+        return;
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&text.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(&text);
+    }
+}
+
+/// `indicators_sma_14-ema_14-rsi_14_20240101T000000_20240131T235900.csv`: the
+/// endpoint, its columns but `timestamp` and the span of the rows, cut to
+/// safe characters.
+fn filename(endpoint: &str, columns: &[String], table: &impl Table) -> String {
+    let timestamped = columns.first().is_some_and(|column| column == "timestamp");
+    let moment = |index: usize| {
+        let text = match table.row(index).first()? {
+            Value::String(text) => text.clone(),
+            Value::Number(number) => number.to_string(),
+            _ => return None,
+        };
+        parse_query_timestamp("timestamp", &text, DayBound::Start)
+            .ok()
+            .map(|at| at.format("%Y%m%dT%H%M%S").to_string())
+    };
+    let mut name = endpoint.to_owned();
+    let named = &columns[usize::from(timestamped)..];
+    if !named.is_empty() {
+        name = format!("{name}_{}", named.join("-"));
+    }
+    if let (true, Some(last)) = (timestamped, table.len().checked_sub(1)) {
+        if let (Some(first), Some(last)) = (moment(0), moment(last)) {
+            name = format!("{name}_{first}_{last}");
+        }
+    }
+    let name = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect::<String>();
+    format!("{name}.csv")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+    use axum::http::StatusCode;
+
+    use crate::build_router;
+    use crate::test_support::{get_uri, seeded_state};
+
+    const ROWS: &str = "('2024-01-01 00:00:00', 1, 3, 1, 2, 10),
+         ('2024-01-01 00:01:00', 2, 3, 1, 1, 20),
+         ('2024-01-01 00:02:00', 2, 3, 1, 4, 20)";
+
+    async fn csv(app: &axum::Router, uri: &str) -> (String, String) {
+        let response = get_uri(app, uri).await;
+        assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/csv");
+        let disposition = response.headers()[CONTENT_DISPOSITION]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (disposition, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn indicator_series_become_one_column_each() {
+        let app = build_router(seeded_state(ROWS));
+        let (disposition, body) = csv(&app, "/api/indicators?format=csv&sma=2&ema=3").await;
+        assert_eq!(
+            disposition,
+            "attachment; filename=\"indicators_sma_14-ema_14-rsi_14-sma_2-ema_3_\
+             20240101T000000_20240101T000200.csv\""
+        );
+        let mut lines = body.lines();
+        assert_eq!(
+            lines.next(),
+            Some("timestamp,sma_14,ema_14,rsi_14,sma_2,ema_3")
+        );
+        assert_eq!(lines.next(), Some("2024-01-01 00:00:00,2,2,,,2"));
+        assert_eq!(
+            lines.next(),
+            Some("2024-01-01 00:01:00,1.5,1.866666666667,0,1.5,1.5")
+        );
+        assert_eq!(lines.count(), 1);
+
+        let (disposition, body) = csv(&app, "/api/stddev?period=2&format=csv&ts_format=unix").await;
+        assert!(
+            disposition.contains("stddev_stddev_2-variance_2_"),
+            "{disposition}"
+        );
+        let mut lines = body.lines();
+        assert_eq!(lines.next(), Some("timestamp,stddev_2,variance_2"));
+        assert_eq!(lines.next(), Some("1704067200,,"));
+
+        let response = get_uri(&app, "/api/indicators?format=csv&envelope=true").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = get_uri(&app, "/api/stddev?period=0&format=csv").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        // The candles' own CSV export is left alone.
+        let response = get_uri(&app, "/api/candles?format=csv").await;
+        assert!(response.headers().get(CONTENT_DISPOSITION).is_none());
+    }
+}
@@ -0,0 +1,585 @@
+//! `POST /api/query`: ad-hoc read-only SQL for operators, off unless
+//! `GRAPH_SQL_ENABLED` is set.
+//!
+//! Pooled readers cannot be opened read-only (see [`Db`](crate::db::Db)), so
+//! reads are enforced twice. The text must be a single `SELECT` or `WITH`
+//! query that starts none of the statements which write, change settings or
+//! attach other databases, in a subquery or after a `WITH` list either;
+//! elsewhere such words are only names, such as a column alias `reset`.
+//! A word just inside a parenthesis is taken to start a statement, so a
+//! column named like one is quoted there, as `coalesce("set", 0)`.
+//! String literals, quoted identifiers and comments are skipped while
+//! checking, so they can mention anything. The query then runs inside a
+//! transaction that is always rolled back, so nothing that slips past the
+//! check survives it. Table functions such as `read_csv` can
+//! still read whatever files the server can.
+
+use std::time::Instant;
+
+use axum::extract::State;
+use axum::http::Uri;
+use axum::Json;
+use chrono::{DateTime, NaiveTime};
+use duckdb::arrow::datatypes::{DataType, TimeUnit};
+use duckdb::types::{FromSql, Value, ValueRef};
+use duckdb::{params_from_iter, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{api_not_found, bad_request, internal_error, AppError};
+use crate::models::{Timestamp, TimestampFormat};
+use crate::timeout::{gateway_timeout, Deadline};
+use crate::AppState;
+
+#[derive(Deserialize)]
+pub(crate) struct SqlRequest {
+    sql: String,
+    /// Bound to `?` or `$1` placeholders in order.
+    #[serde(default)]
+    params: Vec<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct SqlRows {
+    columns: Vec<String>,
+    /// One array per row, in column order.
+    rows: Vec<Vec<serde_json::Value>>,
+    /// More rows followed than `GRAPH_SQL_MAX_ROWS` lets through.
+    truncated: bool,
+}
+
+/// Statements a query may not start, even nested inside a `WITH` or a
+/// subquery.
+const FORBIDDEN: &[&str] = &[
+    "ABORT",
+    "ALTER",
+    "ATTACH",
+    "BEGIN",
+    "CALL",
+    "CHECKPOINT",
+    "COMMIT",
+    "COPY",
+    "CREATE",
+    "DEALLOCATE",
+    "DELETE",
+    "DETACH",
+    "DROP",
+    "EXECUTE",
+    "EXPORT",
+    "IMPORT",
+    "INSERT",
+    "INSTALL",
+    "LOAD",
+    "MERGE",
+    "PRAGMA",
+    "PREPARE",
+    "RESET",
+    "ROLLBACK",
+    "SET",
+    "TRUNCATE",
+    "UPDATE",
+    "USE",
+    "VACUUM",
+];
+
+/// Runs one read-only query and returns its rows, at most
+/// `GRAPH_SQL_MAX_ROWS` of them.
+pub(crate) async fn post_query(
+    State(state): State<AppState>,
+    uri: Uri,
+    timestamps: TimestampFormat,
+    deadline: Deadline,
+    Json(request): Json<SqlRequest>,
+) -> Result<Json<SqlRows>, AppError> {
+    if !state.config.sql_enabled {
+        return Err(api_not_found(uri).await);
+    }
+    let sql = read_only(&request.sql)?.to_owned();
+    let params = request
+        .params
+        .into_iter()
+        .enumerate()
+        .map(|(index, param)| bind_value(index, param))
+        .collect::<Result<Vec<_>, _>>()?;
+    let max_rows = state.config.sql_max_rows;
+    let rows = state
+        .db
+        .read(move |conn| {
+            conn.execute_batch("BEGIN TRANSACTION")
+                .map_err(internal_error)?;
+            let rows = run(conn, &sql, params, max_rows, deadline, timestamps);
+            conn.execute_batch("ROLLBACK").map_err(internal_error)?;
+            rows
+        })
+        .await?;
+    Ok(Json(rows))
+}
+
+fn run(
+    conn: &Connection,
+    sql: &str,
+    params: Vec<Value>,
+    max_rows: usize,
+    deadline: Deadline,
+    timestamps: TimestampFormat,
+) -> Result<SqlRows, AppError> {
+    // DuckDB's own message is the useful answer to a bad query.
+    let duckdb = |err: duckdb::Error| AppError::BadRequest(err.to_string());
+    // Not cached: ad-hoc text would only push the endpoints' statements out.
+    let mut stmt = conn.prepare(sql).map_err(duckdb)?;
+    let mut rows = stmt.query(params_from_iter(params)).map_err(duckdb)?;
+    let stmt = rows.as_ref().expect("rows come from a statement");
+    let columns = stmt.column_names();
+    for (index, name) in columns.iter().enumerate() {
+        let data_type = stmt.column_type(index);
+        if !readable(&data_type) {
+            return Err(bad_request(format!(
+                "column {name:?} is {data_type}, which cannot be returned; cast it to VARCHAR"
+            )));
+        }
+    }
+    let mut out = Vec::new();
+    while let Some(row) = rows.next().map_err(duckdb)? {
+        if out.len() == max_rows {
+            return Ok(SqlRows {
+                columns,
+                rows: out,
+                truncated: true,
+            });
+        }
+        if Instant::now() >= deadline.at {
+            return Err(gateway_timeout(deadline.limit));
+        }
+        let values = (0..columns.len())
+            .map(|index| {
+                row.get::<_, Value>(index)
+                    .map(|value| json_value(value, timestamps))
+            })
+            .collect::<Result<_, _>>()
+            .map_err(duckdb)?;
+        out.push(values);
+    }
+    Ok(SqlRows {
+        columns,
+        rows: out,
+        truncated: false,
+    })
+}
+
+/// `sql` without trailing semicolons, if it is one query that only reads.
+fn read_only(sql: &str) -> Result<&str, AppError> {
+    let refuse = |why: String| {
+        bad_request(format!(
+            "only a single SELECT or WITH query is allowed; {why}"
+        ))
+    };
+    let tokens = tokens(sql)?;
+    let mut end = sql.len();
+    if let Some(at) = tokens
+        .iter()
+        .position(|token| matches!(token, Token::Semicolon(_)))
+    {
+        if !tokens[at..]
+            .iter()
+            .all(|token| matches!(token, Token::Semicolon(_)))
+        {
+            return Err(refuse("found more than one statement".to_owned()));
+        }
+        let Token::Semicolon(offset) = tokens[at] else {
+            unreachable!("position matched a semicolon")
+        };
+        end = offset;
+    }
+    let first = tokens.iter().find_map(|token| match token {
+        Token::Word(word) => Some(*word),
+        _ => None,
+    });
+    match first {
+        Some(word) if word.eq_ignore_ascii_case("SELECT") || word.eq_ignore_ascii_case("WITH") => {}
+        Some(word) => return Err(refuse(format!("found {}", word.to_ascii_uppercase()))),
+        None => return Err(refuse("found no statement".to_owned())),
+    }
+    // A statement can start the query, a parenthesis, or follow the list of
+    // a `WITH`, closing its last query; words anywhere else are names.
+    let mut depth = 0usize;
+    let mut starts = true;
+    let mut with_list = false;
+    for token in &tokens {
+        match token {
+            Token::Open => {
+                depth += 1;
+                starts = true;
+            }
+            Token::Close => {
+                depth = depth.saturating_sub(1);
+                starts = with_list && depth == 0;
+            }
+            Token::Word(word) if starts => {
+                if let Some(forbidden) = FORBIDDEN
+                    .iter()
+                    .find(|forbidden| word.eq_ignore_ascii_case(forbidden))
+                {
+                    return Err(refuse(format!(
+                        "found {forbidden} where a statement starts; \
+                         quote a column of that name, as \"{word}\""
+                    )));
+                }
+                if depth == 0 {
+                    with_list = word.eq_ignore_ascii_case("WITH")
+                        || (with_list && word.eq_ignore_ascii_case("AS"));
+                }
+                starts = false;
+            }
+            _ => starts = false,
+        }
+    }
+    Ok(sql[..end].trim())
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    /// A keyword, identifier or number.
+    Word(&'a str),
+    /// The byte offset of a `;`.
+    Semicolon(usize),
+    /// `(`.
+    Open,
+    /// `)`.
+    Close,
+    /// A literal, quoted identifier or other punctuation.
+    Other,
+}
+
+/// Splits `sql` finely enough to tell statements and keywords apart,
+/// following DuckDB's quoting: `'…'`, `E'…'` with backslash escapes,
+/// `"…"`, `$tag$…$tag$`, `-- …` and `/* … */`.
+fn tokens(sql: &str) -> Result<Vec<Token<'_>>, AppError> {
+    let bytes = sql.as_bytes();
+    let word = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_' || !byte.is_ascii();
+    let unterminated = |what: &str| bad_request(format!("unterminated {what} in sql"));
+    let mut tokens = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        let rest = &sql[at..];
+        let byte = bytes[at];
+        at = if byte.is_ascii_whitespace() {
+            at + 1
+        } else if rest.starts_with("--") {
+            rest.find('\n').map_or(bytes.len(), |end| at + end + 1)
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            let end = comment.find("*/").ok_or_else(|| unterminated("comment"))?;
+            at + 2 + end + 2
+        } else if byte == b'\'' || byte == b'"' {
+            tokens.push(Token::Other);
+            let end = rest[1..]
+                .find(byte as char)
+                .ok_or_else(|| unterminated("quote"))?;
+            at + 1 + end + 1
+        } else if byte == b'$' {
+            tokens.push(Token::Other);
+            let tag_len = rest[1..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len() - 1);
+            let tag = &rest[..tag_len + 1];
+            let starts_tag = !tag[1..].starts_with(|c: char| c.is_ascii_digit());
+            if starts_tag && rest[tag.len()..].starts_with('$') {
+                let tag = &rest[..tag.len() + 1];
+                let end = rest[tag.len()..]
+                    .find(tag)
+                    .ok_or_else(|| unterminated("dollar-quoted string"))?;
+                at + tag.len() + end + tag.len()
+            } else {
+                // A `$1` placeholder.
+                at + tag.len()
+            }
+        } else if byte == b';' {
+            tokens.push(Token::Semicolon(at));
+            at + 1
+        } else if byte == b'(' || byte == b')' {
+            tokens.push(if byte == b'(' {
+                Token::Open
+            } else {
+                Token::Close
+            });
+            at + 1
+        } else if word(byte) {
+            let len = rest
+                .bytes()
+                .position(|byte| !word(byte))
+                .unwrap_or(rest.len());
+            if len == 1 && byte.eq_ignore_ascii_case(&b'e') && rest[1..].starts_with('\'') {
+                tokens.push(Token::Other);
+                at + 1 + escaped_string_len(&rest[1..]).ok_or_else(|| unterminated("quote"))?
+            } else {
+                tokens.push(Token::Word(&rest[..len]));
+                at + len
+            }
+        } else {
+            tokens.push(Token::Other);
+            at + rest.chars().next().map_or(1, char::len_utf8)
+        };
+    }
+    Ok(tokens)
+}
+
+/// The length of the `'…'` literal `rest` starts with, in which a backslash
+/// escapes the next character.
+fn escaped_string_len(rest: &str) -> Option<usize> {
+    let mut chars = rest.char_indices().skip(1);
+    while let Some((at, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' => return Some(at + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether values of `data_type` can be read out as plain [`Value`]s.
+fn readable(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Dictionary(key, _) => {
+            matches!(**key, DataType::UInt8 | DataType::UInt16 | DataType::UInt32)
+        }
+        DataType::Time64(unit) => *unit == TimeUnit::Microsecond,
+        _ => matches!(
+            data_type,
+            DataType::Null
+                | DataType::Boolean
+                | DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::UInt8
+                | DataType::UInt16
+                | DataType::UInt32
+                | DataType::UInt64
+                | DataType::Float16
+                | DataType::Float32
+                | DataType::Float64
+                | DataType::Decimal128(..)
+                | DataType::Utf8
+                | DataType::LargeUtf8
+                | DataType::Binary
+                | DataType::LargeBinary
+                | DataType::Timestamp(..)
+                | DataType::Date32
+        ),
+    }
+}
+
+fn bind_value(index: usize, param: serde_json::Value) -> Result<Value, AppError> {
+    Ok(match param {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(value) => Value::Boolean(value),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(value) => Value::BigInt(value),
+            None => Value::Double(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(value) => Value::Text(value),
+        _ => {
+            return Err(bad_request(format!(
+                "params[{index}] must be a string, number, boolean or null"
+            )))
+        }
+    })
+}
+
+fn json_value(value: Value, timestamps: TimestampFormat) -> serde_json::Value {
+    use serde_json::Value as Json;
+    let float = |value: f64| serde_json::Number::from_f64(value).map_or(Json::Null, Json::Number);
+    match value {
+        Value::Null => Json::Null,
+        Value::Boolean(value) => Json::Bool(value),
+        Value::TinyInt(value) => value.into(),
+        Value::SmallInt(value) => value.into(),
+        Value::Int(value) => value.into(),
+        Value::BigInt(value) => value.into(),
+        Value::HugeInt(value) => match i64::try_from(value) {
+            Ok(value) => value.into(),
+            Err(_) => Json::String(value.to_string()),
+        },
+        Value::UTinyInt(value) => value.into(),
+        Value::USmallInt(value) => value.into(),
+        Value::UInt(value) => value.into(),
+        Value::UBigInt(value) => value.into(),
+        Value::Float(value) => float(value.into()),
+        Value::Double(value) => float(value),
+        Value::Decimal(value) => value.to_string().parse().map_or(Json::Null, float),
+        Value::Timestamp(unit, raw) => Timestamp::column_result(ValueRef::Timestamp(unit, raw))
+            .map_or(Json::Null, |at| {
+                Json::String(at.with_format(timestamps).to_string())
+            }),
+        Value::Date32(days) => DateTime::from_timestamp(i64::from(days) * 86_400, 0)
+            .map_or(Json::Null, |at| Json::String(at.date_naive().to_string())),
+        Value::Time64(_, micros) => NaiveTime::from_num_seconds_from_midnight_opt(
+            (micros / 1_000_000) as u32,
+            (micros % 1_000_000) as u32 * 1_000,
+        )
+        .map_or(Json::Null, |time| Json::String(time.to_string())),
+        Value::Text(value) | Value::Enum(value) => Json::String(value),
+        Value::Blob(bytes) => {
+            Json::String(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+        }
+        // Refused by `readable` before any row is read.
+        Value::Interval { .. } | Value::List(_) => Json::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::Router;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::test_support::*;
+    use crate::{build_router, Config};
+
+    const SEED: &str = "('2024-01-01 00:00:00', 1, 2, 1, 2, 10),
+                        ('2024-01-01 00:01:00', 2, 3, 2, 3, 20),
+                        ('2024-01-01 00:02:00', 3, 4, 3, 4, 30)";
+
+    fn app(config: Config) -> Router {
+        let state = seeded_state(SEED);
+        build_router(AppState::new(state.db, config))
+    }
+
+    async fn post(app: &Router, body: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/api/query")
+            .header("content-type", "application/json")
+            .header("x-api-key", TEST_KEY)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn enabled() -> Config {
+        Config {
+            sql_enabled: true,
+            ..keyed_config()
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_are_off_by_default_and_need_a_key() {
+        let body = json!({"sql": "SELECT 1"});
+        let (status, _) = post(&app(keyed_config()), body.clone()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let request = Request::post("/api/query")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app(enabled()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn queries_return_named_columns_and_bound_params() {
+        let app = app(Config {
+            sql_max_rows: 2,
+            ..enabled()
+        });
+        let (status, body) = post(
+            &app,
+            json!({
+                "sql": "SELECT timestamp, close, close > ? AS up, 'x;y' AS note
+                        FROM candles ORDER BY timestamp;",
+                "params": [2.5],
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(
+            body,
+            json!({
+                "columns": ["timestamp", "close", "up", "note"],
+                "rows": [
+                    ["2024-01-01 00:00:00", 2.0, false, "x;y"],
+                    ["2024-01-01 00:01:00", 3.0, true, "x;y"],
+                ],
+                "truncated": true,
+            })
+        );
+
+        let (status, body) = post(
+            &app,
+            json!({"sql": "WITH c AS (SELECT count(*) AS n FROM candles) SELECT n, DATE '2024-01-02' AS d FROM c"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        assert_eq!(body["rows"], json!([[3, "2024-01-02"]]));
+        assert_eq!(body["truncated"], false);
+
+        // DuckDB's own message comes back.
+        let (status, body) = post(&app, json!({"sql": "SELECT nope FROM candles"})).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("Binder Error"),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn only_single_read_only_queries_run() {
+        let app = app(enabled());
+        for sql in [
+            "INSERT INTO candles VALUES ('2024-01-02', 1, 1, 1, 1, 1)",
+            "DROP TABLE candles",
+            "ATTACH 'other.duckdb' AS other",
+            "WITH x AS (SELECT 1) INSERT INTO candles SELECT * FROM candles",
+            "SELECT 1; DROP TABLE candles",
+            "SELECT 1; SELECT 2",
+            "SELECT $$x$$; DELETE FROM candles",
+            "SELECT E'\\''; DROP TABLE candles; --'",
+            "/* SELECT */ PRAGMA version",
+            "WITH x AS (SELECT 1), y AS (SELECT 2) DELETE FROM candles",
+            "SELECT * FROM (DELETE FROM candles RETURNING *)",
+            "",
+        ] {
+            let (status, body) = post(&app, json!({"sql": sql})).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{sql}: {body}");
+            assert!(
+                body["error"]["message"]
+                    .as_str()
+                    .unwrap()
+                    .contains("only a single"),
+                "{sql}: {body}"
+            );
+        }
+        let (status, body) = post(&app, json!({"sql": "SELECT count(*) AS n FROM candles"})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["rows"], json!([[3]]));
+    }
+
+    #[test]
+    fn quoted_text_can_mention_anything() {
+        for sql in [
+            "SELECT 'DROP TABLE candles; --' AS note",
+            "SELECT \"insert\" FROM (SELECT 1 AS \"insert\")",
+            "SELECT $tag$it's; DROP$tag$ -- ; DELETE\n",
+            "SELECT /* ; ATTACH */ 1;;",
+            "SELECT E'it\\'s; DROP'",
+            "SELECT current_setting('threads') AS reset",
+            "SELECT set, count(*) FROM (SELECT 1 AS set) GROUP BY set",
+            "WITH x AS (SELECT 1 AS update) SELECT update AS delete FROM x",
+        ] {
+            assert!(read_only(sql).is_ok(), "{sql}");
+        }
+        assert_eq!(read_only(" SELECT 1 ; ").unwrap(), "SELECT 1");
+        assert!(read_only("SELECT 'open").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Strict mode: refuses to serve candles whose prices contradict each other.
+//!
+//! With `GRAPH_STRICT_OHLC` set, data routes that read the candles table
+//! answer `422 Unprocessable Entity` while any current row (the last-ingested
+//! one of its timestamp) has a `low` above its open or close, or a `high`
+//! below them, instead of feeding it to indicators and charts. The table is
+//! scanned once per data version, so the check costs one query after each
+//! change rather than one per request.
+
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use chrono::NaiveDateTime;
+use duckdb::Connection;
+use tokio::sync::Mutex;
+
+use crate::error::AppError;
+use crate::models::{Timestamp, TIMESTAMP_FORMAT};
+use crate::AppState;
+
+/// Data routes that never read the candles table.
+const UNCHECKED: &[&str] = &[
+    "/api/events",
+    "/api/symbols",
+    "/api/spread",
+    "/api/continuous",
+];
+
+/// Current candles breaking the OHLC invariants, and the earliest of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Violations {
+    pub(crate) count: i64,
+    pub(crate) first: NaiveDateTime,
+}
+
+/// The last scan and the data version it was made at.
+#[derive(Default)]
+pub(crate) struct OhlcCheck {
+    /// Held across the scan, so requests arriving after a change share one.
+    last: Mutex<Option<(u64, Option<Violations>)>>,
+}
+
+pub(crate) async fn enforce_ohlc(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.config.strict_ohlc || UNCHECKED.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+    let mut last = state.ohlc.last.lock().await;
+    let version = state.bus.data_version();
+    let violations = match *last {
+        Some((checked, violations)) if checked == version => violations,
+        _ => match state.db.read(violations).await {
+            Ok(violations) => {
+                if let Some(found) = violations {
+                    tracing::warn!(
+                        "strict mode: {} candles violate the OHLC invariants, first at {}",
+                        found.count,
+                        found.first
+                    );
+                }
+                *last = Some((version, violations));
+                violations
+            }
+            Err(err) => return AppError::from(err).into_response(),
+        },
+    };
+    drop(last);
+    match violations {
+        None => next.run(request).await,
+        Some(found) => AppError::Unprocessable(format!(
+            "{} candles have a low above their open or close or a high below them, the first \
+             at {}; strict mode refuses to serve them until they are corrected",
+            found.count,
+            found.first.format(TIMESTAMP_FORMAT)
+        ))
+        .into_response(),
+    }
+}
+
+/// Scans the current candles for `low <= min(open, close)` and
+/// `max(open, close) <= high`, which together also mean `low <= high`.
+/// Rows with a missing price are left alone.
+pub(crate) fn violations(conn: &Connection) -> duckdb::Result<Option<Violations>> {
+    let (count, first) = conn
+        .prepare_cached(
+            "SELECT count(*), min(timestamp)
+             FROM (
+                SELECT * FROM candles
+                QUALIFY row_number() OVER (PARTITION BY timestamp ORDER BY rowid DESC) = 1
+             )
+             WHERE NOT (low <= least(open, close) AND greatest(open, close) <= high)",
+        )?
+        .query_row([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Option<Timestamp>>(1)?))
+        })?;
+    Ok(first.map(|first| Violations {
+        count,
+        first: first.at,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::bus::DataEvent;
+    use crate::test_support::*;
+    use crate::{build_router, Config};
+
+    #[tokio::test]
+    async fn strict_mode_refuses_candles_that_break_the_invariants() {
+        let state = seeded_state(
+            "('2024-01-01 00:00:00', 10, 12, 9, 11, 1),
+             ('2024-01-01 00:01:00', 10, 12, 10.5, 11, 1),
+             ('2024-01-01 00:02:00', 10, 12, 9, 11, 1)",
+        );
+        let config = Config {
+            strict_ohlc: true,
+            ..Config::default()
+        };
+        let strict_state = AppState::new(state.db.clone(), config);
+        let strict = build_router(strict_state.clone());
+        let lenient = build_router(AppState::new(state.db.clone(), Config::default()));
+
+        assert_eq!(
+            get_json(&lenient, "/api/candles")
+                .await
+                .as_array()
+                .unwrap()
+                .len(),
+            3
+        );
+        for uri in ["/api/candles", "/api/indicators"] {
+            let response = get_uri(&strict, uri).await;
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY, "{uri}");
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            let message = error["error"]["message"].as_str().unwrap();
+            assert!(message.starts_with("1 candles"), "{message}");
+            assert!(message.contains("2024-01-01 00:01:00"), "{message}");
+        }
+        // No events table here, but the check lets the request through to
+        // find that out.
+        let response = get_uri(&strict, "/api/events").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // A corrected row for the same timestamp supersedes the bad one.
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO candles VALUES ('2024-01-01 00:01:00', 10, 12, 9.5, 11, 1)",
+                )
+            })
+            .await
+            .unwrap();
+        assert_eq!(state.db.read(violations).await.unwrap(), None);
+        // The scan is redone only once the data version moves on.
+        let response = get_uri(&strict, "/api/candles").await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        strict_state.bus.publish([DataEvent::Reloaded]);
+        get_json(&strict, "/api/candles").await;
+    }
+}
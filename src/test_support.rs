@@ -0,0 +1,115 @@
+//! Fixtures shared by the unit tests in every module.
+
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{HeaderName, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use duckdb::Connection;
+use futures_util::{Stream, StreamExt};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+use tower::ServiceExt;
+
+use crate::config::{ApiKey, CsvMode};
+use crate::db::{initialize_db, migrate};
+use crate::{build_router, AppState, Config, Db};
+
+pub(crate) fn memory_db(read_pool_size: usize) -> Arc<Db> {
+    Arc::new(Db::new(Connection::open_in_memory().unwrap(), read_pool_size).unwrap())
+}
+
+/// Checks out a reader and parks on it until the returned sender fires.
+pub(crate) async fn hold_reader(db: &Arc<Db>) -> (mpsc::Sender<()>, tokio::task::JoinHandle<()>) {
+    let (release, parked) = mpsc::channel::<()>();
+    let (started_tx, started) = tokio::sync::oneshot::channel();
+    let db = Arc::clone(db);
+    let handle = tokio::spawn(async move {
+        db.read(move |_conn| {
+            started_tx.send(()).unwrap();
+            parked.recv().ok();
+        })
+        .await
+    });
+    started.await.unwrap();
+    (release, handle)
+}
+
+pub(crate) fn seeded_state(rows: &str) -> AppState {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(&format!(
+        "CREATE TABLE candles (
+            timestamp TIMESTAMP, open DOUBLE, high DOUBLE,
+            low DOUBLE, close DOUBLE, volume DOUBLE
+        );
+        INSERT INTO candles VALUES {rows};"
+    ))
+    .unwrap();
+    migrate(&conn).unwrap();
+    AppState::new(Arc::new(Db::new(conn, 2).unwrap()), Config::default())
+}
+
+/// Accepted as an API key by routers built with [`keyed_config`].
+pub(crate) const TEST_KEY: &str = "test-key";
+
+pub(crate) fn keyed_config() -> Config {
+    Config {
+        api_keys: vec![ApiKey::new("test", TEST_KEY)],
+        ..Config::default()
+    }
+}
+
+pub(crate) async fn get_json(app: &Router, uri: &str) -> serde_json::Value {
+    json_body(get_uri(app, uri).await, uri).await
+}
+
+/// [`get_json`] for the admin routes, sending [`TEST_KEY`].
+pub(crate) async fn admin_json(app: &Router, uri: &str) -> serde_json::Value {
+    let key = [(HeaderName::from_static("x-api-key"), TEST_KEY)];
+    json_body(get_with(app, uri, &key).await, uri).await
+}
+
+async fn json_body(response: Response, uri: &str) -> serde_json::Value {
+    assert_eq!(response.status(), StatusCode::OK, "GET {uri}");
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+pub(crate) async fn get_uri(app: &Router, uri: &str) -> Response {
+    app.clone()
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+pub(crate) async fn get_with(app: &Router, uri: &str, headers: &[(HeaderName, &str)]) -> Response {
+    let mut request = Request::get(uri);
+    for (name, value) in headers {
+        request = request.header(name, *value);
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+pub(crate) fn stocks_app(config: Config) -> Router {
+    let conn = Connection::open_in_memory().unwrap();
+    initialize_db(&conn, Path::new("data/stocks.csv"), CsvMode::Lenient).unwrap();
+    build_router(AppState::new(Arc::new(Db::new(conn, 1).unwrap()), config))
+}
+
+/// The next message on a WebSocket, which must be JSON text.
+pub(crate) async fn next_json(
+    socket: &mut (impl Stream<Item = Result<WsMessage, WsError>> + Unpin),
+) -> serde_json::Value {
+    match socket.next().await.unwrap().unwrap() {
+        WsMessage::Text(text) => serde_json::from_str(&text).unwrap(),
+        other => panic!("expected text, got {other:?}"),
+    }
+}
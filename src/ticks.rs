@@ -0,0 +1,685 @@
+//! Builds candles from the trades posted to `/api/ticks`.
+//!
+//! Each series (the main candles, or a named symbol's) keeps the bar its
+//! ticks are still forming in memory. A tick in a later interval writes that
+//! bar and starts the next; so does the clock, once an interval has been
+//! over for `GRAPH_TICK_GRACE_MS`, so a quiet feed still gets its last bar
+//! stored. The last bar written stays in memory too: a tick that arrives out
+//! of order for it, while the newest tick seen is still within the grace
+//! period of its close, corrects it with an upsert. Ticks older than that are
+//! refused and counted. Forming bars of the main series go to streaming
+//! clients as they change; the hub's poller sends them again once stored.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, TimeDelta};
+use duckdb::{params, Connection};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tokio::time::MissedTickBehavior;
+
+use crate::db::Db;
+use crate::handlers::candle_from_row;
+use crate::hub::{latest_candle, Hub};
+use crate::models::{Candle, QuoteValues, Timestamp, Volume};
+
+/// One trade. Without a symbol it belongs to the main candles table.
+pub(crate) struct Tick {
+    pub(crate) symbol: Option<String>,
+    pub(crate) at: NaiveDateTime,
+    pub(crate) price: f64,
+    pub(crate) size: f64,
+}
+
+/// What a batch of ticks did.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct TickReport {
+    pub(crate) accepted: usize,
+    /// Ticks older than any bar that can still take them.
+    pub(crate) rejected: usize,
+    /// Bars stored or corrected in the table.
+    pub(crate) written: usize,
+}
+
+/// A bar and the times of the ticks behind its open and close, so ticks that
+/// arrive out of order still leave the earliest price as the open and the
+/// latest as the close.
+#[derive(Clone)]
+struct Bar {
+    start: NaiveDateTime,
+    open: (NaiveDateTime, f64),
+    high: f64,
+    low: f64,
+    close: (NaiveDateTime, f64),
+    volume: f64,
+}
+
+impl Bar {
+    fn new(start: NaiveDateTime, tick: &Tick) -> Self {
+        Self {
+            start,
+            open: (tick.at, tick.price),
+            high: tick.price,
+            low: tick.price,
+            close: (tick.at, tick.price),
+            volume: tick.size,
+        }
+    }
+
+    /// A stored candle as a bar: its trades' times are unknown, so the next
+    /// tick in it sets the close and none can set the open.
+    fn stored(candle: &Candle) -> Self {
+        let at = candle.timestamp.at;
+        Self {
+            start: at,
+            open: (at, candle.open),
+            high: candle.high,
+            low: candle.low,
+            close: (at, candle.close),
+            volume: candle.volume.value,
+        }
+    }
+
+    fn add(&mut self, tick: &Tick) {
+        if tick.at < self.open.0 {
+            self.open = (tick.at, tick.price);
+        }
+        if tick.at >= self.close.0 {
+            self.close = (tick.at, tick.price);
+        }
+        self.high = self.high.max(tick.price);
+        self.low = self.low.min(tick.price);
+        self.volume += tick.size;
+    }
+
+    fn candle(&self) -> Candle {
+        Candle {
+            timestamp: Timestamp::new(self.start),
+            open: self.open.1,
+            high: self.high,
+            low: self.low,
+            close: self.close.1,
+            volume: Volume::new(self.volume),
+            events: None,
+            quotes: QuoteValues::default(),
+        }
+    }
+}
+
+/// One series' bars in memory.
+#[derive(Clone, Default)]
+struct Series {
+    /// Still collecting ticks; not stored yet.
+    forming: Option<Bar>,
+    /// The last bar stored, which late ticks may still correct.
+    finalized: Option<Bar>,
+    /// The newest tick accepted.
+    newest: Option<NaiveDateTime>,
+}
+
+enum Added {
+    Forming,
+    /// A later interval began, storing the bar before it if there was one.
+    Rolled(Option<Bar>),
+    Corrected(Bar),
+    Rejected,
+}
+
+impl Series {
+    fn add(&mut self, tick: &Tick, interval: TimeDelta, grace: TimeDelta) -> Added {
+        let start = bucket_start(tick.at, interval);
+        let newest = self.newest.unwrap_or(tick.at).max(tick.at);
+        if let Some(bar) = self.forming.as_mut().filter(|bar| bar.start == start) {
+            bar.add(tick);
+            self.newest = Some(newest);
+            return Added::Forming;
+        }
+        let latest = self.forming.as_ref().or(self.finalized.as_ref());
+        if latest.is_none_or(|bar| start > bar.start) {
+            let done = self.forming.replace(Bar::new(start, tick));
+            if let Some(done) = &done {
+                self.finalized = Some(done.clone());
+            }
+            self.newest = Some(newest);
+            return Added::Rolled(done);
+        }
+        match self.finalized.as_mut() {
+            Some(bar) if bar.start == start && newest < start + interval + grace => {
+                bar.add(tick);
+                self.newest = Some(newest);
+                Added::Corrected(bar.clone())
+            }
+            _ => Added::Rejected,
+        }
+    }
+}
+
+/// The in-memory bars of every series ticks have been posted for.
+pub(crate) struct TickAggregator {
+    interval: TimeDelta,
+    grace: TimeDelta,
+    /// Held across the writes, so batches and flushes apply one at a time.
+    series: Mutex<HashMap<Option<String>, Series>>,
+}
+
+impl TickAggregator {
+    pub(crate) fn new(interval: Duration, grace: Duration) -> Self {
+        let delta = |duration: Duration| TimeDelta::from_std(duration).unwrap_or(TimeDelta::MAX);
+        Self {
+            interval: delta(interval).max(TimeDelta::milliseconds(1)),
+            grace: delta(grace),
+            series: Mutex::default(),
+        }
+    }
+
+    /// Folds `ticks` into their bars in order and stores every bar that
+    /// rolled over or was corrected, all in one transaction. If storing
+    /// fails, the bars are left as they were before the batch.
+    pub(crate) async fn ingest(
+        &self,
+        db: &Arc<Db>,
+        hub: &Hub,
+        ticks: Vec<Tick>,
+    ) -> duckdb::Result<TickReport> {
+        let mut all = self.series.lock().await;
+        let mut touched: HashMap<Option<String>, Series> = HashMap::new();
+        let mut writes = BTreeMap::new();
+        let mut report = TickReport {
+            accepted: 0,
+            rejected: 0,
+            written: 0,
+        };
+        for tick in &ticks {
+            if !touched.contains_key(&tick.symbol) {
+                let series = match all.get(&tick.symbol) {
+                    Some(series) => series.clone(),
+                    None => {
+                        let symbol = tick.symbol.clone();
+                        let stored = db.read(move |conn| latest_stored(conn, symbol.as_deref()));
+                        Series {
+                            finalized: stored.await?.as_ref().map(Bar::stored),
+                            ..Series::default()
+                        }
+                    }
+                };
+                touched.insert(tick.symbol.clone(), series);
+            }
+            let series = touched.get_mut(&tick.symbol).expect("inserted above");
+            match series.add(tick, self.interval, self.grace) {
+                Added::Rejected => {
+                    report.rejected += 1;
+                    continue;
+                }
+                Added::Forming | Added::Rolled(None) => {}
+                Added::Rolled(Some(bar)) | Added::Corrected(bar) => {
+                    writes.insert((tick.symbol.clone(), bar.start), bar);
+                }
+            }
+            report.accepted += 1;
+        }
+        report.written = writes.len();
+        let stored: Vec<_> = writes.into_iter().collect();
+        if !stored.is_empty() {
+            store(db, hub, stored).await?;
+        }
+        if let Some(bar) = touched
+            .get(&None)
+            .and_then(|series| series.forming.as_ref())
+        {
+            hub.push(bar.candle());
+        }
+        all.extend(touched);
+        Ok(report)
+    }
+
+    /// Stores every forming bar whose interval ended at least the grace
+    /// period before `now`, returning how many it stored.
+    pub(crate) async fn flush(
+        &self,
+        db: &Db,
+        hub: &Hub,
+        now: NaiveDateTime,
+    ) -> duckdb::Result<usize> {
+        let mut all = self.series.lock().await;
+        let due = |bar: &Bar| {
+            bar.start
+                .checked_add_signed(self.interval + self.grace)
+                .is_some_and(|closed| closed <= now)
+        };
+        let rows: Vec<_> = all
+            .iter()
+            .filter_map(|(symbol, series)| {
+                let bar = series.forming.as_ref().filter(|bar| due(bar))?;
+                Some(((symbol.clone(), bar.start), bar.clone()))
+            })
+            .collect();
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        let count = rows.len();
+        store(db, hub, rows.clone()).await?;
+        for ((symbol, _), bar) in rows {
+            let series = all.get_mut(&symbol).expect("flushed from this map");
+            series.forming = None;
+            series.finalized = Some(bar);
+        }
+        Ok(count)
+    }
+}
+
+/// Flushes quiet series' bars every `every` until the process exits.
+pub(crate) async fn flush_idle(
+    aggregator: Arc<TickAggregator>,
+    db: Arc<Db>,
+    hub: Hub,
+    every: Duration,
+) {
+    let mut ticker = tokio::time::interval(every);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let now = chrono::Utc::now().naive_utc();
+        if let Err(err) = aggregator.flush(&db, &hub, now).await {
+            tracing::warn!("storing idle tick bars failed: {err}");
+        }
+    }
+}
+
+/// The start of the `interval` bucket holding `at`, counted from the epoch.
+fn bucket_start(at: NaiveDateTime, interval: TimeDelta) -> NaiveDateTime {
+    let micros = at.and_utc().timestamp_micros();
+    let step = interval.num_microseconds().unwrap_or(i64::MAX);
+    DateTime::from_timestamp_micros(micros - micros.rem_euclid(step))
+        .expect("a bucket starts no later than its ticks")
+        .naive_utc()
+}
+
+/// The current row of a series' newest timestamp.
+fn latest_stored(conn: &Connection, symbol: Option<&str>) -> duckdb::Result<Option<Candle>> {
+    let Some(symbol) = symbol else {
+        return latest_candle(conn);
+    };
+    conn.prepare(
+        "SELECT timestamp, open, high, low, close, volume
+         FROM symbol_candles
+         WHERE symbol = ?
+         ORDER BY timestamp DESC, rowid DESC
+         LIMIT 1",
+    )?
+    .query_map([symbol], candle_from_row)?
+    .next()
+    .transpose()
+}
+
+/// Writes `bars` and publishes them, a series at a time.
+async fn store(
+    db: &Db,
+    hub: &Hub,
+    bars: Vec<((Option<String>, NaiveDateTime), Bar)>,
+) -> duckdb::Result<()> {
+    let mut watermark = hub.hold_watermark().await;
+    let replaced = db
+        .write({
+            let bars = bars.clone();
+            move |conn| upsert(conn, &bars)
+        })
+        .await?;
+    let mut by_symbol: BTreeMap<Option<String>, Vec<(Candle, bool)>> = BTreeMap::new();
+    for (((symbol, _), bar), replaced) in bars.into_iter().zip(replaced) {
+        by_symbol
+            .entry(symbol)
+            .or_default()
+            .push((bar.candle(), replaced));
+    }
+    for (symbol, candles) in by_symbol {
+        hub.stored(&mut watermark, symbol.as_deref(), candles);
+    }
+    Ok(())
+}
+
+/// Replaces each bar's rows, in one transaction, reporting for each whether
+/// it had any.
+fn upsert(
+    conn: &Connection,
+    bars: &[((Option<String>, NaiveDateTime), Bar)],
+) -> duckdb::Result<Vec<bool>> {
+    let tx = conn.unchecked_transaction()?;
+    let mut replaced = Vec::with_capacity(bars.len());
+    for ((symbol, _), bar) in bars {
+        let candle = bar.candle();
+        let values = params![
+            candle.timestamp,
+            candle.open,
+            candle.high,
+            candle.low,
+            candle.close,
+            candle.volume.value
+        ];
+        match symbol {
+            None => {
+                let deleted = tx.execute(
+                    "DELETE FROM candles WHERE timestamp = ?",
+                    [candle.timestamp],
+                )?;
+                replaced.push(deleted > 0);
+                tx.execute(
+                    "INSERT INTO candles (timestamp, open, high, low, close, volume)
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                    values,
+                )?;
+            }
+            Some(symbol) => {
+                let deleted = tx.execute(
+                    "DELETE FROM symbol_candles WHERE symbol = ? AND timestamp = ?",
+                    params![symbol, candle.timestamp],
+                )?;
+                replaced.push(deleted > 0);
+                tx.execute(
+                    "INSERT INTO symbol_candles (symbol, timestamp, open, high, low, close, volume)
+                     VALUES (?, ?, ?, ?, ?, ?, ?)",
+                    params![
+                        symbol,
+                        candle.timestamp,
+                        candle.open,
+                        candle.high,
+                        candle.low,
+                        candle.close,
+                        candle.volume.value
+                    ],
+                )?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::bus::DataEvent;
+    use crate::hub::Update;
+    use crate::test_support::*;
+    use crate::{build_router, AppState};
+
+    fn tick(symbol: Option<&str>, at: &str, price: f64, size: f64) -> Tick {
+        Tick {
+            symbol: symbol.map(str::to_owned),
+            at: NaiveDateTime::parse_from_str(at, "%Y-%m-%d %H:%M:%S%.f").unwrap(),
+            price,
+            size,
+        }
+    }
+
+    fn minute_bars() -> TickAggregator {
+        TickAggregator::new(Duration::from_secs(60), Duration::from_secs(2))
+    }
+
+    async fn stored(db: &Arc<Db>, table: &'static str) -> Vec<(String, f64, f64, f64, f64, f64)> {
+        db.read(move |conn| {
+            conn.prepare(&format!(
+                "SELECT strftime(timestamp, '%H:%M:%S'), open, high, low, close, volume
+                 FROM {table} ORDER BY timestamp, rowid"
+            ))?
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })?
+            .collect::<duckdb::Result<Vec<_>>>()
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn ticks_roll_into_bars_at_interval_boundaries() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let ticks = minute_bars();
+        let report = ticks
+            .ingest(
+                &state.db,
+                &state.hub,
+                vec![
+                    tick(None, "2024-01-01 00:01:00", 10.0, 1.0),
+                    // Out of order within the bar: the earliest tick opens it.
+                    tick(None, "2024-01-01 00:01:30", 12.0, 1.0),
+                    tick(None, "2024-01-01 00:01:10", 8.0, 2.0),
+                    tick(None, "2024-01-01 00:00:59.999", 9.0, 1.0),
+                ],
+            )
+            .await
+            .unwrap();
+        // The last tick lands in the stored bar, but the newest tick is already
+        // past its grace period.
+        assert_eq!(
+            report,
+            TickReport {
+                accepted: 3,
+                rejected: 1,
+                written: 0
+            }
+        );
+
+        let report = ticks
+            .ingest(
+                &state.db,
+                &state.hub,
+                vec![
+                    tick(None, "2024-01-01 00:01:59.999", 11.0, 1.0),
+                    tick(None, "2024-01-01 00:02:00", 13.0, 1.0),
+                    // Within the grace period of the bar just stored.
+                    tick(None, "2024-01-01 00:01:20", 14.0, 0.5),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.accepted, 3);
+        assert_eq!(report.written, 1);
+        assert_eq!(
+            stored(&state.db, "candles").await,
+            [
+                ("00:00:00".into(), 1.0, 1.0, 1.0, 1.0, 1.0),
+                ("00:01:00".into(), 10.0, 14.0, 8.0, 11.0, 5.5),
+            ]
+        );
+
+        // A tick further on stores the forming bar; the corrected one is now
+        // out of reach.
+        let report = ticks
+            .ingest(
+                &state.db,
+                &state.hub,
+                vec![
+                    tick(None, "2024-01-01 00:03:05", 15.0, 1.0),
+                    tick(None, "2024-01-01 00:01:40", 1.0, 1.0),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            TickReport {
+                accepted: 1,
+                rejected: 1,
+                written: 1
+            }
+        );
+        let candles = stored(&state.db, "candles").await;
+        assert_eq!(candles[2], ("00:02:00".into(), 13.0, 13.0, 13.0, 13.0, 1.0));
+        assert_eq!(candles.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn the_latest_stored_candle_takes_late_ticks_after_a_restart() {
+        let state = seeded_state("('2024-01-01 00:01:00', 10, 12, 8, 11, 5)");
+        let ticks = minute_bars();
+        let report = ticks
+            .ingest(
+                &state.db,
+                &state.hub,
+                vec![
+                    tick(None, "2024-01-01 00:00:30", 1.0, 1.0),
+                    tick(None, "2024-01-01 00:01:50", 13.0, 1.0),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.rejected, 1);
+        assert_eq!(report.written, 1);
+        assert_eq!(
+            stored(&state.db, "candles").await,
+            [("00:01:00".into(), 10.0, 13.0, 8.0, 13.0, 6.0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn symbols_build_their_own_bars_and_quiet_ones_are_flushed() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        state
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "CREATE TABLE symbol_candles (
+                        symbol VARCHAR, timestamp TIMESTAMP, open DOUBLE, high DOUBLE,
+                        low DOUBLE, close DOUBLE, volume DOUBLE
+                    )",
+                )
+            })
+            .await
+            .unwrap();
+        let ticks = minute_bars();
+        let mut updates = state.hub.subscribe();
+        let mut events = state.bus.subscribe();
+        let at = |text| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap();
+        let appended = |symbol: Option<&str>, minute| DataEvent::CandlesAppended {
+            symbol: symbol.map(str::to_owned),
+            from: at(minute),
+            to: at(minute),
+            count: 1,
+        };
+        ticks
+            .ingest(
+                &state.db,
+                &state.hub,
+                vec![
+                    tick(Some("ES"), "2024-01-01 00:01:00", 4000.0, 1.0),
+                    tick(None, "2024-01-01 00:01:00", 2.0, 1.0),
+                    tick(Some("ES"), "2024-01-01 00:02:00", 4001.0, 1.0),
+                    tick(None, "2024-01-01 00:01:30", 3.0, 1.0),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            stored(&state.db, "symbol_candles").await,
+            [("00:01:00".into(), 4000.0, 4000.0, 4000.0, 4000.0, 1.0)]
+        );
+        // Only the main series' forming bar is streamed, once per batch.
+        let pushed = updates.try_recv().unwrap();
+        let Update::Candle(candle) = pushed.update else {
+            panic!("expected a candle");
+        };
+        assert_eq!(
+            (candle.open, candle.close, candle.volume.value),
+            (2.0, 3.0, 2.0)
+        );
+        assert!(updates.try_recv().is_err());
+        assert_eq!(
+            events.try_recv().unwrap(),
+            appended(Some("ES"), "2024-01-01 00:01:00")
+        );
+
+        let version = state.bus.data_version();
+        let flush = |now| ticks.flush(&state.db, &state.hub, at(now));
+        assert_eq!(flush("2024-01-01 00:02:01").await.unwrap(), 0);
+        assert_eq!(flush("2024-01-01 00:02:02").await.unwrap(), 1);
+        assert_eq!(stored(&state.db, "candles").await.len(), 2);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            appended(None, "2024-01-01 00:01:00")
+        );
+        assert_eq!(flush("2024-01-01 00:03:02").await.unwrap(), 1);
+        assert_eq!(stored(&state.db, "symbol_candles").await.len(), 2);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            appended(Some("ES"), "2024-01-01 00:02:00")
+        );
+        assert!(events.try_recv().is_err());
+        assert!(state.bus.data_version() > version);
+        // The poller does not report the stored bars again.
+        assert_eq!(state.hub.poll(&state.db).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn posted_ticks_are_validated_whole() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = build_router(AppState::new(state.db, keyed_config()));
+        let post = |body: &'static str, key: Option<&'static str>| {
+            let app = app.clone();
+            async move {
+                let mut request =
+                    Request::post("/api/ticks").header("content-type", "application/json");
+                if let Some(key) = key {
+                    request = request.header("x-api-key", key);
+                }
+                let response = app
+                    .oneshot(request.body(Body::from(body)).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+
+        let (status, _) = post("[]", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, error) = post(
+            r#"[{"timestamp": "2024-01-01 00:01:00", "price": 2},
+                {"timestamp": 1704067260.5, "price": -1, "size": -1}]"#,
+            Some(TEST_KEY),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            error["error"]["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("ticks[1].size"),
+            "{error}"
+        );
+        let (status, report) = post(
+            r#"[{"timestamp": "2024-01-01 00:01:00", "price": 2, "size": 1},
+                {"timestamp": 1704067260.5, "price": 3},
+                {"timestamp": 1704067320000, "price": 4},
+                {"timestamp": "2023-12-31", "price": 5}]"#,
+            Some(TEST_KEY),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            report,
+            serde_json::json!({"accepted": 3, "rejected": 1, "written": 1})
+        );
+        let candles = get_json(&app, "/api/candles").await;
+        assert_eq!(candles[1]["timestamp"], "2024-01-01 00:01:00");
+        assert_eq!(candles[1]["close"], 3.0);
+    }
+}
@@ -0,0 +1,193 @@
+//! Per-route time limits on data requests, which a client can shorten for
+//! itself with `X-Request-Timeout-Ms`.
+//!
+//! duckdb-rs does not expose `duckdb_interrupt`, so a statement that is
+//! already executing cannot be stopped from outside. Dropping the handler
+//! future answers the client; paged reads such as candle exports also check
+//! their deadline between pages and rows, which is what hands their pooled
+//! reader back early. They find their deadline in the request's
+//! [`Deadline`] extension.
+
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::error::{bad_request, internal_error, AppError};
+
+/// Milliseconds the caller will wait, e.g. the rest of a gateway's budget.
+/// It can only shorten a route's limit, never extend it.
+pub(crate) const REQUEST_TIMEOUT: HeaderName = HeaderName::from_static("x-request-timeout-ms");
+
+/// When the request's time runs out, and the limit that set it.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Deadline {
+    pub(crate) at: Instant,
+    pub(crate) limit: Duration,
+}
+
+impl Deadline {
+    /// The time left, zero once the deadline has passed.
+    pub(crate) fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Only routes behind [`enforce_timeout`] have a deadline to extract.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Deadline {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Deadline>()
+            .copied()
+            .ok_or_else(|| internal_error("route has no time limit"))
+    }
+}
+
+/// The route's `limit`, or the caller's `X-Request-Timeout-Ms` if shorter.
+fn requested_limit(headers: &HeaderMap, limit: Duration) -> Result<Duration, AppError> {
+    let Some(value) = headers.get(&REQUEST_TIMEOUT) else {
+        return Ok(limit);
+    };
+    let millis = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .filter(|millis| *millis > 0)
+        .ok_or_else(|| {
+            bad_request("X-Request-Timeout-Ms must be a whole number of milliseconds, at least 1")
+        })?;
+    Ok(limit.min(Duration::from_millis(millis)))
+}
+
+pub(crate) async fn enforce_timeout(
+    State(limit): State<Duration>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let limit = match requested_limit(request.headers(), limit) {
+        Ok(limit) => limit,
+        Err(err) => return err.into_response(),
+    };
+    request.extensions_mut().insert(Deadline {
+        at: Instant::now() + limit,
+        limit,
+    });
+    let path = request.uri().path().to_owned();
+    match tokio::time::timeout(limit, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            tracing::warn!("{path} exceeded its {limit:?} time limit");
+            gateway_timeout(limit).into_response()
+        }
+    }
+}
+
+pub(crate) fn gateway_timeout(limit: Duration) -> AppError {
+    AppError::Timeout(format!("query exceeded the {limit:?} time limit"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use axum::http::StatusCode;
+    use duckdb::Connection;
+
+    use super::*;
+    use crate::test_support::*;
+    use crate::{build_router, AppState, Config, Db};
+
+    /// A single pooled reader over enough candles that a full export takes
+    /// many pages.
+    fn one_reader_app(config: Config) -> axum::Router {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE candles AS
+             SELECT TIMESTAMP '2020-01-01' + to_minutes(i) AS timestamp,
+                    1.0 AS open, 1.0 AS high, 1.0 AS low, 1.0 AS close, 1.0 AS volume
+             FROM range(300000) t(i);",
+        )
+        .unwrap();
+        crate::db::migrate(&conn).unwrap();
+        build_router(AppState::new(Arc::new(Db::new(conn, 1).unwrap()), config))
+    }
+
+    #[tokio::test]
+    async fn slow_requests_get_504_and_free_their_reader() {
+        let app = one_reader_app(Config {
+            export_timeout: Duration::from_millis(1),
+            ..Config::default()
+        });
+        // `project` buffers the whole series, checking the deadline per row.
+        let response = get_uri(&app, "/api/candles?limit=300000&project=1").await;
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("time limit"));
+
+        let next = tokio::time::timeout(Duration::from_secs(5), get_uri(&app, "/healthz"));
+        assert_eq!(next.await.unwrap().status(), StatusCode::OK);
+        let next = get_uri(&app, "/api/fib?start=2020-01-01&end=2020-01-02");
+        let next = tokio::time::timeout(Duration::from_secs(5), next).await;
+        assert_eq!(next.expect("reader was freed").status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn callers_can_shorten_but_not_extend_the_limit() {
+        let app = one_reader_app(Config {
+            export_timeout: Duration::from_secs(60),
+            ..Config::default()
+        });
+        let uri = "/api/candles?limit=300000&project=1";
+        let response = get_with(&app, uri, &[(REQUEST_TIMEOUT, "1")]).await;
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("1ms"));
+
+        for bad in ["0", "soon", "-5"] {
+            let response = get_with(&app, uri, &[(REQUEST_TIMEOUT, bad)]).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{bad}");
+        }
+
+        let limit = requested_limit(
+            &[(REQUEST_TIMEOUT, "120000".parse().unwrap())]
+                .into_iter()
+                .collect(),
+            Duration::from_secs(60),
+        );
+        assert_eq!(limit.unwrap(), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn stalled_exports_give_up_their_reader_at_the_deadline() {
+        let app = one_reader_app(Config {
+            export_timeout: Duration::from_millis(200),
+            ..Config::default()
+        });
+        // Never read: the export fills its channel and blocks on the client.
+        let stalled = get_uri(&app, "/api/candles?limit=300000").await;
+        assert_eq!(stalled.status(), StatusCode::OK);
+
+        let started = Instant::now();
+        let next = get_uri(&app, "/api/fib?start=2020-01-01&end=2020-01-02");
+        let next = tokio::time::timeout(Duration::from_secs(5), next).await;
+        assert_eq!(next.expect("reader was freed").status(), StatusCode::OK);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        // The abandoned body ends in an error rather than looking complete.
+        let body = axum::body::to_bytes(stalled.into_body(), usize::MAX).await;
+        assert!(body.is_err());
+    }
+}
@@ -0,0 +1,685 @@
+//! A TradingView UDF datafeed under `/udf`, so the Charting Library's
+//! `UDFCompatibleDatafeed` can chart the stored candles directly.
+//!
+//! The main candles table is listed as `Config::udf.symbol`, beside the
+//! symbols in `symbol_candles`. Resolutions map onto the `/api/candles`
+//! timeframes: minutes as a bare count (`1`, `5`, `60`), and `D`, `W` and
+//! `M` for days and calendar weeks and months. Times are Unix seconds, and
+//! each bar is stamped with the start of its bucket. Failures are answered
+//! the way UDF clients read them, `{"s": "error", "errmsg": "..."}`, with the
+//! usual status.
+
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use duckdb::{params_from_iter, ToSql};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{bad_request, AppError};
+use crate::handlers::{check_range, parse_origin, Timeframe};
+use crate::models::Timestamp;
+use crate::negotiate::{DataResponse, ResponseFormat};
+use crate::AppState;
+
+/// Resolutions offered to the chart's picker; others that parse are served
+/// too.
+const SUPPORTED_RESOLUTIONS: &[&str] = &["1", "5", "15", "30", "60", "240", "D", "W", "M"];
+
+/// Most bars one `/udf/history` answer holds, and the cap on `countback`.
+/// A wider range keeps its newest bars; the chart asks again for the rest.
+pub(crate) const MAX_HISTORY_BARS: u32 = 10_000;
+
+/// Symbols `/udf/search` returns when the request sets no `limit`.
+const SEARCH_LIMIT: usize = 30;
+
+/// An [`AppError`] in the shape UDF clients expect.
+pub(crate) struct UdfError(AppError);
+
+impl<E: Into<AppError>> From<E> for UdfError {
+    fn from(err: E) -> Self {
+        Self(err.into())
+    }
+}
+
+impl IntoResponse for UdfError {
+    fn into_response(self) -> Response {
+        let errmsg = match &self.0 {
+            AppError::Internal(detail) => {
+                tracing::error!("UDF request failed: {detail}");
+                "internal server error"
+            }
+            err => err.message(),
+        };
+        let body = serde_json::json!({ "s": "error", "errmsg": errmsg });
+        (self.0.status(), Json(body)).into_response()
+    }
+}
+
+fn rejected(rejection: QueryRejection) -> UdfError {
+    bad_request(rejection.body_text()).into()
+}
+
+/// `/udf/config`.
+#[derive(Serialize)]
+pub(crate) struct DatafeedConfig {
+    supported_resolutions: &'static [&'static str],
+    supports_search: bool,
+    supports_group_request: bool,
+    supports_marks: bool,
+    supports_timescale_marks: bool,
+    supports_time: bool,
+}
+
+/// `/udf/symbols`: how the chart should draw and label a symbol.
+#[derive(Serialize)]
+pub(crate) struct SymbolInfo {
+    name: String,
+    ticker: String,
+    description: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    session: String,
+    timezone: String,
+    exchange: &'static str,
+    listed_exchange: &'static str,
+    format: &'static str,
+    pricescale: u32,
+    minmov: u32,
+    has_intraday: bool,
+    has_weekly_and_monthly: bool,
+    supported_resolutions: &'static [&'static str],
+    volume_precision: u32,
+    data_status: &'static str,
+}
+
+/// One `/udf/search` match.
+#[derive(Serialize)]
+pub(crate) struct SearchResult {
+    symbol: String,
+    full_name: String,
+    description: String,
+    exchange: &'static str,
+    ticker: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+/// `/udf/history`: bars as parallel arrays, or `no_data` with the start of
+/// the closest earlier bar, if any, so the chart can jump back to it.
+#[derive(Serialize)]
+#[serde(tag = "s", rename_all = "snake_case")]
+pub(crate) enum History {
+    Ok {
+        t: Vec<i64>,
+        o: Vec<Option<f64>>,
+        h: Vec<Option<f64>>,
+        l: Vec<Option<f64>>,
+        c: Vec<Option<f64>>,
+        v: Vec<Option<f64>>,
+    },
+    NoData {
+        #[serde(rename = "nextTime", skip_serializing_if = "Option::is_none")]
+        next_time: Option<i64>,
+    },
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SymbolQuery {
+    symbol: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct SearchQuery {
+    /// Case-insensitive part of a symbol name; empty matches every symbol.
+    query: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HistoryQuery {
+    symbol: Option<String>,
+    resolution: Option<String>,
+    /// Unix seconds; bars starting at or after it.
+    from: Option<i64>,
+    /// Unix seconds; bars starting before it.
+    to: Option<i64>,
+    /// The newest this many bars before `to`, in place of `from`.
+    countback: Option<u32>,
+}
+
+/// Where a symbol's candles are stored.
+enum Source {
+    Main,
+    Symbol(String),
+}
+
+impl Source {
+    /// The candles as a table expression, and the symbol it binds, if any.
+    fn table(&self) -> (&'static str, Option<String>) {
+        match self {
+            Source::Main => ("candles", None),
+            Source::Symbol(symbol) => (
+                "(SELECT * FROM symbol_candles WHERE symbol = ?)",
+                Some(symbol.clone()),
+            ),
+        }
+    }
+}
+
+/// The `/api/candles` timeframe for a resolution. `D`, `W` and `M` may carry
+/// a count, so `1D` is `D`; weeks and months only come singly.
+fn timeframe(resolution: &str) -> Option<Timeframe> {
+    let split = resolution
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(resolution.len());
+    let (count, unit) = resolution.split_at(split);
+    let unit = match unit {
+        "" if !count.is_empty() => "m",
+        "D" => "d",
+        "W" => "w",
+        "M" => "M",
+        _ => return None,
+    };
+    let count = if count.is_empty() { "1" } else { count };
+    Timeframe::parse(&format!("{count}{unit}"))
+}
+
+fn unix_timestamp(name: &str, seconds: i64) -> Result<Timestamp, AppError> {
+    DateTime::from_timestamp(seconds, 0)
+        .map(|at| Timestamp::new(at.naive_utc()))
+        .ok_or_else(|| bad_request(format!("{name} is out of range")))
+}
+
+/// Every listed symbol: the main table's first, then `symbol_candles`' own.
+async fn symbols(state: &AppState) -> Result<Vec<String>, AppError> {
+    let main = state.config.udf.symbol.clone();
+    let others = state
+        .db
+        .read(|conn| {
+            conn.prepare_cached("SELECT DISTINCT symbol FROM symbol_candles ORDER BY symbol")?
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<duckdb::Result<Vec<_>>>()
+        })
+        .await?;
+    Ok(std::iter::once(main.clone())
+        .chain(others.into_iter().filter(|symbol| *symbol != main))
+        .collect())
+}
+
+/// Where `symbol`'s candles are; an unknown symbol is a 404.
+async fn source(state: &AppState, symbol: String) -> Result<Source, AppError> {
+    if symbol == state.config.udf.symbol {
+        return Ok(Source::Main);
+    }
+    let known = state
+        .db
+        .read({
+            let symbol = symbol.clone();
+            move |conn| {
+                conn.prepare_cached(
+                    "SELECT EXISTS (SELECT 1 FROM symbol_candles WHERE symbol = ?)",
+                )?
+                .query_row([&symbol], |row| row.get::<_, bool>(0))
+            }
+        })
+        .await?;
+    match known {
+        true => Ok(Source::Symbol(symbol)),
+        false => Err(AppError::NotFound(format!("unknown symbol {symbol:?}"))),
+    }
+}
+
+pub(crate) async fn get_config(
+    format: Result<ResponseFormat, AppError>,
+) -> Result<DataResponse<DatafeedConfig>, UdfError> {
+    Ok(DataResponse::new(
+        format?,
+        DatafeedConfig {
+            supported_resolutions: SUPPORTED_RESOLUTIONS,
+            supports_search: true,
+            supports_group_request: false,
+            supports_marks: false,
+            supports_timescale_marks: false,
+            supports_time: true,
+        },
+    ))
+}
+
+pub(crate) async fn get_symbol(
+    State(state): State<AppState>,
+    format: Result<ResponseFormat, AppError>,
+    query: Result<Query<SymbolQuery>, QueryRejection>,
+) -> Result<DataResponse<SymbolInfo>, UdfError> {
+    let format = format?;
+    let Query(query) = query.map_err(rejected)?;
+    let symbol = query
+        .symbol
+        .ok_or_else(|| bad_request("symbol is required"))?;
+    source(&state, symbol.clone()).await?;
+    let udf = &state.config.udf;
+    Ok(DataResponse::new(
+        format,
+        SymbolInfo {
+            name: symbol.clone(),
+            ticker: symbol.clone(),
+            description: symbol,
+            kind: "stock",
+            session: udf.session.clone(),
+            timezone: udf.timezone.clone(),
+            exchange: "",
+            listed_exchange: "",
+            format: "price",
+            pricescale: udf.pricescale,
+            minmov: 1,
+            has_intraday: true,
+            has_weekly_and_monthly: true,
+            supported_resolutions: SUPPORTED_RESOLUTIONS,
+            volume_precision: state.config.volume_precision.unwrap_or(0),
+            data_status: "streaming",
+        },
+    ))
+}
+
+pub(crate) async fn search(
+    State(state): State<AppState>,
+    format: Result<ResponseFormat, AppError>,
+    query: Result<Query<SearchQuery>, QueryRejection>,
+) -> Result<DataResponse<Vec<SearchResult>>, UdfError> {
+    let format = format?;
+    let Query(query) = query.map_err(rejected)?;
+    let needle = query.query.unwrap_or_default().to_lowercase();
+    let matches = symbols(&state)
+        .await?
+        .into_iter()
+        .filter(|symbol| symbol.to_lowercase().contains(&needle))
+        .take(query.limit.unwrap_or(SEARCH_LIMIT))
+        .map(|symbol| SearchResult {
+            full_name: symbol.clone(),
+            description: symbol.clone(),
+            ticker: symbol.clone(),
+            symbol,
+            exchange: "",
+            kind: "stock",
+        })
+        .collect();
+    Ok(DataResponse::new(format, matches))
+}
+
+pub(crate) async fn get_history(
+    State(state): State<AppState>,
+    format: Result<ResponseFormat, AppError>,
+    query: Result<Query<HistoryQuery>, QueryRejection>,
+) -> Result<DataResponse<History>, UdfError> {
+    let format = format?;
+    let Query(query) = query.map_err(rejected)?;
+    let symbol = query
+        .symbol
+        .ok_or_else(|| bad_request("symbol is required"))?;
+    let resolution = query
+        .resolution
+        .ok_or_else(|| bad_request("resolution is required"))?;
+    let timeframe = timeframe(&resolution).ok_or_else(|| {
+        bad_request(format!(
+            "unsupported resolution {resolution:?}; expected minutes such as 5 or 60, \
+             a count of days such as D or 2D, W or M"
+        ))
+    })?;
+    let to = unix_timestamp("to", query.to.ok_or_else(|| bad_request("to is required"))?)?;
+    let (from, limit) = match query.countback {
+        Some(countback) => (None, countback.min(MAX_HISTORY_BARS)),
+        None => {
+            let from = query
+                .from
+                .ok_or_else(|| bad_request("from or countback is required"))?;
+            (Some(unix_timestamp("from", from)?), MAX_HISTORY_BARS)
+        }
+    };
+    if from.is_some_and(|from| from.at > to.at) {
+        return Err(bad_request("from is after to").into());
+    }
+    let source = source(&state, symbol).await?;
+    if from.is_some() {
+        check_range(&state, from, Some(to)).await?;
+    }
+
+    // Buckets are kept whole: a bar starts within [from, to), and its
+    // candles may run on past `to` until the next bucket starts.
+    let (bucket, bucket_params) = match timeframe.calendar() {
+        Some(period) => (
+            format!("CAST(date_trunc('{period}', timestamp) AS TIMESTAMP)"),
+            None,
+        ),
+        None => (
+            "time_bucket(CAST(? AS INTERVAL), timestamp, CAST(? AS TIMESTAMP))".to_owned(),
+            Some((timeframe.sql_interval(), parse_origin(None)?)),
+        ),
+    };
+    let (table, symbol) = source.table();
+    // What the bucket and the table bind, ahead of each query's own.
+    let leading = move || {
+        let mut params = Vec::<Box<dyn ToSql>>::new();
+        if let Some((interval, origin)) = &bucket_params {
+            params.push(Box::new(interval.clone()));
+            params.push(Box::new(*origin));
+        }
+        if let Some(symbol) = &symbol {
+            params.push(Box::new(symbol.clone()));
+        }
+        params
+    };
+    let bars_until = Timestamp::new(timeframe.advance(to.at, 1));
+    let bars_sql = format!(
+        "SELECT
+            bucket, arg_min(open, timestamp), max(high), min(low),
+            arg_max(close, timestamp), sum(volume)
+         FROM (
+            SELECT {bucket} AS bucket, timestamp, open, high, low, close, volume
+            FROM {table}
+            WHERE (? IS NULL OR timestamp >= CAST(? AS TIMESTAMP)) AND timestamp < ?
+         )
+         WHERE (? IS NULL OR bucket >= CAST(? AS TIMESTAMP)) AND bucket < ?
+         GROUP BY bucket
+         ORDER BY bucket DESC
+         LIMIT ?"
+    );
+    let next_sql = format!(
+        "SELECT max(bucket)
+         FROM (SELECT {bucket} AS bucket FROM {table} WHERE timestamp < ?)
+         WHERE bucket < ?"
+    );
+    let history = state
+        .db
+        .read(move |conn| {
+            let mut params = leading();
+            params.push(Box::new(from));
+            params.push(Box::new(from));
+            params.push(Box::new(bars_until));
+            params.push(Box::new(from));
+            params.push(Box::new(from));
+            params.push(Box::new(to));
+            params.push(Box::new(limit));
+            let mut bars = conn
+                .prepare(&bars_sql)?
+                .query_map(params_from_iter(&params), |row| {
+                    Ok((
+                        row.get::<_, Timestamp>(0)?,
+                        [
+                            row.get(1)?,
+                            row.get(2)?,
+                            row.get(3)?,
+                            row.get(4)?,
+                            row.get(5)?,
+                        ],
+                    ))
+                })?
+                .collect::<duckdb::Result<Vec<(Timestamp, [Option<f64>; 5])>>>()?;
+            if bars.is_empty() {
+                // After countback found nothing, nothing earlier exists.
+                let Some(from) = from else {
+                    return Ok(History::NoData { next_time: None });
+                };
+                let mut params = leading();
+                params.push(Box::new(Timestamp::new(timeframe.advance(from.at, 1))));
+                params.push(Box::new(from));
+                let next = conn
+                    .prepare(&next_sql)?
+                    .query_row(params_from_iter(&params), |row| {
+                        row.get::<_, Option<Timestamp>>(0)
+                    })?;
+                return Ok(History::NoData {
+                    next_time: next.map(|at| at.at.and_utc().timestamp()),
+                });
+            }
+            bars.reverse();
+            let column = |i: usize| bars.iter().map(|(_, values)| values[i]).collect();
+            Ok::<_, duckdb::Error>(History::Ok {
+                t: bars
+                    .iter()
+                    .map(|(at, _)| at.at.and_utc().timestamp())
+                    .collect(),
+                o: column(0),
+                h: column(1),
+                l: column(2),
+                c: column(3),
+                v: column(4),
+            })
+        })
+        .await?;
+    Ok(DataResponse::new(format, history))
+}
+
+/// The server's clock, so the chart can place the forming bar.
+pub(crate) async fn get_time() -> String {
+    Utc::now().timestamp().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use axum::http::StatusCode;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::config::{CsvMode, UdfSettings};
+    use crate::db::initialize_symbols;
+    use crate::test_support::*;
+    use crate::{build_router, Config};
+
+    /// 2024-01-01 00:00:00 UTC.
+    const T: i64 = 1_704_067_200;
+
+    /// Ten one-minute candles from [`T`], the `i`th opening at `i`, and an
+    /// empty `symbol_candles`.
+    async fn minute_candles() -> AppState {
+        let rows = (0..10)
+            .map(|i| format!("('2024-01-01 00:0{i}:00', {i}, {i} + 0.5, {i} - 0.5, {i} + 0.25, 1)"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let state = seeded_state(&rows);
+        state
+            .db
+            .write(|conn| initialize_symbols(conn, Path::new("missing.csv"), CsvMode::Lenient))
+            .await
+            .unwrap();
+        state
+    }
+
+    async fn history(app: &axum::Router, query: &str) -> Value {
+        get_json(app, &format!("/udf/history?symbol=MAIN&{query}")).await
+    }
+
+    async fn error(app: &axum::Router, uri: &str) -> (StatusCode, Value) {
+        let response = get_uri(app, uri).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn history_resamples_resolutions_into_column_arrays() {
+        let app = build_router(minute_candles().await);
+
+        let bars = history(&app, &format!("resolution=5&from={T}&to={}", T + 600)).await;
+        assert_eq!(
+            bars,
+            json!({
+                "s": "ok",
+                "t": [T, T + 300],
+                "o": [0.0, 5.0],
+                "h": [4.5, 9.5],
+                "l": [-0.5, 4.5],
+                "c": [4.25, 9.25],
+                "v": [5.0, 5.0],
+            })
+        );
+        // Bars start within [from, to) and are never cut short: the first
+        // bucket starts before `from`, and the second runs past `to`.
+        let bars = history(
+            &app,
+            &format!("resolution=5&from={}&to={}", T + 60, T + 360),
+        )
+        .await;
+        assert_eq!(bars["t"], json!([T + 300]));
+        assert_eq!(bars["v"], json!([5.0]));
+        // countback takes the newest bars before `to` and ignores `from`.
+        let bars = history(
+            &app,
+            &format!("resolution=1&from={}&to={}&countback=2", T + 600, T + 300),
+        )
+        .await;
+        assert_eq!(bars["t"], json!([T + 180, T + 240]));
+        assert_eq!(bars["o"], json!([3.0, 4.0]));
+
+        for resolution in ["D", "1D", "W", "M"] {
+            let bars = history(
+                &app,
+                &format!("resolution={resolution}&from=0&to={}", T + 60),
+            )
+            .await;
+            assert_eq!(bars["v"], json!([10.0]), "resolution {resolution}");
+            assert_eq!(bars["c"], json!([9.25]), "resolution {resolution}");
+        }
+        // 2024-01-01 is a Monday, so the week starts with it too.
+        let bars = history(&app, &format!("resolution=W&from=0&to={}", T + 60)).await;
+        assert_eq!(bars["t"], json!([T]));
+
+        for query in [
+            "resolution=H&from=0&to=1",
+            "resolution=2W&from=0&to=1",
+            "resolution=&from=0&to=1",
+            "resolution=5&to=1",
+            "resolution=5&from=2&to=1",
+            "resolution=5&from=soon&to=1",
+        ] {
+            let (status, body) = error(&app, &format!("/udf/history?symbol=MAIN&{query}")).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{query}");
+            assert_eq!(body["s"], "error", "{query}");
+            assert!(body["errmsg"].is_string(), "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_ranges_answer_no_data_with_the_closest_earlier_bar() {
+        let app = build_router(minute_candles().await);
+
+        let later = format!("resolution=5&from={}&to={}", T + 3600, T + 7200);
+        assert_eq!(
+            history(&app, &later).await,
+            json!({ "s": "no_data", "nextTime": T + 300 })
+        );
+        let tomorrow = format!("resolution=D&from={}&to={}", T + 86_400, T + 2 * 86_400);
+        assert_eq!(history(&app, &tomorrow).await["nextTime"], T);
+        // Nothing before the range: no nextTime, so the chart stops asking.
+        let earlier = format!("resolution=5&from={}&to={T}", T - 3600);
+        assert_eq!(history(&app, &earlier).await, json!({ "s": "no_data" }));
+        let countback = format!("resolution=5&to={T}&countback=10");
+        assert_eq!(history(&app, &countback).await, json!({ "s": "no_data" }));
+
+        let (status, body) = error(&app, "/udf/history?symbol=NOPE&resolution=5&from=0&to=1").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            body,
+            json!({ "s": "error", "errmsg": "unknown symbol \"NOPE\"" })
+        );
+    }
+
+    #[tokio::test]
+    async fn symbols_come_from_both_tables_with_the_configured_metadata() {
+        let seeded = minute_candles().await;
+        seeded
+            .db
+            .write(|conn| {
+                conn.execute_batch(
+                    "INSERT INTO symbol_candles VALUES
+                        ('AAA', '2024-01-01 00:00:00', 1, 2, 0.5, 1.5, 7),
+                        ('BAB', '2024-01-01 00:00:00', 1, 1, 1, 1, 1);",
+                )
+            })
+            .await
+            .unwrap();
+        let app = build_router(AppState::new(
+            Arc::clone(&seeded.db),
+            Config {
+                udf: UdfSettings {
+                    symbol: "SPX".to_owned(),
+                    pricescale: 10_000,
+                    session: "0930-1600".to_owned(),
+                    timezone: "America/New_York".to_owned(),
+                },
+                volume_precision: Some(2),
+                ..Config::default()
+            },
+        ));
+
+        let spx = get_json(&app, "/udf/symbols?symbol=SPX").await;
+        assert_eq!(spx["name"], "SPX");
+        assert_eq!(spx["pricescale"], 10_000);
+        assert_eq!(spx["session"], "0930-1600");
+        assert_eq!(spx["timezone"], "America/New_York");
+        assert_eq!(spx["volume_precision"], 2);
+        assert_eq!(
+            get_json(&app, "/udf/symbols?symbol=AAA").await["ticker"],
+            "AAA"
+        );
+        let (status, body) = error(&app, "/udf/symbols?symbol=MAIN").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["s"], "error");
+
+        let names = |results: Value| {
+            results
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|result| result["symbol"].as_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(get_json(&app, "/udf/search?query=").await),
+            ["SPX", "AAA", "BAB"]
+        );
+        assert_eq!(
+            names(get_json(&app, "/udf/search?query=b&limit=5").await),
+            ["BAB"]
+        );
+        assert_eq!(names(get_json(&app, "/udf/search?limit=1").await), ["SPX"]);
+
+        let aaa = get_json(
+            &app,
+            &format!(
+                "/udf/history?symbol=AAA&resolution=1&from={T}&to={}",
+                T + 60
+            ),
+        )
+        .await;
+        assert_eq!(aaa["h"], json!([2.0]));
+        assert_eq!(aaa["v"], json!([7.0]));
+        let spx = get_json(
+            &app,
+            &format!(
+                "/udf/history?symbol=SPX&resolution=1&from={T}&to={}",
+                T + 60
+            ),
+        )
+        .await;
+        assert_eq!(spx["h"], json!([0.5]));
+
+        let config = get_json(&app, "/udf/config").await;
+        assert_eq!(config["supports_time"], true);
+        assert!(config["supported_resolutions"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("D")));
+        let response = get_uri(&app, "/udf/time").await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let now = std::str::from_utf8(&body).unwrap().parse::<i64>().unwrap();
+        assert!((now - Utc::now().timestamp()).abs() <= 5);
+    }
+}
@@ -0,0 +1,189 @@
+//! Notices when DuckDB stops answering and reopens it.
+//!
+//! Every `GRAPH_WATCHDOG_INTERVAL_MS` the watchdog runs a small query on the
+//! writer and on a pooled reader. After `GRAPH_WATCHDOG_FAILURES` failed
+//! probes in a row it reopens the database, running the same idempotent setup
+//! as startup, and keeps trying on every later failure. `/ready` answers
+//! `503` from the first failed probe until one succeeds again.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use duckdb::Connection;
+use serde::Serialize;
+use tokio::time::MissedTickBehavior;
+
+use crate::db::{Db, Reopen};
+
+/// How long a reopen waits for in-flight queries before trying again later.
+const REOPEN_GRACE: Duration = Duration::from_secs(30);
+
+/// What the watchdog has seen, shared with `/ready`.
+#[derive(Default)]
+pub(crate) struct Health {
+    failures: AtomicU32,
+    recoveries: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct Readiness {
+    pub(crate) ready: bool,
+    /// Failed probes since the last one that succeeded.
+    consecutive_failures: u32,
+    /// Times the database has been reopened since startup.
+    recoveries: u64,
+}
+
+impl Health {
+    pub(crate) fn readiness(&self) -> Readiness {
+        let failures = self.failures.load(Ordering::Relaxed);
+        Readiness {
+            ready: failures == 0,
+            consecutive_failures: failures,
+            recoveries: self.recoveries.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Probes every `interval` until the process exits, reopening the database
+/// through `reopen` once `threshold` probes in a row have failed.
+pub(crate) async fn run(
+    db: Arc<Db>,
+    health: Arc<Health>,
+    reopen: Arc<Reopen>,
+    interval: Duration,
+    threshold: u32,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        check(&db, &health, &reopen, threshold).await;
+    }
+}
+
+/// One probe, and a reopen if it makes `threshold` failures in a row.
+pub(crate) async fn check(db: &Arc<Db>, health: &Health, reopen: &Arc<Reopen>, threshold: u32) {
+    let err = match probe(db).await {
+        Ok(()) => {
+            let failures = health.failures.swap(0, Ordering::Relaxed);
+            if failures > 0 {
+                tracing::info!("database answering again after {failures} failed probes");
+            }
+            return;
+        }
+        Err(err) => err,
+    };
+    let failures = health.failures.fetch_add(1, Ordering::Relaxed) + 1;
+    tracing::warn!("database probe failed ({failures} in a row): {err}");
+    if failures < threshold {
+        return;
+    }
+    tracing::warn!("reopening the database after {failures} failed probes");
+    match db.reopen(Arc::clone(reopen), REOPEN_GRACE).await {
+        Ok(()) => {
+            health.recoveries.fetch_add(1, Ordering::Relaxed);
+            tracing::info!("database reopened; ready once the next probe succeeds");
+        }
+        Err(err) => tracing::error!("reopening the database failed: {err:#}"),
+    }
+}
+
+async fn probe(db: &Arc<Db>) -> duckdb::Result<()> {
+    fn query(conn: &Connection) -> duckdb::Result<()> {
+        conn.prepare_cached("SELECT count(*) FROM candles")?
+            .query_row([], |row| row.get::<_, i64>(0))
+            .map(drop)
+    }
+    db.write(query).await?;
+    db.read(query).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+
+    use super::*;
+    use crate::build_router;
+    use crate::config::CsvMode;
+    use crate::db::initialize_db;
+    use crate::test_support::*;
+
+    #[tokio::test]
+    async fn repeated_failures_reopen_the_database() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let app = build_router(state.clone());
+        let reopen = Arc::new(Reopen {
+            open: Box::new(|| Ok(Connection::open_in_memory()?)),
+            setup: Box::new(|conn| {
+                initialize_db(
+                    conn,
+                    std::path::Path::new("data/stocks.csv"),
+                    CsvMode::Lenient,
+                )
+                .map(drop)
+            }),
+        });
+        let check = || check(&state.db, &state.health, &reopen, 2);
+
+        check().await;
+        assert!(state.health.readiness().ready);
+        // Lose the table the probes and every data route read.
+        state
+            .db
+            .write(|conn| conn.execute_batch("DROP TABLE candles"))
+            .await
+            .unwrap();
+        check().await;
+        let response = get_uri(&app, "/ready").await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let readiness: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            readiness,
+            serde_json::json!({"ready": false, "consecutive_failures": 1, "recoveries": 0})
+        );
+
+        // The second failure reopens; the next probe confirms it.
+        check().await;
+        assert_eq!(state.health.readiness().recoveries, 1);
+        assert!(!state.health.readiness().ready);
+        check().await;
+        let readiness = get_json(&app, "/ready").await;
+        assert_eq!(readiness["ready"], true);
+        let candles = get_json(&app, "/api/candles?limit=1").await;
+        assert_eq!(candles.as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_failed_reopen_leaves_queries_failing_cleanly() {
+        let state = seeded_state("('2024-01-01 00:00:00', 1, 1, 1, 1, 1)");
+        let reopen = Arc::new(Reopen {
+            open: Box::new(|| anyhow::bail!("disk gone")),
+            setup: Box::new(|_| Ok(())),
+        });
+        let error = state
+            .db
+            .reopen(Arc::clone(&reopen), Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "disk gone");
+        let app = build_router(state.clone());
+        let response = get_uri(&app, "/api/candles").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // A reader that never comes back makes the reopen give up untouched.
+        let (release, held) = hold_reader(&state.db).await;
+        let error = state
+            .db
+            .reopen(reopen, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("still busy"), "{error}");
+        release.send(()).unwrap();
+        held.await.unwrap();
+    }
+}
@@ -0,0 +1,705 @@
+//! `GET /api/export/xlsx`: candles, indicators and a summary of a range as
+//! an Excel workbook, with real dates and numbers rather than CSV text.
+//!
+//! There is no spreadsheet crate in this build, so the workbook is written
+//! here: the few XML parts Excel needs, deflated into a zip. Sheets use
+//! inline strings, so there is no shared string table to keep, and a
+//! header row frozen above the data. An xlsx file cannot be streamed, so
+//! the whole workbook is built in memory and `GRAPH_XLSX_MAX_ROWS` bounds
+//! it.
+
+use std::fmt::Write as _;
+use std::io::Write;
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::response::{IntoResponse, Response};
+use chrono::{NaiveDate, NaiveDateTime};
+use duckdb::{params, Connection};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::Deserialize;
+
+use crate::error::{bad_request, internal_error, AppError};
+use crate::handlers::{candle_extent, indicator_points, parse_range, raw_candles};
+use crate::indicators::PriceSource;
+use crate::models::{Candle, IndicatorPoint, Timestamp};
+use crate::precision;
+use crate::AppState;
+
+pub(crate) const XLSX: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+
+#[derive(Deserialize)]
+pub(crate) struct XlsxQuery {
+    start: Option<String>,
+    end: Option<String>,
+    /// Comma-separated sheets: `candles`, `indicators` and `summary`, all
+    /// three by default.
+    include: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Dataset {
+    Candles,
+    Indicators,
+    Summary,
+}
+
+fn parse_include(text: Option<&str>) -> Result<Vec<Dataset>, AppError> {
+    let Some(text) = text else {
+        return Ok(vec![
+            Dataset::Candles,
+            Dataset::Indicators,
+            Dataset::Summary,
+        ]);
+    };
+    let mut datasets = Vec::new();
+    for part in text.split_terminator(',') {
+        let dataset = match part.trim() {
+            "candles" => Dataset::Candles,
+            "indicators" => Dataset::Indicators,
+            "summary" => Dataset::Summary,
+            other => {
+                return Err(bad_request(format!(
+                    "unknown include {other:?}; expected candles, indicators or summary"
+                )))
+            }
+        };
+        if !datasets.contains(&dataset) {
+            datasets.push(dataset);
+        }
+    }
+    if datasets.is_empty() {
+        return Err(bad_request("include names no sheets"));
+    }
+    Ok(datasets)
+}
+
+/// The range's summary statistics, over the same rows as the candles sheet.
+pub(crate) struct Summary {
+    pub(crate) open: Option<f64>,
+    pub(crate) high: Option<f64>,
+    pub(crate) low: Option<f64>,
+    pub(crate) close: Option<f64>,
+    pub(crate) volume: Option<f64>,
+}
+
+impl Summary {
+    /// The relative change from the first open to the last close.
+    pub(crate) fn change(&self) -> Option<f64> {
+        match (self.open, self.close) {
+            (Some(open), Some(close)) if open != 0.0 => Some(close / open - 1.0),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn summarize(
+    conn: &Connection,
+    from: Option<Timestamp>,
+    until: Option<Timestamp>,
+) -> duckdb::Result<Summary> {
+    conn.prepare_cached(
+        "SELECT arg_min(open, timestamp), max(high), min(low),
+                arg_max(close, timestamp), sum(volume)
+         FROM candles
+         WHERE (? IS NULL OR timestamp >= CAST(? AS TIMESTAMP))
+           AND (? IS NULL OR timestamp <= CAST(? AS TIMESTAMP))",
+    )?
+    .query_row(params![from, from, until, until], |row| {
+        Ok(Summary {
+            open: row.get(0)?,
+            high: row.get(1)?,
+            low: row.get(2)?,
+            close: row.get(3)?,
+            volume: row.get(4)?,
+        })
+    })
+}
+
+/// A workbook of the sheets `include` names, refused with 413 when they
+/// would hold more than `GRAPH_XLSX_MAX_ROWS` rows between them.
+pub(crate) async fn export(
+    State(state): State<AppState>,
+    Query(query): Query<XlsxQuery>,
+) -> Result<Response, AppError> {
+    let datasets = parse_include(query.include.as_deref())?;
+    let (from, until) = parse_range(query.start.as_deref(), query.end.as_deref())?;
+    let (rows, first, last) = state
+        .db
+        .read(move |conn| candle_extent(conn, from, until))
+        .await?;
+    let sheets_of_candles = datasets
+        .iter()
+        .filter(|dataset| **dataset != Dataset::Summary)
+        .count();
+    let total = rows as usize * sheets_of_candles;
+    let cap = state.config.xlsx_max_rows;
+    if total > cap {
+        return Err(AppError::TooLarge(format!(
+            "the export would hold {total} rows, over the limit of {cap}; narrow the range"
+        )));
+    }
+
+    let cached = Arc::clone(&state.indicators);
+    let precision = state.config.volume_precision;
+    let wanted = datasets.clone();
+    let (candles, points, summary) = state
+        .db
+        .read(move |conn| {
+            let candles = match wanted.contains(&Dataset::Candles) {
+                true => raw_candles(conn, from, until, rows as i64, precision)?,
+                false => Vec::new(),
+            };
+            let points = match wanted.contains(&Dataset::Indicators) {
+                true => indicator_points(conn, &cached, PriceSource::Close)?
+                    .into_iter()
+                    .filter(|point| {
+                        from.is_none_or(|from| point.timestamp.at >= from.at)
+                            && until.is_none_or(|until| point.timestamp.at <= until.at)
+                    })
+                    .collect(),
+                false => Vec::new(),
+            };
+            let summary = summarize(conn, from, until)?;
+            Ok::<_, duckdb::Error>((candles, points, summary))
+        })
+        .await?;
+
+    let symbol = state.config.udf.symbol.clone();
+    let span = (from.or(first), until.or(last));
+    let formats = Formats {
+        price_decimals: state.config.udf.pricescale.max(1).ilog10() as usize,
+        volume_decimals: precision
+            .map_or_else(|| volume_decimals(&candles), |precision| precision as usize),
+    };
+    let filename = filename(&symbol, span);
+    let workbook = tokio::task::spawn_blocking(move || {
+        let sheets = datasets
+            .iter()
+            .map(|dataset| match dataset {
+                Dataset::Candles => candle_sheet(&candles),
+                Dataset::Indicators => indicator_sheet(&points),
+                Dataset::Summary => summary_sheet(&symbol, span, rows, &summary),
+            })
+            .collect::<Vec<_>>();
+        workbook(&sheets, formats)
+    })
+    .await
+    .map_err(internal_error)?;
+    let disposition = format!("attachment; filename=\"{filename}\"");
+    Ok((
+        [
+            (CONTENT_TYPE, XLSX.to_owned()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        workbook,
+    )
+        .into_response())
+}
+
+/// `MAIN_20240101T000000_20240131T235900.xlsx`: the symbol, cut to safe
+/// characters, and the range exported, or the candles' own when it is open.
+fn filename(symbol: &str, span: (Option<Timestamp>, Option<Timestamp>)) -> String {
+    let symbol = symbol
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.'))
+        .collect::<String>();
+    let symbol = if symbol.is_empty() {
+        "candles"
+    } else {
+        &symbol
+    };
+    match span {
+        (Some(from), Some(until)) => format!(
+            "{symbol}_{}_{}.xlsx",
+            from.at.format("%Y%m%dT%H%M%S"),
+            until.at.format("%Y%m%dT%H%M%S")
+        ),
+        _ => format!("{symbol}.xlsx"),
+    }
+}
+
+/// The fewest decimals, up to 8, that show every volume exactly.
+fn volume_decimals(candles: &[Candle]) -> usize {
+    let volumes = candles
+        .iter()
+        .map(|candle| candle.volume.value)
+        .collect::<Vec<_>>();
+    precision::decimals(&volumes, 8) as usize
+}
+
+fn candle_sheet(candles: &[Candle]) -> Sheet {
+    Sheet {
+        name: "Candles",
+        columns: &[
+            ("timestamp", 20),
+            ("open", 12),
+            ("high", 12),
+            ("low", 12),
+            ("close", 12),
+            ("volume", 14),
+        ],
+        rows: candles
+            .iter()
+            .map(|candle| {
+                vec![
+                    Cell::Date(candle.timestamp.at),
+                    Cell::Number(candle.open, Style::Price),
+                    Cell::Number(candle.high, Style::Price),
+                    Cell::Number(candle.low, Style::Price),
+                    Cell::Number(candle.close, Style::Price),
+                    Cell::Number(candle.volume.value, Style::Volume),
+                ]
+            })
+            .collect(),
+    }
+}
+
+fn indicator_sheet(points: &[IndicatorPoint]) -> Sheet {
+    let number =
+        |value: Option<f64>, style| value.map_or(Cell::Empty, |value| Cell::Number(value, style));
+    Sheet {
+        name: "Indicators",
+        columns: &[
+            ("timestamp", 20),
+            ("sma_14", 12),
+            ("ema_14", 12),
+            ("rsi_14", 10),
+        ],
+        rows: points
+            .iter()
+            .map(|point| {
+                vec![
+                    Cell::Date(point.timestamp.at),
+                    number(point.sma_14, Style::Price),
+                    number(point.ema_14, Style::Price),
+                    number(point.rsi_14, Style::Decimal),
+                ]
+            })
+            .collect(),
+    }
+}
+
+fn summary_sheet(
+    symbol: &str,
+    (from, until): (Option<Timestamp>, Option<Timestamp>),
+    rows: u64,
+    summary: &Summary,
+) -> Sheet {
+    let date = |at: Option<Timestamp>| at.map_or(Cell::Empty, |at| Cell::Date(at.at));
+    let number =
+        |value: Option<f64>, style| value.map_or(Cell::Empty, |value| Cell::Number(value, style));
+    let row = |field: &str, value| vec![Cell::Text(field.to_owned()), value];
+    Sheet {
+        name: "Summary",
+        columns: &[("field", 14), ("value", 20)],
+        rows: vec![
+            row("symbol", Cell::Text(symbol.to_owned())),
+            row("start", date(from)),
+            row("end", date(until)),
+            row("candles", Cell::Number(rows as f64, Style::Integer)),
+            row("open", number(summary.open, Style::Price)),
+            row("high", number(summary.high, Style::Price)),
+            row("low", number(summary.low, Style::Price)),
+            row("close", number(summary.close, Style::Price)),
+            row("volume", number(summary.volume, Style::Volume)),
+            row("change", number(summary.change(), Style::Percent)),
+        ],
+    }
+}
+
+struct Sheet {
+    name: &'static str,
+    /// Header text and column width in characters.
+    columns: &'static [(&'static str, u32)],
+    rows: Vec<Vec<Cell>>,
+}
+
+enum Cell {
+    Empty,
+    Text(String),
+    /// Written as an Excel serial date in the date-time format.
+    Date(NaiveDateTime),
+    Number(f64, Style),
+}
+
+/// Number formats, indexes into the `cellXfs` of [`styles`].
+#[derive(Clone, Copy)]
+enum Style {
+    Price,
+    Volume,
+    /// Two decimals, for oscillators.
+    Decimal,
+    Percent,
+    Integer,
+}
+
+impl Style {
+    fn index(self) -> usize {
+        match self {
+            Style::Price => 3,
+            Style::Volume => 4,
+            Style::Decimal => 5,
+            Style::Percent => 6,
+            Style::Integer => 7,
+        }
+    }
+}
+
+const HEADER_STYLE: usize = 1;
+const DATE_STYLE: usize = 2;
+
+/// Decimals shown for prices, from `GRAPH_UDF_PRICESCALE`, and volumes.
+#[derive(Clone, Copy)]
+struct Formats {
+    price_decimals: usize,
+    volume_decimals: usize,
+}
+
+fn number_format(decimals: usize) -> String {
+    match decimals {
+        0 => "#,##0".to_owned(),
+        decimals => format!("#,##0.{}", "0".repeat(decimals)),
+    }
+}
+
+/// Excel's serial date: days since 1899-12-30, the time of day as the
+/// fraction.
+fn serial_date(at: NaiveDateTime) -> f64 {
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30)
+        .expect("a valid date")
+        .and_hms_opt(0, 0, 0)
+        .expect("a valid time");
+    (at - epoch).num_milliseconds() as f64 / 86_400_000.0
+}
+
+/// `A`, `B`, ..., `Z`, `AA`, ... for zero-based `column`.
+fn column_name(mut column: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (column % 26) as u8);
+        if column < 26 {
+            break;
+        }
+        column = column / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).expect("ASCII letters")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn sheet_xml(sheet: &Sheet) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetViews><sheetView workbookViewId="0"><pane ySplit="1" topLeftCell="A2" activePane="bottomLeft" state="frozen"/></sheetView></sheetViews><cols>"#,
+    );
+    for (i, (_, width)) in sheet.columns.iter().enumerate() {
+        let _ = write!(
+            xml,
+            r#"<col min="{n}" max="{n}" width="{width}" customWidth="1"/>"#,
+            n = i + 1
+        );
+    }
+    xml.push_str(r#"</cols><sheetData><row r="1">"#);
+    for (i, (header, _)) in sheet.columns.iter().enumerate() {
+        let _ = write!(
+            xml,
+            r#"<c r="{}1" s="{HEADER_STYLE}" t="inlineStr"><is><t>{}</t></is></c>"#,
+            column_name(i),
+            escape(header)
+        );
+    }
+    xml.push_str("</row>");
+    for (r, row) in sheet.rows.iter().enumerate() {
+        let r = r + 2;
+        let _ = write!(xml, r#"<row r="{r}">"#);
+        for (i, cell) in row.iter().enumerate() {
+            let at = format!("{}{r}", column_name(i));
+            let _ = match cell {
+                Cell::Text(text) => write!(
+                    xml,
+                    r#"<c r="{at}" t="inlineStr"><is><t>{}</t></is></c>"#,
+                    escape(text)
+                ),
+                Cell::Date(date) => write!(
+                    xml,
+                    r#"<c r="{at}" s="{DATE_STYLE}"><v>{}</v></c>"#,
+                    serial_date(*date)
+                ),
+                Cell::Number(value, style) if value.is_finite() => write!(
+                    xml,
+                    r#"<c r="{at}" s="{}"><v>{value}</v></c>"#,
+                    style.index()
+                ),
+                Cell::Number(..) | Cell::Empty => Ok(()),
+            };
+        }
+        xml.push_str("</row>");
+    }
+    xml.push_str("</sheetData></worksheet>");
+    xml
+}
+
+/// The stylesheet behind [`Style`]: `cellXfs` 0 is the default, then the
+/// bold header, the date-time, and the number formats in [`Style::index`]
+/// order.
+fn styles(formats: Formats) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><numFmts count="3"><numFmt numFmtId="164" formatCode="yyyy-mm-dd hh:mm:ss"/><numFmt numFmtId="165" formatCode="{price}"/><numFmt numFmtId="166" formatCode="{volume}"/></numFmts><fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts><fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills><borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders><cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs><cellXfs count="8"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/><xf numFmtId="0" fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1"/><xf numFmtId="164" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/><xf numFmtId="165" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/><xf numFmtId="166" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/><xf numFmtId="2" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/><xf numFmtId="10" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/><xf numFmtId="3" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/></cellXfs><cellStyles count="1"><cellStyle name="Normal" xfId="0" builtinId="0"/></cellStyles></styleSheet>"#,
+        price = number_format(formats.price_decimals),
+        volume = number_format(formats.volume_decimals),
+    )
+}
+
+/// The xlsx package: the sheets, the workbook listing them, their styles,
+/// and the relationships and content types tying the parts together.
+fn workbook(sheets: &[Sheet], formats: Formats) -> Vec<u8> {
+    let mut content_types = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>"#,
+    );
+    let mut listed = String::new();
+    let mut relationships = String::new();
+    for (i, sheet) in sheets.iter().enumerate() {
+        let n = i + 1;
+        let _ = write!(
+            content_types,
+            r#"<Override PartName="/xl/worksheets/sheet{n}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#
+        );
+        let _ = write!(
+            listed,
+            r#"<sheet name="{}" sheetId="{n}" r:id="rId{n}"/>"#,
+            sheet.name
+        );
+        let _ = write!(
+            relationships,
+            r#"<Relationship Id="rId{n}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{n}.xml"/>"#
+        );
+    }
+    content_types.push_str("</Types>");
+    let styles_id = sheets.len() + 1;
+    let _ = write!(
+        relationships,
+        r#"<Relationship Id="rId{styles_id}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>"#
+    );
+
+    let mut zip = Zip::default();
+    zip.add("[Content_Types].xml", content_types.as_bytes());
+    zip.add(
+        "_rels/.rels",
+        br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#,
+    );
+    zip.add(
+        "xl/workbook.xml",
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>{listed}</sheets></workbook>"#
+        )
+        .as_bytes(),
+    );
+    zip.add(
+        "xl/_rels/workbook.xml.rels",
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{relationships}</Relationships>"#
+        )
+        .as_bytes(),
+    );
+    zip.add("xl/styles.xml", styles(formats).as_bytes());
+    for (i, sheet) in sheets.iter().enumerate() {
+        let name = format!("xl/worksheets/sheet{}.xml", i + 1);
+        zip.add(&name, sheet_xml(sheet).as_bytes());
+    }
+    zip.finish()
+}
+
+/// A zip archive of deflated entries, written whole in memory. Entries and
+/// the archive stay well under the 4 GiB that would need zip64, since the
+/// row cap bounds them.
+#[derive(Default)]
+struct Zip {
+    bytes: Vec<u8>,
+    directory: Vec<u8>,
+    entries: u16,
+}
+
+impl Zip {
+    /// 1980-01-01 00:00 in MS-DOS form, the earliest zip can record.
+    const DOS_DATE: u16 = (1 << 5) | 1;
+
+    fn add(&mut self, name: &str, data: &[u8]) {
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate
+            .write_all(data)
+            .expect("writing to memory cannot fail");
+        let compressed = deflate.finish().expect("writing to memory cannot fail");
+        let crc = crc32fast::hash(data);
+        let offset = self.bytes.len() as u32;
+
+        // Version 2.0, no flags, deflate, the DOS timestamp, then the CRC,
+        // compressed and original sizes and the name's length: shared by the
+        // local header and the directory entry.
+        let mut common = Vec::with_capacity(26);
+        for half in [20u16, 0, 8, 0, Self::DOS_DATE] {
+            common.extend_from_slice(&half.to_le_bytes());
+        }
+        for word in [crc, compressed.len() as u32, data.len() as u32] {
+            common.extend_from_slice(&word.to_le_bytes());
+        }
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+        self.bytes.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        self.bytes.extend_from_slice(&common);
+        self.bytes.extend_from_slice(&0u16.to_le_bytes());
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.extend_from_slice(&compressed);
+
+        self.directory
+            .extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        self.directory.extend_from_slice(&20u16.to_le_bytes());
+        self.directory.extend_from_slice(&common);
+        // No extra field or comment, disk 0, no attributes.
+        self.directory.extend_from_slice(&[0; 12]);
+        self.directory.extend_from_slice(&offset.to_le_bytes());
+        self.directory.extend_from_slice(name.as_bytes());
+        self.entries += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(&self.directory);
+        self.bytes.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        self.bytes.extend_from_slice(&[0; 4]);
+        self.bytes.extend_from_slice(&self.entries.to_le_bytes());
+        self.bytes.extend_from_slice(&self.entries.to_le_bytes());
+        self.bytes
+            .extend_from_slice(&(self.directory.len() as u32).to_le_bytes());
+        self.bytes.extend_from_slice(&offset.to_le_bytes());
+        self.bytes.extend_from_slice(&0u16.to_le_bytes());
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+    use axum::http::StatusCode;
+    use flate2::read::DeflateDecoder;
+
+    use super::XLSX;
+    use crate::build_router;
+    use crate::config::Config;
+    use crate::test_support::{get_uri, seeded_state};
+    use crate::AppState;
+
+    /// Each entry of a zip as [`super::Zip`] writes it, inflated.
+    fn unzip(zip: &[u8]) -> HashMap<String, String> {
+        let (mut entries, mut at) = (HashMap::new(), 0);
+        let half = |at: usize| u16::from_le_bytes([zip[at], zip[at + 1]]) as usize;
+        while zip[at..at + 4] == 0x0403_4b50u32.to_le_bytes() {
+            let size = u32::from_le_bytes(zip[at + 18..at + 22].try_into().unwrap()) as usize;
+            let (name_len, extra_len) = (half(at + 26), half(at + 28));
+            let name = String::from_utf8(zip[at + 30..at + 30 + name_len].to_vec()).unwrap();
+            let start = at + 30 + name_len + extra_len;
+            let mut text = String::new();
+            DeflateDecoder::new(&zip[start..start + size])
+                .read_to_string(&mut text)
+                .unwrap();
+            entries.insert(name, text);
+            at = start + size;
+        }
+        assert_eq!(zip[at..at + 4], 0x0201_4b50u32.to_le_bytes());
+        entries
+    }
+
+    #[tokio::test]
+    async fn workbooks_hold_typed_sheets() {
+        let rows = "('2024-01-01 00:00:00', 10, 12, 9, 11, 100),
+             ('2024-01-01 00:01:00', 11, 13, 10, 12.5, 250.5)";
+        let app = build_router(seeded_state(rows));
+
+        let response = get_uri(
+            &app,
+            "/api/export/xlsx?start=2024-01-01&end=2024-01-01%2000:01:00",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], XLSX);
+        assert_eq!(
+            response.headers()[CONTENT_DISPOSITION],
+            "attachment; filename=\"MAIN_20240101T000000_20240101T000100.xlsx\""
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let parts = unzip(&body);
+        for part in [
+            "[Content_Types].xml",
+            "_rels/.rels",
+            "xl/workbook.xml",
+            "xl/styles.xml",
+        ] {
+            assert!(parts.contains_key(part), "no {part}");
+        }
+        assert!(parts["xl/workbook.xml"].contains(r#"<sheet name="Summary" sheetId="3""#));
+        assert!(parts["xl/styles.xml"].contains(r##"formatCode="#,##0.00""##));
+        // One decimal is all the volumes need.
+        assert!(parts["xl/styles.xml"].contains(r##"formatCode="#,##0.0""##));
+
+        let candles = &parts["xl/worksheets/sheet1.xml"];
+        assert!(candles.contains(r#"<pane ySplit="1" topLeftCell="A2""#));
+        assert!(candles.contains(r#"<c r="A1" s="1" t="inlineStr"><is><t>timestamp</t></is></c>"#));
+        // 2024-01-01 is day 45292 of Excel's calendar; a minute is 1/1440.
+        assert!(candles.contains(r#"<c r="A2" s="2"><v>45292</v></c>"#));
+        assert!(candles.contains(r#"<c r="A3" s="2"><v>45292.000694444"#));
+        assert!(candles.contains(r#"<c r="F3" s="4"><v>250.5</v></c>"#));
+        // RSI has no value yet; its cell is left out.
+        let indicators = &parts["xl/worksheets/sheet2.xml"];
+        assert!(indicators.contains(r#"<c r="B2" s="3"><v>11</v></c>"#));
+        assert!(!indicators.contains(r#"r="D2""#));
+        let summary = &parts["xl/worksheets/sheet3.xml"];
+        assert!(summary.contains(r#"<c r="B5" s="7"><v>2</v></c>"#));
+        assert!(summary.contains(r#"<c r="B8" s="3"><v>9</v></c>"#));
+        assert!(summary.contains(r#"<c r="B11" s="6"><v>0.25</v></c>"#));
+
+        let response = get_uri(&app, "/api/export/xlsx?include=summary").await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let parts = unzip(&body);
+        assert!(parts["xl/worksheets/sheet1.xml"].contains("<t>change</t>"));
+        assert!(!parts.contains_key("xl/worksheets/sheet2.xml"));
+
+        for uri in [
+            "/api/export/xlsx?include=trades",
+            "/api/export/xlsx?include=",
+        ] {
+            let response = get_uri(&app, uri).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "GET {uri}");
+        }
+
+        let state = seeded_state(rows);
+        let config = Config {
+            xlsx_max_rows: 3,
+            ..Config::default()
+        };
+        let capped = build_router(AppState::new(state.db, config));
+        let response = get_uri(&capped, "/api/export/xlsx").await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response = get_uri(&capped, "/api/export/xlsx?include=candles,summary").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
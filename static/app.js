@@ -16,10 +16,10 @@ function formatNumber(value) {
 }
 
 async function loadCandles() {
-  const response = await fetch('/api/candles');
+  const response = await fetch('/api/candles?ts_format=unix_ms');
   const candles = await response.json();
   const data = candles.map((candle) => ({
-    timestamp: new Date(candle.timestamp).getTime(),
+    timestamp: candle.timestamp,
     open: candle.open,
     high: candle.high,
     low: candle.low,
@@ -30,13 +30,16 @@ async function loadCandles() {
 }
 
 async function loadIndicators() {
-  const response = await fetch('/api/indicators');
-  const indicators = await response.json();
+  const response = await fetch('/api/indicators?envelope=true');
+  const { data: indicators, meta } = await response.json();
   const last = indicators[indicators.length - 1];
   if (!last) {
     indicatorEl.textContent = 'No indicator data found.';
     return;
   }
+  const warnings = (meta.warnings || [])
+    .map((warning) => `<p class="warning">${warning}</p>`)
+    .join('');
   indicatorEl.innerHTML = `
     <dl>
       <div>
@@ -52,6 +55,7 @@ async function loadIndicators() {
         <dd>${formatNumber(last.rsi_14)}</dd>
       </div>
     </dl>
+    ${warnings}
   `;
 }
 
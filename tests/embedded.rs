@@ -0,0 +1,78 @@
+//! The front end built into the binary, served with no `static/` directory
+//! in sight. A file of its own, as it changes the working directory.
+
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::http::header::{ACCEPT, CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
+use axum::http::{Request, StatusCode};
+use axum::response::Response;
+use duckdb::Connection;
+use graph::{build_router, AppState, Config, Db};
+use tower::ServiceExt;
+
+async fn get(app: &axum::Router, uri: &str, headers: &[(&str, &str)]) -> Response {
+    let mut request = Request::get(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    app.clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+async fn text(response: Response) -> String {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn the_embedded_front_end_needs_no_static_directory() {
+    let cwd = std::env::temp_dir().join(format!("graph-embedded-{}", std::process::id()));
+    std::fs::create_dir_all(&cwd).unwrap();
+    std::env::set_current_dir(&cwd).unwrap();
+    let db = Arc::new(Db::new(Connection::open_in_memory().unwrap(), 1).unwrap());
+    let app = build_router(AppState::new(Arc::clone(&db), Config::default()));
+
+    let index = get(&app, "/", &[]).await;
+    assert_eq!(index.status(), StatusCode::OK);
+    assert_eq!(index.headers()[CONTENT_TYPE], "text/html; charset=utf-8");
+    assert_eq!(index.headers()[CACHE_CONTROL], "no-cache");
+    let etag = index.headers()[ETAG].to_str().unwrap().to_owned();
+    assert_eq!(text(index).await, include_str!("../static/index.html"));
+
+    let script = get(&app, "/app.js", &[]).await;
+    assert_eq!(script.status(), StatusCode::OK);
+    assert_eq!(
+        script.headers()[CONTENT_TYPE],
+        "text/javascript; charset=utf-8"
+    );
+    let unchanged = get(&app, "/index.html", &[(IF_NONE_MATCH.as_str(), &etag)]).await;
+    assert_eq!(unchanged.status(), StatusCode::NOT_MODIFIED);
+
+    // Pages the front end routes itself load it; missing files do not.
+    let page = get(&app, "/charts/es", &[(ACCEPT.as_str(), "text/html")]).await;
+    assert_eq!(page.status(), StatusCode::OK);
+    assert_eq!(text(page).await, include_str!("../static/index.html"));
+    let missing = get(&app, "/missing.js", &[(ACCEPT.as_str(), "text/html")]).await;
+    assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        get(&app, "/charts/es", &[]).await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    // A static directory, when given, wins.
+    std::fs::write(cwd.join("index.html"), "<p>local</p>").unwrap();
+    let config = Config {
+        static_dir: Some(cwd.clone()),
+        ..Config::default()
+    };
+    let app = build_router(AppState::new(db, config));
+    assert_eq!(text(get(&app, "/", &[]).await).await, "<p>local</p>");
+    let page = get(&app, "/charts/es", &[(ACCEPT.as_str(), "text/html")]).await;
+    assert_eq!(text(page).await, "<p>local</p>");
+    std::fs::remove_dir_all(&cwd).unwrap();
+}